@@ -24,10 +24,11 @@ Centralized storage for all settings, constants, and mappings.
 Loads environment variables and provides typed access to them.
 """
 
+import json
 import os
 import re
 from pathlib import Path
-from typing import Dict, List, Optional
+from typing import Any, Dict, List, Optional
 from dotenv import load_dotenv
 
 # Load environment variables
@@ -247,6 +248,43 @@ MODEL_ALIASES: Dict[str, str] = {
 # Default: ["auto"] to show only "auto-kiro" alias
 HIDDEN_FROM_LIST: List[str] = ["auto"]
 
+
+# ==================================================================================================
+# Model Routing Table (managed by the desktop host)
+# ==================================================================================================
+
+def _load_model_routes() -> List[Dict[str, Any]]:
+    """
+    Parse the routing table passed by the host in MODEL_ROUTES.
+
+    Format: JSON list of rules, first match wins:
+        [{"pattern": "gpt-4*", "target": "claude-sonnet-4.5",
+          "max_tokens": 8192, "fake_reasoning": false}]
+    - pattern: model name requested by the client; `*` and `?` are wildcards
+    - target: Kiro model the request is sent to
+    - max_tokens / fake_reasoning: optional defaults for requests matching the rule
+
+    Invalid JSON or rules without pattern/target are ignored (the host validates them).
+    """
+    raw = os.getenv("MODEL_ROUTES", "").strip()
+    if not raw:
+        return []
+    try:
+        routes = json.loads(raw)
+    except ValueError:
+        return []
+    if not isinstance(routes, list):
+        return []
+    return [
+        route for route in routes
+        if isinstance(route, dict)
+        and isinstance(route.get("pattern"), str) and route["pattern"]
+        and isinstance(route.get("target"), str) and route["target"]
+    ]
+
+
+MODEL_ROUTES: List[Dict[str, Any]] = _load_model_routes()
+
 # ==================================================================================================
 # Fallback Models Configuration (DNS Failure Recovery)
 # ==================================================================================================
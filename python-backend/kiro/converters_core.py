@@ -41,6 +41,7 @@ from kiro.config import (
     FAKE_REASONING_ENABLED,
     FAKE_REASONING_MAX_TOKENS,
 )
+from kiro.model_routes import fake_reasoning_enabled
 
 
 # ==================================================================================================
@@ -287,7 +288,7 @@ def get_thinking_system_prompt_addition() -> str:
     Returns:
         System prompt addition text (empty string if fake reasoning is disabled)
     """
-    if not FAKE_REASONING_ENABLED:
+    if not fake_reasoning_enabled(FAKE_REASONING_ENABLED):
         return ""
 
     return (
@@ -346,7 +347,7 @@ def inject_thinking_tags(content: str) -> str:
     Returns:
         Content with thinking tags prepended (if enabled) or original content
     """
-    if not FAKE_REASONING_ENABLED:
+    if not fake_reasoning_enabled(FAKE_REASONING_ENABLED):
         return content
 
     # Thinking instruction to improve reasoning quality
@@ -0,0 +1,151 @@
+# -*- coding: utf-8 -*-
+
+# Kiro Gateway
+# https://github.com/jwadow/kiro-gateway
+# Copyright (C) 2025 Jwadow
+#
+# This program is free software: you can redistribute it and/or modify
+# it under the terms of the GNU Affero General Public License as published by
+# the Free Software Foundation, either version 3 of the License, or
+# (at your option) any later version.
+#
+# This program is distributed in the hope that it will be useful,
+# but WITHOUT ANY WARRANTY; without even the implied warranty of
+# MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+# GNU Affero General Public License for more details.
+#
+# You should have received a copy of the GNU Affero General Public License
+# along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+"""
+Graceful shutdown for the desktop host.
+
+Before stopping the gateway, the host POSTs /admin/drain. From then on new
+requests are refused with 503 (which the host's proxy retries on another
+gateway), while requests already in flight, long streams included, run to
+completion. The host polls GET /admin/drain until `in_flight` is 0 and only
+then signals the process.
+"""
+
+import json
+from typing import Any, Awaitable, Callable, Dict, List, MutableMapping, Tuple
+
+from fastapi import APIRouter, Depends
+from loguru import logger
+
+from kiro.routes_openai import verify_api_key
+
+Scope = MutableMapping[str, Any]
+Message = MutableMapping[str, Any]
+Receive = Callable[[], Awaitable[Message]]
+Send = Callable[[Message], Awaitable[None]]
+
+# Paths that keep working while draining and are not counted as in flight
+EXEMPT_PATHS = frozenset({
+    "/health",
+    "/admin/drain",
+})
+
+
+class DrainState:
+    """Whether the gateway is draining, and how many requests are in flight."""
+
+    def __init__(self) -> None:
+        self.draining = False
+        self.in_flight = 0
+
+    def snapshot(self) -> Dict[str, Any]:
+        """State as reported by /admin/drain."""
+        return {"draining": self.draining, "in_flight": self.in_flight}
+
+
+drain_state = DrainState()
+
+
+async def _send_json(send: Send, status: int, body: Dict[str, Any], headers: List[Tuple[bytes, bytes]]) -> None:
+    """Send a complete JSON response and close the connection."""
+    payload = json.dumps(body).encode("utf-8")
+    await send({
+        "type": "http.response.start",
+        "status": status,
+        "headers": [
+            (b"content-type", b"application/json"),
+            (b"content-length", str(len(payload)).encode("ascii")),
+            (b"connection", b"close"),
+            *headers,
+        ],
+    })
+    await send({"type": "http.response.body", "body": payload})
+
+
+def _draining_error(path: str) -> Dict[str, Any]:
+    """Error body in the shape the client's API expects."""
+    message = "The gateway is shutting down; retry the request"
+    if path.startswith("/v1/messages"):
+        return {"type": "error", "error": {"type": "overloaded_error", "message": message}}
+    return {"error": {"message": message, "type": "server_error", "code": "draining"}}
+
+
+class DrainMiddleware:
+    """
+    Counts requests in flight and refuses new ones while draining.
+
+    A plain ASGI middleware rather than BaseHTTPMiddleware, so a streaming
+    response counts as in flight until its last chunk has been sent.
+    """
+
+    def __init__(self, app: Callable[[Scope, Receive, Send], Awaitable[None]], state: DrainState = drain_state):
+        self.app = app
+        self.state = state
+
+    async def __call__(self, scope: Scope, receive: Receive, send: Send) -> None:
+        if scope["type"] != "http":
+            await self.app(scope, receive, send)
+            return
+
+        path = scope.get("path", "")
+        if path in EXEMPT_PATHS:
+            if path == "/health" and self.state.draining:
+                await _send_json(send, 503, {"status": "draining"}, [])
+                return
+            await self.app(scope, receive, send)
+            return
+
+        if self.state.draining:
+            await _send_json(send, 503, _draining_error(path), [(b"retry-after", b"1")])
+            return
+
+        self.state.in_flight += 1
+        try:
+            await self.app(scope, receive, send)
+        finally:
+            self.state.in_flight -= 1
+
+
+# --- Router ---
+router = APIRouter()
+
+
+@router.post("/admin/drain", dependencies=[Depends(verify_api_key)])
+async def start_drain():
+    """
+    Stop accepting new requests; those in flight run to completion.
+
+    Returns:
+        Draining flag and the number of requests still in flight
+    """
+    if not drain_state.draining:
+        drain_state.draining = True
+        logger.info(f"Draining: refusing new requests, {drain_state.in_flight} in flight")
+    return drain_state.snapshot()
+
+
+@router.get("/admin/drain", dependencies=[Depends(verify_api_key)])
+async def drain_status():
+    """
+    Report drain progress.
+
+    Returns:
+        Draining flag and the number of requests still in flight
+    """
+    return drain_state.snapshot()
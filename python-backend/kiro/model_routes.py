@@ -0,0 +1,108 @@
+# -*- coding: utf-8 -*-
+
+# Kiro Gateway
+# https://github.com/jwadow/kiro-gateway
+# Copyright (C) 2025 Jwadow
+#
+# This program is free software: you can redistribute it and/or modify
+# it under the terms of the GNU Affero General Public License as published by
+# the Free Software Foundation, either version 3 of the License, or
+# (at your option) any later version.
+#
+# This program is distributed in the hope that it will be useful,
+# but WITHOUT ANY WARRANTY; without even the implied warranty of
+# MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+# GNU Affero General Public License for more details.
+#
+# You should have received a copy of the GNU Affero General Public License
+# along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+"""
+Model routing table managed by the desktop host.
+
+Clients often ask for model names that don't exist on Kiro (`gpt-4o`,
+`claude-3-5-sonnet-latest`). The host passes an ordered list of rules in
+MODEL_ROUTES; the first rule whose glob pattern matches the requested model
+rewrites it to a Kiro model and may supply defaults for the request.
+
+Matching is case-insensitive and supports `*` and `?` only, the same as the
+host's preview of which rule applies.
+"""
+
+import fnmatch
+from contextvars import ContextVar
+from typing import Any, Dict, List, Optional, TypeVar
+
+from loguru import logger
+
+from kiro.config import MODEL_ROUTES
+
+RequestT = TypeVar("RequestT")
+
+# Fake reasoning setting of the rule that matched the current request (None = global setting)
+_fake_reasoning_override: ContextVar[Optional[bool]] = ContextVar("fake_reasoning_override", default=None)
+
+
+def find_route(model: str, routes: Optional[List[Dict[str, Any]]] = None) -> Optional[Dict[str, Any]]:
+    """
+    Find the first rule whose pattern matches a model name.
+
+    Args:
+        model: Model name requested by the client
+        routes: Rules to search (defaults to MODEL_ROUTES)
+
+    Returns:
+        The matching rule, or None if no rule matches
+    """
+    for route in MODEL_ROUTES if routes is None else routes:
+        if fnmatch.fnmatchcase(model.lower(), route["pattern"].lower()):
+            return route
+    return None
+
+
+def apply_model_route(request_data: RequestT, routes: Optional[List[Dict[str, Any]]] = None) -> RequestT:
+    """
+    Rewrite the model of a request according to the routing table.
+
+    Sets the rule's max_tokens when the request has none, and its fake
+    reasoning setting for the rest of the request.
+
+    Args:
+        request_data: OpenAI or Anthropic request model
+        routes: Rules to apply (defaults to MODEL_ROUTES)
+
+    Returns:
+        A copy of the request with the routed model, or the request unchanged
+    """
+    _fake_reasoning_override.set(None)
+    route = find_route(request_data.model, routes)
+    if route is None:
+        return request_data
+
+    update: Dict[str, Any] = {"model": route["target"]}
+    max_tokens = route.get("max_tokens")
+    if isinstance(max_tokens, int) and max_tokens > 0:
+        has_limit = getattr(request_data, "max_tokens", None) is not None or (
+            getattr(request_data, "max_completion_tokens", None) is not None
+        )
+        if not has_limit:
+            update["max_tokens"] = max_tokens
+    if isinstance(route.get("fake_reasoning"), bool):
+        _fake_reasoning_override.set(route["fake_reasoning"])
+
+    logger.debug(f"Model route '{route['pattern']}': {request_data.model} -> {route['target']}")
+    return request_data.model_copy(update=update)
+
+
+def fake_reasoning_enabled(default: bool) -> bool:
+    """
+    Whether fake reasoning applies to the current request.
+
+    Args:
+        default: The global FAKE_REASONING setting
+
+    Returns:
+        The matched rule's setting if it has one, otherwise the default
+    """
+    override = _fake_reasoning_override.get()
+    return default if override is None else override
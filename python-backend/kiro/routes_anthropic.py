@@ -44,6 +44,7 @@ from kiro.models_anthropic import (
 from kiro.auth import KiroAuthManager, AuthType
 from kiro.cache import ModelInfoCache
 from kiro.converters_anthropic import anthropic_to_kiro
+from kiro.model_routes import apply_model_route
 from kiro.streaming_anthropic import (
     stream_kiro_to_anthropic,
     collect_anthropic_response,
@@ -142,6 +143,9 @@ async def messages(
         HTTPException: On validation or API errors
     """
     logger.info(f"Request to /v1/messages (model={request_data.model}, stream={request_data.stream})")
+
+    # Map client model names to Kiro models using the host's routing table
+    request_data = apply_model_route(request_data)
     
     if anthropic_version:
         logger.debug(f"Anthropic-Version header: {anthropic_version}")
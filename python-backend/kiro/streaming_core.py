@@ -44,6 +44,7 @@ from kiro.config import (
     FAKE_REASONING_ENABLED,
     FAKE_REASONING_HANDLING,
 )
+from kiro.model_routes import fake_reasoning_enabled
 from kiro.thinking_parser import ThinkingParser
 
 if TYPE_CHECKING:
@@ -142,7 +143,7 @@ async def parse_kiro_stream(
     
     # Initialize thinking parser if fake reasoning is enabled
     thinking_parser: Optional[ThinkingParser] = None
-    if FAKE_REASONING_ENABLED and enable_thinking_parser:
+    if fake_reasoning_enabled(FAKE_REASONING_ENABLED) and enable_thinking_parser:
         thinking_parser = ThinkingParser(handling_mode=FAKE_REASONING_HANDLING)
         logger.debug(f"Thinking parser initialized with mode: {FAKE_REASONING_HANDLING}")
     
@@ -82,6 +82,7 @@ from kiro.routes_openai import router as openai_router
 from kiro.routes_anthropic import router as anthropic_router
 from kiro.exceptions import validation_exception_handler
 from kiro.debug_middleware import DebugLoggerMiddleware
+from kiro.drain import DrainMiddleware, router as drain_router
 
 
 # --- Loguru Configuration ---
@@ -454,6 +455,12 @@ app.add_middleware(
 app.add_middleware(DebugLoggerMiddleware)
 
 
+# --- Drain Middleware ---
+# Added last so it is outermost: while the host drains the gateway before
+# stopping it, new requests are refused before any other processing
+app.add_middleware(DrainMiddleware)
+
+
 # --- Validation Error Handler Registration ---
 app.add_exception_handler(RequestValidationError, validation_exception_handler)
 
@@ -465,6 +472,9 @@ app.include_router(openai_router)
 # Anthropic-compatible API: /v1/messages
 app.include_router(anthropic_router)
 
+# Graceful shutdown for the desktop host: /admin/drain
+app.include_router(drain_router)
+
 
 # --- Uvicorn log config ---
 # Minimal configuration for redirecting uvicorn logs to loguru.
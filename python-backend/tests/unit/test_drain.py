@@ -0,0 +1,248 @@
+# -*- coding: utf-8 -*-
+
+"""
+Unit tests for draining the gateway before the host stops it.
+
+Tests cover:
+- Counting requests in flight, streams included, until they complete
+- Refusing new requests with 503 while draining
+- Health and drain endpoints while draining
+- The /admin/drain handlers
+"""
+
+import asyncio
+import json
+from unittest.mock import patch
+
+import pytest
+
+from kiro.drain import DrainMiddleware, DrainState, drain_status, start_drain
+
+
+def http_scope(path):
+    """Minimal ASGI scope of an HTTP request."""
+    return {"type": "http", "method": "POST", "path": path, "headers": []}
+
+
+async def receive():
+    return {"type": "http.request", "body": b"", "more_body": False}
+
+
+class Recorder:
+    """Collects the ASGI messages a middleware sends."""
+
+    def __init__(self):
+        self.messages = []
+
+    async def __call__(self, message):
+        self.messages.append(message)
+
+    @property
+    def status(self):
+        return self.messages[0]["status"]
+
+    @property
+    def headers(self):
+        return dict(self.messages[0]["headers"])
+
+    @property
+    def json(self):
+        return json.loads(self.messages[-1]["body"])
+
+
+async def ok_app(scope, receive, send):
+    """Downstream app answering 200."""
+    await send({"type": "http.response.start", "status": 200, "headers": []})
+    await send({"type": "http.response.body", "body": b"{}"})
+
+
+class TestDrainMiddlewareCounting:
+    """Tests for counting requests in flight."""
+
+    @pytest.mark.asyncio
+    async def test_counts_request_until_stream_ends(self):
+        """
+        What it does: Streams a response in two chunks and checks the count between them.
+        Purpose: Ensure a stream counts as in flight until its last chunk is sent.
+        """
+        state = DrainState()
+        release = asyncio.Event()
+        counts = []
+
+        async def streaming_app(scope, receive, send):
+            await send({"type": "http.response.start", "status": 200, "headers": []})
+            await send({"type": "http.response.body", "body": b"data: 1\n\n", "more_body": True})
+            counts.append(state.in_flight)
+            await release.wait()
+            await send({"type": "http.response.body", "body": b"data: [DONE]\n\n"})
+
+        middleware = DrainMiddleware(streaming_app, state=state)
+        print("Action: Starting a streamed request...")
+        task = asyncio.create_task(middleware(http_scope("/v1/chat/completions"), receive, Recorder()))
+        await asyncio.sleep(0)
+
+        print(f"In flight mid-stream: {counts}")
+        assert counts == [1]
+        assert state.in_flight == 1
+
+        release.set()
+        await task
+        print(f"In flight after the stream: {state.in_flight}")
+        assert state.in_flight == 0
+
+    @pytest.mark.asyncio
+    async def test_failed_request_is_no_longer_counted(self):
+        """
+        What it does: Raises from the downstream app.
+        Purpose: Ensure an error does not leave the request counted forever.
+        """
+        state = DrainState()
+
+        async def failing_app(scope, receive, send):
+            raise RuntimeError("boom")
+
+        middleware = DrainMiddleware(failing_app, state=state)
+        with pytest.raises(RuntimeError):
+            await middleware(http_scope("/v1/messages"), receive, Recorder())
+
+        assert state.in_flight == 0
+
+    @pytest.mark.asyncio
+    async def test_health_and_drain_endpoints_are_not_counted(self):
+        """
+        What it does: Requests /health and /admin/drain while checking the count.
+        Purpose: Ensure polling the drain status never keeps it above 0.
+        """
+        state = DrainState()
+        counts = []
+
+        async def counting_app(scope, receive, send):
+            counts.append(state.in_flight)
+            await ok_app(scope, receive, send)
+
+        middleware = DrainMiddleware(counting_app, state=state)
+        await middleware(http_scope("/health"), receive, Recorder())
+        await middleware(http_scope("/admin/drain"), receive, Recorder())
+
+        print(f"Counts seen by the app: {counts}")
+        assert counts == [0, 0]
+
+    @pytest.mark.asyncio
+    async def test_passes_through_non_http_scopes(self):
+        """
+        What it does: Sends a lifespan scope through the middleware.
+        Purpose: Ensure startup and shutdown events reach the app.
+        """
+        seen = []
+
+        async def app(scope, receive, send):
+            seen.append(scope["type"])
+
+        await DrainMiddleware(app, state=DrainState())({"type": "lifespan"}, receive, Recorder())
+
+        assert seen == ["lifespan"]
+
+
+class TestDrainMiddlewareDraining:
+    """Tests for refusing requests while draining."""
+
+    @pytest.mark.asyncio
+    async def test_refuses_new_requests_with_503(self):
+        """
+        What it does: Sends an OpenAI request to a draining gateway.
+        Purpose: Ensure it is refused in a shape the proxy retries elsewhere.
+        """
+        state = DrainState()
+        state.draining = True
+        called = []
+
+        async def app(scope, receive, send):
+            called.append(True)
+
+        send = Recorder()
+        await DrainMiddleware(app, state=state)(http_scope("/v1/chat/completions"), receive, send)
+
+        print(f"Status: {send.status}, body: {send.json}")
+        assert send.status == 503
+        assert send.headers[b"retry-after"] == b"1"
+        assert send.headers[b"connection"] == b"close"
+        assert send.json["error"]["code"] == "draining"
+        assert called == []
+        assert state.in_flight == 0
+
+    @pytest.mark.asyncio
+    async def test_refuses_anthropic_requests_in_anthropic_shape(self):
+        """
+        What it does: Sends an Anthropic request to a draining gateway.
+        Purpose: Ensure Anthropic clients get an error they understand.
+        """
+        state = DrainState()
+        state.draining = True
+
+        send = Recorder()
+        await DrainMiddleware(ok_app, state=state)(http_scope("/v1/messages"), receive, send)
+
+        assert send.status == 503
+        assert send.json["type"] == "error"
+        assert send.json["error"]["type"] == "overloaded_error"
+
+    @pytest.mark.asyncio
+    async def test_health_reports_draining(self):
+        """
+        What it does: Requests /health from a draining gateway.
+        Purpose: Ensure health probes see that the gateway is going away.
+        """
+        state = DrainState()
+        state.draining = True
+
+        send = Recorder()
+        await DrainMiddleware(ok_app, state=state)(http_scope("/health"), receive, send)
+
+        assert send.status == 503
+        assert send.json == {"status": "draining"}
+
+    @pytest.mark.asyncio
+    async def test_drain_endpoint_still_answers(self):
+        """
+        What it does: Requests /admin/drain from a draining gateway.
+        Purpose: Ensure the host can keep polling drain progress.
+        """
+        state = DrainState()
+        state.draining = True
+
+        send = Recorder()
+        await DrainMiddleware(ok_app, state=state)(http_scope("/admin/drain"), receive, send)
+
+        assert send.status == 200
+
+
+class TestDrainEndpoints:
+    """Tests for the /admin/drain handlers."""
+
+    @pytest.mark.asyncio
+    async def test_start_drain_sets_flag_and_reports_in_flight(self):
+        """
+        What it does: Starts draining with two requests in flight.
+        Purpose: Ensure the host learns how many requests it waits for.
+        """
+        state = DrainState()
+        state.in_flight = 2
+        with patch("kiro.drain.drain_state", state):
+            result = await start_drain()
+
+        print(f"Result: {result}")
+        assert result == {"draining": True, "in_flight": 2}
+        assert state.draining is True
+
+    @pytest.mark.asyncio
+    async def test_drain_status_does_not_start_draining(self):
+        """
+        What it does: Reads the drain status of a serving gateway.
+        Purpose: Ensure polling alone does not refuse requests.
+        """
+        state = DrainState()
+        with patch("kiro.drain.drain_state", state):
+            result = await drain_status()
+
+        assert result == {"draining": False, "in_flight": 0}
+        assert state.draining is False
@@ -0,0 +1,129 @@
+# -*- coding: utf-8 -*-
+
+"""
+Unit tests for the host-managed model routing table.
+
+Tests cover:
+- Glob matching of requested model names (first rule wins, case-insensitive)
+- Rewriting the model and filling in max_tokens defaults
+- Per-rule fake reasoning override
+- Parsing MODEL_ROUTES from the environment
+"""
+
+import os
+from unittest.mock import patch
+
+import pytest
+
+from kiro.model_routes import apply_model_route, fake_reasoning_enabled, find_route
+from kiro.models_anthropic import AnthropicMessagesRequest
+from kiro.models_openai import ChatCompletionRequest
+
+
+@pytest.fixture
+def routes():
+    """Routing table as the host passes it."""
+    return [
+        {"pattern": "gpt-4o-mini", "target": "claude-haiku-4.5", "max_tokens": 2048},
+        {"pattern": "gpt-4*", "target": "claude-sonnet-4.5", "fake_reasoning": False},
+        {"pattern": "claude-3-5-sonnet-?atest", "target": "claude-sonnet-4"},
+    ]
+
+
+class TestFindRoute:
+    """Tests for matching model names against the routing table."""
+
+    def test_first_matching_rule_wins(self, routes):
+        """
+        What it does: Matches a name that fits two patterns.
+        Purpose: Ensure rules are tried in order.
+        """
+        print("Action: Resolving gpt-4o-mini...")
+        route = find_route("gpt-4o-mini", routes)
+        print(f"Comparing: Expected claude-haiku-4.5, Got {route}")
+        assert route["target"] == "claude-haiku-4.5"
+
+    def test_wildcards_and_case(self, routes):
+        """
+        What it does: Matches names through `*` and `?` in different case.
+        Purpose: Ensure glob matching is case-insensitive.
+        """
+        assert find_route("GPT-4o", routes)["target"] == "claude-sonnet-4.5"
+        assert find_route("claude-3-5-sonnet-latest", routes)["target"] == "claude-sonnet-4"
+
+    def test_no_match(self, routes):
+        """
+        What it does: Resolves a name no rule covers.
+        Purpose: Ensure unmatched models are left to the gateway's own resolution.
+        """
+        assert find_route("claude-opus-4.5", routes) is None
+
+
+class TestApplyModelRoute:
+    """Tests for rewriting requests according to the routing table."""
+
+    def test_rewrites_model_and_sets_max_tokens(self, routes):
+        """
+        What it does: Routes an OpenAI request without max_tokens.
+        Purpose: Ensure the target model and the rule's max_tokens are used.
+        """
+        request = ChatCompletionRequest(model="gpt-4o-mini", messages=[{"role": "user", "content": "Hi"}])
+        routed = apply_model_route(request, routes)
+        print(f"Routed request: model={routed.model}, max_tokens={routed.max_tokens}")
+        assert routed.model == "claude-haiku-4.5"
+        assert routed.max_tokens == 2048
+        assert request.model == "gpt-4o-mini"
+
+    def test_keeps_client_max_tokens(self, routes):
+        """
+        What it does: Routes an Anthropic request that sets max_tokens.
+        Purpose: Ensure the rule only supplies a default.
+        """
+        request = AnthropicMessagesRequest(
+            model="gpt-4o-mini", max_tokens=100, messages=[{"role": "user", "content": "Hi"}]
+        )
+        routed = apply_model_route(request, routes)
+        assert routed.model == "claude-haiku-4.5"
+        assert routed.max_tokens == 100
+
+    def test_fake_reasoning_override(self, routes):
+        """
+        What it does: Routes requests with and without a fake reasoning setting.
+        Purpose: Ensure the rule's setting applies and is reset for the next request.
+        """
+        request = ChatCompletionRequest(model="gpt-4.1", messages=[{"role": "user", "content": "Hi"}])
+        apply_model_route(request, routes)
+        assert fake_reasoning_enabled(True) is False
+
+        request = ChatCompletionRequest(model="claude-opus-4.5", messages=[{"role": "user", "content": "Hi"}])
+        routed = apply_model_route(request, routes)
+        assert routed is request
+        assert fake_reasoning_enabled(True) is True
+
+
+class TestModelRoutesConfig:
+    """Tests for reading MODEL_ROUTES from the environment."""
+
+    def test_parses_valid_rules(self):
+        """
+        What it does: Loads a table with one valid and one incomplete rule.
+        Purpose: Ensure incomplete rules are dropped.
+        """
+        raw = '[{"pattern": "gpt-*", "target": "auto"}, {"pattern": "o1"}]'
+        with patch.dict(os.environ, {"MODEL_ROUTES": raw}):
+            import importlib
+            import kiro.config as config_module
+            importlib.reload(config_module)
+            print(f"MODEL_ROUTES: {config_module.MODEL_ROUTES}")
+            assert config_module.MODEL_ROUTES == [{"pattern": "gpt-*", "target": "auto"}]
+
+    def test_invalid_json_is_ignored(self):
+        """
+        What it does: Loads malformed JSON.
+        Purpose: Ensure the gateway still starts without routes.
+        """
+        with patch.dict(os.environ, {"MODEL_ROUTES": "not json"}):
+            import importlib
+            import kiro.config as config_module
+            importlib.reload(config_module)
+            assert config_module.MODEL_ROUTES == []
@@ -0,0 +1,218 @@
+use crate::config::AppConfig;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+/// Shortest `proxy_api_key` accepted when the gateway is exposed to the network
+const MIN_KEY_LENGTH: usize = 24;
+/// Fewest different characters in such a key, so `aaaa…` does not pass
+const MIN_KEY_CHARACTERS: usize = 10;
+
+/// An address range in CIDR notation; a bare address is a range of one
+#[derive(Debug, Clone, Copy)]
+pub struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (text, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address or CIDR range", text))?;
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= bits)
+                .ok_or_else(|| format!("'{}' has an invalid prefix length", text))?,
+            None => bits,
+        };
+        Ok(Self {
+            address: canonical(address),
+            prefix,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// IPv4 clients of a dual-stack listener arrive as `::ffff:a.b.c.d`
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+fn parse_all(list: &[String], name: &str) -> Result<Vec<Network>, String> {
+    list.iter()
+        .filter(|n| !n.trim().is_empty())
+        .map(|n| Network::parse(n).map_err(|e| format!("{}: {}", name, e)))
+        .collect()
+}
+
+/// Check the allowed and denied networks in `config`
+pub fn validate(config: &AppConfig) -> Result<(), String> {
+    parse_all(&config.allowed_networks, "Allowed networks")?;
+    parse_all(&config.denied_networks, "Denied networks")?;
+    Ok(())
+}
+
+/// Whether `config` limits which addresses may connect, which takes the proxy
+pub fn restricts(config: &AppConfig) -> bool {
+    config.allowed_networks.iter().chain(&config.denied_networks).any(|n| !n.trim().is_empty())
+}
+
+#[derive(Default)]
+struct Rules {
+    allowed: Vec<Network>,
+    denied: Vec<Network>,
+}
+
+/// Client addresses the proxy accepts requests from
+#[derive(Default)]
+pub struct AccessList {
+    rules: Mutex<Rules>,
+}
+
+impl AccessList {
+    /// Apply the allowed and denied networks from `config`; invalid entries are skipped
+    pub fn configure(&self, config: &AppConfig) {
+        let parse = |list: &[String]| -> Vec<Network> {
+            list.iter()
+                .filter(|n| !n.trim().is_empty())
+                .filter_map(|n| match Network::parse(n) {
+                    Ok(network) => Some(network),
+                    Err(e) => {
+                        eprintln!("[Access] Ignoring {}", e);
+                        None
+                    }
+                })
+                .collect()
+        };
+        let rules = Rules {
+            allowed: parse(&config.allowed_networks),
+            denied: parse(&config.denied_networks),
+        };
+        *self.rules.lock().unwrap_or_else(|e| e.into_inner()) = rules;
+    }
+
+    /// Whether a client at `ip` may use the gateway: loopback always may (the
+    /// app itself connects from there), others if they are in an allowed
+    /// network (or none are set) and not in a denied one
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let ip = canonical(ip);
+        if ip.is_loopback() {
+            return true;
+        }
+        let rules = self.rules.lock().unwrap_or_else(|e| e.into_inner());
+        if rules.denied.iter().any(|n| n.contains(ip)) {
+            return false;
+        }
+        rules.allowed.is_empty() || rules.allowed.iter().any(|n| n.contains(ip))
+    }
+}
+
+/// Whether `host` only accepts connections from this machine
+pub fn is_loopback_host(host: &str) -> bool {
+    let host = host.trim().trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost") || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Why `key` is too weak to protect a gateway on the network, if it is
+pub fn key_weakness(key: &str) -> Option<String> {
+    let mut characters: Vec<char> = key.chars().collect();
+    if characters.len() < MIN_KEY_LENGTH {
+        return Some(format!("The proxy API key must be at least {} characters long", MIN_KEY_LENGTH));
+    }
+    characters.sort_unstable();
+    characters.dedup();
+    if characters.len() < MIN_KEY_CHARACTERS {
+        return Some(format!(
+            "The proxy API key must use at least {} different characters",
+            MIN_KEY_CHARACTERS
+        ));
+    }
+    None
+}
+
+/// How the configured `server_host` exposes the gateway to other machines
+#[derive(Debug, Clone, Serialize)]
+pub struct LanExposure {
+    pub host: String,
+    /// Whether other machines can reach the gateway
+    pub exposed: bool,
+    /// Whether the user confirmed exposing it on this host
+    pub confirmed: bool,
+    /// Why the proxy API key is not strong enough, if it is not
+    pub key_weakness: Option<String>,
+    /// Whether allowed or denied networks limit who can connect
+    pub restricted: bool,
+    /// Addresses other machines would use
+    pub urls: Vec<String>,
+}
+
+pub fn exposure(config: &AppConfig) -> LanExposure {
+    let host = config.server_host.trim().to_string();
+    let exposed = !is_loopback_host(&host);
+    let scheme = if config.tls_enabled { "https" } else { "http" };
+    let mut urls = Vec::new();
+    if exposed {
+        let unspecified = host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified());
+        let address = if unspecified {
+            crate::tls::lan_address().map(|ip| ip.to_string())
+        } else {
+            Some(host.clone())
+        };
+        if let Some(address) = address {
+            let address = if address.contains(':') { format!("[{}]", address) } else { address };
+            urls.push(format!("{}://{}:{}", scheme, address, config.server_port));
+        }
+    }
+    LanExposure {
+        confirmed: exposed && config.lan_exposure_host.as_deref().map(str::trim) == Some(host.as_str()),
+        key_weakness: if exposed { key_weakness(&config.proxy_api_key) } else { None },
+        restricted: restricts(config),
+        exposed,
+        host,
+        urls,
+    }
+}
+
+/// Refuse to serve on a non-loopback host unless the user confirmed it and
+/// the proxy API key is strong
+pub fn check_exposure(config: &AppConfig) -> Result<(), String> {
+    let exposure = exposure(config);
+    if !exposure.exposed {
+        return Ok(());
+    }
+    if !exposure.confirmed {
+        return Err(format!(
+            "Listening on {} makes the gateway reachable from other machines; confirm LAN exposure in Settings first",
+            exposure.host
+        ));
+    }
+    match exposure.key_weakness {
+        Some(weakness) => Err(format!("{} to listen on {}", weakness, exposure.host)),
+        None => Ok(()),
+    }
+}
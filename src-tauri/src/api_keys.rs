@@ -0,0 +1,342 @@
+use crate::conversations::{generate_id, get_conversations_path, now_millis, write_synced};
+use crate::request_log::mask_key;
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::fs;
+
+/// Prefix of generated client keys
+const KEY_PREFIX: &str = "sk-kiro-";
+/// How stale `last_used_at` may get on disk before it is written again
+const LAST_USED_FLUSH_MS: u64 = 60_000;
+
+/// API family a key may be used with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// `/v1/chat/completions`, `/usage` and other OpenAI-style endpoints
+    Openai,
+    /// `/v1/messages` and `/v1/messages/count_tokens`
+    Anthropic,
+}
+
+/// A client API key; only a hash of the secret is stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub label: String,
+    /// Masked secret shown to recognize the key
+    pub hint: String,
+    /// SHA-256 of the secret
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    secret_hash: String,
+    pub scopes: Vec<ApiScope>,
+    /// Models the key may request (empty = any)
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub revoked_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+}
+
+impl ApiKey {
+    /// Copy without the secret hash, for the frontend
+    fn public(&self) -> ApiKey {
+        ApiKey {
+            secret_hash: String::new(),
+            ..self.clone()
+        }
+    }
+
+    fn is_active(&self, now: u64) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|t| now < t)
+    }
+}
+
+/// A newly created key together with its secret, which is never shown again
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedApiKey {
+    pub key: ApiKey,
+    pub secret: String,
+}
+
+/// Why a request was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyDenied {
+    /// No key, or a key that is not in the registry
+    Unknown,
+    Revoked,
+    Expired,
+    /// The key is not allowed on this endpoint
+    Scope(ApiScope),
+    /// The key is not allowed to use this model
+    Model(String),
+    /// The key is limited to some models and the request names none
+    ModelRequired,
+    /// Admin endpoints (`/admin/...`) accept only the gateway's own key
+    Admin,
+}
+
+impl KeyDenied {
+    /// HTTP status to answer with
+    pub fn status(&self) -> u16 {
+        match self {
+            KeyDenied::Unknown | KeyDenied::Revoked | KeyDenied::Expired => 401,
+            KeyDenied::Scope(_) | KeyDenied::Model(_) | KeyDenied::ModelRequired | KeyDenied::Admin => 403,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            KeyDenied::Unknown => "Invalid or missing API key".to_string(),
+            KeyDenied::Revoked => "API key has been revoked".to_string(),
+            KeyDenied::Expired => "API key has expired".to_string(),
+            KeyDenied::Scope(ApiScope::Openai) => "API key is not allowed to use the OpenAI API".to_string(),
+            KeyDenied::Scope(ApiScope::Anthropic) => "API key is not allowed to use the Anthropic API".to_string(),
+            KeyDenied::Model(model) => format!("API key is not allowed to use model {}", model),
+            KeyDenied::ModelRequired => "API key is limited to some models; the request must name one".to_string(),
+            KeyDenied::Admin => "Admin endpoints accept only the gateway key".to_string(),
+        }
+    }
+}
+
+/// Who a request was authorized as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyIdentity {
+    /// The gateway's own `proxy_api_key`, used by the app itself
+    Master,
+    Client { id: String, label: String },
+}
+
+/// API family of an endpoint, or `None` for public endpoints (`/`, `/health`)
+/// and endpoints every scope may use (`/v1/models`)
+pub fn endpoint_scope(path: &str) -> Option<ApiScope> {
+    match path {
+        "/" | "/health" | "/v1/models" => None,
+        p if p.starts_with("/v1/messages") => Some(ApiScope::Anthropic),
+        _ => Some(ApiScope::Openai),
+    }
+}
+
+/// Whether an endpoint generates a response from the model named in the body
+/// (`/v1/chat/completions`, `/v1/completions`, `/v1/messages...`)
+pub fn takes_model(path: &str) -> bool {
+    matches!(path, "/v1/chat/completions" | "/v1/completions") || path.starts_with("/v1/messages")
+}
+
+/// Whether an endpoint controls the gateway itself, e.g. `/admin/drain`
+pub fn is_admin(path: &str) -> bool {
+    path == "/admin" || path.starts_with("/admin/")
+}
+
+/// Whether an endpoint can be called without a key
+pub fn is_public(path: &str) -> bool {
+    matches!(path, "/" | "/health")
+}
+
+/// Compare a presented key with the expected one without leaking how much of it matched
+pub fn keys_match(presented: &str, expected: &str) -> bool {
+    let presented = Sha256::digest(presented.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    presented.iter().zip(expected.iter()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 24];
+    OsRng.fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", KEY_PREFIX, hex)
+}
+
+fn get_keys_path() -> Result<PathBuf, String> {
+    let path = get_conversations_path()?;
+    let parent = path.parent().ok_or("Failed to get data directory")?;
+    Ok(parent.join("api-keys.json"))
+}
+
+/// Client API keys accepted by the proxy, persisted in `api-keys.json`.
+///
+/// The keys are checked at the proxy; requests are then forwarded to the gateway
+/// with `proxy_api_key`, which the gateway itself still requires.
+pub struct KeyRegistry {
+    keys: Mutex<Vec<ApiKey>>,
+    /// Last time `last_used_at` was written to disk
+    flushed_at: Mutex<u64>,
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl KeyRegistry {
+    /// Load the registry, starting empty if it does not exist or cannot be read
+    pub fn open() -> Self {
+        let keys = get_keys_path()
+            .ok()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|content| match serde_json::from_slice::<Vec<ApiKey>>(&content) {
+                Ok(keys) => Some(keys),
+                Err(e) => {
+                    eprintln!("[Keys] Failed to parse API keys: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self {
+            keys: Mutex::new(keys),
+            flushed_at: Mutex::new(now_millis()),
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<ApiKey>> {
+        self.keys.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether any key can currently be used
+    pub fn has_active(&self) -> bool {
+        let now = now_millis();
+        self.lock().iter().any(|key| key.is_active(now))
+    }
+
+    /// All keys, without their secret hashes
+    pub fn list(&self) -> Vec<ApiKey> {
+        self.lock().iter().map(ApiKey::public).collect()
+    }
+
+    /// Write the registry to disk
+    pub async fn save(&self) -> Result<(), String> {
+        let _guard = self.save_lock.lock().await;
+        let content = {
+            let keys = self.lock();
+            serde_json::to_vec_pretty(&*keys).map_err(|e| format!("Failed to serialize API keys: {}", e))?
+        };
+        let path = get_keys_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create data directory: {}", e))?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        write_synced(&tmp_path, &content)
+            .await
+            .map_err(|e| format!("Failed to write API keys: {}", e))?;
+        fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|e| format!("Failed to rename API keys temp file: {}", e))?;
+        *self.flushed_at.lock().unwrap_or_else(|e| e.into_inner()) = now_millis();
+        Ok(())
+    }
+
+    /// Create a key; the returned secret is not stored anywhere
+    pub async fn create(
+        &self,
+        label: String,
+        scopes: Vec<ApiScope>,
+        allowed_models: Vec<String>,
+        expires_at: Option<u64>,
+    ) -> Result<CreatedApiKey, String> {
+        let label = label.trim().to_string();
+        if label.is_empty() {
+            return Err("Key label cannot be empty".to_string());
+        }
+        if scopes.is_empty() {
+            return Err("A key needs at least one scope".to_string());
+        }
+
+        let secret = generate_secret();
+        let key = ApiKey {
+            id: generate_id(),
+            label,
+            hint: mask_key(&secret),
+            secret_hash: hash_secret(&secret),
+            scopes,
+            allowed_models: allowed_models
+                .into_iter()
+                .map(|m| m.trim().to_string())
+                .filter(|m| !m.is_empty())
+                .collect(),
+            created_at: now_millis(),
+            expires_at,
+            revoked_at: None,
+            last_used_at: None,
+        };
+        let public = key.public();
+        self.lock().push(key);
+        self.save().await?;
+        Ok(CreatedApiKey { key: public, secret })
+    }
+
+    /// Revoke a key; it stays listed so its history remains visible
+    pub async fn revoke(&self, id: &str) -> Result<(), String> {
+        {
+            let mut keys = self.lock();
+            let key = keys.iter_mut().find(|k| k.id == id).ok_or("API key not found")?;
+            if key.revoked_at.is_none() {
+                key.revoked_at = Some(now_millis());
+            }
+        }
+        self.save().await
+    }
+
+    /// Remove a key from the registry
+    pub async fn delete(&self, id: &str) -> Result<(), String> {
+        {
+            let mut keys = self.lock();
+            let before = keys.len();
+            keys.retain(|k| k.id != id);
+            if keys.len() == before {
+                return Err("API key not found".to_string());
+            }
+        }
+        self.save().await
+    }
+
+    /// Check `secret` against the registry for a request to `path` using `model`.
+    ///
+    /// Records the key as used; returns whether `last_used_at` should be flushed to disk.
+    pub fn authorize(&self, secret: &str, path: &str, model: Option<&str>) -> Result<(KeyIdentity, bool), KeyDenied> {
+        let hash = hash_secret(secret);
+        let now = now_millis();
+        let mut keys = self.lock();
+        let key = keys.iter_mut().find(|k| k.secret_hash == hash).ok_or(KeyDenied::Unknown)?;
+
+        if key.revoked_at.is_some() {
+            return Err(KeyDenied::Revoked);
+        }
+        if !key.is_active(now) {
+            return Err(KeyDenied::Expired);
+        }
+        if is_admin(path) {
+            return Err(KeyDenied::Admin);
+        }
+        if let Some(scope) = endpoint_scope(path) {
+            if !key.scopes.contains(&scope) {
+                return Err(KeyDenied::Scope(scope));
+            }
+        }
+        // A restricted key may not leave the model out where one is used
+        if !key.allowed_models.is_empty() && takes_model(path) {
+            match model {
+                Some(model) if key.allowed_models.iter().any(|m| m == model) => {}
+                Some(model) => return Err(KeyDenied::Model(model.to_string())),
+                None => return Err(KeyDenied::ModelRequired),
+            }
+        }
+
+        key.last_used_at = Some(now);
+        let flushed_at = *self.flushed_at.lock().unwrap_or_else(|e| e.into_inner());
+        Ok((
+            KeyIdentity::Client {
+                id: key.id.clone(),
+                label: key.label.clone(),
+            },
+            now.saturating_sub(flushed_at) >= LAST_USED_FLUSH_MS,
+        ))
+    }
+}
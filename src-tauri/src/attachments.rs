@@ -0,0 +1,295 @@
+use crate::conversations::{
+    get_conversations_path, rewrite_files, sync_dir, write_synced, Conversation, ConversationsData,
+};
+use crate::encryption::{self, DecryptError, StoreKey};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Prefix of attachment references stored in message content in place of data URLs.
+///
+/// A reference looks like `attachment:<mime>;sha256,<hex digest>`, mirroring the
+/// `data:<mime>;base64,<payload>` URL it replaces.
+const REFERENCE_PREFIX: &str = "attachment:";
+
+fn get_attachments_dir() -> Result<PathBuf, String> {
+    let path = get_conversations_path()?;
+    let parent = path.parent().ok_or("Failed to get conversations directory")?;
+    Ok(parent.join("attachments"))
+}
+
+/// Fields of a content part that may hold an inline file:
+/// `image_url.url` for images and `file.file_data` for other files
+fn part_slot(part: &Value) -> Option<&Value> {
+    part.get("image_url")
+        .and_then(|i| i.get("url"))
+        .or_else(|| part.get("file").and_then(|f| f.get("file_data")))
+}
+
+fn part_slot_mut(part: &mut Value) -> Option<&mut Value> {
+    if part.get("image_url").is_some() {
+        part.get_mut("image_url").and_then(|i| i.get_mut("url"))
+    } else {
+        part.get_mut("file").and_then(|f| f.get_mut("file_data"))
+    }
+}
+
+fn slots_mut(content: &mut Value) -> Vec<&mut Value> {
+    match content {
+        Value::Array(parts) => parts.iter_mut().filter_map(part_slot_mut).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Split a `data:<mime>;base64,<payload>` URL
+fn parse_data_url(url: &str) -> Option<(&str, Vec<u8>)> {
+    let (header, payload) = url.strip_prefix("data:")?.split_once(',')?;
+    let mime = header.strip_suffix(";base64")?;
+    let bytes = BASE64.decode(payload.trim()).ok()?;
+    Some((mime, bytes))
+}
+
+/// Split an `attachment:<mime>;sha256,<hex>` reference into mime type and digest
+fn parse_reference(reference: &str) -> Option<(&str, &str)> {
+    let (mime, hash) = reference.strip_prefix(REFERENCE_PREFIX)?.split_once(";sha256,")?;
+    if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some((mime, hash))
+    } else {
+        None
+    }
+}
+
+fn is_data_url(value: &Value) -> bool {
+    value.as_str().is_some_and(|s| s.starts_with("data:"))
+}
+
+fn has_inline_attachments(data: &ConversationsData) -> bool {
+    data.conversations.iter().any(|c| {
+        c.messages.iter().any(|m| match &m.content {
+            Value::Array(parts) => parts.iter().filter_map(part_slot).any(is_data_url),
+            _ => false,
+        })
+    })
+}
+
+/// Store `bytes` under their SHA-256 digest (encrypted if encryption is enabled)
+async fn write_blob(dir: &Path, bytes: &[u8]) -> Result<String, String> {
+    let hash = format!("{:x}", Sha256::digest(bytes));
+    let path = dir.join(&hash);
+    if path.exists() {
+        return Ok(hash);
+    }
+
+    let content = encryption::encrypt_if_enabled(bytes.to_vec())?;
+    let tmp_path = path.with_extension("tmp");
+    write_synced(&tmp_path, &content)
+        .await
+        .map_err(|e| format!("Failed to write attachment: {}", e))?;
+    fs::rename(&tmp_path, &path)
+        .await
+        .map_err(|e| format!("Failed to rename attachment temp file: {}", e))?;
+    Ok(hash)
+}
+
+/// Read and decrypt a blob by its digest
+async fn read_blob(hash: &str) -> Result<Vec<u8>, String> {
+    let path = get_attachments_dir()?.join(hash);
+    let content = fs::read(&path)
+        .await
+        .map_err(|e| format!("Failed to read attachment: {}", e))?;
+    if !encryption::is_encrypted(&content) {
+        return Ok(content);
+    }
+    encryption::decrypt(&content).map_err(|e| match e {
+        DecryptError::Locked => crate::conversations::LOCKED_MESSAGE.to_string(),
+        DecryptError::Invalid(e) => format!("Failed to decrypt attachment: {}", e),
+    })
+}
+
+/// Move inline data URLs in message content into the blob store, replacing them
+/// with references. Data without inline attachments is returned as is.
+pub async fn externalize(data: &ConversationsData) -> Result<Cow<'_, ConversationsData>, String> {
+    if !has_inline_attachments(data) {
+        return Ok(Cow::Borrowed(data));
+    }
+
+    let dir = get_attachments_dir()?;
+    fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create attachments directory: {}", e))?;
+
+    let mut data = data.clone();
+    for conversation in data.conversations.iter_mut() {
+        for message in conversation.messages.iter_mut() {
+            for slot in slots_mut(&mut message.content) {
+                let reference = match slot.as_str().and_then(parse_data_url) {
+                    Some((mime, bytes)) => {
+                        let hash = write_blob(&dir, &bytes).await?;
+                        format!("{}{};sha256,{}", REFERENCE_PREFIX, mime, hash)
+                    }
+                    None => continue,
+                };
+                *slot = Value::String(reference);
+            }
+        }
+    }
+
+    sync_dir(&dir)
+        .await
+        .map_err(|e| format!("Failed to sync attachments directory: {}", e))?;
+    Ok(Cow::Owned(data))
+}
+
+/// Replace attachment references in a conversation with data URLs.
+///
+/// References whose blob is missing are left in place so the message still loads.
+pub async fn inline_conversation(conversation: &mut Conversation) -> Result<(), String> {
+    for message in conversation.messages.iter_mut() {
+        for slot in slots_mut(&mut message.content) {
+            let data_url = match slot.as_str().and_then(parse_reference) {
+                Some((mime, hash)) => match read_blob(hash).await {
+                    Ok(bytes) => format!("data:{};base64,{}", mime, BASE64.encode(bytes)),
+                    Err(e) => {
+                        eprintln!("[Attachments] Failed to inline {}: {}", hash, e);
+                        continue;
+                    }
+                },
+                None => continue,
+            };
+            *slot = Value::String(data_url);
+        }
+    }
+    Ok(())
+}
+
+/// Replace attachment references in every conversation with data URLs
+pub async fn inline(data: &mut ConversationsData) -> Result<(), String> {
+    for conversation in data.conversations.iter_mut() {
+        inline_conversation(conversation).await?;
+    }
+    Ok(())
+}
+
+/// Resolve an attachment reference to a data URL
+pub async fn resolve(reference: &str) -> Result<String, String> {
+    let (mime, hash) = parse_reference(reference).ok_or("Invalid attachment reference")?;
+    let bytes = read_blob(hash).await?;
+    Ok(format!("data:{};base64,{}", mime, BASE64.encode(bytes)))
+}
+
+/// Number of references to each blob in `data`, trashed conversations included
+pub fn reference_counts(data: &ConversationsData) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for conversation in &data.conversations {
+        for message in &conversation.messages {
+            if let Value::Array(parts) = &message.content {
+                for (_, hash) in parts
+                    .iter()
+                    .filter_map(part_slot)
+                    .filter_map(|v| v.as_str())
+                    .filter_map(parse_reference)
+                {
+                    *counts.entry(hash.to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+    counts
+}
+
+/// Blob files in the store as (digest, path)
+async fn blob_files(dir: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut entries = fs::read_dir(dir)
+        .await
+        .map_err(|e| format!("Failed to read attachments directory: {}", e))?;
+    let mut files = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            files.push((name, entry.path()));
+        }
+    }
+    Ok(files)
+}
+
+/// Delete blobs no longer referenced by the conversations or by any backup,
+/// returning how many were removed.
+///
+/// Backups count as references so that restoring one never loses attachments.
+pub async fn collect_garbage(data: &ConversationsData) -> Result<usize, String> {
+    let mut counts = reference_counts(data);
+    for backup in crate::backups::load_all_backups().await? {
+        for (hash, count) in reference_counts(&backup) {
+            *counts.entry(hash).or_insert(0) += count;
+        }
+    }
+
+    let dir = get_attachments_dir()?;
+    let mut removed = 0;
+    for (hash, path) in blob_files(&dir).await? {
+        if counts.get(&hash).copied().unwrap_or(0) > 0 {
+            continue;
+        }
+        match fs::remove_file(&path).await {
+            Ok(()) => removed += 1,
+            Err(e) => eprintln!("[Attachments] Failed to remove {}: {}", hash, e),
+        }
+    }
+    Ok(removed)
+}
+
+/// Copy the blobs referenced by `data` between the local store and a shared
+/// folder, in whichever direction they are missing
+pub async fn sync_blobs(data: &ConversationsData, remote_dir: &Path) -> Result<(), String> {
+    let local_dir = get_attachments_dir()?;
+    let hashes = reference_counts(data);
+    if hashes.is_empty() {
+        return Ok(());
+    }
+    for dir in [&local_dir, &remote_dir.to_path_buf()] {
+        fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Failed to create attachments directory: {}", e))?;
+    }
+
+    for hash in hashes.keys() {
+        let local = local_dir.join(hash);
+        let remote = remote_dir.join(hash);
+        let (from, to) = match (local.exists(), remote.exists()) {
+            (true, false) => (local, remote),
+            (false, true) => (remote, local),
+            _ => continue,
+        };
+        let content = match fs::read(&from).await {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("[Attachments] Failed to read {}: {}", hash, e);
+                continue;
+            }
+        };
+        let tmp_path = to.with_extension("tmp");
+        write_synced(&tmp_path, &content)
+            .await
+            .map_err(|e| format!("Failed to copy attachment: {}", e))?;
+        fs::rename(&tmp_path, &to)
+            .await
+            .map_err(|e| format!("Failed to rename attachment temp file: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Re-encode every blob from the `old` key to the `new` key (`None` = plaintext),
+/// returning the digests that could not be rewritten
+pub async fn rewrite_blobs(old: Option<&StoreKey>, new: Option<&StoreKey>) -> Result<Vec<String>, String> {
+    let dir = get_attachments_dir()?;
+    let files = blob_files(&dir).await?;
+    rewrite_files(&dir, files, old, new).await
+}
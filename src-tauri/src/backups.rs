@@ -0,0 +1,414 @@
+use crate::conversations::{
+    decode_store, get_conversations_path, is_readable_store, now_millis, rewrite_files, sync_dir, write_synced,
+    Conversation, ConversationsData, Folder, StoreError, LOCKED_MESSAGE,
+};
+use crate::encryption::StoreKey;
+use crate::usage::UsageLedger;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs;
+
+const BACKUP_PREFIX: &str = "conversations-";
+
+/// A backup of the conversations file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub name: String,
+    pub created_at: u64,
+    pub size: u64,
+}
+
+/// What happened the last time a corrupt conversations file was recovered
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    pub recovered_at: u64,
+    pub error: String,
+    /// Where the unreadable file was moved to
+    pub quarantined_path: Option<String>,
+    pub salvaged_conversations: usize,
+    pub unreadable_conversations: usize,
+    /// Backup used when nothing could be salvaged from the corrupt file
+    pub restored_backup: Option<String>,
+}
+
+static LAST_RECOVERY: Mutex<Option<RecoveryReport>> = Mutex::new(None);
+
+/// Report of the most recent recovery during this session, if any
+pub fn last_recovery() -> Option<RecoveryReport> {
+    LAST_RECOVERY.lock().ok().and_then(|r| r.clone())
+}
+
+fn get_backups_dir() -> Result<PathBuf, String> {
+    let path = get_conversations_path()?;
+    let parent = path.parent().ok_or("Failed to get conversations directory")?;
+    Ok(parent.join("backups"))
+}
+
+/// Backup files (newest first)
+async fn backup_files(dir: &Path) -> Result<Vec<(String, PathBuf)>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = fs::read_dir(dir)
+        .await
+        .map_err(|e| format!("Failed to read backups directory: {}", e))?;
+    let mut files = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with(BACKUP_PREFIX) && name.ends_with(".json") {
+            files.push((name, entry.path()));
+        }
+    }
+    // Timestamped names sort chronologically
+    files.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(files)
+}
+
+/// List available backups, newest first
+pub async fn list_backups() -> Result<Vec<BackupInfo>, String> {
+    let dir = get_backups_dir()?;
+    let mut backups = Vec::new();
+    for (name, path) in backup_files(&dir).await? {
+        let metadata = match fs::metadata(&path).await {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        let created_at = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        backups.push(BackupInfo {
+            name,
+            created_at,
+            size: metadata.len(),
+        });
+    }
+    Ok(backups)
+}
+
+/// Copy the current conversations file into the backups directory
+async fn write_backup(dir: &Path, suffix: &str) -> Result<Option<BackupInfo>, String> {
+    let source = get_conversations_path()?;
+    let content = match fs::read(&source).await {
+        Ok(content) => content,
+        Err(_) => return Ok(None),
+    };
+
+    // A backup of an unreadable file is no use for recovery
+    if !is_readable_store(&content) {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(dir)
+        .await
+        .map_err(|e| format!("Failed to create backups directory: {}", e))?;
+
+    let name = format!(
+        "{}{}{}.json",
+        BACKUP_PREFIX,
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        suffix
+    );
+    let path = dir.join(&name);
+    write_synced(&path, &content)
+        .await
+        .map_err(|e| format!("Failed to write backup: {}", e))?;
+    sync_dir(dir)
+        .await
+        .map_err(|e| format!("Failed to sync backups directory: {}", e))?;
+
+    Ok(Some(BackupInfo {
+        name,
+        created_at: now_millis(),
+        size: content.len() as u64,
+    }))
+}
+
+/// Create today's backup if it does not exist yet and keep only the newest `keep` backups.
+///
+/// A `keep` of 0 disables automatic backups.
+pub async fn create_daily_backup(keep: usize) -> Result<Option<BackupInfo>, String> {
+    if keep == 0 {
+        return Ok(None);
+    }
+
+    let dir = get_backups_dir()?;
+    let today = format!("{}{}", BACKUP_PREFIX, chrono::Local::now().format("%Y%m%d"));
+    let existing = backup_files(&dir).await?;
+    let created = if existing.iter().any(|(name, _)| name.starts_with(&today)) {
+        None
+    } else {
+        write_backup(&dir, "").await?
+    };
+
+    for (_, path) in backup_files(&dir).await?.into_iter().skip(keep) {
+        if let Err(e) = fs::remove_file(&path).await {
+            eprintln!("[Backups] Failed to remove old backup {:?}: {}", path, e);
+        }
+    }
+
+    Ok(created)
+}
+
+/// Read a backup by name, after first backing up the current file so the restore can be undone
+pub async fn read_backup_for_restore(name: &str) -> Result<ConversationsData, String> {
+    if name.contains('/') || name.contains('\\') || !name.starts_with(BACKUP_PREFIX) {
+        return Err("Invalid backup name".to_string());
+    }
+
+    let dir = get_backups_dir()?;
+    let content = fs::read(dir.join(name))
+        .await
+        .map_err(|e| format!("Failed to read backup: {}", e))?;
+    let mut data = decode_store(&content).map_err(|e| match e {
+        StoreError::Locked => LOCKED_MESSAGE.to_string(),
+        StoreError::Corrupt(e) => format!("Failed to read backup: {}", e),
+    })?;
+    data.migrate();
+
+    write_backup(&dir, "-pre-restore").await?;
+    Ok(data)
+}
+
+/// Re-encode every backup from the `old` key to the `new` key (`None` = plaintext)
+///
+/// Used when encryption is enabled, disabled or rotated so that backups never
+/// keep a plaintext copy or depend on a retired key. Backups that cannot be
+/// read with `old` are left untouched and reported.
+pub async fn rewrite_backups(old: Option<&StoreKey>, new: Option<&StoreKey>) -> Result<Vec<String>, String> {
+    let dir = get_backups_dir()?;
+    let files = backup_files(&dir).await?;
+    rewrite_files(&dir, files, old, new).await
+}
+
+/// Contents of every readable backup.
+///
+/// Fails if any backup is encrypted and locked, since its contents are unknown.
+pub async fn load_all_backups() -> Result<Vec<ConversationsData>, String> {
+    let dir = get_backups_dir()?;
+    let mut all = Vec::new();
+    for (name, path) in backup_files(&dir).await? {
+        let content = match fs::read(&path).await {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        match decode_store(&content) {
+            Ok(data) => all.push(data),
+            Err(StoreError::Locked) => return Err(LOCKED_MESSAGE.to_string()),
+            Err(StoreError::Corrupt(e)) => eprintln!("[Backups] Skipping unreadable backup {}: {}", name, e),
+        }
+    }
+    Ok(all)
+}
+
+/// Move an unreadable conversations file aside so it is never overwritten
+async fn quarantine(path: &Path) -> Result<PathBuf, String> {
+    let target = path.with_file_name(format!(
+        "conversations.corrupt-{}.json",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    fs::rename(path, &target)
+        .await
+        .map_err(|e| format!("Failed to quarantine corrupt conversations file: {}", e))?;
+    Ok(target)
+}
+
+/// Recover from a conversations file that failed to parse.
+///
+/// The broken file is quarantined, then individual conversations are salvaged
+/// from it. If nothing can be salvaged, the newest readable backup is used.
+/// The usage ledger is kept from the file when it is still readable there,
+/// otherwise it is taken from the newest readable backup.
+pub async fn recover(path: &Path, content: &[u8], error: &str) -> Result<ConversationsData, String> {
+    let quarantined_path = quarantine(path).await?;
+    eprintln!("[Conversations] Quarantined corrupt file to {:?}", quarantined_path);
+
+    let text = String::from_utf8_lossy(content);
+    let (conversations, unreadable_conversations) = salvage_array::<Conversation>(&text, "conversations");
+    let (folders, _) = salvage_array::<Folder>(&text, "folders");
+
+    let mut report = RecoveryReport {
+        recovered_at: now_millis(),
+        error: error.to_string(),
+        quarantined_path: Some(quarantined_path.to_string_lossy().to_string()),
+        salvaged_conversations: conversations.len(),
+        unreadable_conversations,
+        restored_backup: None,
+    };
+
+    let usage = serde_json::from_str::<serde_json::Value>(&text)
+        .ok()
+        .and_then(|value| serde_json::from_value::<UsageLedger>(value.get("usage")?.clone()).ok());
+    let salvaged_usage = usage.is_some();
+
+    let mut data = ConversationsData {
+        // Salvaged items may predate the current layout; migrate() is idempotent
+        version: 0,
+        conversations,
+        folders,
+        usage: usage.unwrap_or_default(),
+    };
+
+    if data.conversations.is_empty() || !salvaged_usage {
+        let dir = get_backups_dir()?;
+        for (name, backup_path) in backup_files(&dir).await? {
+            let parsed = fs::read(&backup_path)
+                .await
+                .ok()
+                .and_then(|bytes| decode_store(&bytes).ok());
+            if let Some(backup) = parsed {
+                if data.conversations.is_empty() {
+                    data = backup;
+                    report.restored_backup = Some(name);
+                } else {
+                    data.usage = backup.usage;
+                }
+                break;
+            }
+        }
+    }
+
+    data.migrate();
+    eprintln!(
+        "[Conversations] Recovered {} conversation(s), {} unreadable, backup used: {:?}",
+        report.salvaged_conversations, report.unreadable_conversations, report.restored_backup
+    );
+    if let Ok(mut last) = LAST_RECOVERY.lock() {
+        *last = Some(report);
+    }
+
+    Ok(data)
+}
+
+/// Deserialize every readable element of the top-level array `key`.
+///
+/// Works on files that are not valid JSON as a whole (e.g. truncated writes) by
+/// scanning for balanced objects inside the array. Returns the readable items
+/// and the number of elements that could not be read.
+fn salvage_array<T: DeserializeOwned>(text: &str, key: &str) -> (Vec<T>, usize) {
+    // Well-formed JSON with some invalid elements
+    if let Ok(value) = serde_json::from_str::<serde_json::Value>(text) {
+        let items = match value.get(key).and_then(|v| v.as_array()) {
+            Some(items) => items,
+            None => return (Vec::new(), 0),
+        };
+        let mut ok = Vec::new();
+        let mut failed = 0;
+        for item in items {
+            match serde_json::from_value::<T>(item.clone()) {
+                Ok(parsed) => ok.push(parsed),
+                Err(_) => failed += 1,
+            }
+        }
+        return (ok, failed);
+    }
+
+    // Malformed JSON: locate the array and scan its top-level objects
+    let needle = format!("\"{}\"", key);
+    let start = match text
+        .find(&needle)
+        .and_then(|pos| text[pos + needle.len()..].find('[').map(|i| pos + needle.len() + i + 1))
+    {
+        Some(start) => start,
+        None => return (Vec::new(), 0),
+    };
+
+    let mut ok = Vec::new();
+    let mut failed = 0;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut object_start = None;
+
+    for (offset, ch) in text[start..].char_indices() {
+        let index = start + offset;
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match ch {
+            '"' => in_string = true,
+            '{' | '[' => {
+                if depth == 0 && ch == '{' {
+                    object_start = Some(index);
+                }
+                depth += 1;
+            }
+            '}' | ']' => {
+                if depth == 0 {
+                    // End of the array
+                    break;
+                }
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(begin) = object_start.take() {
+                        match serde_json::from_str::<T>(&text[begin..=index]) {
+                            Ok(parsed) => ok.push(parsed),
+                            Err(_) => failed += 1,
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // An object cut off by the end of the file
+    if object_start.is_some() {
+        failed += 1;
+    }
+
+    (ok, failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usage::UsageTotals;
+    use std::collections::BTreeMap;
+
+    fn ledger(date: &str) -> UsageLedger {
+        UsageLedger {
+            daily: BTreeMap::from([(date.to_string(), BTreeMap::from([("model".to_string(), UsageTotals::default())]))]),
+        }
+    }
+
+    #[tokio::test]
+    async fn recovery_keeps_the_usage_ledger_of_the_newest_backup() {
+        let dir = get_backups_dir().unwrap();
+        fs::create_dir_all(&dir).await.unwrap();
+        let backup = ConversationsData {
+            usage: ledger("2024-03-01"),
+            ..Default::default()
+        };
+        let backup_path = dir.join(format!("{}99991231-recovery.json", BACKUP_PREFIX));
+        fs::write(&backup_path, serde_json::to_vec(&backup).unwrap()).await.unwrap();
+
+        // A truncated write: one conversation survives, the ledger does not
+        let corrupt = r#"{"version":1,"conversations":[{"id":"c-1","title":"Kept","createdAt":1,"updatedAt":2,"messages":[],"model":null}],"usage":{"daily":{"#;
+        let path = dir.with_file_name("recovery-test.json");
+        fs::write(&path, corrupt).await.unwrap();
+
+        let data = recover(&path, corrupt.as_bytes(), "truncated").await.unwrap();
+        fs::remove_file(&backup_path).await.unwrap();
+        if let Some(quarantined) = last_recovery().and_then(|r| r.quarantined_path) {
+            let _ = fs::remove_file(quarantined).await;
+        }
+
+        assert_eq!(data.conversations.len(), 1);
+        assert_eq!(data.conversations[0].title, "Kept");
+        assert!(data.usage.daily.contains_key("2024-03-01"));
+    }
+}
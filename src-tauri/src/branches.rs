@@ -0,0 +1,338 @@
+use crate::conversations::{Conversation, ConversationMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Summary of one branch (root-to-leaf path) of a conversation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BranchInfo {
+    pub leaf_id: String,
+    /// First message of this branch that differs from the active branch
+    pub fork_message_id: Option<String>,
+    pub message_count: usize,
+    pub updated_at: u64,
+    pub preview: String,
+    pub active: bool,
+}
+
+/// Convert a conversation stored in the legacy flat layout into a single-branch tree.
+///
+/// Conversations that already have parent links or an active leaf are left alone.
+pub fn migrate_flat(conversation: &mut Conversation) {
+    if conversation.active_leaf_id.is_some()
+        || conversation.messages.iter().any(|m| m.parent_id.is_some())
+    {
+        return;
+    }
+
+    let mut parent: Option<String> = None;
+    for message in conversation.messages.iter_mut() {
+        message.parent_id = parent.take();
+        parent = Some(message.id.clone());
+    }
+    conversation.active_leaf_id = parent;
+}
+
+/// Messages on the active branch, root first
+pub fn active_path(conversation: &Conversation) -> Vec<ConversationMessage> {
+    match &conversation.active_leaf_id {
+        Some(leaf) => path_to(conversation, leaf),
+        None => Vec::new(),
+    }
+}
+
+/// Messages from the root down to (and including) `message_id`
+fn path_to(conversation: &Conversation, message_id: &str) -> Vec<ConversationMessage> {
+    let by_id: HashMap<&str, &ConversationMessage> = conversation
+        .messages
+        .iter()
+        .map(|m| (m.id.as_str(), m))
+        .collect();
+
+    let mut path = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = by_id.get(message_id).copied();
+    while let Some(message) = current {
+        if !visited.insert(message.id.as_str()) {
+            break;
+        }
+        path.push(message.clone());
+        current = message
+            .parent_id
+            .as_deref()
+            .and_then(|parent| by_id.get(parent).copied());
+    }
+    path.reverse();
+    path
+}
+
+/// Replace the active branch with `path`, keeping every other branch intact.
+///
+/// The frontend sends the visible conversation as a flat list. Messages that are
+/// not on the new path (e.g. the previous continuation of an edited message or a
+/// regenerated answer) stay in the tree as sibling branches.
+/// A message whose id is already stored replaces it, so the frontend only
+/// reuses an id while the message is unchanged.
+pub fn apply_active_path(conversation: &mut Conversation, path: Vec<ConversationMessage>) {
+    let path_ids: HashSet<String> = path.iter().map(|m| m.id.clone()).collect();
+    let mut messages: Vec<ConversationMessage> = conversation
+        .messages
+        .drain(..)
+        .filter(|m| !path_ids.contains(&m.id))
+        .collect();
+
+    let mut parent: Option<String> = None;
+    for mut message in path {
+        message.parent_id = parent.take();
+        parent = Some(message.id.clone());
+        messages.push(message);
+    }
+
+    conversation.messages = messages;
+    conversation.active_leaf_id = parent;
+}
+
+/// Make the parent of `message_id` the active leaf, so the next message sent
+/// starts a new branch alongside `message_id` instead of replacing it.
+pub fn fork_at(conversation: &mut Conversation, message_id: &str) -> Result<(), String> {
+    let message = conversation
+        .messages
+        .iter()
+        .find(|m| m.id == message_id)
+        .ok_or("Message not found")?;
+    conversation.active_leaf_id = message.parent_id.clone();
+    Ok(())
+}
+
+/// Activate the branch containing `message_id`, continuing down to its most
+/// recently updated leaf.
+pub fn switch_to(conversation: &mut Conversation, message_id: &str) -> Result<(), String> {
+    if !conversation.messages.iter().any(|m| m.id == message_id) {
+        return Err("Message not found".to_string());
+    }
+
+    let children = children_map(conversation);
+    let mut current = message_id.to_string();
+    let mut visited = HashSet::new();
+    while visited.insert(current.clone()) {
+        let next = children.get(current.as_str()).and_then(|kids| {
+            kids.iter()
+                .max_by_key(|m| subtree_latest(&children, m))
+                .map(|m| m.id.clone())
+        });
+        match next {
+            Some(id) => current = id,
+            None => break,
+        }
+    }
+
+    conversation.active_leaf_id = Some(current);
+    Ok(())
+}
+
+/// Ids of the messages sharing a parent with `message_id` (including itself), oldest first
+pub fn siblings(conversation: &Conversation, message_id: &str) -> Result<Vec<String>, String> {
+    let message = conversation
+        .messages
+        .iter()
+        .find(|m| m.id == message_id)
+        .ok_or("Message not found")?;
+
+    let mut siblings: Vec<&ConversationMessage> = conversation
+        .messages
+        .iter()
+        .filter(|m| m.parent_id == message.parent_id)
+        .collect();
+    siblings.sort_by_key(|m| m.timestamp);
+    Ok(siblings.into_iter().map(|m| m.id.clone()).collect())
+}
+
+/// List every branch of the conversation, most recently updated first
+pub fn list_branches(conversation: &Conversation) -> Vec<BranchInfo> {
+    let children = children_map(conversation);
+    let active: Vec<String> = active_path(conversation).into_iter().map(|m| m.id).collect();
+    let active_ids: HashSet<&str> = active.iter().map(String::as_str).collect();
+
+    let mut branches: Vec<BranchInfo> = conversation
+        .messages
+        .iter()
+        .filter(|m| !children.contains_key(m.id.as_str()))
+        .map(|leaf| {
+            let path = path_to(conversation, &leaf.id);
+            let fork_message_id = path
+                .iter()
+                .find(|m| !active_ids.contains(m.id.as_str()))
+                .map(|m| m.id.clone());
+            let preview = path
+                .iter()
+                .rev()
+                .find(|m| m.role == "user")
+                .map(|m| message_preview(&m.content))
+                .unwrap_or_default();
+            BranchInfo {
+                leaf_id: leaf.id.clone(),
+                fork_message_id,
+                message_count: path.len(),
+                updated_at: path.iter().map(|m| m.timestamp).max().unwrap_or(0),
+                preview,
+                active: conversation.active_leaf_id.as_deref() == Some(leaf.id.as_str()),
+            }
+        })
+        .collect();
+
+    branches.sort_by_key(|b| std::cmp::Reverse(b.updated_at));
+    branches
+}
+
+fn children_map(conversation: &Conversation) -> HashMap<&str, Vec<&ConversationMessage>> {
+    let mut children: HashMap<&str, Vec<&ConversationMessage>> = HashMap::new();
+    for message in &conversation.messages {
+        if let Some(parent) = message.parent_id.as_deref() {
+            children.entry(parent).or_default().push(message);
+        }
+    }
+    children
+}
+
+/// Latest timestamp anywhere in the subtree rooted at `message`
+fn subtree_latest(children: &HashMap<&str, Vec<&ConversationMessage>>, message: &ConversationMessage) -> u64 {
+    let mut latest = message.timestamp;
+    let mut stack = vec![message];
+    let mut visited = HashSet::new();
+    while let Some(current) = stack.pop() {
+        if !visited.insert(current.id.as_str()) {
+            continue;
+        }
+        latest = latest.max(current.timestamp);
+        if let Some(kids) = children.get(current.id.as_str()) {
+            stack.extend(kids.iter().copied());
+        }
+    }
+    latest
+}
+
+fn message_preview(content: &serde_json::Value) -> String {
+    let text = match content {
+        serde_json::Value::String(s) => s.as_str(),
+        serde_json::Value::Array(parts) => parts
+            .iter()
+            .find(|p| p.get("type").and_then(|t| t.as_str()) == Some("text"))
+            .and_then(|p| p.get("text"))
+            .and_then(|t| t.as_str())
+            .unwrap_or(""),
+        _ => "",
+    };
+    text.chars().take(80).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, role: &str, timestamp: u64) -> ConversationMessage {
+        ConversationMessage {
+            id: id.to_string(),
+            role: role.to_string(),
+            content: serde_json::Value::String(format!("{} text", id)),
+            timestamp,
+            parent_id: None,
+            usage: None,
+        }
+    }
+
+    /// A conversation in the legacy flat layout: `m1` (user) .. `m4` (assistant)
+    fn flat_conversation() -> Conversation {
+        Conversation {
+            id: "c".to_string(),
+            title: "Test".to_string(),
+            created_at: 1,
+            updated_at: 4,
+            messages: vec![
+                message("m1", "user", 1),
+                message("m2", "assistant", 2),
+                message("m3", "user", 3),
+                message("m4", "assistant", 4),
+            ],
+            active_leaf_id: None,
+            model: None,
+            folder_id: None,
+            tags: Vec::new(),
+            pinned: false,
+            archived: false,
+            deleted_at: None,
+            source_id: None,
+            usage_totals: Default::default(),
+            title_source: Default::default(),
+            summary: None,
+            summarized_count: 0,
+        }
+    }
+
+    fn ids(messages: &[ConversationMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.id.as_str()).collect()
+    }
+
+    #[test]
+    fn flat_conversations_become_a_single_branch() {
+        let mut conversation = flat_conversation();
+        migrate_flat(&mut conversation);
+
+        assert_eq!(conversation.active_leaf_id.as_deref(), Some("m4"));
+        assert_eq!(conversation.messages[0].parent_id, None);
+        assert_eq!(conversation.messages[2].parent_id.as_deref(), Some("m2"));
+        assert_eq!(ids(&active_path(&conversation)), ["m1", "m2", "m3", "m4"]);
+
+        // Already migrated conversations are left alone
+        conversation.active_leaf_id = Some("m2".to_string());
+        migrate_flat(&mut conversation);
+        assert_eq!(conversation.active_leaf_id.as_deref(), Some("m2"));
+    }
+
+    #[test]
+    fn applying_a_path_keeps_the_previous_continuation_as_a_branch() {
+        let mut conversation = flat_conversation();
+        migrate_flat(&mut conversation);
+
+        // Message 2 of 4 was edited into a new message with a new id
+        apply_active_path(&mut conversation, vec![message("m1", "user", 1), message("m2b", "assistant", 5)]);
+
+        assert_eq!(ids(&active_path(&conversation)), ["m1", "m2b"]);
+        assert_eq!(conversation.messages.len(), 5);
+        assert_eq!(siblings(&conversation, "m2b").unwrap(), ["m2", "m2b"]);
+        assert_eq!(list_branches(&conversation).len(), 2);
+    }
+
+    #[test]
+    fn editing_a_message_and_switching_back_restores_the_original_branch() {
+        let mut conversation = flat_conversation();
+        migrate_flat(&mut conversation);
+
+        apply_active_path(&mut conversation, vec![message("m1", "user", 1), message("m2b", "assistant", 5)]);
+        switch_to(&mut conversation, "m2").unwrap();
+
+        let path = active_path(&conversation);
+        assert_eq!(ids(&path), ["m1", "m2", "m3", "m4"]);
+        assert_eq!(path[3].content, "m4 text");
+
+        switch_to(&mut conversation, "m2b").unwrap();
+        assert_eq!(ids(&active_path(&conversation)), ["m1", "m2b"]);
+    }
+
+    #[test]
+    fn forking_makes_the_next_message_a_sibling() {
+        let mut conversation = flat_conversation();
+        migrate_flat(&mut conversation);
+
+        fork_at(&mut conversation, "m3").unwrap();
+        assert_eq!(conversation.active_leaf_id.as_deref(), Some("m2"));
+
+        let mut path = active_path(&conversation);
+        path.push(message("m3b", "user", 6));
+        apply_active_path(&mut conversation, path);
+
+        assert_eq!(siblings(&conversation, "m3").unwrap(), ["m3", "m3b"]);
+        // The most recently updated branch below a message is the one switched to
+        switch_to(&mut conversation, "m1").unwrap();
+        assert_eq!(ids(&active_path(&conversation)), ["m1", "m2", "m3b"]);
+        assert!(fork_at(&mut conversation, "missing").is_err());
+    }
+}
@@ -0,0 +1,538 @@
+use crate::config::AppConfig;
+use crate::conversations::{get_conversations_path, write_synced};
+use crate::model_routes::glob_match;
+use crate::request_log::RequestRecord;
+use crate::titling::Gateway;
+use hyper::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::fs;
+
+/// Headers whose values never reach a capture file
+const SECRET_HEADERS: &[&str] = &["authorization", "proxy-authorization", "x-api-key", "cookie", "set-cookie"];
+/// Request body fields whose string values are redacted, compared without `-`/`_` and case
+const SECRET_FIELDS: &[&str] = &[
+    "apikey",
+    "accesstoken",
+    "refreshtoken",
+    "clientsecret",
+    "password",
+    "secret",
+    "authorization",
+];
+/// Headers not sent again when replaying a capture
+const NOT_REPLAYED: &[&str] = &["host", "content-length", "connection", "transfer-encoding", "x-forwarded-for"];
+const REDACTED: &str = "[REDACTED]";
+/// Response body kept per capture; the rest is dropped and the capture marked truncated
+const MAX_CAPTURED_BYTES: usize = 16 * 1024 * 1024;
+/// Header that marks a replayed request, so it is not captured again
+pub const REPLAY_HEADER: &str = "x-capture-replay";
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// A piece of a response body and when it arrived
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureChunk {
+    /// ms since the request arrived
+    pub at_ms: u64,
+    pub data: String,
+}
+
+/// A request through the proxy and the response it got, with secrets redacted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capture {
+    pub id: String,
+    /// Id of the request in the request log
+    pub request_id: u64,
+    /// ms since the Unix epoch
+    pub started_at: u64,
+    pub method: String,
+    /// Path and query string
+    pub path: String,
+    pub model: Option<String>,
+    /// Client key id, or `master` for the gateway's own key
+    pub client_key_id: String,
+    pub account: Option<String>,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: String,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+    pub streamed: bool,
+    /// Answered from the response cache
+    pub cached: bool,
+    pub chunks: Vec<CaptureChunk>,
+    pub response_bytes: u64,
+    /// The body was larger than a capture keeps
+    pub truncated: bool,
+    pub ttfb_ms: Option<u64>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// A capture without its headers and bodies, for listing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureSummary {
+    pub id: String,
+    pub started_at: u64,
+    pub method: String,
+    pub path: String,
+    pub model: Option<String>,
+    pub client_key_id: String,
+    pub account: Option<String>,
+    pub status: u16,
+    pub streamed: bool,
+    pub cached: bool,
+    pub response_bytes: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// What the gateway answered when a capture was sent again
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayResult {
+    pub capture_id: String,
+    pub status: u16,
+    pub response_headers: Vec<(String, String)>,
+    pub streamed: bool,
+    pub chunks: Vec<CaptureChunk>,
+    pub ttfb_ms: Option<u64>,
+    pub duration_ms: u64,
+    /// The body is the same as the captured one
+    pub identical: bool,
+}
+
+#[derive(Default)]
+struct CaptureSettings {
+    enabled: bool,
+    key_ids: Vec<String>,
+    models: Vec<String>,
+    dir: Option<PathBuf>,
+    max_count: u32,
+}
+
+/// Which proxied requests are captured, and the capture files on disk
+#[derive(Default)]
+pub struct Captures {
+    settings: Mutex<CaptureSettings>,
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+fn default_capture_dir() -> Result<PathBuf, String> {
+    let path = get_conversations_path()?;
+    let parent = path.parent().ok_or("Failed to get data directory")?;
+    Ok(parent.join("captures"))
+}
+
+/// Capture ids become file names; anything else could leave the capture directory
+fn check_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Invalid capture id: {}", id));
+    }
+    Ok(())
+}
+
+impl Captures {
+    fn lock(&self) -> std::sync::MutexGuard<'_, CaptureSettings> {
+        self.settings.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply the capture settings from `config`
+    pub fn configure(&self, config: &AppConfig) {
+        let mut settings = self.lock();
+        settings.enabled = config.capture_enabled;
+        settings.key_ids = config.capture_key_ids.clone();
+        settings.models = config.capture_models.clone();
+        settings.dir = config
+            .capture_dir
+            .as_deref()
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from);
+        settings.max_count = config.capture_max_count;
+    }
+
+    /// Whether a request with `key_id` (or `master`) for `model` is captured:
+    /// it matches a listed key id or model pattern, or nothing is listed
+    pub fn wants(&self, key_id: &str, model: Option<&str>) -> bool {
+        let settings = self.lock();
+        if !settings.enabled {
+            return false;
+        }
+        if settings.key_ids.is_empty() && settings.models.is_empty() {
+            return true;
+        }
+        settings.key_ids.iter().any(|id| id == key_id)
+            || model.is_some_and(|model| settings.models.iter().any(|pattern| glob_match(pattern, model)))
+    }
+
+    pub fn dir(&self) -> Result<PathBuf, String> {
+        match &self.lock().dir {
+            Some(dir) => Ok(dir.clone()),
+            None => default_capture_dir(),
+        }
+    }
+
+    /// Write a capture and drop the oldest ones beyond the configured count
+    pub async fn save(&self, capture: &Capture) -> Result<(), String> {
+        let _guard = self.save_lock.lock().await;
+        check_id(&capture.id)?;
+        let dir = self.dir()?;
+        fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| format!("Failed to create capture directory: {}", e))?;
+        let content = serde_json::to_vec_pretty(capture).map_err(|e| format!("Failed to serialize capture: {}", e))?;
+        let path = dir.join(format!("{}.json", capture.id));
+        let tmp_path = path.with_extension("json.tmp");
+        write_synced(&tmp_path, &content)
+            .await
+            .map_err(|e| format!("Failed to write capture: {}", e))?;
+        fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|e| format!("Failed to rename capture temp file: {}", e))?;
+
+        let max_count = self.lock().max_count as usize;
+        if max_count > 0 {
+            let mut ids = self.ids().await?;
+            if ids.len() > max_count {
+                // Ids start with the arrival time, so they sort oldest first
+                ids.sort();
+                for id in &ids[..ids.len() - max_count] {
+                    let _ = fs::remove_file(dir.join(format!("{}.json", id))).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Ids of the capture files in the capture directory
+    async fn ids(&self) -> Result<Vec<String>, String> {
+        let dir = self.dir()?;
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read capture directory: {}", e)),
+        };
+        let mut ids = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read capture directory: {}", e))?
+        {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(id) = name.strip_suffix(".json") {
+                if check_id(id).is_ok() {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Captures on disk, newest first; unreadable files are skipped
+    pub async fn list(&self) -> Result<Vec<CaptureSummary>, String> {
+        let dir = self.dir()?;
+        let mut summaries = Vec::new();
+        for id in self.ids().await? {
+            let content = match fs::read(dir.join(format!("{}.json", id))).await {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            if let Ok(summary) = serde_json::from_slice::<CaptureSummary>(&content) {
+                summaries.push(summary);
+            }
+        }
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.started_at));
+        Ok(summaries)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Capture, String> {
+        check_id(id)?;
+        let path = self.dir()?.join(format!("{}.json", id));
+        let content = fs::read(&path)
+            .await
+            .map_err(|e| format!("Failed to read capture {}: {}", id, e))?;
+        serde_json::from_slice(&content).map_err(|e| format!("Failed to parse capture {}: {}", id, e))
+    }
+
+    /// Delete one capture; returns whether it existed
+    pub async fn delete(&self, id: &str) -> Result<bool, String> {
+        check_id(id)?;
+        let path = self.dir()?.join(format!("{}.json", id));
+        match fs::remove_file(&path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("Failed to delete capture {}: {}", id, e)),
+        }
+    }
+
+    /// Delete all captures; returns how many were deleted
+    pub async fn clear(&self) -> Result<usize, String> {
+        let mut count = 0;
+        for id in self.ids().await? {
+            if self.delete(&id).await? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+fn is_secret_header(name: &str) -> bool {
+    SECRET_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h))
+}
+
+fn is_secret_field(name: &str) -> bool {
+    let name: String = name
+        .chars()
+        .filter(|c| *c != '-' && *c != '_')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    SECRET_FIELDS.contains(&name.as_str())
+}
+
+/// Replace every occurrence of a known key in `text`
+fn redact_text(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .filter(|s| !s.is_empty())
+        .fold(text.to_string(), |text, secret| text.replace(secret.as_str(), REDACTED))
+}
+
+/// Replace the string values of fields that look like credentials; other
+/// values (such as a tool schema describing a `password` property) are kept
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map.iter_mut() {
+                if value.is_string() && is_secret_field(name) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+fn redact_headers(headers: &HeaderMap, secrets: &[String]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_secret_header(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                redact_text(&String::from_utf8_lossy(value.as_bytes()), secrets)
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// The text of `pending` up to an incomplete UTF-8 character at its end,
+/// which is left for the next chunk
+fn take_text(pending: &mut Vec<u8>) -> Option<String> {
+    let complete = match std::str::from_utf8(pending) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => pending.len(),
+    };
+    let data: Vec<u8> = pending.drain(..complete).collect();
+    (!data.is_empty()).then(|| String::from_utf8_lossy(&data).to_string())
+}
+
+/// A capture being filled in while its response passes through the proxy
+pub struct Recording {
+    capture: Capture,
+    started: Instant,
+    /// Keys that are replaced wherever they appear
+    secrets: Vec<String>,
+    /// Start of a UTF-8 character split across chunks
+    pending: Vec<u8>,
+}
+
+impl Recording {
+    /// Start capturing the request described by `record`, with the headers
+    /// the client sent and its body
+    pub fn new(
+        record: &RequestRecord,
+        key_id: &str,
+        request: &hyper::http::request::Parts,
+        body: &[u8],
+        secrets: Vec<String>,
+        started: Instant,
+    ) -> Self {
+        let request_body = match serde_json::from_slice::<Value>(body) {
+            Ok(mut json) => {
+                redact_json(&mut json);
+                redact_text(&json.to_string(), &secrets)
+            }
+            Err(_) => redact_text(&String::from_utf8_lossy(body), &secrets),
+        };
+        let capture = Capture {
+            id: format!("{}-{}", record.started_at, record.id),
+            request_id: record.id,
+            started_at: record.started_at,
+            method: record.method.clone(),
+            path: request
+                .uri
+                .path_and_query()
+                .map(|p| p.as_str().to_string())
+                .unwrap_or_else(|| record.endpoint.clone()),
+            model: record.model.clone(),
+            client_key_id: key_id.to_string(),
+            account: None,
+            request_headers: redact_headers(&request.headers, &secrets),
+            request_body,
+            status: 0,
+            response_headers: Vec::new(),
+            streamed: false,
+            cached: false,
+            chunks: Vec::new(),
+            response_bytes: 0,
+            truncated: false,
+            ttfb_ms: None,
+            duration_ms: 0,
+            error: None,
+        };
+        Self {
+            capture,
+            started,
+            secrets,
+            pending: Vec::new(),
+        }
+    }
+
+    /// The response headers sent to the client
+    pub fn respond(&mut self, headers: &HeaderMap) {
+        self.capture.response_headers = redact_headers(headers, &self.secrets);
+    }
+
+    /// A piece of the response body as it reaches the client
+    pub fn push(&mut self, chunk: &[u8]) {
+        let at_ms = self.started.elapsed().as_millis() as u64;
+        self.capture.response_bytes += chunk.len() as u64;
+        if self.capture.truncated {
+            return;
+        }
+        if self.capture.response_bytes as usize > MAX_CAPTURED_BYTES {
+            self.capture.truncated = true;
+            return;
+        }
+        self.pending.extend_from_slice(chunk);
+        if let Some(data) = take_text(&mut self.pending) {
+            self.capture.chunks.push(CaptureChunk {
+                at_ms,
+                data: redact_text(&data, &self.secrets),
+            });
+        }
+    }
+
+    /// The capture of the finished request
+    pub fn finish(mut self, record: &RequestRecord) -> Capture {
+        if !self.pending.is_empty() {
+            let data = String::from_utf8_lossy(&self.pending).to_string();
+            self.capture.chunks.push(CaptureChunk {
+                at_ms: self.started.elapsed().as_millis() as u64,
+                data: redact_text(&data, &self.secrets),
+            });
+        }
+        self.capture.account = record.account.clone();
+        self.capture.status = record.status;
+        self.capture.streamed = record.streamed;
+        self.capture.cached = record.cached;
+        self.capture.ttfb_ms = record.ttfb_ms;
+        self.capture.duration_ms = record.latency_ms;
+        self.capture.error = record.error.clone();
+        self.capture
+    }
+}
+
+/// Send a captured request to `gateway` again and record the response the
+/// same way. Redacted headers are not sent; the gateway's key authenticates.
+pub async fn replay(client: &reqwest::Client, gateway: &Gateway, capture: &Capture) -> Result<ReplayResult, String> {
+    let method = reqwest::Method::from_bytes(capture.method.as_bytes())
+        .map_err(|e| format!("Invalid method in capture: {}", e))?;
+    let mut request = client
+        .request(method, format!("{}{}", gateway.base_url, capture.path))
+        .bearer_auth(&gateway.api_key)
+        .header(REPLAY_HEADER, capture.id.as_str())
+        // The point is to ask the gateway again, not the response cache
+        .header("cache-control", "no-cache, no-store")
+        .timeout(REPLAY_TIMEOUT);
+    for (name, value) in &capture.request_headers {
+        let skipped = value == REDACTED
+            || NOT_REPLAYED.iter().any(|h| name.eq_ignore_ascii_case(h))
+            || name.eq_ignore_ascii_case("cache-control");
+        if !skipped {
+            request = request.header(name.as_str(), value.as_str());
+        }
+    }
+    if capture.request_headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("x-api-key")) {
+        request = request.header("x-api-key", gateway.api_key.as_str());
+    }
+    if !capture.request_body.is_empty() {
+        request = request.body(capture.request_body.clone());
+    }
+
+    let started = Instant::now();
+    let mut response = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach gateway: {}", e))?;
+    let status = response.status().as_u16();
+    let response_headers = redact_headers(response.headers(), std::slice::from_ref(&gateway.api_key));
+    let streamed = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+
+    let mut chunks = Vec::new();
+    let mut ttfb_ms = None;
+    let mut pending: Vec<u8> = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Failed to read gateway response: {}", e))?
+    {
+        let at_ms = started.elapsed().as_millis() as u64;
+        ttfb_ms.get_or_insert(at_ms);
+        pending.extend_from_slice(&chunk);
+        if let Some(data) = take_text(&mut pending) {
+            chunks.push(CaptureChunk { at_ms, data });
+        }
+    }
+    if !pending.is_empty() {
+        chunks.push(CaptureChunk {
+            at_ms: started.elapsed().as_millis() as u64,
+            data: String::from_utf8_lossy(&pending).to_string(),
+        });
+    }
+
+    let body: String = chunks.iter().map(|c| c.data.as_str()).collect();
+    let captured: String = capture.chunks.iter().map(|c| c.data.as_str()).collect();
+    Ok(ReplayResult {
+        capture_id: capture.id.clone(),
+        status,
+        response_headers,
+        streamed,
+        identical: !capture.truncated && status == capture.status && body == captured,
+        chunks,
+        ttfb_ms,
+        duration_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// Write a finished capture in the background
+pub fn save_in_background(captures: std::sync::Arc<Captures>, capture: Capture) {
+    if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+        runtime.spawn(async move {
+            if let Err(e) = captures.save(&capture).await {
+                eprintln!("[Captures] {}", e);
+            }
+        });
+    }
+}
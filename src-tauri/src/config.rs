@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use tokio::fs;
 
@@ -11,6 +12,94 @@ pub enum AuthMethod {
     CliDb,
 }
 
+/// What serves the gateway's API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendMode {
+    /// The Python gateway, talking to Kiro
+    #[default]
+    Kiro,
+    /// A built-in stand-in with scripted or echoed replies; needs no credentials or network
+    Mock,
+}
+
+/// How starting the server decides a gateway is ready
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessMode {
+    /// `health_path` answers with a success status
+    #[default]
+    Health,
+    /// `/v1/models` answers with a success status to the proxy API key
+    Models,
+    /// The gateway prints a line containing `readiness_log_line` (the mock
+    /// backend prints nothing and is checked on `health_path` instead)
+    LogLine,
+}
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+/// Routes client model names matching `pattern` to a Kiro model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelRoute {
+    /// Requested model name; `*` and `?` are wildcards, case is ignored
+    pub pattern: String,
+    /// Kiro model the request is sent to
+    pub target: String,
+    /// Used when the request does not set a maximum itself
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// Overrides `fake_reasoning` for matching requests
+    #[serde(default)]
+    pub fake_reasoning: Option<bool>,
+}
+
+/// Request and token limits for one client key, or for all proxied traffic combined
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientLimits {
+    pub requests_per_minute: Option<u32>,
+    pub max_concurrent_streams: Option<u32>,
+    /// Tokens (prompt + completion) per local calendar day
+    pub daily_tokens: Option<u64>,
+    /// Tokens (prompt + completion) per local calendar month
+    pub monthly_tokens: Option<u64>,
+}
+
+/// One Kiro account, served by its own gateway process in the account pool
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub id: String,
+    pub label: String,
+    pub auth_method: AuthMethod,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub kiro_creds_file: Option<String>,
+    #[serde(default)]
+    pub kiro_cli_db_file: Option<String>,
+    /// Region of the account (unset = `kiro_region`)
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+/// How the account pool spreads requests over its accounts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolStrategy {
+    #[default]
+    RoundRobin,
+    /// The account with the fewest requests in flight
+    LeastLoaded,
+}
+
 /// Application configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
@@ -20,13 +109,120 @@ pub struct AppConfig {
     pub kiro_creds_file: Option<String>,
     pub kiro_cli_db_file: Option<String>,
 
+    /// Accounts to spread requests over; when any is enabled they replace the
+    /// credential above and each runs its own gateway behind the proxy
+    #[serde(default)]
+    pub accounts: Vec<Account>,
+    #[serde(default)]
+    pub pool_strategy: PoolStrategy,
+    /// Keep sending a client key's requests to the same account while it is healthy
+    #[serde(default = "default_true")]
+    pub sticky_sessions: bool,
+
+    /// `mock` serves canned replies instead of starting the gateway, for offline development and tests
+    #[serde(default)]
+    pub backend_mode: BackendMode,
+    /// JSON file with scripted replies for the mock backend (unset = echo every request)
+    #[serde(default)]
+    pub mock_script: Option<String>,
+
     // Required
     pub proxy_api_key: String,
 
     // Server
     pub server_host: String,
     pub server_port: u16,
+    /// When another process holds `server_port`, serve on the first free port
+    /// after it up to this one (unset = fail to start)
+    #[serde(default)]
+    pub server_port_fallback_end: Option<u16>,
+    /// Seconds to wait for the gateways to become ready
+    #[serde(default = "default_startup_timeout_secs")]
+    pub startup_timeout_secs: u64,
+    /// Milliseconds between readiness checks
+    #[serde(default = "default_startup_poll_interval_ms")]
+    pub startup_poll_interval_ms: u64,
+    #[serde(default)]
+    pub readiness_mode: ReadinessMode,
+    /// Path of the gateway's health endpoint
+    #[serde(default = "default_health_path")]
+    pub health_path: String,
+    /// Text in the gateway's output that means it is ready, for `ReadinessMode::LogLine`
+    #[serde(default)]
+    pub readiness_log_line: Option<String>,
+    /// Seconds a stop or restart waits for requests in flight before signalling the gateways
+    #[serde(default = "default_drain_timeout_secs")]
+    pub drain_timeout_secs: u64,
     pub kiro_region: String,
+    /// Serve `server_port` through the built-in request-logging proxy
+    #[serde(default)]
+    pub proxy_enabled: bool,
+    /// Internal port of the gateway behind the proxy (unset = any free port);
+    /// pooled accounts use consecutive ports from here
+    #[serde(default)]
+    pub proxy_upstream_port: Option<u16>,
+    /// Limits on all requests through the proxy combined
+    #[serde(default)]
+    pub global_limits: ClientLimits,
+    /// Limits per client API key, keyed by key id
+    #[serde(default)]
+    pub key_limits: HashMap<String, ClientLimits>,
+    /// Serve the API over HTTPS (through the proxy)
+    #[serde(default)]
+    pub tls_enabled: bool,
+    /// PEM certificate chain to serve (unset = issued by the app's local CA)
+    #[serde(default)]
+    pub tls_cert_file: Option<String>,
+    /// PEM private key of `tls_cert_file`
+    #[serde(default)]
+    pub tls_key_file: Option<String>,
+    /// Host names and IP addresses to add to the certificate issued by the local CA
+    #[serde(default)]
+    pub tls_extra_names: Vec<String>,
+    /// Client networks allowed to connect, in CIDR notation, e.g. `192.168.1.0/24`
+    /// (empty = any; loopback is always allowed)
+    #[serde(default)]
+    pub allowed_networks: Vec<String>,
+    /// Client networks refused even when in `allowed_networks`
+    #[serde(default)]
+    pub denied_networks: Vec<String>,
+    /// Non-loopback `server_host` the user confirmed exposing the gateway on
+    #[serde(default)]
+    pub lan_exposure_host: Option<String>,
+    /// Address to serve Prometheus metrics on, e.g. `127.0.0.1:9464` (unset = disabled)
+    #[serde(default)]
+    pub metrics_listen: Option<String>,
+    /// Days of credit and traffic history to keep for the usage dashboard (0 = forever)
+    #[serde(default = "default_usage_history_days")]
+    pub usage_history_days: u32,
+
+    /// Replay responses to repeated temperature-0 requests from a cache in the proxy
+    #[serde(default)]
+    pub response_cache_enabled: bool,
+    #[serde(default = "default_response_cache_ttl_secs")]
+    pub response_cache_ttl_secs: u64,
+    #[serde(default = "default_response_cache_max_mb")]
+    pub response_cache_max_mb: u32,
+    /// Model name rules applied by the gateway, first match wins
+    #[serde(default)]
+    pub model_routes: Vec<ModelRoute>,
+
+    /// Record full request/response pairs through the proxy for debugging
+    #[serde(default)]
+    pub capture_enabled: bool,
+    /// Client key ids to capture (`master` for the gateway's own key); with no
+    /// key ids and no models every request is captured
+    #[serde(default)]
+    pub capture_key_ids: Vec<String>,
+    /// Model name patterns (`*` and `?`) to capture
+    #[serde(default)]
+    pub capture_models: Vec<String>,
+    /// Directory for capture files (unset = `captures` in the data directory)
+    #[serde(default)]
+    pub capture_dir: Option<String>,
+    /// Captures to keep, oldest deleted first (0 = keep all)
+    #[serde(default = "default_capture_max_count")]
+    pub capture_max_count: u32,
 
     // Advanced
     pub vpn_proxy_url: Option<String>,
@@ -44,11 +240,92 @@ pub struct AppConfig {
     #[serde(default)]
     pub auto_start_server: bool,
 
+    // Conversations
+    /// Days to keep trashed conversations before purging them (0 = keep until emptied)
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u32,
+    /// Number of daily conversation backups to keep (0 = no automatic backups)
+    #[serde(default = "default_conversation_backup_count")]
+    pub conversation_backup_count: u32,
+    /// Prices used to estimate the cost of responses, keyed by model name
+    #[serde(default)]
+    pub model_prices: HashMap<String, ModelPrice>,
+    /// Generate titles for new conversations through the local gateway
+    #[serde(default = "default_true")]
+    pub auto_title: bool,
+    /// Keep a running summary of long conversations through the local gateway
+    #[serde(default)]
+    pub auto_summary: bool,
+    /// Model used for titles and summaries (defaults to the conversation's model)
+    #[serde(default)]
+    pub titling_model: Option<String>,
+    /// Minimum seconds between titling requests to the gateway
+    #[serde(default = "default_titling_interval_secs")]
+    pub titling_interval_secs: u32,
+    /// Shared folder (Syncthing, NFS, Dropbox, ...) to sync conversations through; unset = no sync
+    #[serde(default)]
+    pub sync_folder: Option<String>,
+    /// Seconds between automatic syncs
+    #[serde(default = "default_sync_interval_secs")]
+    pub sync_interval_secs: u32,
+
     // Client identity
     #[serde(default)]
     pub client_id: Option<String>,
 }
 
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+fn default_conversation_backup_count() -> u32 {
+    7
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_titling_interval_secs() -> u32 {
+    10
+}
+
+fn default_sync_interval_secs() -> u32 {
+    300
+}
+
+fn default_usage_history_days() -> u32 {
+    90
+}
+
+fn default_response_cache_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_response_cache_max_mb() -> u32 {
+    64
+}
+
+fn default_capture_max_count() -> u32 {
+    500
+}
+
+fn default_startup_timeout_secs() -> u64 {
+    30
+}
+
+fn default_startup_poll_interval_ms() -> u64 {
+    500
+}
+
+fn default_health_path() -> String {
+    "/health".to_string()
+}
+
+fn default_drain_timeout_secs() -> u64 {
+    60
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
@@ -56,10 +333,44 @@ impl Default for AppConfig {
             refresh_token: None,
             kiro_creds_file: None,
             kiro_cli_db_file: None,
+            accounts: Vec::new(),
+            pool_strategy: PoolStrategy::default(),
+            sticky_sessions: true,
+            backend_mode: BackendMode::default(),
+            mock_script: None,
             proxy_api_key: String::new(),
             server_host: "127.0.0.1".to_string(),
             server_port: 8000,
+            server_port_fallback_end: None,
+            startup_timeout_secs: default_startup_timeout_secs(),
+            startup_poll_interval_ms: default_startup_poll_interval_ms(),
+            readiness_mode: ReadinessMode::default(),
+            health_path: default_health_path(),
+            readiness_log_line: None,
+            drain_timeout_secs: default_drain_timeout_secs(),
             kiro_region: "us-east-1".to_string(),
+            proxy_enabled: false,
+            proxy_upstream_port: None,
+            global_limits: ClientLimits::default(),
+            key_limits: HashMap::new(),
+            tls_enabled: false,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_extra_names: Vec::new(),
+            allowed_networks: Vec::new(),
+            denied_networks: Vec::new(),
+            lan_exposure_host: None,
+            metrics_listen: None,
+            usage_history_days: default_usage_history_days(),
+            response_cache_enabled: false,
+            response_cache_ttl_secs: default_response_cache_ttl_secs(),
+            response_cache_max_mb: default_response_cache_max_mb(),
+            model_routes: Vec::new(),
+            capture_enabled: false,
+            capture_key_ids: Vec::new(),
+            capture_models: Vec::new(),
+            capture_dir: None,
+            capture_max_count: default_capture_max_count(),
             vpn_proxy_url: None,
             first_token_timeout: 15.0,
             streaming_read_timeout: 300.0,
@@ -70,6 +381,15 @@ impl Default for AppConfig {
             debug_mode: "off".to_string(),
             auto_launch: false,
             auto_start_server: false,
+            trash_retention_days: default_trash_retention_days(),
+            conversation_backup_count: default_conversation_backup_count(),
+            model_prices: HashMap::new(),
+            auto_title: true,
+            auto_summary: false,
+            titling_model: None,
+            titling_interval_secs: default_titling_interval_secs(),
+            sync_folder: None,
+            sync_interval_secs: default_sync_interval_secs(),
             client_id: None,
         }
     }
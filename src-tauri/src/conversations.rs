@@ -1,6 +1,13 @@
+use crate::encryption::{self, DecryptError, StoreKey};
+use crate::titling::TitleSource;
+use crate::usage::{MessageUsage, UsageLedger, UsageTotals};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 
 /// A single message in a conversation
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,6 +16,12 @@ pub struct ConversationMessage {
     pub role: String,
     pub content: serde_json::Value,
     pub timestamp: u64,
+    /// Previous message on the same branch (`None` for the first message)
+    #[serde(rename = "parentId", default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    /// Token usage of the response, for assistant messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<MessageUsage>,
 }
 
 /// A conversation with its messages
@@ -20,18 +33,118 @@ pub struct Conversation {
     pub created_at: u64,
     #[serde(rename = "updatedAt")]
     pub updated_at: u64,
+    /// Every message of every branch; the active branch is reached from `active_leaf_id`
     pub messages: Vec<ConversationMessage>,
+    #[serde(rename = "activeLeafId", default, skip_serializing_if = "Option::is_none")]
+    pub active_leaf_id: Option<String>,
     pub model: Option<String>,
+    #[serde(rename = "folderId", default, skip_serializing_if = "Option::is_none")]
+    pub folder_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub archived: bool,
+    /// When the conversation was moved to the trash
+    #[serde(rename = "deletedAt", default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<u64>,
+    /// Identifier of the conversation in the system it was imported from
+    #[serde(rename = "sourceId", default, skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<String>,
+    /// Usage summed over all messages, kept up to date as usage is recorded
+    #[serde(rename = "usageTotals", default)]
+    pub usage_totals: UsageTotals,
+    #[serde(rename = "titleSource", default)]
+    pub title_source: TitleSource,
+    /// Running summary generated through the gateway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Active-branch message count covered by `summary`
+    #[serde(rename = "summarizedCount", default)]
+    pub summarized_count: usize,
 }
 
+/// A folder for organizing conversations; folders can be nested via `parent_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Folder {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "parentId", default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+}
+
+/// Current on-disk layout version of `ConversationsData`
+///
+/// - 0: flat message lists
+/// - 1: message trees with parent links and an active leaf
+pub const CONVERSATIONS_VERSION: u32 = 1;
+
 /// Container for all conversations
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ConversationsData {
+    #[serde(default)]
+    pub version: u32,
     pub conversations: Vec<Conversation>,
+    #[serde(default)]
+    pub folders: Vec<Folder>,
+    #[serde(default)]
+    pub usage: UsageLedger,
+}
+
+impl ConversationsData {
+    /// Bring data written by older versions up to the current layout
+    pub fn migrate(&mut self) {
+        if self.version < 1 {
+            for conversation in self.conversations.iter_mut() {
+                crate::branches::migrate_flat(conversation);
+            }
+        }
+        self.version = CONVERSATIONS_VERSION;
+    }
+}
+
+/// Current time in milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Generate a unique ID in the same `<millis>-<suffix>` shape the frontend uses
+pub fn generate_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    format!("{}-{}", now_millis(), to_base36(nanos ^ (seq << 32)))
+}
+
+fn to_base36(mut n: u64) -> String {
+    const DIGITS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    let mut out = Vec::new();
+    loop {
+        out.push(DIGITS[(n % 36) as usize]);
+        n /= 36;
+        if n == 0 {
+            break;
+        }
+    }
+    out.reverse();
+    String::from_utf8(out).unwrap_or_default()
 }
 
 /// Get the conversations file path
-fn get_conversations_path() -> Result<PathBuf, String> {
+pub(crate) fn get_conversations_path() -> Result<PathBuf, String> {
+    // Tests keep their files out of the user's data directory
+    #[cfg(test)]
+    let data_dir = std::env::temp_dir().join(format!("kiroaas-test-{}", std::process::id()));
+    #[cfg(not(test))]
     let data_dir = dirs::data_local_dir()
         .ok_or("Failed to get local data directory")?
         .join("kiroaas");
@@ -39,26 +152,104 @@ fn get_conversations_path() -> Result<PathBuf, String> {
     Ok(data_dir.join("conversations.json"))
 }
 
+/// Why the stored conversations could not be read
+pub(crate) enum StoreError {
+    /// The store is encrypted and no key is available
+    Locked,
+    /// The file is damaged or cannot be decrypted
+    Corrupt(String),
+}
+
+pub(crate) const LOCKED_MESSAGE: &str = "Conversations are encrypted and locked. Unlock them to continue.";
+
+/// Decrypt raw store bytes if they are encrypted
+pub(crate) fn read_plaintext(bytes: &[u8]) -> Result<Cow<'_, [u8]>, StoreError> {
+    if !encryption::is_encrypted(bytes) {
+        return Ok(Cow::Borrowed(bytes));
+    }
+    encryption::decrypt(bytes)
+        .map(Cow::Owned)
+        .map_err(|e| match e {
+            DecryptError::Locked => StoreError::Locked,
+            DecryptError::Invalid(msg) => StoreError::Corrupt(msg),
+        })
+}
+
+/// Decode raw store bytes (plaintext or encrypted) into conversations
+pub(crate) fn decode_store(bytes: &[u8]) -> Result<ConversationsData, StoreError> {
+    let plaintext = read_plaintext(bytes)?;
+    serde_json::from_slice(&plaintext).map_err(|e| StoreError::Corrupt(e.to_string()))
+}
+
+/// Whether raw store bytes are usable; locked encrypted stores count as usable
+pub(crate) fn is_readable_store(bytes: &[u8]) -> bool {
+    !matches!(decode_store(bytes), Err(StoreError::Corrupt(_)))
+}
+
+/// Raw bytes of the conversations file, if it exists
+pub(crate) async fn read_store_bytes() -> Result<Option<Vec<u8>>, String> {
+    let path = get_conversations_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read(&path)
+        .await
+        .map(Some)
+        .map_err(|e| format!("Failed to read conversations file: {}", e))
+}
+
 /// Load conversations from disk
+///
+/// If the file cannot be parsed it is quarantined and as much as possible is
+/// recovered from it (or from the newest backup) instead of failing outright.
 pub async fn load_conversations() -> Result<ConversationsData, String> {
     let path = get_conversations_path()?;
 
-    if !path.exists() {
-        return Ok(ConversationsData::default());
-    }
+    let bytes = match read_store_bytes().await? {
+        Some(bytes) => bytes,
+        None => return Ok(ConversationsData::default()),
+    };
 
-    let content = fs::read_to_string(&path)
-        .await
-        .map_err(|e| format!("Failed to read conversations file: {}", e))?;
+    let parsed = match read_plaintext(&bytes) {
+        Ok(plaintext) => serde_json::from_slice::<ConversationsData>(&plaintext)
+            .map_err(|e| (e.to_string(), plaintext.into_owned())),
+        Err(StoreError::Locked) => return Err(LOCKED_MESSAGE.to_string()),
+        Err(StoreError::Corrupt(e)) => Err((e, Vec::new())),
+    };
 
-    let data: ConversationsData = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse conversations file: {}", e))?;
+    let mut data = match parsed {
+        Ok(data) => data,
+        Err((e, plaintext)) => {
+            eprintln!("[Conversations] Failed to parse conversations file: {}", e);
+            let data = crate::backups::recover(&path, &plaintext, &e).await?;
+            save_conversations(&data).await?;
+            data
+        }
+    };
+    data.migrate();
 
     Ok(data)
 }
 
 /// Save conversations to disk (atomic write)
+///
+/// Refuses to write while an encrypted store is locked, so plaintext never
+/// replaces encrypted data by accident.
 pub async fn save_conversations(data: &ConversationsData) -> Result<(), String> {
+    if encryption::active_key().is_none() {
+        if let Some(existing) = read_store_bytes().await? {
+            if encryption::is_encrypted(&existing) {
+                return Err(LOCKED_MESSAGE.to_string());
+            }
+        }
+    }
+    write_conversations(data).await
+}
+
+/// Write conversations, encrypted with the active key if there is one
+///
+/// Inline attachments are moved to the attachment store first.
+pub(crate) async fn write_conversations(data: &ConversationsData) -> Result<(), String> {
     let path = get_conversations_path()?;
 
     // Create parent directory if it doesn't exist
@@ -68,12 +259,14 @@ pub async fn save_conversations(data: &ConversationsData) -> Result<(), String>
             .map_err(|e| format!("Failed to create conversations directory: {}", e))?;
     }
 
-    let content = serde_json::to_string_pretty(data)
+    let data = crate::attachments::externalize(data).await?;
+    let content = serde_json::to_string_pretty(data.as_ref())
         .map_err(|e| format!("Failed to serialize conversations: {}", e))?;
+    let content = encryption::encrypt_if_enabled(content.into_bytes())?;
 
-    // Atomic write: write to temp file first, then rename
+    // Atomic write: write to temp file first, flush it to disk, then rename
     let tmp_path = path.with_extension("json.tmp");
-    fs::write(&tmp_path, &content)
+    write_synced(&tmp_path, &content)
         .await
         .map_err(|e| format!("Failed to write conversations temp file: {}", e))?;
 
@@ -81,5 +274,83 @@ pub async fn save_conversations(data: &ConversationsData) -> Result<(), String>
         .await
         .map_err(|e| format!("Failed to rename conversations temp file: {}", e))?;
 
+    // Persist the rename itself
+    if let Some(parent) = path.parent() {
+        sync_dir(parent)
+            .await
+            .map_err(|e| format!("Failed to sync conversations directory: {}", e))?;
+    }
+
     Ok(())
 }
+
+/// Write a file and fsync it before returning
+pub(crate) async fn write_synced(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut file = fs::File::create(path).await?;
+    file.write_all(content).await?;
+    file.sync_all().await
+}
+
+/// Fsync a directory so renames inside it survive a crash
+#[cfg(unix)]
+pub(crate) async fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir).await?.sync_all().await
+}
+
+/// Directories cannot be opened for syncing on Windows; NTFS journals renames
+#[cfg(not(unix))]
+pub(crate) async fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Re-encode `files` (name, path) from the `old` key to the `new` key
+/// (`None` = plaintext), then sync `dir`.
+///
+/// Files that cannot be read with `old` are left untouched; their names are returned.
+pub(crate) async fn rewrite_files(
+    dir: &Path,
+    files: Vec<(String, PathBuf)>,
+    old: Option<&StoreKey>,
+    new: Option<&StoreKey>,
+) -> Result<Vec<String>, String> {
+    let mut failed = Vec::new();
+
+    for (name, path) in files {
+        let content = match fs::read(&path).await {
+            Ok(content) => content,
+            Err(_) => {
+                failed.push(name);
+                continue;
+            }
+        };
+
+        let plaintext = if encryption::is_encrypted(&content) {
+            match old.map(|key| key.open(&content)) {
+                Some(Ok(plaintext)) => plaintext,
+                _ => {
+                    failed.push(name);
+                    continue;
+                }
+            }
+        } else {
+            content
+        };
+
+        let output = match new {
+            Some(key) => key.seal(&plaintext)?,
+            None => plaintext,
+        };
+        let mut tmp_path = OsString::from(&path);
+        tmp_path.push(".tmp");
+        if write_synced(tmp_path.as_ref(), &output).await.is_err() || fs::rename(&tmp_path, &path).await.is_err() {
+            failed.push(name);
+        }
+    }
+    if dir.exists() {
+        sync_dir(dir)
+            .await
+            .map_err(|e| format!("Failed to sync {}: {}", dir.display(), e))?;
+    }
+
+    Ok(failed)
+}
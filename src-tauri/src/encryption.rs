@@ -0,0 +1,454 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+const ENVELOPE_FORMAT: &str = "kiroaas-encrypted";
+const ENVELOPE_VERSION: u32 = 1;
+const KEYRING_SERVICE: &str = "kiroaas";
+const KEYRING_USER: &str = "conversations-key";
+/// Holds the previous key while a rotation is in progress, so a crash mid-rotation
+/// cannot leave the store encrypted with a key that is no longer available
+const KEYRING_PREVIOUS_USER: &str = "conversations-key-previous";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+
+/// Where the conversation encryption key comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeySource {
+    /// Random key stored in the OS keyring (Keychain, Credential Manager, Secret Service)
+    Keyring,
+    /// Key derived from a user passphrase with Argon2id
+    Passphrase,
+}
+
+/// Encryption state of the conversation store
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    pub key_source: Option<KeySource>,
+    pub locked: bool,
+    /// Backups and attachments the last key change could not convert; the old
+    /// key is kept so they stay readable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_files: Vec<String>,
+}
+
+/// Why encrypted data could not be read
+#[derive(Debug, Clone)]
+pub enum DecryptError {
+    /// No key is available; the user has to unlock first
+    Locked,
+    /// The data is not a valid envelope or fails authentication
+    Invalid(String),
+}
+
+/// On-disk format of an encrypted store; only the ciphertext carries user data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Envelope {
+    format: String,
+    version: u32,
+    key_source: KeySource,
+    /// Argon2id salt for passphrase-derived keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// An encryption key together with how it was obtained
+#[derive(Clone)]
+pub struct StoreKey {
+    key: [u8; KEY_LEN],
+    source: KeySource,
+    salt: Option<Vec<u8>>,
+}
+
+impl StoreKey {
+    /// Generate a new random key to be kept in the OS keyring
+    pub fn new_keyring() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        Self {
+            key,
+            source: KeySource::Keyring,
+            salt: None,
+        }
+    }
+
+    /// Store this key in the OS keyring, keeping any existing key as the previous one
+    pub fn save_to_keyring(&self) -> Result<(), String> {
+        if let Ok(current) = keyring_entry(KEYRING_USER)?.get_password() {
+            keyring_entry(KEYRING_PREVIOUS_USER)?
+                .set_password(&current)
+                .map_err(|e| format!("Failed to store previous key in OS keyring: {}", e))?;
+        }
+        keyring_entry(KEYRING_USER)?
+            .set_password(&BASE64.encode(self.key))
+            .map_err(|e| format!("Failed to store key in OS keyring: {}", e))
+    }
+
+    /// Derive a key from a passphrase with a fresh salt
+    pub fn new_passphrase(passphrase: &str) -> Result<Self, String> {
+        let mut salt = vec![0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self::from_passphrase(passphrase, salt)
+    }
+
+    fn from_passphrase(passphrase: &str, salt: Vec<u8>) -> Result<Self, String> {
+        if passphrase.is_empty() {
+            return Err("Passphrase must not be empty".to_string());
+        }
+        let mut key = [0u8; KEY_LEN];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| format!("Failed to derive key from passphrase: {}", e))?;
+        Ok(Self {
+            key,
+            source: KeySource::Passphrase,
+            salt: Some(salt),
+        })
+    }
+
+    fn from_keyring(user: &str) -> Result<Self, String> {
+        let encoded = keyring_entry(user)?
+            .get_password()
+            .map_err(|e| format!("Failed to read key from OS keyring: {}", e))?;
+        let bytes = BASE64
+            .decode(encoded)
+            .map_err(|e| format!("Invalid key in OS keyring: {}", e))?;
+        let key: [u8; KEY_LEN] = bytes
+            .try_into()
+            .map_err(|_| "Invalid key length in OS keyring".to_string())?;
+        Ok(Self {
+            key,
+            source: KeySource::Keyring,
+            salt: None,
+        })
+    }
+
+    /// Find the keyring key (current or previous) that opens `envelope`
+    fn from_keyring_for(envelope: &Envelope) -> Result<Self, String> {
+        let mut last_error = "No key found in OS keyring".to_string();
+        for user in [KEYRING_USER, KEYRING_PREVIOUS_USER] {
+            match Self::from_keyring(user) {
+                Ok(key) if key.open_envelope(envelope).is_ok() => return Ok(key),
+                Ok(_) => last_error = "Key in OS keyring does not match the encrypted store".to_string(),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn source(&self) -> KeySource {
+        self.source
+    }
+
+    /// Encrypt `plaintext` into an envelope
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let cipher = Aes256Gcm::new_from_slice(&self.key).map_err(|e| format!("Invalid key: {}", e))?;
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| "Failed to encrypt conversations".to_string())?;
+
+        let envelope = Envelope {
+            format: ENVELOPE_FORMAT.to_string(),
+            version: ENVELOPE_VERSION,
+            key_source: self.source,
+            salt: self.salt.as_ref().map(|s| BASE64.encode(s)),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+        serde_json::to_vec(&envelope).map_err(|e| format!("Failed to serialize envelope: {}", e))
+    }
+
+    /// Decrypt an envelope produced by `seal`
+    pub fn open(&self, bytes: &[u8]) -> Result<Vec<u8>, DecryptError> {
+        let envelope = parse_envelope(bytes).ok_or_else(|| DecryptError::Invalid("Not an encrypted store".to_string()))?;
+        self.open_envelope(&envelope)
+    }
+
+    fn open_envelope(&self, envelope: &Envelope) -> Result<Vec<u8>, DecryptError> {
+        let nonce = BASE64
+            .decode(&envelope.nonce)
+            .map_err(|e| DecryptError::Invalid(format!("Invalid nonce: {}", e)))?;
+        if nonce.len() != NONCE_LEN {
+            return Err(DecryptError::Invalid("Invalid nonce length".to_string()));
+        }
+        let ciphertext = BASE64
+            .decode(&envelope.ciphertext)
+            .map_err(|e| DecryptError::Invalid(format!("Invalid ciphertext: {}", e)))?;
+        let cipher = Aes256Gcm::new_from_slice(&self.key).map_err(|e| DecryptError::Invalid(format!("Invalid key: {}", e)))?;
+        cipher
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| DecryptError::Invalid("Decryption failed (wrong key or corrupted data)".to_string()))
+    }
+}
+
+fn keyring_entry(user: &str) -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, user).map_err(|e| format!("Failed to access OS keyring: {}", e))
+}
+
+/// Key currently used for reading and writing the store
+static ACTIVE_KEY: Mutex<Option<StoreKey>> = Mutex::new(None);
+
+/// The key used for reading and writing the store, if unlocked
+pub fn active_key() -> Option<StoreKey> {
+    ACTIVE_KEY.lock().ok().and_then(|k| k.clone())
+}
+
+/// Make `key` the key used for subsequent saves (`None` writes plaintext again)
+pub fn set_active_key(key: Option<StoreKey>) {
+    if let Ok(mut active) = ACTIVE_KEY.lock() {
+        *active = key;
+    }
+}
+
+/// Forget the in-memory key; passphrase-protected stores need to be unlocked again
+pub fn lock() {
+    set_active_key(None);
+}
+
+/// Remove keys from the OS keyring once no store depends on them.
+///
+/// `include_current` also removes the current key (after encryption has been
+/// disabled or switched to a passphrase); otherwise only the key kept during a
+/// rotation is removed.
+pub fn delete_keyring_keys(include_current: bool) {
+    let mut users = vec![KEYRING_PREVIOUS_USER];
+    if include_current {
+        users.push(KEYRING_USER);
+    }
+    for user in users {
+        if let Ok(entry) = keyring_entry(user) {
+            let _ = entry.delete_password();
+        }
+    }
+}
+
+/// Keyring keys left unused by re-encrypting from `old` to `new` (`None` =
+/// plaintext), as the `include_current` argument of `delete_keyring_keys`.
+///
+/// Nothing is retired while `failed` files are still encrypted with the old key.
+pub fn retired_keyring_keys(old: KeySource, new: Option<KeySource>, failed: &[String]) -> Option<bool> {
+    if !failed.is_empty() {
+        return None;
+    }
+    match (old, new) {
+        (KeySource::Keyring, None) => Some(true),
+        (_, Some(KeySource::Keyring)) => Some(false),
+        (KeySource::Keyring, Some(KeySource::Passphrase)) => Some(true),
+        (KeySource::Passphrase, _) => None,
+    }
+}
+
+fn parse_envelope(bytes: &[u8]) -> Option<Envelope> {
+    serde_json::from_slice::<Envelope>(bytes)
+        .ok()
+        .filter(|e| e.format == ENVELOPE_FORMAT)
+}
+
+/// Whether `bytes` is an encrypted store
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    parse_envelope(bytes).is_some()
+}
+
+/// Encrypt store contents with the active key, or return them unchanged when encryption is off
+pub fn encrypt_if_enabled(plaintext: Vec<u8>) -> Result<Vec<u8>, String> {
+    match active_key() {
+        Some(key) => key.seal(&plaintext),
+        None => Ok(plaintext),
+    }
+}
+
+/// Decrypt an envelope with the active key.
+///
+/// Keyring-backed stores are unlocked automatically on first access.
+pub fn decrypt(bytes: &[u8]) -> Result<Vec<u8>, DecryptError> {
+    let envelope = parse_envelope(bytes).ok_or_else(|| DecryptError::Invalid("Not an encrypted store".to_string()))?;
+
+    let key = match active_key() {
+        Some(key) => key,
+        None if envelope.key_source == KeySource::Keyring => {
+            let key = StoreKey::from_keyring_for(&envelope).map_err(|_| DecryptError::Locked)?;
+            set_active_key(Some(key.clone()));
+            key
+        }
+        None => return Err(DecryptError::Locked),
+    };
+    key.open_envelope(&envelope)
+}
+
+/// Derive the key for an existing store from a passphrase (or fetch it from the
+/// keyring) and verify it decrypts `bytes` before making it active.
+pub fn unlock(bytes: &[u8], passphrase: Option<&str>) -> Result<(), String> {
+    let envelope = parse_envelope(bytes).ok_or("Conversations are not encrypted")?;
+    let key = match envelope.key_source {
+        KeySource::Keyring => StoreKey::from_keyring_for(&envelope)?,
+        KeySource::Passphrase => {
+            let salt = envelope
+                .salt
+                .as_ref()
+                .and_then(|s| BASE64.decode(s).ok())
+                .ok_or("Encrypted store is missing its salt")?;
+            StoreKey::from_passphrase(passphrase.ok_or("A passphrase is required")?, salt)?
+        }
+    };
+
+    key.open_envelope(&envelope).map_err(|e| match e {
+        DecryptError::Locked => "Conversations are locked".to_string(),
+        DecryptError::Invalid(_) => "Incorrect passphrase or key".to_string(),
+    })?;
+    set_active_key(Some(key));
+    Ok(())
+}
+
+/// Describe the encryption state of the store whose raw bytes are `bytes`
+pub fn status(bytes: Option<&[u8]>) -> EncryptionStatus {
+    let envelope = bytes.and_then(parse_envelope);
+    let active = active_key();
+    match (envelope, active) {
+        (Some(envelope), active) => EncryptionStatus {
+            enabled: true,
+            key_source: Some(envelope.key_source),
+            locked: active.is_none(),
+            failed_files: Vec::new(),
+        },
+        // Encryption enabled but nothing written yet
+        (None, Some(key)) => EncryptionStatus {
+            enabled: true,
+            key_source: Some(key.source),
+            locked: false,
+            failed_files: Vec::new(),
+        },
+        (None, None) => EncryptionStatus {
+            enabled: false,
+            key_source: None,
+            locked: false,
+            failed_files: Vec::new(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversations::{
+        get_conversations_path, load_conversations, rewrite_files, save_conversations, ConversationsData,
+    };
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let key = StoreKey::new_keyring();
+        let sealed = key.seal(b"hello conversations").unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert_eq!(key.open(&sealed).unwrap(), b"hello conversations");
+    }
+
+    #[test]
+    fn open_rejects_the_wrong_key() {
+        let sealed = StoreKey::new_keyring().seal(b"hello conversations").unwrap();
+
+        assert!(matches!(StoreKey::new_keyring().open(&sealed), Err(DecryptError::Invalid(_))));
+    }
+
+    #[test]
+    fn open_rejects_tampered_ciphertext() {
+        let key = StoreKey::new_keyring();
+        let mut envelope = parse_envelope(&key.seal(b"hello conversations").unwrap()).unwrap();
+        let mut ciphertext = BASE64.decode(&envelope.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        envelope.ciphertext = BASE64.encode(ciphertext);
+
+        assert!(key.open_envelope(&envelope).is_err());
+    }
+
+    #[test]
+    fn passphrase_derives_the_same_key_from_the_same_salt() {
+        let key = StoreKey::new_passphrase("correct horse").unwrap();
+        let sealed = key.seal(b"hello conversations").unwrap();
+        let salt = key.salt.clone().unwrap();
+
+        let again = StoreKey::from_passphrase("correct horse", salt.clone()).unwrap();
+        assert_eq!(again.key, key.key);
+        assert_eq!(again.open(&sealed).unwrap(), b"hello conversations");
+
+        let wrong = StoreKey::from_passphrase("battery staple", salt).unwrap();
+        assert!(wrong.open(&sealed).is_err());
+        assert!(StoreKey::from_passphrase("", vec![0; SALT_LEN]).is_err());
+    }
+
+    #[test]
+    fn passphrase_salt_is_stored_in_the_envelope() {
+        let key = StoreKey::new_passphrase("correct horse").unwrap();
+        let sealed = key.seal(b"hello conversations").unwrap();
+        let envelope = parse_envelope(&sealed).unwrap();
+
+        assert_eq!(envelope.key_source, KeySource::Passphrase);
+        assert_eq!(BASE64.decode(envelope.salt.unwrap()).unwrap(), key.salt.unwrap());
+    }
+
+    #[tokio::test]
+    async fn encrypted_store_holds_no_plaintext() {
+        const SECRET: &str = "the spare key is under the third flowerpot";
+        let data: ConversationsData = serde_json::from_value(serde_json::json!({
+            "version": 1,
+            "conversations": [{
+                "id": "c1",
+                "title": "Garden",
+                "createdAt": 1,
+                "updatedAt": 1,
+                "model": null,
+                "messages": [{ "id": "m1", "role": "user", "content": SECRET, "timestamp": 1 }],
+            }],
+        }))
+        .unwrap();
+
+        set_active_key(Some(StoreKey::new_passphrase("correct horse").unwrap()));
+        save_conversations(&data).await.unwrap();
+        let raw = std::fs::read(get_conversations_path().unwrap()).unwrap();
+        let loaded = load_conversations().await.unwrap();
+        lock();
+
+        assert!(is_encrypted(&raw));
+        assert!(!raw.windows(SECRET.len()).any(|w| w == SECRET.as_bytes()));
+        assert!(!raw.windows(6).any(|w| w == b"Garden"));
+        assert_eq!(loaded.conversations[0].messages[0].content, SECRET);
+        assert!(matches!(load_conversations().await, Err(e) if e == crate::conversations::LOCKED_MESSAGE));
+        std::fs::remove_file(get_conversations_path().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn old_key_is_kept_while_a_rewrite_fails() {
+        let dir = get_conversations_path().unwrap().with_file_name("rewrite-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let old = StoreKey::new_keyring();
+        let unrelated = StoreKey::new_keyring();
+        let readable = dir.join("readable.json");
+        let unreadable = dir.join("unreadable.json");
+        std::fs::write(&readable, old.seal(b"readable").unwrap()).unwrap();
+        std::fs::write(&unreadable, unrelated.seal(b"unreadable").unwrap()).unwrap();
+
+        let files = vec![("readable".to_string(), readable.clone()), ("unreadable".to_string(), unreadable.clone())];
+        let failed = rewrite_files(&dir, files, Some(&old), None).await.unwrap();
+        let plaintext = std::fs::read(&readable).unwrap();
+        let still_sealed = std::fs::read(&unreadable).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(failed, ["unreadable"]);
+        assert_eq!(plaintext, b"readable");
+        assert!(is_encrypted(&still_sealed));
+        assert_eq!(retired_keyring_keys(KeySource::Keyring, None, &failed), None);
+        assert_eq!(retired_keyring_keys(KeySource::Keyring, Some(KeySource::Keyring), &failed), None);
+        assert_eq!(retired_keyring_keys(KeySource::Keyring, None, &[]), Some(true));
+        assert_eq!(retired_keyring_keys(KeySource::Keyring, Some(KeySource::Keyring), &[]), Some(false));
+    }
+}
@@ -0,0 +1,521 @@
+use crate::branches::migrate_flat;
+use crate::conversations::{generate_id, now_millis, Conversation, ConversationMessage, ConversationsData};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use tokio::fs;
+
+/// Export format detected for an imported file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    Chatgpt,
+    Claude,
+    Unknown,
+}
+
+/// An item from an export that was not imported
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedItem {
+    pub source_id: Option<String>,
+    pub title: Option<String>,
+    pub reason: String,
+}
+
+/// Import result for a single export file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileImportReport {
+    pub path: String,
+    pub format: ImportFormat,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub skipped: Vec<SkippedItem>,
+    pub error: Option<String>,
+}
+
+/// Import result for a batch of export files
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ImportReport {
+    pub files: Vec<FileImportReport>,
+    pub total_imported: usize,
+}
+
+/// Import ChatGPT / Claude.ai `conversations.json` exports into `data`.
+///
+/// Conversations whose source id is already present are counted as duplicates
+/// and left untouched. Newly imported conversations are placed at the front of
+/// the list, most recently updated first.
+pub async fn import_files(paths: &[String], data: &mut ConversationsData) -> ImportReport {
+    let mut seen: HashSet<String> = data
+        .conversations
+        .iter()
+        .filter_map(|c| c.source_id.clone())
+        .collect();
+    let mut report = ImportReport::default();
+    let mut imported = Vec::new();
+
+    for path in paths {
+        let mut file_report = FileImportReport {
+            path: path.clone(),
+            format: ImportFormat::Unknown,
+            imported: 0,
+            duplicates: 0,
+            skipped: Vec::new(),
+            error: None,
+        };
+
+        let parsed = match fs::read_to_string(path).await {
+            Ok(content) => serde_json::from_str::<Value>(&content)
+                .map_err(|e| format!("Failed to parse export file: {}", e)),
+            Err(e) => Err(format!("Failed to read export file: {}", e)),
+        };
+
+        let items = match parsed {
+            Ok(Value::Array(items)) => items,
+            Ok(_) => {
+                file_report.error = Some("Export file is not a list of conversations".to_string());
+                report.files.push(file_report);
+                continue;
+            }
+            Err(e) => {
+                file_report.error = Some(e);
+                report.files.push(file_report);
+                continue;
+            }
+        };
+
+        file_report.format = detect_format(&items);
+        if file_report.format == ImportFormat::Unknown {
+            file_report.error = Some("Unrecognized export format".to_string());
+            report.files.push(file_report);
+            continue;
+        }
+
+        for item in &items {
+            let result = match file_report.format {
+                ImportFormat::Chatgpt => convert_chatgpt(item),
+                ImportFormat::Claude => convert_claude(item),
+                ImportFormat::Unknown => unreachable!(),
+            };
+
+            match result {
+                Ok(conversation) => {
+                    let source_id = conversation.source_id.clone().unwrap_or_default();
+                    if !seen.insert(source_id) {
+                        file_report.duplicates += 1;
+                        continue;
+                    }
+                    file_report.imported += 1;
+                    imported.push(conversation);
+                }
+                Err(reason) => file_report.skipped.push(SkippedItem {
+                    source_id: item_source_id(item),
+                    title: item_title(item),
+                    reason,
+                }),
+            }
+        }
+
+        report.total_imported += file_report.imported;
+        report.files.push(file_report);
+    }
+
+    imported.sort_by_key(|c| std::cmp::Reverse(c.updated_at));
+    data.conversations.splice(0..0, imported);
+
+    report
+}
+
+fn detect_format(items: &[Value]) -> ImportFormat {
+    for item in items {
+        if item.get("mapping").is_some() {
+            return ImportFormat::Chatgpt;
+        }
+        if item.get("chat_messages").is_some() {
+            return ImportFormat::Claude;
+        }
+    }
+    ImportFormat::Unknown
+}
+
+fn item_source_id(item: &Value) -> Option<String> {
+    ["conversation_id", "id", "uuid"]
+        .iter()
+        .find_map(|key| item.get(*key).and_then(Value::as_str))
+        .map(str::to_string)
+}
+
+fn item_title(item: &Value) -> Option<String> {
+    ["title", "name"]
+        .iter()
+        .find_map(|key| item.get(*key).and_then(Value::as_str))
+        .map(str::to_string)
+}
+
+/// Convert fractional Unix seconds (ChatGPT) to milliseconds
+fn seconds_to_millis(value: Option<&Value>) -> Option<u64> {
+    value
+        .and_then(Value::as_f64)
+        .filter(|s| *s > 0.0)
+        .map(|s| (s * 1000.0) as u64)
+}
+
+/// Convert an RFC 3339 timestamp (Claude.ai) to milliseconds
+fn rfc3339_to_millis(value: Option<&Value>) -> Option<u64> {
+    value
+        .and_then(Value::as_str)
+        .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+        .map(|dt| dt.timestamp_millis().max(0) as u64)
+}
+
+/// Convert a conversation from a ChatGPT export, following only the active branch
+fn convert_chatgpt(item: &Value) -> Result<Conversation, String> {
+    let source_id = item_source_id(item).ok_or("Missing conversation id")?;
+    let mapping = item
+        .get("mapping")
+        .and_then(Value::as_object)
+        .ok_or("Missing message mapping")?;
+
+    let created_at = seconds_to_millis(item.get("create_time")).unwrap_or_else(now_millis);
+    let updated_at = seconds_to_millis(item.get("update_time")).unwrap_or(created_at);
+
+    // Walk from the current node up to the root, then reverse into display order
+    let mut node_id = item
+        .get("current_node")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or("Missing current node")?;
+    let mut chain = Vec::new();
+    let mut visited = HashSet::new();
+    while visited.insert(node_id.clone()) {
+        let node = match mapping.get(&node_id) {
+            Some(node) => node,
+            None => break,
+        };
+        chain.push(node);
+        match node.get("parent").and_then(Value::as_str) {
+            Some(parent) => node_id = parent.to_string(),
+            None => break,
+        }
+    }
+    chain.reverse();
+
+    let mut messages = Vec::new();
+    let mut last_model = None;
+    for node in chain {
+        let message = match node.get("message") {
+            Some(m) if !m.is_null() => m,
+            _ => continue,
+        };
+        let hidden = message
+            .pointer("/metadata/is_visually_hidden_from_conversation")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let role = match message.pointer("/author/role").and_then(Value::as_str) {
+            Some("user") => "user",
+            Some("assistant") => "assistant",
+            _ => continue,
+        };
+        if hidden {
+            continue;
+        }
+
+        let text = chatgpt_message_text(message);
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        if role == "assistant" {
+            if let Some(model) = message.pointer("/metadata/model_slug").and_then(Value::as_str) {
+                last_model = Some(model.to_string());
+            }
+        }
+
+        messages.push(ConversationMessage {
+            id: message
+                .get("id")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(generate_id),
+            role: role.to_string(),
+            content: Value::String(text),
+            timestamp: seconds_to_millis(message.get("create_time")).unwrap_or(created_at),
+            parent_id: None,
+            usage: None,
+        });
+    }
+
+    if messages.is_empty() {
+        return Err("Conversation has no user or assistant messages".to_string());
+    }
+
+    let model = item
+        .get("default_model_slug")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or(last_model);
+
+    let mut conversation = Conversation {
+        id: generate_id(),
+        title: item_title(item)
+            .filter(|t| !t.trim().is_empty())
+            .unwrap_or_else(|| "Imported chat".to_string()),
+        created_at,
+        updated_at,
+        messages,
+        active_leaf_id: None,
+        folder_id: None,
+        tags: Vec::new(),
+        pinned: false,
+        archived: false,
+        deleted_at: None,
+        model,
+        source_id: Some(format!("chatgpt:{}", source_id)),
+        usage_totals: Default::default(),
+        title_source: Default::default(),
+        summary: None,
+        summarized_count: 0,
+    };
+    migrate_flat(&mut conversation);
+    Ok(conversation)
+}
+
+/// Flatten the text parts of a ChatGPT message
+fn chatgpt_message_text(message: &Value) -> String {
+    let content = match message.get("content") {
+        Some(c) => c,
+        None => return String::new(),
+    };
+
+    match content.get("content_type").and_then(Value::as_str) {
+        Some("text") | Some("multimodal_text") => content
+            .get("parts")
+            .and_then(Value::as_array)
+            .map(|parts| {
+                parts
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default(),
+        Some("code") => content
+            .get("text")
+            .and_then(Value::as_str)
+            .map(|t| format!("```\n{}\n```", t))
+            .unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+/// Convert a conversation from a Claude.ai export
+fn convert_claude(item: &Value) -> Result<Conversation, String> {
+    let source_id = item_source_id(item).ok_or("Missing conversation uuid")?;
+    let chat_messages = item
+        .get("chat_messages")
+        .and_then(Value::as_array)
+        .ok_or("Missing chat messages")?;
+
+    let created_at = rfc3339_to_millis(item.get("created_at")).unwrap_or_else(now_millis);
+    let updated_at = rfc3339_to_millis(item.get("updated_at")).unwrap_or(created_at);
+
+    let mut messages = Vec::new();
+    for message in chat_messages {
+        let role = match message.get("sender").and_then(Value::as_str) {
+            Some("human") => "user",
+            Some("assistant") => "assistant",
+            _ => continue,
+        };
+
+        let text = claude_message_text(message);
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        messages.push(ConversationMessage {
+            id: message
+                .get("uuid")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .unwrap_or_else(generate_id),
+            role: role.to_string(),
+            content: Value::String(text),
+            timestamp: rfc3339_to_millis(message.get("created_at")).unwrap_or(created_at),
+            parent_id: None,
+            usage: None,
+        });
+    }
+
+    if messages.is_empty() {
+        return Err("Conversation has no messages".to_string());
+    }
+
+    let mut conversation = Conversation {
+        id: generate_id(),
+        title: item_title(item)
+            .filter(|t| !t.trim().is_empty())
+            .unwrap_or_else(|| "Imported chat".to_string()),
+        created_at,
+        updated_at,
+        messages,
+        active_leaf_id: None,
+        folder_id: None,
+        tags: Vec::new(),
+        pinned: false,
+        archived: false,
+        deleted_at: None,
+        model: item.get("model").and_then(Value::as_str).map(str::to_string),
+        source_id: Some(format!("claude:{}", source_id)),
+        usage_totals: Default::default(),
+        title_source: Default::default(),
+        summary: None,
+        summarized_count: 0,
+    };
+    migrate_flat(&mut conversation);
+    Ok(conversation)
+}
+
+/// Prefer structured text blocks, falling back to the flat `text` field
+fn claude_message_text(message: &Value) -> String {
+    let from_blocks = message
+        .get("content")
+        .and_then(Value::as_array)
+        .map(|blocks| {
+            blocks
+                .iter()
+                .filter(|b| b.get("type").and_then(Value::as_str) == Some("text"))
+                .filter_map(|b| b.get("text").and_then(Value::as_str))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .unwrap_or_default();
+
+    if !from_blocks.trim().is_empty() {
+        return from_blocks;
+    }
+
+    message
+        .get("text")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::branches::active_path;
+
+    const CHATGPT_EXPORT: &str = include_str!("../tests/fixtures/chatgpt-export.json");
+    const CLAUDE_EXPORT: &str = include_str!("../tests/fixtures/claude-export.json");
+
+    /// Write `content` to a file of its own in the temp directory
+    fn export_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("kiroaas-test-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn texts(conversation: &Conversation) -> Vec<(String, String)> {
+        active_path(conversation)
+            .into_iter()
+            .map(|m| (m.role, m.content.as_str().unwrap_or_default().to_string()))
+            .collect()
+    }
+
+    fn by_source<'a>(data: &'a ConversationsData, source_id: &str) -> &'a Conversation {
+        data.conversations
+            .iter()
+            .find(|c| c.source_id.as_deref() == Some(source_id))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn chatgpt_export_follows_the_current_branch() {
+        let paths = [export_file("chatgpt.json", CHATGPT_EXPORT)];
+        let mut data = ConversationsData::default();
+
+        let report = import_files(&paths, &mut data).await;
+        std::fs::remove_file(&paths[0]).unwrap();
+
+        let file = &report.files[0];
+        assert_eq!(file.format, ImportFormat::Chatgpt);
+        assert_eq!((file.imported, file.duplicates), (2, 0));
+        assert_eq!(file.skipped.len(), 1);
+        assert_eq!(file.skipped[0].source_id.as_deref(), Some("chat-3"));
+
+        let lifetimes = by_source(&data, "chatgpt:chat-1");
+        assert_eq!(lifetimes.title, "Rust lifetimes");
+        assert_eq!(lifetimes.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(lifetimes.created_at, 1_700_000_000_500);
+        assert_eq!(
+            texts(lifetimes),
+            [
+                ("user".to_string(), "What is a lifetime?".to_string()),
+                ("assistant".to_string(), "```\nfn f<'a>(x: &'a str) -> &'a str { x }\n```".to_string()),
+            ]
+        );
+
+        let untitled = by_source(&data, "chatgpt:chat-2");
+        assert_eq!(untitled.title, "Imported chat");
+        assert_eq!(texts(untitled), [("user".to_string(), "Hello\nthere".to_string())]);
+        // Most recently updated first
+        assert_eq!(data.conversations[0].source_id.as_deref(), Some("chatgpt:chat-2"));
+    }
+
+    #[tokio::test]
+    async fn claude_export_keeps_text_blocks() {
+        let paths = [export_file("claude.json", CLAUDE_EXPORT)];
+        let mut data = ConversationsData::default();
+
+        let report = import_files(&paths, &mut data).await;
+        std::fs::remove_file(&paths[0]).unwrap();
+
+        let file = &report.files[0];
+        assert_eq!(file.format, ImportFormat::Claude);
+        assert_eq!((file.imported, file.duplicates), (2, 0));
+        assert_eq!(file.skipped[0].title.as_deref(), Some("Empty"));
+
+        let trip = by_source(&data, "claude:claude-1");
+        assert_eq!(trip.title, "Trip planning");
+        assert_eq!(trip.updated_at, 1_709_287_500_000);
+        assert_eq!(
+            texts(trip),
+            [
+                ("user".to_string(), "Plan a weekend in Lisbon".to_string()),
+                ("assistant".to_string(), "Day one: Alfama.\nDay two: Belém.".to_string()),
+            ]
+        );
+        assert_eq!(
+            texts(by_source(&data, "claude:claude-2")),
+            [("user".to_string(), "Only the flat text field".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn reimporting_an_export_skips_the_duplicates() {
+        let paths = [export_file("reimport.json", CLAUDE_EXPORT)];
+        let mut data = ConversationsData::default();
+
+        import_files(&paths, &mut data).await;
+        let report = import_files(&paths, &mut data).await;
+        std::fs::remove_file(&paths[0]).unwrap();
+
+        assert_eq!(report.total_imported, 0);
+        assert_eq!(report.files[0].duplicates, 2);
+        assert_eq!(data.conversations.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unrecognized_files_are_reported() {
+        let paths = [export_file("unknown.json", r#"[{ "messages": [] }]"#)];
+        let mut data = ConversationsData::default();
+
+        let report = import_files(&paths, &mut data).await;
+        std::fs::remove_file(&paths[0]).unwrap();
+
+        assert_eq!(report.files[0].format, ImportFormat::Unknown);
+        assert_eq!(report.files[0].error.as_deref(), Some("Unrecognized export format"));
+        assert!(data.conversations.is_empty());
+    }
+}
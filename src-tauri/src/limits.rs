@@ -0,0 +1,450 @@
+use crate::config::{AppConfig, ClientLimits};
+use crate::conversations::{get_conversations_path, now_millis, write_synced};
+use chrono::{Datelike, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::fs;
+
+/// Scope name of the limits on all traffic combined
+pub const GLOBAL_SCOPE: &str = "global";
+/// How stale the token counters may get on disk before they are written again
+const USAGE_FLUSH_MS: u64 = 10_000;
+
+/// Why a request was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefusalKind {
+    RateLimited,
+    TooManyStreams,
+    QuotaExceeded,
+}
+
+/// A request refused by a limit
+#[derive(Debug, Clone)]
+pub struct Refusal {
+    pub kind: RefusalKind,
+    pub message: String,
+    /// Seconds until the request may succeed
+    pub retry_after_secs: u64,
+}
+
+/// Token counters for the current day and month, per scope
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct QuotaUsage {
+    day: String,
+    month: String,
+    daily: HashMap<String, u64>,
+    monthly: HashMap<String, u64>,
+}
+
+impl QuotaUsage {
+    /// Reset counters whose period has ended
+    fn roll(&mut self) {
+        let now = Local::now();
+        let day = now.format("%Y-%m-%d").to_string();
+        let month = now.format("%Y-%m").to_string();
+        if self.day != day {
+            self.day = day;
+            self.daily.clear();
+        }
+        if self.month != month {
+            self.month = month;
+            self.monthly.clear();
+        }
+    }
+}
+
+/// Requests per minute as a token bucket holding up to a minute's worth
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(per_minute: u32) -> Self {
+        Self {
+            tokens: per_minute as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, per_minute: u32) {
+        let rate = per_minute as f64 / 60.0;
+        self.tokens = (self.tokens + self.updated.elapsed().as_secs_f64() * rate).min(per_minute as f64);
+        self.updated = Instant::now();
+    }
+
+    /// Seconds until a request is available
+    fn wait_secs(&self, per_minute: u32) -> u64 {
+        if per_minute == 0 {
+            return 60;
+        }
+        let rate = per_minute as f64 / 60.0;
+        ((1.0 - self.tokens) / rate).ceil().max(1.0) as u64
+    }
+}
+
+#[derive(Default)]
+struct LimiterState {
+    global: ClientLimits,
+    keys: HashMap<String, ClientLimits>,
+    buckets: HashMap<String, Bucket>,
+    streams: HashMap<String, u32>,
+    usage: QuotaUsage,
+    flushed_at: u64,
+}
+
+impl LimiterState {
+    fn limits(&self, scope: &str) -> Option<&ClientLimits> {
+        if scope == GLOBAL_SCOPE {
+            Some(&self.global)
+        } else {
+            self.keys.get(scope)
+        }
+    }
+}
+
+/// Current limits and consumption of one scope
+#[derive(Debug, Clone, Serialize)]
+pub struct LimitStatus {
+    /// `global` or a client key id
+    pub scope: String,
+    pub limits: ClientLimits,
+    pub daily_tokens: u64,
+    pub monthly_tokens: u64,
+    pub active_streams: u32,
+    /// Requests that could be made right now (only with a per-minute limit)
+    pub available_requests: Option<u32>,
+}
+
+fn get_usage_path() -> Result<PathBuf, String> {
+    let path = get_conversations_path()?;
+    let parent = path.parent().ok_or("Failed to get data directory")?;
+    Ok(parent.join("quota-usage.json"))
+}
+
+/// Whether `config` sets any limit, globally or for a key
+pub fn any_configured(config: &AppConfig) -> bool {
+    let unlimited = ClientLimits::default();
+    config.global_limits != unlimited || config.key_limits.values().any(|limits| *limits != unlimited)
+}
+
+/// Seconds until the next local midnight, or the start of next month
+fn secs_until_reset(monthly: bool) -> u64 {
+    let now = Local::now().naive_local();
+    let today = now.date();
+    let next = if monthly {
+        let (year, month) = if today.month() == 12 {
+            (today.year() + 1, 1)
+        } else {
+            (today.year(), today.month() + 1)
+        };
+        NaiveDate::from_ymd_opt(year, month, 1)
+    } else {
+        today.succ_opt()
+    };
+    next.and_then(|d| d.and_hms_opt(0, 0, 0))
+        .map(|t| (t - now).num_seconds().max(1) as u64)
+        .unwrap_or(60)
+}
+
+/// Enforces the request rates, concurrent streams and token quotas in `AppConfig`
+pub struct Limiter {
+    state: Mutex<LimiterState>,
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl Limiter {
+    /// Create a limiter, restoring today's and this month's token counters
+    pub fn open() -> Self {
+        let usage = get_usage_path()
+            .ok()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|content| serde_json::from_slice::<QuotaUsage>(&content).ok())
+            .unwrap_or_default();
+        Self {
+            state: Mutex::new(LimiterState {
+                usage,
+                flushed_at: now_millis(),
+                ..Default::default()
+            }),
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LimiterState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply the limits from `config`; counters are kept
+    pub fn configure(&self, config: &AppConfig) {
+        let mut state = self.lock();
+        let rate = |state: &LimiterState, scope: &str| state.limits(scope).and_then(|l| l.requests_per_minute);
+        let previous: Vec<(String, Option<u32>)> = state
+            .buckets
+            .keys()
+            .map(|scope| (scope.clone(), rate(&state, scope)))
+            .collect();
+        state.global = config.global_limits.clone();
+        state.keys = config.key_limits.clone();
+        // Buckets whose rate changed are recreated on the next request; the
+        // others keep their level, so saving other settings refills nothing
+        for (scope, per_minute) in previous {
+            if rate(&state, &scope) != per_minute {
+                state.buckets.remove(&scope);
+            }
+        }
+    }
+
+    /// Admit a request from `key_id` (`None` for the gateway's own key), or
+    /// say which limit refuses it. The returned guard must be kept until the
+    /// response is finished.
+    pub fn admit(self: &Arc<Self>, key_id: Option<&str>, stream: bool) -> Result<Admission, Refusal> {
+        let mut state = self.lock();
+        state.usage.roll();
+
+        // Keys are tracked even without limits of their own, so their usage shows up
+        let mut scopes = vec![GLOBAL_SCOPE.to_string()];
+        scopes.extend(key_id.map(str::to_string));
+
+        // Check every scope before consuming anything, so a refused request costs nothing
+        for scope in &scopes {
+            let limits = match state.limits(scope) {
+                Some(limits) => limits.clone(),
+                None => continue,
+            };
+            let who = if scope == GLOBAL_SCOPE { "Gateway" } else { "API key" };
+
+            let daily = state.usage.daily.get(scope).copied().unwrap_or(0);
+            if limits.daily_tokens.is_some_and(|max| daily >= max) {
+                return Err(Refusal {
+                    kind: RefusalKind::QuotaExceeded,
+                    message: format!("{} daily token quota exceeded", who),
+                    retry_after_secs: secs_until_reset(false),
+                });
+            }
+            let monthly = state.usage.monthly.get(scope).copied().unwrap_or(0);
+            if limits.monthly_tokens.is_some_and(|max| monthly >= max) {
+                return Err(Refusal {
+                    kind: RefusalKind::QuotaExceeded,
+                    message: format!("{} monthly token quota exceeded", who),
+                    retry_after_secs: secs_until_reset(true),
+                });
+            }
+
+            if let Some(per_minute) = limits.requests_per_minute {
+                let bucket = state
+                    .buckets
+                    .entry(scope.clone())
+                    .or_insert_with(|| Bucket::full(per_minute));
+                bucket.refill(per_minute);
+                if bucket.tokens < 1.0 {
+                    return Err(Refusal {
+                        kind: RefusalKind::RateLimited,
+                        message: format!("{} rate limit of {} requests per minute exceeded", who, per_minute),
+                        retry_after_secs: bucket.wait_secs(per_minute),
+                    });
+                }
+            }
+
+            if stream {
+                let active = state.streams.get(scope).copied().unwrap_or(0);
+                if let Some(max) = limits.max_concurrent_streams {
+                    if active >= max {
+                        return Err(Refusal {
+                            kind: RefusalKind::TooManyStreams,
+                            message: format!("{} limit of {} concurrent streams reached", who, max),
+                            retry_after_secs: 1,
+                        });
+                    }
+                }
+            }
+        }
+
+        for scope in &scopes {
+            if let Some(bucket) = state.buckets.get_mut(scope) {
+                bucket.tokens -= 1.0;
+            }
+            if stream {
+                *state.streams.entry(scope.clone()).or_insert(0) += 1;
+            }
+        }
+
+        Ok(Admission {
+            limiter: self.clone(),
+            scopes,
+            stream,
+        })
+    }
+
+    /// Write the token counters to disk
+    pub async fn save(&self) -> Result<(), String> {
+        let _guard = self.save_lock.lock().await;
+        let content = serde_json::to_vec_pretty(&self.lock().usage)
+            .map_err(|e| format!("Failed to serialize quota usage: {}", e))?;
+        let path = get_usage_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create data directory: {}", e))?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        write_synced(&tmp_path, &content)
+            .await
+            .map_err(|e| format!("Failed to write quota usage: {}", e))?;
+        fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|e| format!("Failed to rename quota usage temp file: {}", e))
+    }
+
+    /// Limits and consumption of the global scope and of every key with limits or usage
+    pub fn status(&self) -> Vec<LimitStatus> {
+        let mut state = self.lock();
+        state.usage.roll();
+
+        let mut scopes: Vec<String> = state
+            .keys
+            .keys()
+            .chain(state.usage.monthly.keys())
+            .filter(|s| s.as_str() != GLOBAL_SCOPE)
+            .cloned()
+            .collect();
+        scopes.sort();
+        scopes.dedup();
+        scopes.insert(0, GLOBAL_SCOPE.to_string());
+
+        scopes
+            .into_iter()
+            .map(|scope| {
+                let limits = state.limits(&scope).cloned().unwrap_or_default();
+                let available_requests = limits.requests_per_minute.map(|per_minute| match state.buckets.get_mut(&scope) {
+                    Some(bucket) => {
+                        bucket.refill(per_minute);
+                        bucket.tokens.floor() as u32
+                    }
+                    None => per_minute,
+                });
+                LimitStatus {
+                    daily_tokens: state.usage.daily.get(&scope).copied().unwrap_or(0),
+                    monthly_tokens: state.usage.monthly.get(&scope).copied().unwrap_or(0),
+                    active_streams: state.streams.get(&scope).copied().unwrap_or(0),
+                    available_requests,
+                    limits,
+                    scope,
+                }
+            })
+            .collect()
+    }
+}
+
+/// An admitted request; counts toward concurrent streams until dropped
+pub struct Admission {
+    limiter: Arc<Limiter>,
+    scopes: Vec<String>,
+    stream: bool,
+}
+
+impl Admission {
+    /// Count tokens used by the response against the quotas of its scopes
+    pub fn record_tokens(&self, tokens: u64) {
+        if tokens == 0 {
+            return;
+        }
+        let flush = {
+            let mut state = self.limiter.lock();
+            state.usage.roll();
+            for scope in &self.scopes {
+                *state.usage.daily.entry(scope.clone()).or_insert(0) += tokens;
+                *state.usage.monthly.entry(scope.clone()).or_insert(0) += tokens;
+            }
+            let now = now_millis();
+            if now.saturating_sub(state.flushed_at) >= USAGE_FLUSH_MS {
+                state.flushed_at = now;
+                true
+            } else {
+                false
+            }
+        };
+        if flush {
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                let limiter = self.limiter.clone();
+                runtime.spawn(async move {
+                    if let Err(e) = limiter.save().await {
+                        eprintln!("[Limits] {}", e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        if !self.stream {
+            return;
+        }
+        let mut state = self.limiter.lock();
+        for scope in &self.scopes {
+            if let Some(active) = state.streams.get_mut(scope) {
+                *active = active.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(global: ClientLimits, key: ClientLimits) -> AppConfig {
+        AppConfig {
+            global_limits: global,
+            key_limits: HashMap::from([("key-1".to_string(), key)]),
+            ..Default::default()
+        }
+    }
+
+    fn per_minute(requests: u32) -> ClientLimits {
+        ClientLimits {
+            requests_per_minute: Some(requests),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rate_limit_refuses_requests_beyond_the_bucket() {
+        let limiter = Arc::new(Limiter::open());
+        limiter.configure(&config(ClientLimits::default(), per_minute(2)));
+
+        assert!(limiter.admit(Some("key-1"), false).is_ok());
+        assert!(limiter.admit(Some("key-1"), false).is_ok());
+        let refusal = limiter.admit(Some("key-1"), false).err().unwrap();
+
+        assert_eq!(refusal.kind, RefusalKind::RateLimited);
+        assert!(refusal.retry_after_secs >= 1);
+        // Other keys and the gateway's own key are not limited by key-1's bucket
+        assert!(limiter.admit(Some("key-2"), false).is_ok());
+        assert!(limiter.admit(None, false).is_ok());
+    }
+
+    #[test]
+    fn saving_other_settings_keeps_the_buckets() {
+        let limiter = Arc::new(Limiter::open());
+        limiter.configure(&config(ClientLimits::default(), per_minute(1)));
+        assert!(limiter.admit(Some("key-1"), false).is_ok());
+
+        let unrelated = ClientLimits {
+            daily_tokens: Some(1_000_000),
+            ..Default::default()
+        };
+        limiter.configure(&config(unrelated, per_minute(1)));
+        assert!(limiter.admit(Some("key-1"), false).is_err());
+
+        // A new rate starts with a full bucket
+        limiter.configure(&config(ClientLimits::default(), per_minute(5)));
+        assert!(limiter.admit(Some("key-1"), false).is_ok());
+    }
+}
@@ -2,17 +2,60 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 #![allow(unexpected_cfgs)]
 
+mod access;
+mod api_keys;
+mod attachments;
+mod backups;
+mod branches;
+mod captures;
 mod config;
 mod conversations;
+mod encryption;
+mod importer;
+mod limits;
+mod metrics;
+mod mock;
+mod model_routes;
+mod organization;
+mod pool;
+mod ports;
+mod proxy;
+mod request_log;
+mod response_cache;
 mod server;
-
+mod sync;
+mod titling;
+mod tls;
+mod trash;
+mod usage;
+mod usage_history;
+
+use access::LanExposure;
+use api_keys::{ApiKey, ApiScope, CreatedApiKey};
 use auto_launch::AutoLaunch;
-use config::{AppConfig, load_config, save_config};
-use conversations::{Conversation, ConversationsData, load_conversations, save_conversations};
-use server::{ServerManager, ServerStatus};
+use config::{AppConfig, ModelRoute, load_config, save_config};
+use conversations::{Conversation, ConversationsData, Folder, load_conversations, save_conversations};
+use encryption::{EncryptionStatus, KeySource, StoreKey};
+use usage::{DailyUsage, ModelUsage, UsageTotals};
+use usage_history::{SeriesBucket, UsagePoint};
+use titling::{Gateway, RateLimiter, TitleSource, TitlingQueue};
+use tls::TlsStatus;
+use sync::{SyncConflict, SyncReport};
+use backups::{BackupInfo, RecoveryReport};
+use branches::BranchInfo;
+use captures::{Capture, CaptureSummary, ReplayResult};
+use importer::ImportReport;
+use organization::{ConversationFilter, ConversationSummary, TagCount};
+use limits::LimitStatus;
+use model_routes::RouteMatch;
+use pool::UpstreamStatus;
+use ports::PortOccupierInfo;
+use proxy::{ListenerHandle, ProxyServices};
+use request_log::{RequestLogFilter, RequestRecord};
+use response_cache::{CacheEntryInfo, CacheStats};
+use server::{ServerManager, ServerStatus, StartError};
 use tauri::{Manager, State, SystemTray, SystemTrayEvent, SystemTrayMenu, CustomMenuItem, AppHandle};
 use tokio::sync::Mutex;
-use std::process::Command;
 
 #[cfg(target_os = "macos")]
 mod macos_dock {
@@ -102,18 +145,38 @@ mod macos_dock {
 struct AppState {
     server_manager: Mutex<ServerManager>,
     conversations_lock: Mutex<()>,
+    titling: TitlingQueue,
+    proxy: ProxyServices,
+    /// Address and listener of the metrics endpoint, while enabled
+    metrics_server: Mutex<Option<(String, ListenerHandle)>>,
 }
 
 fn has_credentials(config: &AppConfig) -> bool {
+    // The mock backend needs none
+    if config.backend_mode == crate::config::BackendMode::Mock {
+        return true;
+    }
+    // Enabled pool accounts replace the single credential, and each needs its own
+    let accounts: Vec<_> = config.accounts.iter().filter(|a| a.enabled).collect();
+    if !accounts.is_empty() {
+        return accounts.iter().all(|account| {
+            let value = match account.auth_method {
+                crate::config::AuthMethod::RefreshToken => &account.refresh_token,
+                crate::config::AuthMethod::CredsFile => &account.kiro_creds_file,
+                crate::config::AuthMethod::CliDb => &account.kiro_cli_db_file,
+            };
+            value.as_ref().is_some_and(|v| !v.is_empty())
+        });
+    }
     match &config.auth_method {
         crate::config::AuthMethod::RefreshToken => {
-            config.refresh_token.as_ref().map_or(false, |t| !t.is_empty())
+            config.refresh_token.as_ref().is_some_and(|t| !t.is_empty())
         }
         crate::config::AuthMethod::CredsFile => {
-            config.kiro_creds_file.as_ref().map_or(false, |f| !f.is_empty())
+            config.kiro_creds_file.as_ref().is_some_and(|f| !f.is_empty())
         }
         crate::config::AuthMethod::CliDb => {
-            config.kiro_cli_db_file.as_ref().map_or(false, |d| !d.is_empty())
+            config.kiro_cli_db_file.as_ref().is_some_and(|d| !d.is_empty())
         }
     }
 }
@@ -123,16 +186,41 @@ fn has_credentials(config: &AppConfig) -> bool {
 async fn start_server(
     config: AppConfig,
     state: State<'_, AppState>,
-) -> Result<ServerStatus, String> {
+) -> Result<ServerStatus, StartError> {
     // Validate that credentials are configured for the selected auth method
     if !has_credentials(&config) {
-        return Err("No credentials configured. Please set up authentication in Settings first.".to_string());
+        return Err("No credentials configured. Please set up authentication in Settings first.".into());
     }
 
     let mut manager = state.server_manager.lock().await;
     manager.start(config).await
 }
 
+/// Restart the server with the given configuration, letting requests in
+/// flight finish instead of cutting them off
+#[tauri::command]
+async fn restart_server(
+    config: AppConfig,
+    state: State<'_, AppState>,
+) -> Result<ServerStatus, StartError> {
+    if !has_credentials(&config) {
+        return Err("No credentials configured. Please set up authentication in Settings first.".into());
+    }
+
+    let mut manager = state.server_manager.lock().await;
+    manager.restart(config).await
+}
+
+/// The saved configuration, with the port the server actually listens on
+/// while it is running (it may have moved to a fallback port)
+async fn load_running_config(state: &AppState) -> Result<AppConfig, String> {
+    let mut config = load_config().await?;
+    if let Some(port) = state.server_manager.lock().await.running_port() {
+        config.server_port = port;
+    }
+    Ok(config)
+}
+
 /// Stop the running Python server
 #[tauri::command]
 async fn stop_server(state: State<'_, AppState>) -> Result<(), String> {
@@ -272,164 +360,10 @@ async fn clear_server_logs(state: State<'_, AppState>) -> Result<(), String> {
     Ok(())
 }
 
-#[derive(Debug, serde::Serialize)]
-struct PortOccupierInfo {
-    pid: u32,
-    process_name: String,
-    command: String,
-}
-
-#[cfg(unix)]
-fn get_process_command(pid: u32) -> String {
-    let output = Command::new("ps")
-        .args(["-p", &pid.to_string(), "-o", "command="])
-        .output();
-    if let Ok(out) = output {
-        if out.status.success() {
-            return String::from_utf8_lossy(&out.stdout).trim().to_string();
-        }
-    }
-    String::new()
-}
-
-#[cfg(windows)]
-fn get_process_command(pid: u32) -> String {
-    let output = Command::new("wmic")
-        .args([
-            "process",
-            "where",
-            &format!("ProcessId={}", pid),
-            "get",
-            "CommandLine",
-            "/value",
-        ])
-        .creation_flags(0x08000000)
-        .output();
-    if let Ok(out) = output {
-        if out.status.success() {
-            let text = String::from_utf8_lossy(&out.stdout);
-            for line in text.lines() {
-                if let Some(rest) = line.strip_prefix("CommandLine=") {
-                    return rest.trim().to_string();
-                }
-            }
-        }
-    }
-    String::new()
-}
-
 /// Detect which process is listening on a TCP port.
 #[tauri::command]
 async fn get_port_occupier(port: u16) -> Result<Option<PortOccupierInfo>, String> {
-    #[cfg(unix)]
-    {
-        let output = Command::new("lsof")
-            .args([
-                "-nP",
-                &format!("-iTCP:{}", port),
-                "-sTCP:LISTEN",
-                "-Fpc",
-            ])
-            .output()
-            .map_err(|e| format!("Failed to execute lsof: {}", e))?;
-
-        if !output.status.success() {
-            return Ok(None);
-        }
-
-        let text = String::from_utf8_lossy(&output.stdout);
-        let mut pid: Option<u32> = None;
-        let mut process_name = String::new();
-
-        for line in text.lines() {
-            if let Some(rest) = line.strip_prefix('p') {
-                if pid.is_none() {
-                    pid = rest.trim().parse::<u32>().ok();
-                }
-            } else if let Some(rest) = line.strip_prefix('c') {
-                if process_name.is_empty() {
-                    process_name = rest.trim().to_string();
-                }
-            }
-            if pid.is_some() && !process_name.is_empty() {
-                break;
-            }
-        }
-
-        if let Some(found_pid) = pid {
-            let command = get_process_command(found_pid);
-            return Ok(Some(PortOccupierInfo {
-                pid: found_pid,
-                process_name,
-                command,
-            }));
-        }
-
-        return Ok(None);
-    }
-
-    #[cfg(windows)]
-    {
-        let output = Command::new("netstat")
-            .args(["-ano", "-p", "tcp"])
-            .creation_flags(0x08000000)
-            .output()
-            .map_err(|e| format!("Failed to execute netstat: {}", e))?;
-
-        if !output.status.success() {
-            return Ok(None);
-        }
-
-        let text = String::from_utf8_lossy(&output.stdout);
-        let needle = format!(":{}", port);
-        let mut found_pid: Option<u32> = None;
-
-        for line in text.lines() {
-            if !line.contains("LISTENING") || !line.contains(&needle) {
-                continue;
-            }
-            let cols: Vec<&str> = line.split_whitespace().collect();
-            if let Some(pid_col) = cols.last() {
-                found_pid = pid_col.parse::<u32>().ok();
-                if found_pid.is_some() {
-                    break;
-                }
-            }
-        }
-
-        if let Some(pid) = found_pid {
-            let process_name = {
-                let out = Command::new("tasklist")
-                    .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
-                    .creation_flags(0x08000000)
-                    .output();
-                if let Ok(o) = out {
-                    if o.status.success() {
-                        let row = String::from_utf8_lossy(&o.stdout);
-                        let first = row.lines().next().unwrap_or("").trim();
-                        if let Some(stripped) = first.strip_prefix('"') {
-                            stripped.split('"').next().unwrap_or("").to_string()
-                        } else {
-                            String::new()
-                        }
-                    } else {
-                        String::new()
-                    }
-                } else {
-                    String::new()
-                }
-            };
-
-            let command = get_process_command(pid);
-            return Ok(Some(PortOccupierInfo {
-                pid,
-                process_name,
-                command,
-            }));
-        }
-
-        return Ok(None);
-    }
+    ports::port_occupier(port)
 }
 
 /// Terminate a process by PID (used only after explicit user confirmation in UI).
@@ -442,11 +376,13 @@ async fn terminate_process(pid: u32) -> Result<(), String> {
                 return Err(format!("Failed to terminate process {} with SIGTERM", pid));
             }
         }
-        return Ok(());
+        Ok(())
     }
 
     #[cfg(windows)]
     {
+        use std::process::Command;
+        use std::os::windows::process::CommandExt;
         let output = Command::new("taskkill")
             .args(["/F", "/PID", &pid.to_string()])
             .creation_flags(0x08000000)
@@ -457,19 +393,50 @@ async fn terminate_process(pid: u32) -> Result<(), String> {
             return Ok(());
         }
 
-        return Err(format!("Failed to terminate process {}", pid));
+        Err(format!("Failed to terminate process {}", pid))
     }
 }
 
 /// Save configuration to disk
 #[tauri::command]
-async fn save_config_cmd(config: AppConfig) -> Result<(), String> {
+async fn save_config_cmd(state: State<'_, AppState>, config: AppConfig) -> Result<(), String> {
+    model_routes::validate(&config.model_routes)?;
+    access::validate(&config)?;
+    server::ready_line(&config)?;
+    state.proxy.configure(&config);
+    if let Err(e) = apply_metrics_listen(&state, &config).await {
+        eprintln!("[Metrics] {}", e);
+    }
     if let Err(e) = apply_auto_launch(config.auto_launch) {
         eprintln!("[Config] Auto-launch apply failed (non-fatal): {}", e);
     }
     save_config(&config).await
 }
 
+/// Start, move or stop the metrics endpoint to match `metrics_listen`
+async fn apply_metrics_listen(state: &AppState, config: &AppConfig) -> Result<(), String> {
+    let wanted = config
+        .metrics_listen
+        .as_deref()
+        .map(str::trim)
+        .filter(|l| !l.is_empty());
+    let mut server = state.metrics_server.lock().await;
+    if server.as_ref().map(|(listen, _)| listen.as_str()) == wanted {
+        return Ok(());
+    }
+    if let Some((_, handle)) = server.take() {
+        handle.stop().await;
+    }
+    if let Some(listen) = wanted {
+        let addr = listen
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| format!("Invalid metrics address {}: {}", listen, e))?;
+        let handle = metrics::serve(addr, state.proxy.metrics.clone()).await?;
+        *server = Some((listen.to_string(), handle));
+    }
+    Ok(())
+}
+
 /// Load configuration from disk
 #[tauri::command]
 async fn load_config_cmd() -> Result<AppConfig, String> {
@@ -601,49 +568,208 @@ async fn scan_all_credentials() -> Result<CredentialScanResult, String> {
 }
 
 /// Load conversations from disk
+///
+/// Attachments are inlined as data URLs unless `inline_attachments` is false, in
+/// which case messages carry references that can be resolved with `get_attachment`.
+#[tauri::command]
+async fn load_conversations_cmd(inline_attachments: Option<bool>) -> Result<ConversationsData, String> {
+    let mut data = load_conversations().await?;
+    if inline_attachments.unwrap_or(true) {
+        attachments::inline(&mut data).await?;
+    }
+    Ok(data)
+}
+
+/// Resolve an attachment reference from message content to a data URL
 #[tauri::command]
-async fn load_conversations_cmd() -> Result<ConversationsData, String> {
-    load_conversations().await
+async fn get_attachment(reference: String) -> Result<String, String> {
+    attachments::resolve(&reference).await
+}
+
+/// Remove attachments that no conversation or backup refers to any more
+async fn collect_attachment_garbage(data: &ConversationsData) {
+    match attachments::collect_garbage(data).await {
+        Ok(0) => {}
+        Ok(removed) => println!("[Attachments] Removed {} unreferenced attachment(s)", removed),
+        Err(e) => eprintln!("[Attachments] Skipped garbage collection: {}", e),
+    }
 }
 
 /// Save conversations to disk
 #[tauri::command]
-async fn save_conversations_cmd(data: ConversationsData, state: State<'_, AppState>) -> Result<(), String> {
+async fn save_conversations_cmd(mut data: ConversationsData, state: State<'_, AppState>) -> Result<(), String> {
     let _lock = state.conversations_lock.lock().await;
+    let config = load_config().await?;
+    let previous = load_conversations().await?;
+    data.migrate();
+    // The usage ledger is maintained here, not by the frontend
+    data.usage = previous.usage.clone();
+    usage::record_all(&mut data, &previous, &config.model_prices);
     save_conversations(&data).await
 }
 
 /// Create a new conversation
 #[tauri::command]
-async fn create_conversation(conversation: Conversation, state: State<'_, AppState>) -> Result<(), String> {
+async fn create_conversation(mut conversation: Conversation, state: State<'_, AppState>) -> Result<(), String> {
     let _lock = state.conversations_lock.lock().await;
+    let config = load_config().await?;
     let mut data = load_conversations().await?;
+    branches::migrate_flat(&mut conversation);
+    usage::record(&mut data.usage, &config.model_prices, &mut conversation, None);
+    let queue_titling = titling::needs(&conversation, &config).any();
+    let id = conversation.id.clone();
     data.conversations.insert(0, conversation);
-    save_conversations(&data).await
+    save_conversations(&data).await?;
+    if queue_titling {
+        state.titling.enqueue(&id);
+    }
+    Ok(())
 }
 
 /// Update an existing conversation
+///
+/// A message list without parent links is treated as the new active branch and
+/// merged into the stored tree, so earlier continuations are kept as branches.
+/// Folder, tags, pin, archive and trash state have their own commands and are
+/// kept as stored.
 #[tauri::command]
-async fn update_conversation(conversation: Conversation, state: State<'_, AppState>) -> Result<(), String> {
+async fn update_conversation(mut conversation: Conversation, state: State<'_, AppState>) -> Result<(), String> {
     let _lock = state.conversations_lock.lock().await;
+    let config = load_config().await?;
     let mut data = load_conversations().await?;
-    if let Some(pos) = data.conversations.iter().position(|c| c.id == conversation.id) {
-        data.conversations[pos] = conversation;
-        save_conversations(&data).await
+    if let Some(existing) = data.conversations.iter_mut().find(|c| c.id == conversation.id) {
+        let previous = existing.clone();
+        if conversation.messages.iter().all(|m| m.parent_id.is_none()) {
+            let path = std::mem::take(&mut conversation.messages);
+            conversation.messages = std::mem::take(&mut existing.messages);
+            branches::apply_active_path(&mut conversation, path);
+        }
+        usage::record(&mut data.usage, &config.model_prices, &mut conversation, Some(&previous));
+        titling::keep_generated(&mut conversation, &previous);
+        organization::keep_organization(&mut conversation, &previous);
+        let queue_titling = titling::needs(&conversation, &config).any();
+        *existing = conversation;
+        save_conversations(&data).await?;
+        if queue_titling {
+            state.titling.enqueue(&previous.id);
+        }
+        Ok(())
     } else {
         Err("Conversation not found".to_string())
     }
 }
 
-/// Delete a conversation
+/// Delete a conversation (moves it to the trash)
 #[tauri::command]
 async fn delete_conversation(id: String, state: State<'_, AppState>) -> Result<(), String> {
     let _lock = state.conversations_lock.lock().await;
     let mut data = load_conversations().await?;
-    data.conversations.retain(|c| c.id != id);
+    if trash::trash(&mut data, std::slice::from_ref(&id)) == 0 {
+        return Err("Conversation not found".to_string());
+    }
     save_conversations(&data).await
 }
 
+/// Restore a conversation from the trash
+#[tauri::command]
+async fn restore_conversation(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    let _lock = state.conversations_lock.lock().await;
+    let mut data = load_conversations().await?;
+    if trash::restore(&mut data, std::slice::from_ref(&id)) == 0 {
+        return Err("Conversation not found in trash".to_string());
+    }
+    save_conversations(&data).await
+}
+
+/// Move several conversations to the trash
+#[tauri::command]
+async fn delete_conversations(ids: Vec<String>, state: State<'_, AppState>) -> Result<usize, String> {
+    modify_conversations(&state, |data| Ok(trash::trash(data, &ids))).await
+}
+
+/// Restore several conversations from the trash
+#[tauri::command]
+async fn restore_conversations(ids: Vec<String>, state: State<'_, AppState>) -> Result<usize, String> {
+    modify_conversations(&state, |data| Ok(trash::restore(data, &ids))).await
+}
+
+/// Permanently delete trashed conversations and their attachments
+#[tauri::command]
+async fn purge_conversations(ids: Vec<String>, state: State<'_, AppState>) -> Result<usize, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let mut data = load_conversations().await?;
+    let purged = trash::purge(&mut data, &ids);
+    save_conversations(&data).await?;
+    collect_attachment_garbage(&data).await;
+    Ok(purged)
+}
+
+/// Permanently delete everything in the trash, including attachments
+#[tauri::command]
+async fn empty_trash(state: State<'_, AppState>) -> Result<usize, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let mut data = load_conversations().await?;
+    let purged = trash::empty(&mut data);
+    save_conversations(&data).await?;
+    collect_attachment_garbage(&data).await;
+    Ok(purged)
+}
+
+/// List trashed conversations
+#[tauri::command]
+async fn list_trash(state: State<'_, AppState>) -> Result<Vec<ConversationSummary>, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    Ok(trash::list(&data))
+}
+
+/// Periodic conversation upkeep: take the daily backup, purge expired trash and
+/// remove attachments nothing refers to any more
+async fn conversation_maintenance(app_handle: &AppHandle) -> Result<(), String> {
+    let config = load_config().await?;
+    let state: State<AppState> = app_handle.state();
+    let _lock = state.conversations_lock.lock().await;
+
+    match backups::create_daily_backup(config.conversation_backup_count as usize).await {
+        Ok(Some(backup)) => println!("[Backups] Created {}", backup.name),
+        Ok(None) => {}
+        Err(e) => eprintln!("[Backups] Failed to create daily backup: {}", e),
+    }
+
+    let mut data = load_conversations().await?;
+    let purged = trash::purge_expired(&mut data, config.trash_retention_days);
+    if purged > 0 {
+        save_conversations(&data).await?;
+        println!("[Trash] Purged {} expired conversation(s)", purged);
+    }
+    collect_attachment_garbage(&data).await;
+    Ok(())
+}
+
+/// List conversation backups, newest first
+#[tauri::command]
+async fn list_conversation_backups() -> Result<Vec<BackupInfo>, String> {
+    backups::list_backups().await
+}
+
+/// Replace all conversations with the contents of a backup
+///
+/// The current file is backed up first so the restore can be undone.
+#[tauri::command]
+async fn restore_conversation_backup(name: String, state: State<'_, AppState>) -> Result<ConversationsData, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let mut data = backups::read_backup_for_restore(&name).await?;
+    save_conversations(&data).await?;
+    attachments::inline(&mut data).await?;
+    Ok(data)
+}
+
+/// Details of the last recovery from a corrupt conversations file, if one happened
+#[tauri::command]
+async fn get_conversation_recovery_report() -> Result<Option<RecoveryReport>, String> {
+    Ok(backups::last_recovery())
+}
+
 /// Rename a conversation
 #[tauri::command]
 async fn rename_conversation(id: String, title: String, state: State<'_, AppState>) -> Result<(), String> {
@@ -651,12 +777,695 @@ async fn rename_conversation(id: String, title: String, state: State<'_, AppStat
     let mut data = load_conversations().await?;
     if let Some(conv) = data.conversations.iter_mut().find(|c| c.id == id) {
         conv.title = title;
+        conv.title_source = TitleSource::Manual;
         save_conversations(&data).await
     } else {
         Err("Conversation not found".to_string())
     }
 }
 
+/// Queue a conversation for a generated title, replacing its current title
+#[tauri::command]
+async fn generate_conversation_title(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    modify_conversations(&state, |data| {
+        let conv = data
+            .conversations
+            .iter_mut()
+            .find(|c| c.id == id)
+            .ok_or("Conversation not found")?;
+        conv.title_source = TitleSource::Client;
+        Ok(())
+    })
+    .await?;
+    state.titling.enqueue(&id);
+    Ok(())
+}
+
+/// Title and summary produced for a conversation, sent to the frontend
+#[derive(Clone, serde::Serialize)]
+struct ConversationTitled {
+    id: String,
+    title: String,
+    summary: Option<String>,
+}
+
+/// Generate the title and/or summary a queued conversation still needs
+async fn run_titling_job(app_handle: &AppHandle, limiter: &mut RateLimiter, id: &str) -> Result<(), String> {
+    let state: State<AppState> = app_handle.state();
+    let config = load_running_config(&state).await?;
+    limiter.set_interval(std::time::Duration::from_secs(config.titling_interval_secs as u64));
+
+    let conversation = {
+        let _lock = state.conversations_lock.lock().await;
+        match load_conversations().await?.conversations.into_iter().find(|c| c.id == id) {
+            Some(conversation) => conversation,
+            None => return Ok(()),
+        }
+    };
+    let needs = titling::needs(&conversation, &config);
+    if !needs.any() {
+        return Ok(());
+    }
+    let model = config
+        .titling_model
+        .clone()
+        .or_else(|| conversation.model.clone())
+        .ok_or("No model configured for titling")?;
+    let gateway = Gateway::from_config(&config);
+    let client = &tls::local_client(&config).await?;
+
+    let generated = titling::generate(&gateway, client, limiter, &model, &conversation, needs).await?;
+    if generated == titling::Generated::default() {
+        return Ok(());
+    }
+    let summarized_count = branches::active_path(&conversation).len();
+
+    let _lock = state.conversations_lock.lock().await;
+    let mut data = load_conversations().await?;
+    let conv = match data.conversations.iter_mut().find(|c| c.id == id) {
+        Some(conv) => conv,
+        None => return Ok(()),
+    };
+    titling::apply(conv, generated, summarized_count);
+    let payload = ConversationTitled {
+        id: conv.id.clone(),
+        title: conv.title.clone(),
+        summary: conv.summary.clone(),
+    };
+    save_conversations(&data).await?;
+
+    if let Err(e) = app_handle.emit_all("conversation-titled", payload) {
+        eprintln!("[Titling] Failed to notify frontend: {}", e);
+    }
+    Ok(())
+}
+
+/// Background task working through the titling queue once the gateway is running
+async fn run_titling(app_handle: AppHandle, mut receiver: tokio::sync::mpsc::UnboundedReceiver<String>) {
+    let mut limiter = RateLimiter::new(std::time::Duration::from_secs(10));
+
+    while let Some(id) = receiver.recv().await {
+        let state: State<AppState> = app_handle.state();
+        // Jobs wait in the queue while the gateway is stopped or starting
+        loop {
+            let running = state
+                .server_manager
+                .try_lock()
+                .map(|manager| manager.get_status().status == "running")
+                .unwrap_or(false);
+            if running {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        }
+
+        state.titling.finish(&id);
+        if let Err(e) = run_titling_job(&app_handle, &mut limiter, &id).await {
+            eprintln!("[Titling] Failed to title {}: {}", id, e);
+        }
+    }
+}
+
+/// Record the gateway's credit usage every 15 minutes while it runs and
+/// write the usage history every 5 minutes
+async fn run_usage_history(app_handle: AppHandle) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(5 * 60));
+    let mut ticks: u64 = 0;
+    loop {
+        interval.tick().await;
+        let state: State<AppState> = app_handle.state();
+
+        if ticks % 3 == 0 {
+            let port = state
+                .server_manager
+                .try_lock()
+                .ok()
+                .and_then(|manager| manager.running_port());
+            if let Some(port) = port {
+                if let Ok(mut config) = load_config().await {
+                    config.server_port = port;
+                    let fetched = match tls::local_client(&config).await {
+                        Ok(client) => usage_history::fetch_credits(&client, &Gateway::from_config(&config)).await,
+                        Err(e) => Err(e),
+                    };
+                    match fetched {
+                        Ok(sample) => state.proxy.history.add_credits(sample),
+                        Err(e) => eprintln!("[Usage] Failed to record credits: {}", e),
+                    }
+                }
+            }
+        }
+        ticks += 1;
+
+        if let Err(e) = state.proxy.history.save().await {
+            eprintln!("[Usage] {}", e);
+        }
+    }
+}
+
+/// Apply a branch operation to a stored conversation and return the updated conversation
+async fn modify_branches<F>(id: &str, state: &State<'_, AppState>, op: F) -> Result<Conversation, String>
+where
+    F: FnOnce(&mut Conversation) -> Result<(), String>,
+{
+    let _lock = state.conversations_lock.lock().await;
+    let mut data = load_conversations().await?;
+    let conv = data
+        .conversations
+        .iter_mut()
+        .find(|c| c.id == id)
+        .ok_or("Conversation not found")?;
+    op(conv)?;
+    let mut updated = conv.clone();
+    save_conversations(&data).await?;
+    attachments::inline_conversation(&mut updated).await?;
+    Ok(updated)
+}
+
+/// Fork a conversation so the next message becomes a sibling of `message_id`
+#[tauri::command]
+async fn fork_conversation(id: String, message_id: String, state: State<'_, AppState>) -> Result<Conversation, String> {
+    modify_branches(&id, &state, |conv| branches::fork_at(conv, &message_id)).await
+}
+
+/// Switch the active branch to the one containing `message_id`
+#[tauri::command]
+async fn switch_conversation_branch(id: String, message_id: String, state: State<'_, AppState>) -> Result<Conversation, String> {
+    modify_branches(&id, &state, |conv| branches::switch_to(conv, &message_id)).await
+}
+
+/// List all branches of a conversation
+#[tauri::command]
+async fn list_conversation_branches(id: String, state: State<'_, AppState>) -> Result<Vec<BranchInfo>, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    let conv = data
+        .conversations
+        .iter()
+        .find(|c| c.id == id)
+        .ok_or("Conversation not found")?;
+    Ok(branches::list_branches(conv))
+}
+
+/// List the ids of alternative versions of a message (same parent), oldest first
+#[tauri::command]
+async fn get_message_siblings(id: String, message_id: String, state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    let conv = data
+        .conversations
+        .iter()
+        .find(|c| c.id == id)
+        .ok_or("Conversation not found")?;
+    branches::siblings(conv, &message_id)
+}
+
+/// Apply a change to the stored conversations under the conversations lock and save
+async fn modify_conversations<T, F>(state: &State<'_, AppState>, op: F) -> Result<T, String>
+where
+    F: FnOnce(&mut ConversationsData) -> Result<T, String>,
+{
+    let _lock = state.conversations_lock.lock().await;
+    let mut data = load_conversations().await?;
+    let result = op(&mut data)?;
+    save_conversations(&data).await?;
+    Ok(result)
+}
+
+/// List conversations matching a filter (pinned first, archived hidden by default)
+#[tauri::command]
+async fn list_conversations(filter: Option<ConversationFilter>, state: State<'_, AppState>) -> Result<Vec<ConversationSummary>, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    Ok(organization::list(&data, &filter.unwrap_or_default()))
+}
+
+/// Create a conversation folder
+#[tauri::command]
+async fn create_folder(name: String, parent_id: Option<String>, state: State<'_, AppState>) -> Result<Folder, String> {
+    modify_conversations(&state, |data| organization::create_folder(data, name, parent_id)).await
+}
+
+/// Rename a conversation folder
+#[tauri::command]
+async fn rename_folder(id: String, name: String, state: State<'_, AppState>) -> Result<(), String> {
+    modify_conversations(&state, |data| organization::rename_folder(data, &id, name)).await
+}
+
+/// Move a folder under another folder, or to the top level
+#[tauri::command]
+async fn move_folder(id: String, parent_id: Option<String>, state: State<'_, AppState>) -> Result<(), String> {
+    modify_conversations(&state, |data| organization::move_folder(data, &id, parent_id)).await
+}
+
+/// Delete a folder, moving its contents to the parent folder
+#[tauri::command]
+async fn delete_folder(id: String, state: State<'_, AppState>) -> Result<(), String> {
+    modify_conversations(&state, |data| organization::delete_folder(data, &id)).await
+}
+
+/// Move a conversation into a folder
+#[tauri::command]
+async fn set_conversation_folder(id: String, folder_id: Option<String>, state: State<'_, AppState>) -> Result<(), String> {
+    modify_conversations(&state, |data| organization::set_folder(data, &id, folder_id)).await
+}
+
+/// Replace the tags of a conversation
+#[tauri::command]
+async fn set_conversation_tags(id: String, tags: Vec<String>, state: State<'_, AppState>) -> Result<(), String> {
+    modify_conversations(&state, |data| organization::set_tags(data, &id, tags)).await
+}
+
+/// Pin or unpin a conversation
+#[tauri::command]
+async fn set_conversation_pinned(id: String, pinned: bool, state: State<'_, AppState>) -> Result<(), String> {
+    modify_conversations(&state, |data| organization::set_pinned(data, &id, pinned)).await
+}
+
+/// Archive or unarchive a conversation
+#[tauri::command]
+async fn set_conversation_archived(id: String, archived: bool, state: State<'_, AppState>) -> Result<(), String> {
+    modify_conversations(&state, |data| organization::set_archived(data, &id, archived)).await
+}
+
+/// List all tags in use
+#[tauri::command]
+async fn list_conversation_tags(state: State<'_, AppState>) -> Result<Vec<TagCount>, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    Ok(organization::list_tags(&data))
+}
+
+/// Import conversations from ChatGPT or Claude.ai `conversations.json` exports
+#[tauri::command]
+async fn import_conversations(paths: Vec<String>, state: State<'_, AppState>) -> Result<ImportReport, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let mut data = load_conversations().await?;
+    let report = importer::import_files(&paths, &mut data).await;
+    if report.total_imported > 0 {
+        save_conversations(&data).await?;
+    }
+    Ok(report)
+}
+
+/// Token usage and cost of a single conversation
+#[tauri::command]
+async fn get_conversation_usage(id: String, state: State<'_, AppState>) -> Result<UsageTotals, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    data.conversations
+        .iter()
+        .find(|c| c.id == id)
+        .map(|c| c.usage_totals.clone())
+        .ok_or_else(|| "Conversation not found".to_string())
+}
+
+/// Token usage and cost per model, across all conversations
+#[tauri::command]
+async fn get_usage_by_model(state: State<'_, AppState>) -> Result<Vec<ModelUsage>, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    Ok(usage::by_model(&data.usage))
+}
+
+/// Token usage and cost per day (`YYYY-MM-DD`, inclusive range)
+#[tauri::command]
+async fn get_usage_by_day(
+    from: Option<String>,
+    to: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<DailyUsage>, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    Ok(usage::by_day(&data.usage, from.as_deref(), to.as_deref()))
+}
+
+/// Sync conversations with the configured shared folder
+async fn sync_conversations(app_handle: &AppHandle) -> Result<SyncReport, String> {
+    let config = load_config().await?;
+    let folder = config
+        .sync_folder
+        .filter(|f| !f.trim().is_empty())
+        .ok_or("No sync folder is configured")?;
+
+    let state: State<AppState> = app_handle.state();
+    let _lock = state.conversations_lock.lock().await;
+    // Sync files are plaintext; they must not leak an encrypted store
+    if current_encryption_status().await?.enabled {
+        return Err("Sync is not available while conversations are encrypted at rest".to_string());
+    }
+
+    let mut data = load_conversations().await?;
+    let outcome = sync::sync(std::path::Path::new(&folder), &mut data).await?;
+    if outcome.report.changed_local() {
+        save_conversations(&data).await?;
+    }
+    let report = outcome.commit().await?;
+
+    if report.changed_local() {
+        if let Err(e) = app_handle.emit_all("conversations-synced", report.clone()) {
+            eprintln!("[Sync] Failed to notify frontend: {}", e);
+        }
+    }
+    Ok(report)
+}
+
+/// Sync conversations with the shared folder now
+#[tauri::command]
+async fn sync_conversations_now(app: AppHandle) -> Result<SyncReport, String> {
+    sync_conversations(&app).await
+}
+
+/// Conflicts resolved by past syncs, most recent last
+#[tauri::command]
+async fn get_sync_conflicts() -> Result<Vec<SyncConflict>, String> {
+    sync::conflicts().await
+}
+
+/// Clear the sync conflict report
+#[tauri::command]
+async fn clear_sync_conflicts() -> Result<(), String> {
+    sync::clear_conflicts().await
+}
+
+/// Requests recorded by the proxy, newest first
+#[tauri::command]
+async fn query_request_log(
+    state: State<'_, AppState>,
+    filter: Option<RequestLogFilter>,
+) -> Result<Vec<RequestRecord>, String> {
+    Ok(state.proxy.log.query(&filter.unwrap_or_default()))
+}
+
+/// Delete all recorded proxy requests
+#[tauri::command]
+async fn clear_request_log(state: State<'_, AppState>) -> Result<(), String> {
+    state.proxy.log.clear()
+}
+
+/// Credits, requests and tokens over time in hour, day or week buckets
+#[tauri::command]
+async fn get_usage_series(
+    state: State<'_, AppState>,
+    bucket: SeriesBucket,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<Vec<UsagePoint>, String> {
+    Ok(state.proxy.history.series(bucket, from, to))
+}
+
+/// Write a usage series to a CSV file, returning the number of rows
+#[tauri::command]
+async fn export_usage_csv(
+    state: State<'_, AppState>,
+    path: String,
+    bucket: SeriesBucket,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<usize, String> {
+    let points = state.proxy.history.series(bucket, from, to);
+    tokio::fs::write(&path, usage_history::to_csv(&points))
+        .await
+        .map_err(|e| format!("Failed to write CSV: {}", e))?;
+    Ok(points.len())
+}
+
+/// Rate limits, quotas and their consumption: global first, then per client key
+#[tauri::command]
+async fn get_quota_status(state: State<'_, AppState>) -> Result<Vec<LimitStatus>, String> {
+    Ok(state.proxy.limiter.status())
+}
+
+/// The model routing rule a model name resolves to, from `routes` if given
+/// (to preview unsaved edits) or else the saved config
+#[tauri::command]
+async fn resolve_model_route(model: String, routes: Option<Vec<ModelRoute>>) -> Result<Option<RouteMatch>, String> {
+    let routes = match routes {
+        Some(routes) => routes,
+        None => load_config().await?.model_routes,
+    };
+    Ok(model_routes::resolve(&routes, model.trim()))
+}
+
+/// Routing state of every account behind the proxy
+#[tauri::command]
+async fn get_account_pool_status(state: State<'_, AppState>) -> Result<Vec<UpstreamStatus>, String> {
+    Ok(state.proxy.pool.status())
+}
+
+/// Size, settings and hit rate of the proxy's response cache
+#[tauri::command]
+async fn get_response_cache_stats(state: State<'_, AppState>) -> Result<CacheStats, String> {
+    Ok(state.proxy.cache.stats())
+}
+
+/// Responses in the proxy's response cache, newest first
+#[tauri::command]
+async fn list_response_cache(state: State<'_, AppState>) -> Result<Vec<CacheEntryInfo>, String> {
+    Ok(state.proxy.cache.entries())
+}
+
+/// Remove one cached response by key, or all of them; returns how many were removed
+#[tauri::command]
+async fn purge_response_cache(key: Option<String>, state: State<'_, AppState>) -> Result<usize, String> {
+    Ok(state.proxy.cache.purge(key.as_deref()))
+}
+
+/// Debug captures of proxied requests, newest first
+#[tauri::command]
+async fn list_captures(state: State<'_, AppState>) -> Result<Vec<CaptureSummary>, String> {
+    state.proxy.captures.list().await
+}
+
+/// A debug capture with its request and response
+#[tauri::command]
+async fn get_capture(id: String, state: State<'_, AppState>) -> Result<Capture, String> {
+    state.proxy.captures.get(&id).await
+}
+
+/// Delete one debug capture by id, or all of them; returns how many were deleted
+#[tauri::command]
+async fn delete_captures(id: Option<String>, state: State<'_, AppState>) -> Result<usize, String> {
+    match id {
+        Some(id) => Ok(state.proxy.captures.delete(&id).await? as usize),
+        None => state.proxy.captures.clear().await,
+    }
+}
+
+/// Send a captured request to the running gateway again and return its response
+#[tauri::command]
+async fn replay_capture(id: String, state: State<'_, AppState>) -> Result<ReplayResult, String> {
+    if state.server_manager.lock().await.running_port().is_none() {
+        return Err("The gateway is not running".to_string());
+    }
+    let capture = state.proxy.captures.get(&id).await?;
+    let config = load_running_config(&state).await?;
+    let client = tls::local_client(&config).await?;
+    captures::replay(&client, &Gateway::from_config(&config), &capture).await
+}
+
+/// HTTPS settings and the local CA certificate clients need to trust
+#[tauri::command]
+async fn get_tls_status() -> Result<TlsStatus, String> {
+    let config = load_config().await?;
+    tls::status(&config).await
+}
+
+/// Write the local CA certificate (PEM) to `path`, creating the CA if needed
+#[tauri::command]
+async fn export_tls_ca_certificate(path: String) -> Result<(), String> {
+    let pem = tls::ca_certificate().await?;
+    tokio::fs::write(&path, pem)
+        .await
+        .map_err(|e| format!("Failed to write CA certificate: {}", e))
+}
+
+/// Replace the local CA; takes effect when the server is restarted, and
+/// clients must trust the new certificate
+#[tauri::command]
+async fn regenerate_tls_ca() -> Result<TlsStatus, String> {
+    tls::regenerate_local_ca().await?;
+    let config = load_config().await?;
+    tls::status(&config).await
+}
+
+/// Whether `config` (or the saved configuration) exposes the gateway to other
+/// machines, and what starting it that way still needs
+#[tauri::command]
+async fn get_lan_exposure(config: Option<AppConfig>) -> Result<LanExposure, String> {
+    let config = match config {
+        Some(config) => config,
+        None => load_config().await?,
+    };
+    Ok(access::exposure(&config))
+}
+
+/// Client API keys accepted by the request proxy
+#[tauri::command]
+async fn list_api_keys(state: State<'_, AppState>) -> Result<Vec<ApiKey>, String> {
+    Ok(state.proxy.keys.list())
+}
+
+/// Create a client API key; its secret is returned only this once
+#[tauri::command]
+async fn create_api_key(
+    state: State<'_, AppState>,
+    label: String,
+    scopes: Vec<ApiScope>,
+    allowed_models: Option<Vec<String>>,
+    expires_at: Option<u64>,
+) -> Result<CreatedApiKey, String> {
+    state
+        .proxy
+        .keys
+        .create(label, scopes, allowed_models.unwrap_or_default(), expires_at)
+        .await
+}
+
+/// Revoke a client API key
+#[tauri::command]
+async fn revoke_api_key(state: State<'_, AppState>, id: String) -> Result<(), String> {
+    state.proxy.keys.revoke(&id).await
+}
+
+/// Delete a client API key
+#[tauri::command]
+async fn delete_api_key(state: State<'_, AppState>, id: String) -> Result<(), String> {
+    state.proxy.keys.delete(&id).await
+}
+
+/// Current encryption state of the conversation store
+async fn current_encryption_status() -> Result<EncryptionStatus, String> {
+    let bytes = conversations::read_store_bytes().await?;
+    Ok(encryption::status(bytes.as_deref()))
+}
+
+/// Create a new conversation encryption key from the requested source
+fn new_store_key(key_source: KeySource, passphrase: Option<&str>) -> Result<StoreKey, String> {
+    match key_source {
+        KeySource::Keyring => {
+            let key = StoreKey::new_keyring();
+            key.save_to_keyring()?;
+            Ok(key)
+        }
+        KeySource::Passphrase => StoreKey::new_passphrase(passphrase.ok_or("A passphrase is required")?),
+    }
+}
+
+/// Get the encryption state of the conversation store
+#[tauri::command]
+async fn get_encryption_status(state: State<'_, AppState>) -> Result<EncryptionStatus, String> {
+    let _lock = state.conversations_lock.lock().await;
+    current_encryption_status().await
+}
+
+/// Encrypt the conversation store (with its backups and attachments) at rest
+#[tauri::command]
+async fn enable_conversation_encryption(
+    key_source: KeySource,
+    passphrase: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<EncryptionStatus, String> {
+    let _lock = state.conversations_lock.lock().await;
+    if current_encryption_status().await?.enabled {
+        return Err("Conversation encryption is already enabled".to_string());
+    }
+
+    let data = load_conversations().await?;
+    let key = new_store_key(key_source, passphrase.as_deref())?;
+    encryption::set_active_key(Some(key.clone()));
+    if let Err(e) = conversations::write_conversations(&data).await {
+        encryption::set_active_key(None);
+        return Err(e);
+    }
+
+    let mut failed = backups::rewrite_backups(None, Some(&key)).await?;
+    failed.extend(attachments::rewrite_blobs(None, Some(&key)).await?);
+    if !failed.is_empty() {
+        eprintln!("[Encryption] Could not encrypt backups or attachments: {:?}", failed);
+    }
+    let mut status = current_encryption_status().await?;
+    status.failed_files = failed;
+    Ok(status)
+}
+
+/// Decrypt the conversation store (with its backups and attachments) back to plaintext
+#[tauri::command]
+async fn disable_conversation_encryption(state: State<'_, AppState>) -> Result<EncryptionStatus, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    let old = encryption::active_key().ok_or("Conversation encryption is not enabled")?;
+
+    encryption::set_active_key(None);
+    if let Err(e) = conversations::write_conversations(&data).await {
+        encryption::set_active_key(Some(old));
+        return Err(e);
+    }
+
+    let mut failed = backups::rewrite_backups(Some(&old), None).await?;
+    failed.extend(attachments::rewrite_blobs(Some(&old), None).await?);
+    if !failed.is_empty() {
+        eprintln!("[Encryption] Could not decrypt backups or attachments, keeping the key: {:?}", failed);
+    }
+    if let Some(include_current) = encryption::retired_keyring_keys(old.source(), None, &failed) {
+        encryption::delete_keyring_keys(include_current);
+    }
+    let mut status = current_encryption_status().await?;
+    status.failed_files = failed;
+    Ok(status)
+}
+
+/// Re-encrypt the conversation store, backups and attachments with a new key
+#[tauri::command]
+async fn rotate_conversation_key(
+    key_source: KeySource,
+    passphrase: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<EncryptionStatus, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let data = load_conversations().await?;
+    let old = encryption::active_key().ok_or("Conversation encryption is not enabled")?;
+
+    let new = new_store_key(key_source, passphrase.as_deref())?;
+    encryption::set_active_key(Some(new.clone()));
+    if let Err(e) = conversations::write_conversations(&data).await {
+        encryption::set_active_key(Some(old));
+        return Err(e);
+    }
+
+    let mut failed = backups::rewrite_backups(Some(&old), Some(&new)).await?;
+    failed.extend(attachments::rewrite_blobs(Some(&old), Some(&new)).await?);
+    if !failed.is_empty() {
+        eprintln!("[Encryption] Could not re-encrypt backups or attachments, keeping the old key: {:?}", failed);
+    }
+    // The old keyring key is no longer needed once everything uses the new key
+    if let Some(include_current) = encryption::retired_keyring_keys(old.source(), Some(new.source()), &failed) {
+        encryption::delete_keyring_keys(include_current);
+    }
+    let mut status = current_encryption_status().await?;
+    status.failed_files = failed;
+    Ok(status)
+}
+
+/// Unlock the encrypted conversation store
+#[tauri::command]
+async fn unlock_conversations(passphrase: Option<String>, state: State<'_, AppState>) -> Result<EncryptionStatus, String> {
+    let _lock = state.conversations_lock.lock().await;
+    let bytes = conversations::read_store_bytes()
+        .await?
+        .ok_or("No conversations are stored yet")?;
+    encryption::unlock(&bytes, passphrase.as_deref())?;
+    current_encryption_status().await
+}
+
+/// Forget the conversation key until the store is unlocked again
+#[tauri::command]
+async fn lock_conversations(state: State<'_, AppState>) -> Result<EncryptionStatus, String> {
+    let _lock = state.conversations_lock.lock().await;
+    encryption::lock();
+    current_encryption_status().await
+}
+
 /// Update tray menu labels for i18n
 #[tauri::command]
 async fn update_tray_language(
@@ -686,6 +1495,28 @@ async fn update_tray_usage(app: AppHandle, text: String) -> Result<(), String> {
     Ok(())
 }
 
+/// Compact token count for the tray, e.g. 12.3k or 1.5M
+fn format_tokens(tokens: u64) -> String {
+    match tokens {
+        t if t >= 1_000_000 => format!("{:.1}M", t as f64 / 1_000_000.0),
+        t if t >= 1_000 => format!("{:.1}k", t as f64 / 1_000.0),
+        t => t.to_string(),
+    }
+}
+
+/// Tray text for the global quota: the tightest configured quota, or today's tokens
+fn quota_tray_text(status: &LimitStatus) -> String {
+    match (status.limits.daily_tokens, status.limits.monthly_tokens) {
+        (Some(max), _) => format!("Quota: {} / {} today", format_tokens(status.daily_tokens), format_tokens(max)),
+        (None, Some(max)) => format!(
+            "Quota: {} / {} this month",
+            format_tokens(status.monthly_tokens),
+            format_tokens(max)
+        ),
+        (None, None) => format!("Tokens today: {}", format_tokens(status.daily_tokens)),
+    }
+}
+
 /// Update tray menu items based on server running state
 #[tauri::command]
 async fn update_tray_server_state(app: AppHandle, running: bool) -> Result<(), String> {
@@ -698,6 +1529,7 @@ async fn update_tray_server_state(app: AppHandle, running: bool) -> Result<(), S
 
 fn main() {
     let credit_usage_item = CustomMenuItem::new("credit_usage".to_string(), "Credit: --").disabled();
+    let quota_usage_item = CustomMenuItem::new("quota_usage".to_string(), "Quota: --").disabled();
     let start_server_item = CustomMenuItem::new("start_server".to_string(), "启动服务器");
     let stop_server_item = CustomMenuItem::new("stop_server".to_string(), "停止服务器").disabled();
     let restart_server_item = CustomMenuItem::new("restart_server".to_string(), "重启服务器").disabled();
@@ -706,6 +1538,7 @@ fn main() {
     let quit = CustomMenuItem::new("quit".to_string(), "退出");
     let tray_menu = SystemTrayMenu::new()
         .add_item(credit_usage_item)
+        .add_item(quota_usage_item)
         .add_native_item(tauri::SystemTrayMenuItem::Separator)
         .add_item(start_server_item)
         .add_item(stop_server_item)
@@ -716,6 +1549,8 @@ fn main() {
         .add_native_item(tauri::SystemTrayMenuItem::Separator)
         .add_item(quit);
     let system_tray = SystemTray::new().with_menu(tray_menu);
+    let (titling_queue, titling_receiver) = TitlingQueue::new();
+    let proxy_services = ProxyServices::open();
 
     tauri::Builder::default()
         .system_tray(system_tray)
@@ -773,11 +1608,8 @@ fn main() {
                         };
                         let state: State<AppState> = app_handle.state();
                         let mut manager = state.server_manager.lock().await;
-                        if let Err(e) = manager.stop().await {
-                            eprintln!("[Tray] Failed to stop server: {}", e);
-                        }
-                        if let Err(e) = manager.start(config).await {
-                            eprintln!("[Tray] Failed to start server: {}", e);
+                        if let Err(e) = manager.restart(config).await {
+                            eprintln!("[Tray] Failed to restart server: {}", e);
                         }
                     });
                 }
@@ -806,13 +1638,89 @@ fn main() {
             _ => {}
         })
         .manage(AppState {
-            server_manager: Mutex::new(ServerManager::new()),
+            server_manager: Mutex::new(ServerManager::new(proxy_services.clone())),
             conversations_lock: Mutex::new(()),
+            titling: titling_queue,
+            proxy: proxy_services,
+            metrics_server: Mutex::new(None),
         })
-        .setup(|app| {
+        .setup(move |app| {
             #[cfg(target_os = "macos")]
             macos_dock::setup_dock_click_handler(app.handle());
 
+            // Report the steps of starting the server to the UI
+            let progress_handle = app.handle();
+            let progress_state: State<AppState> = app.state();
+            if let Ok(mut manager) = progress_state.server_manager.try_lock() {
+                manager.on_progress(std::sync::Arc::new(move |progress| {
+                    let _ = progress_handle.emit_all("server-start-progress", progress);
+                }));
+            }
+
+            tauri::async_runtime::spawn(run_titling(app.handle(), titling_receiver));
+            tauri::async_runtime::spawn(run_usage_history(app.handle()));
+
+            // Sync with the shared folder, if one is configured, every `sync_interval_secs`
+            let sync_handle = app.handle();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+                let mut last_sync: Option<std::time::Instant> = None;
+                loop {
+                    interval.tick().await;
+                    let config = match load_config().await {
+                        Ok(config) => config,
+                        Err(_) => continue,
+                    };
+                    if config.sync_folder.as_deref().map_or(true, |f| f.trim().is_empty()) {
+                        continue;
+                    }
+                    let due = last_sync.map_or(true, |t| t.elapsed().as_secs() >= config.sync_interval_secs as u64);
+                    if !due {
+                        continue;
+                    }
+                    last_sync = Some(std::time::Instant::now());
+                    match sync_conversations(&sync_handle).await {
+                        Ok(report) if report.pushed + report.pulled + report.deleted > 0 => println!(
+                            "[Sync] Pushed {}, pulled {}, deleted {}, conflicts {}",
+                            report.pushed,
+                            report.pulled,
+                            report.deleted,
+                            report.conflicts.len()
+                        ),
+                        Ok(_) => {}
+                        Err(e) => eprintln!("[Sync] Failed: {}", e),
+                    }
+                }
+            });
+
+            let probe_state: State<AppState> = app.state();
+            tauri::async_runtime::spawn(metrics::run_probe(probe_state.proxy.metrics.clone()));
+
+            // Keep the tray's quota line current
+            let quota_handle = app.handle();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+                loop {
+                    interval.tick().await;
+                    let state: State<AppState> = quota_handle.state();
+                    if let Some(global) = state.proxy.limiter.status().first() {
+                        let _ = quota_handle.tray_handle().get_item("quota_usage").set_title(quota_tray_text(global));
+                    }
+                }
+            });
+
+            // Back up conversations and purge expired trash on startup and then hourly
+            let maintenance_handle = app.handle();
+            tauri::async_runtime::spawn(async move {
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = conversation_maintenance(&maintenance_handle).await {
+                        eprintln!("[Conversations] Maintenance failed: {}", e);
+                    }
+                }
+            });
+
             let app_handle = app.handle();
             tauri::async_runtime::spawn(async move {
                 match load_config().await {
@@ -821,6 +1729,12 @@ fn main() {
                             eprintln!("[Setup] Auto-launch apply failed: {}", e);
                         }
 
+                        let state: State<AppState> = app_handle.state();
+                        state.proxy.configure(&config);
+                        if let Err(e) = apply_metrics_listen(&state, &config).await {
+                            eprintln!("[Setup] {}", e);
+                        }
+
                         if config.auto_start_server {
                             if !has_credentials(&config) {
                                 eprintln!("[Setup] Auto-start server skipped: missing credentials");
@@ -843,6 +1757,7 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             start_server,
             stop_server,
+            restart_server,
             get_server_status,
             get_server_logs,
             clear_server_logs,
@@ -862,7 +1777,67 @@ fn main() {
             create_conversation,
             update_conversation,
             delete_conversation,
+            restore_conversation,
+            delete_conversations,
+            restore_conversations,
+            purge_conversations,
+            empty_trash,
+            list_trash,
             rename_conversation,
+            generate_conversation_title,
+            fork_conversation,
+            switch_conversation_branch,
+            list_conversation_branches,
+            get_message_siblings,
+            list_conversations,
+            create_folder,
+            rename_folder,
+            move_folder,
+            delete_folder,
+            set_conversation_folder,
+            set_conversation_tags,
+            set_conversation_pinned,
+            set_conversation_archived,
+            list_conversation_tags,
+            import_conversations,
+            list_conversation_backups,
+            restore_conversation_backup,
+            get_conversation_recovery_report,
+            get_attachment,
+            get_conversation_usage,
+            get_usage_by_model,
+            get_usage_by_day,
+            sync_conversations_now,
+            get_sync_conflicts,
+            clear_sync_conflicts,
+            query_request_log,
+            clear_request_log,
+            get_quota_status,
+            get_account_pool_status,
+            resolve_model_route,
+            get_response_cache_stats,
+            list_response_cache,
+            purge_response_cache,
+            list_captures,
+            get_capture,
+            delete_captures,
+            replay_capture,
+            get_tls_status,
+            export_tls_ca_certificate,
+            regenerate_tls_ca,
+            get_lan_exposure,
+            get_usage_series,
+            export_usage_csv,
+            list_api_keys,
+            create_api_key,
+            revoke_api_key,
+            delete_api_key,
+            get_encryption_status,
+            enable_conversation_encryption,
+            disable_conversation_encryption,
+            rotate_conversation_key,
+            unlock_conversations,
+            lock_conversations,
             update_tray_language,
             update_tray_usage,
             update_tray_server_state,
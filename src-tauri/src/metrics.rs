@@ -0,0 +1,300 @@
+use crate::proxy::ListenerHandle;
+use crate::request_log::RequestRecord;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Upper bounds (seconds) of the latency histogram buckets
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+/// Endpoints reported by name; anything else is reported as `other`
+const KNOWN_ENDPOINTS: &[&str] = &[
+    "/",
+    "/health",
+    "/usage",
+    "/v1/models",
+    "/v1/chat/completions",
+    "/v1/messages",
+    "/v1/messages/count_tokens",
+];
+const PROBE_INTERVAL: Duration = Duration::from_secs(15);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default, Clone)]
+struct Histogram {
+    /// Cumulative count per bucket of `LATENCY_BUCKETS`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct GatewayState {
+    pid: Option<u32>,
+    started_at: Option<Instant>,
+    starts: u64,
+    health_url: Option<String>,
+    /// Success and duration of the last health probe
+    probe: Option<(bool, f64)>,
+}
+
+#[derive(Default)]
+struct MetricsState {
+    gateway: GatewayState,
+    /// Requests by (endpoint, status)
+    requests: BTreeMap<(String, u16), u64>,
+    /// Requests that failed in the proxy or were cut off, by endpoint
+    errors: BTreeMap<String, u64>,
+    latency: BTreeMap<String, Histogram>,
+    ttfb: BTreeMap<String, Histogram>,
+    /// Tokens by (model, prompt|completion)
+    tokens: BTreeMap<(String, &'static str), u64>,
+}
+
+/// Metrics of the managed gateway and of the requests through the proxy
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+fn endpoint_label(endpoint: &str) -> String {
+    if KNOWN_ENDPOINTS.contains(&endpoint) {
+        endpoint.to_string()
+    } else {
+        "other".to_string()
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_histograms(out: &mut String, name: &str, help: &str, histograms: &BTreeMap<String, Histogram>) {
+    header(out, name, "histogram", help);
+    for (endpoint, histogram) in histograms {
+        let endpoint = escape_label(endpoint);
+        for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(out, "{}_bucket{{endpoint=\"{}\",le=\"{}\"}} {}", name, endpoint, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{endpoint=\"{}\",le=\"+Inf\"}} {}", name, endpoint, histogram.count);
+        let _ = writeln!(out, "{}_sum{{endpoint=\"{}\"}} {}", name, endpoint, histogram.sum);
+        let _ = writeln!(out, "{}_count{{endpoint=\"{}\"}} {}", name, endpoint, histogram.count);
+    }
+}
+
+/// Resident memory and CPU seconds of a process
+#[cfg(target_os = "linux")]
+fn process_stats(pid: u32) -> Option<(u64, f64)> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // Fields after the command name, which is in parentheses and may contain spaces
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: f64 = fields.get(11)?.parse().ok()?;
+    let stime: f64 = fields.get(12)?.parse().ok()?;
+    let rss_pages: u64 = fields.get(21)?.parse().ok()?;
+    let (ticks, page_size) = unsafe { (libc::sysconf(libc::_SC_CLK_TCK), libc::sysconf(libc::_SC_PAGESIZE)) };
+    if ticks <= 0 || page_size <= 0 {
+        return None;
+    }
+    Some((rss_pages * page_size as u64, (utime + stime) / ticks as f64))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_stats(_pid: u32) -> Option<(u64, f64)> {
+    None
+}
+
+impl Metrics {
+    fn lock(&self) -> std::sync::MutexGuard<'_, MetricsState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The gateway process started and answers on `health_url`
+    pub fn gateway_started(&self, pid: u32, health_url: String) {
+        let mut state = self.lock();
+        state.gateway.pid = Some(pid);
+        state.gateway.started_at = Some(Instant::now());
+        state.gateway.starts += 1;
+        state.gateway.health_url = Some(health_url);
+        state.gateway.probe = Some((true, 0.0));
+    }
+
+    /// The gateway process was stopped
+    pub fn gateway_stopped(&self) {
+        let mut state = self.lock();
+        state.gateway.pid = None;
+        state.gateway.started_at = None;
+        state.gateway.health_url = None;
+        state.gateway.probe = None;
+    }
+
+    /// Count a request that went through the proxy
+    pub fn observe_request(&self, record: &RequestRecord) {
+        let endpoint = endpoint_label(&record.endpoint);
+        let mut state = self.lock();
+        *state.requests.entry((endpoint.clone(), record.status)).or_insert(0) += 1;
+        if record.error.is_some() {
+            *state.errors.entry(endpoint.clone()).or_insert(0) += 1;
+        }
+        state
+            .latency
+            .entry(endpoint.clone())
+            .or_default()
+            .observe(record.latency_ms as f64 / 1000.0);
+        if let Some(ttfb) = record.ttfb_ms {
+            state.ttfb.entry(endpoint).or_default().observe(ttfb as f64 / 1000.0);
+        }
+        let model = record.model.clone().unwrap_or_else(|| "unknown".to_string());
+        for (kind, tokens) in [("prompt", record.prompt_tokens), ("completion", record.completion_tokens)] {
+            if let Some(tokens) = tokens.filter(|t| *t > 0) {
+                *state.tokens.entry((model.clone(), kind)).or_insert(0) += tokens;
+            }
+        }
+    }
+
+    /// Probe the gateway's health endpoint once, if it is running
+    pub async fn probe(&self, client: &reqwest::Client) {
+        let url = match self.lock().gateway.health_url.clone() {
+            Some(url) => url,
+            None => return,
+        };
+        let started = Instant::now();
+        let ok = matches!(
+            client.get(&url).timeout(PROBE_TIMEOUT).send().await,
+            Ok(response) if response.status().is_success()
+        );
+        let mut state = self.lock();
+        // The gateway may have been stopped while the probe ran
+        if state.gateway.health_url.as_deref() == Some(url.as_str()) {
+            state.gateway.probe = Some((ok, started.elapsed().as_secs_f64()));
+        }
+    }
+
+    /// Render all metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let state = self.lock();
+        let gateway = &state.gateway;
+        let mut out = String::new();
+
+        let up = gateway.pid.is_some() && gateway.probe.is_some_and(|(ok, _)| ok);
+        header(&mut out, "kiro_gateway_up", "gauge", "Whether the managed gateway is running and healthy");
+        let _ = writeln!(out, "kiro_gateway_up {}", up as u8);
+        header(&mut out, "kiro_gateway_restarts_total", "counter", "Gateway starts after the first since the app launched");
+        let _ = writeln!(out, "kiro_gateway_restarts_total {}", gateway.starts.saturating_sub(1));
+        header(&mut out, "kiro_gateway_uptime_seconds", "gauge", "Seconds since the gateway was started");
+        let uptime = gateway.started_at.map(|t| t.elapsed().as_secs_f64()).unwrap_or(0.0);
+        let _ = writeln!(out, "kiro_gateway_uptime_seconds {}", uptime);
+
+        if let Some((rss, cpu)) = gateway.pid.and_then(process_stats) {
+            header(&mut out, "kiro_gateway_resident_memory_bytes", "gauge", "Resident memory of the gateway process");
+            let _ = writeln!(out, "kiro_gateway_resident_memory_bytes {}", rss);
+            header(&mut out, "kiro_gateway_cpu_seconds_total", "counter", "CPU time used by the gateway process");
+            let _ = writeln!(out, "kiro_gateway_cpu_seconds_total {}", cpu);
+        }
+
+        if let Some((ok, seconds)) = gateway.probe {
+            header(&mut out, "kiro_gateway_health_probe_success", "gauge", "Whether the last health probe succeeded");
+            let _ = writeln!(out, "kiro_gateway_health_probe_success {}", ok as u8);
+            header(&mut out, "kiro_gateway_health_probe_seconds", "gauge", "Duration of the last health probe");
+            let _ = writeln!(out, "kiro_gateway_health_probe_seconds {}", seconds);
+        }
+
+        // Request metrics are only available when requests go through the proxy
+        header(&mut out, "kiro_proxy_requests_total", "counter", "Requests through the proxy by endpoint and status");
+        for ((endpoint, status), count) in &state.requests {
+            let _ = writeln!(
+                out,
+                "kiro_proxy_requests_total{{endpoint=\"{}\",status=\"{}\"}} {}",
+                escape_label(endpoint),
+                status,
+                count
+            );
+        }
+        header(&mut out, "kiro_proxy_errors_total", "counter", "Requests that failed in the proxy or were cut off");
+        for (endpoint, count) in &state.errors {
+            let _ = writeln!(out, "kiro_proxy_errors_total{{endpoint=\"{}\"}} {}", escape_label(endpoint), count);
+        }
+        write_histograms(&mut out, "kiro_proxy_request_duration_seconds", "Time until the response was fully sent", &state.latency);
+        write_histograms(&mut out, "kiro_proxy_time_to_first_byte_seconds", "Time until the first byte of the response body", &state.ttfb);
+        header(&mut out, "kiro_proxy_tokens_total", "counter", "Tokens reported in responses by model and kind");
+        for ((model, kind), count) in &state.tokens {
+            let _ = writeln!(
+                out,
+                "kiro_proxy_tokens_total{{model=\"{}\",kind=\"{}\"}} {}",
+                escape_label(model),
+                kind,
+                count
+            );
+        }
+        out
+    }
+}
+
+/// Probe the gateway's health every few seconds while it runs
+pub async fn run_probe(metrics: Arc<Metrics>) {
+    let client = reqwest::Client::new();
+    let mut interval = tokio::time::interval(PROBE_INTERVAL);
+    loop {
+        interval.tick().await;
+        metrics.probe(&client).await;
+    }
+}
+
+/// Serve `GET /metrics` on `listen`
+pub async fn serve(listen: SocketAddr, metrics: Arc<Metrics>) -> Result<ListenerHandle, String> {
+    let builder = Server::try_bind(&listen).map_err(|e| format!("Failed to bind metrics endpoint to {}: {}", listen, e))?;
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let metrics = metrics.clone();
+                async move {
+                    let response = if request.method() == Method::GET && request.uri().path() == "/metrics" {
+                        Response::builder()
+                            .header(hyper::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                            .body(Body::from(metrics.render()))
+                    } else {
+                        Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty())
+                    };
+                    Ok::<_, Infallible>(response.unwrap_or_else(|_| Response::new(Body::empty())))
+                }
+            }))
+        }
+    });
+
+    let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+    let server = builder.serve(make_service).with_graceful_shutdown(async {
+        let _ = shutdown_rx.await;
+    });
+    let task = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("[Metrics] Server error: {}", e);
+        }
+    });
+    println!("[Metrics] Serving /metrics on {}", listen);
+    Ok(ListenerHandle::new(shutdown, task))
+}
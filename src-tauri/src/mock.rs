@@ -0,0 +1,637 @@
+use crate::conversations::now_millis;
+use crate::model_routes::glob_match;
+use crate::proxy::ListenerHandle;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Models listed by the mock backend, as the gateway lists them without a connection
+const MOCK_MODELS: &[&str] = &["auto", "claude-sonnet-4", "claude-haiku-4.5", "claude-sonnet-4.5", "claude-opus-4.5"];
+
+/// A tool call the mock backend makes
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockToolCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+/// A scripted reply, used for requests it matches; requests no rule matches
+/// get their last message echoed back
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MockRule {
+    /// Requested model name pattern (`*` and `?`); unset matches every model
+    pub model: Option<String>,
+    /// Text the last message must contain; unset matches every message
+    pub contains: Option<String>,
+    /// Reply text; unset echoes the last message unless the rule only calls tools
+    pub reply: Option<String>,
+    pub tool_calls: Vec<MockToolCall>,
+    /// Answer with this HTTP error status instead
+    pub status: Option<u16>,
+    /// Message of the error response
+    pub error: Option<String>,
+    /// Wait before responding
+    pub delay_ms: u64,
+    /// Wait between stream events
+    pub chunk_delay_ms: u64,
+}
+
+impl MockRule {
+    fn matches(&self, model: &str, text: &str) -> bool {
+        self.model.as_ref().is_none_or(|pattern| glob_match(pattern, model))
+            && self.contains.as_ref().is_none_or(|needle| text.contains(needle.as_str()))
+    }
+}
+
+/// Read the scripted replies from a JSON file holding an array of rules
+pub async fn load_script(path: Option<&str>) -> Result<Vec<MockRule>, String> {
+    let path = match path.map(str::trim).filter(|p| !p.is_empty()) {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    let content = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("Failed to read mock script {}: {}", path, e))?;
+    let rules: Vec<MockRule> =
+        serde_json::from_slice(&content).map_err(|e| format!("Failed to parse mock script {}: {}", path, e))?;
+    for (i, rule) in rules.iter().enumerate() {
+        if rule.status.is_some_and(|s| !(400..=599).contains(&s)) {
+            return Err(format!("Mock script rule {}: status must be an HTTP error (400-599)", i + 1));
+        }
+    }
+    Ok(rules)
+}
+
+/// Key and replies of a mock backend
+pub struct MockOptions {
+    /// Clients must present this key, as with the real gateway
+    pub api_key: String,
+    pub rules: Vec<MockRule>,
+}
+
+struct MockContext {
+    options: MockOptions,
+    /// Numbers the ids of responses and tool calls
+    counter: AtomicU64,
+}
+
+/// Start a stand-in for the gateway on `listen` that needs no Kiro credentials
+/// or network: it serves `/health`, `/v1/models`, `/v1/chat/completions` and
+/// `/v1/messages`, answering completions from the script or with an echo
+pub async fn start(listen: SocketAddr, options: MockOptions) -> Result<ListenerHandle, String> {
+    let context = Arc::new(MockContext {
+        options,
+        counter: AtomicU64::new(1),
+    });
+
+    let builder =
+        Server::try_bind(&listen).map_err(|e| format!("Failed to bind mock backend to {}: {}", listen, e))?;
+    let make_service = make_service_fn(move |_| {
+        let context = context.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let context = context.clone();
+                async move { Ok::<_, Infallible>(handle(context, request).await) }
+            }))
+        }
+    });
+
+    let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+    let server = builder.serve(make_service).with_graceful_shutdown(async {
+        let _ = shutdown_rx.await;
+    });
+    let task = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("[Mock] Server error: {}", e);
+        }
+    });
+    println!("[Mock] Listening on {}", listen);
+
+    Ok(ListenerHandle::new(shutdown, task))
+}
+
+/// The two API shapes the mock backend speaks
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Api {
+    OpenAi,
+    Anthropic,
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
+
+fn error_response(api: Api, status: StatusCode, message: &str) -> Response<Body> {
+    let kind = match status.as_u16() {
+        400 | 422 => "invalid_request_error",
+        401 => "authentication_error",
+        429 => "rate_limit_error",
+        _ => "api_error",
+    };
+    let body = match api {
+        Api::OpenAi => json!({ "error": { "message": message, "type": kind, "code": status.as_u16() } }),
+        Api::Anthropic => json!({ "type": "error", "error": { "type": kind, "message": message } }),
+    };
+    json_response(status, body)
+}
+
+fn authorized(context: &MockContext, request: &Request<Body>, api: Api) -> bool {
+    let header = |name: &str| request.headers().get(name).and_then(|v| v.to_str().ok());
+    let bearer = format!("Bearer {}", context.options.api_key);
+    header("authorization") == Some(bearer.as_str())
+        || (api == Api::Anthropic && header("x-api-key") == Some(context.options.api_key.as_str()))
+}
+
+async fn handle(context: Arc<MockContext>, request: Request<Body>) -> Response<Body> {
+    let path = request.uri().path().to_string();
+    let api = match path.as_str() {
+        "/v1/messages" => Api::Anthropic,
+        _ => Api::OpenAi,
+    };
+    match (request.method(), path.as_str()) {
+        (&Method::GET, "/health") => {
+            return json_response(StatusCode::OK, json!({ "status": "healthy", "mode": "mock" }));
+        }
+        (&Method::GET, "/v1/models") | (&Method::POST, "/v1/chat/completions") | (&Method::POST, "/v1/messages") => {}
+        _ => return error_response(api, StatusCode::NOT_FOUND, "Not found"),
+    }
+    if !authorized(&context, &request, api) {
+        return error_response(api, StatusCode::UNAUTHORIZED, "Invalid or missing API key");
+    }
+    if path == "/v1/models" {
+        let created = now_millis() / 1000;
+        let models: Vec<Value> = MOCK_MODELS
+            .iter()
+            .map(|id| json!({ "id": id, "object": "model", "created": created, "owned_by": "anthropic" }))
+            .collect();
+        return json_response(StatusCode::OK, json!({ "object": "list", "data": models }));
+    }
+
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return error_response(api, StatusCode::BAD_REQUEST, &format!("Failed to read request body: {}", e)),
+    };
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => return error_response(api, StatusCode::UNPROCESSABLE_ENTITY, &format!("Invalid JSON: {}", e)),
+    };
+    let messages = match body.get("messages").and_then(|m| m.as_array()) {
+        Some(messages) if !messages.is_empty() => messages,
+        _ => return error_response(api, StatusCode::UNPROCESSABLE_ENTITY, "messages must be a non-empty array"),
+    };
+    let model = body.get("model").and_then(|m| m.as_str()).unwrap_or("auto").to_string();
+    let stream = body.get("stream").and_then(|s| s.as_bool()).unwrap_or(false);
+    let prompt: String = messages.iter().map(message_text).collect::<Vec<_>>().join("\n");
+    let last = messages.last().map(message_text).unwrap_or_default();
+
+    let rule = context
+        .options
+        .rules
+        .iter()
+        .find(|rule| rule.matches(&model, &last))
+        .cloned()
+        .unwrap_or_default();
+    if rule.delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(rule.delay_ms)).await;
+    }
+    if let Some(status) = rule.status {
+        let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        return error_response(api, status, rule.error.as_deref().unwrap_or("Scripted mock error"));
+    }
+
+    let text = match &rule.reply {
+        Some(reply) => reply.clone(),
+        None if rule.tool_calls.is_empty() => format!("Echo: {}", last),
+        None => String::new(),
+    };
+    let reply = Reply {
+        id: context.counter.fetch_add(1, Ordering::Relaxed),
+        model,
+        text,
+        tool_calls: rule.tool_calls,
+        prompt_tokens: estimate_tokens(&prompt),
+    };
+    match (api, stream) {
+        (Api::OpenAi, false) => json_response(StatusCode::OK, reply.openai_message()),
+        (Api::Anthropic, false) => json_response(StatusCode::OK, reply.anthropic_message()),
+        (Api::OpenAi, true) => event_stream(reply.openai_events(), rule.chunk_delay_ms),
+        (Api::Anthropic, true) => event_stream(reply.anthropic_events(), rule.chunk_delay_ms),
+    }
+}
+
+/// Text of a message in either API's shape: a string, or the text of its
+/// content blocks (including tool results)
+fn message_text(message: &Value) -> String {
+    fn text_of(content: &Value) -> String {
+        match content {
+            Value::String(text) => text.clone(),
+            Value::Array(blocks) => blocks
+                .iter()
+                .map(|block| match block.get("type").and_then(|t| t.as_str()) {
+                    Some("text") => block.get("text").and_then(|t| t.as_str()).unwrap_or("").to_string(),
+                    Some("tool_result") => block.get("content").map(text_of).unwrap_or_default(),
+                    _ => String::new(),
+                })
+                .filter(|text| !text.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => String::new(),
+        }
+    }
+    message.get("content").map(text_of).unwrap_or_default()
+}
+
+/// Roughly four characters per token
+fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4).max(1)
+}
+
+/// Pieces a streamed reply is sent in: words with their following whitespace
+fn split_words(text: &str) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        if !c.is_whitespace() && current.ends_with(char::is_whitespace) {
+            pieces.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// A completion the mock backend answers with
+struct Reply {
+    id: u64,
+    model: String,
+    text: String,
+    tool_calls: Vec<MockToolCall>,
+    prompt_tokens: u64,
+}
+
+impl Reply {
+    fn completion_tokens(&self) -> u64 {
+        let arguments: String = self.tool_calls.iter().map(|c| c.arguments.to_string()).collect();
+        estimate_tokens(&format!("{}{}", self.text, arguments))
+    }
+
+    fn tool_call_id(&self, index: usize, prefix: &str) -> String {
+        format!("{}_mock_{}_{}", prefix, self.id, index)
+    }
+
+    fn openai_tool_calls(&self) -> Vec<Value> {
+        self.tool_calls
+            .iter()
+            .enumerate()
+            .map(|(i, call)| {
+                json!({
+                    "id": self.tool_call_id(i, "call"),
+                    "type": "function",
+                    "function": { "name": call.name, "arguments": call.arguments.to_string() }
+                })
+            })
+            .collect()
+    }
+
+    fn openai_finish_reason(&self) -> &'static str {
+        if self.tool_calls.is_empty() {
+            "stop"
+        } else {
+            "tool_calls"
+        }
+    }
+
+    fn openai_usage(&self) -> Value {
+        let completion = self.completion_tokens();
+        json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": completion,
+            "total_tokens": self.prompt_tokens + completion,
+        })
+    }
+
+    fn openai_message(&self) -> Value {
+        let mut message = json!({
+            "role": "assistant",
+            "content": if self.text.is_empty() { Value::Null } else { Value::String(self.text.clone()) },
+        });
+        if !self.tool_calls.is_empty() {
+            message["tool_calls"] = Value::Array(self.openai_tool_calls());
+        }
+        json!({
+            "id": format!("chatcmpl-mock-{}", self.id),
+            "object": "chat.completion",
+            "created": now_millis() / 1000,
+            "model": self.model,
+            "choices": [{ "index": 0, "message": message, "finish_reason": self.openai_finish_reason() }],
+            "usage": self.openai_usage(),
+        })
+    }
+
+    fn openai_events(&self) -> Vec<String> {
+        let created = now_millis() / 1000;
+        let chunk = |delta: Value, finish_reason: Value| {
+            json!({
+                "id": format!("chatcmpl-mock-{}", self.id),
+                "object": "chat.completion.chunk",
+                "created": created,
+                "model": self.model,
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+            })
+        };
+        let mut events = vec![chunk(json!({ "role": "assistant", "content": "" }), Value::Null)];
+        for piece in split_words(&self.text) {
+            events.push(chunk(json!({ "content": piece }), Value::Null));
+        }
+        for (i, call) in self.openai_tool_calls().into_iter().enumerate() {
+            let start = json!({ "tool_calls": [{
+                "index": i,
+                "id": call["id"],
+                "type": "function",
+                "function": { "name": call["function"]["name"], "arguments": "" }
+            }] });
+            let arguments = json!({ "tool_calls": [{
+                "index": i,
+                "function": { "arguments": call["function"]["arguments"] }
+            }] });
+            events.push(chunk(start, Value::Null));
+            events.push(chunk(arguments, Value::Null));
+        }
+        let mut last = chunk(json!({}), Value::String(self.openai_finish_reason().to_string()));
+        last["usage"] = self.openai_usage();
+        events.push(last);
+
+        let mut lines: Vec<String> = events.iter().map(|event| format!("data: {}\n\n", event)).collect();
+        lines.push("data: [DONE]\n\n".to_string());
+        lines
+    }
+
+    fn anthropic_stop_reason(&self) -> &'static str {
+        if self.tool_calls.is_empty() {
+            "end_turn"
+        } else {
+            "tool_use"
+        }
+    }
+
+    fn anthropic_tool_input(call: &MockToolCall) -> Value {
+        match &call.arguments {
+            Value::Null => json!({}),
+            arguments => arguments.clone(),
+        }
+    }
+
+    fn anthropic_message(&self) -> Value {
+        let mut content = Vec::new();
+        if !self.text.is_empty() {
+            content.push(json!({ "type": "text", "text": self.text }));
+        }
+        for (i, call) in self.tool_calls.iter().enumerate() {
+            content.push(json!({
+                "type": "tool_use",
+                "id": self.tool_call_id(i, "toolu"),
+                "name": call.name,
+                "input": Self::anthropic_tool_input(call),
+            }));
+        }
+        json!({
+            "id": format!("msg_mock_{}", self.id),
+            "type": "message",
+            "role": "assistant",
+            "model": self.model,
+            "content": content,
+            "stop_reason": self.anthropic_stop_reason(),
+            "stop_sequence": null,
+            "usage": { "input_tokens": self.prompt_tokens, "output_tokens": self.completion_tokens() },
+        })
+    }
+
+    fn anthropic_events(&self) -> Vec<String> {
+        let mut events: Vec<(&str, Value)> = vec![(
+            "message_start",
+            json!({ "type": "message_start", "message": {
+                "id": format!("msg_mock_{}", self.id),
+                "type": "message",
+                "role": "assistant",
+                "model": self.model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": { "input_tokens": self.prompt_tokens, "output_tokens": 0 },
+            } }),
+        )];
+        let mut index = 0;
+        if !self.text.is_empty() {
+            events.push((
+                "content_block_start",
+                json!({ "type": "content_block_start", "index": index, "content_block": { "type": "text", "text": "" } }),
+            ));
+            for piece in split_words(&self.text) {
+                events.push((
+                    "content_block_delta",
+                    json!({ "type": "content_block_delta", "index": index, "delta": { "type": "text_delta", "text": piece } }),
+                ));
+            }
+            events.push(("content_block_stop", json!({ "type": "content_block_stop", "index": index })));
+            index += 1;
+        }
+        for (i, call) in self.tool_calls.iter().enumerate() {
+            events.push((
+                "content_block_start",
+                json!({ "type": "content_block_start", "index": index, "content_block": {
+                    "type": "tool_use",
+                    "id": self.tool_call_id(i, "toolu"),
+                    "name": call.name,
+                    "input": {},
+                } }),
+            ));
+            events.push((
+                "content_block_delta",
+                json!({ "type": "content_block_delta", "index": index, "delta": {
+                    "type": "input_json_delta",
+                    "partial_json": Self::anthropic_tool_input(call).to_string(),
+                } }),
+            ));
+            events.push(("content_block_stop", json!({ "type": "content_block_stop", "index": index })));
+            index += 1;
+        }
+        events.push((
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": self.anthropic_stop_reason(), "stop_sequence": null },
+                "usage": { "output_tokens": self.completion_tokens() },
+            }),
+        ));
+        events.push(("message_stop", json!({ "type": "message_stop" })));
+
+        events
+            .into_iter()
+            .map(|(name, data)| format!("event: {}\ndata: {}\n\n", name, data))
+            .collect()
+    }
+}
+
+/// A server-sent event stream sending `events` with `delay_ms` between them
+fn event_stream(events: Vec<String>, delay_ms: u64) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        for (i, event) in events.into_iter().enumerate() {
+            if i > 0 && delay_ms > 0 {
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            }
+            if sender.send_data(event.into()).await.is_err() {
+                break;
+            }
+        }
+    });
+    Response::builder()
+        .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+        .header(hyper::header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "mock-key";
+
+    /// Start a mock backend with `rules` on a free port and return its base URL
+    async fn mock(rules: Vec<MockRule>) -> (String, ListenerHandle) {
+        let listen = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let options = MockOptions {
+            api_key: KEY.to_string(),
+            rules,
+        };
+        let handle = start(listen, options).await.unwrap();
+        (format!("http://{}", listen), handle)
+    }
+
+    fn rules(script: Value) -> Vec<MockRule> {
+        serde_json::from_value(script).unwrap()
+    }
+
+    async fn chat(base_url: &str, body: Value) -> (u16, Value) {
+        let response = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", base_url))
+            .bearer_auth(KEY)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        (response.status().as_u16(), response.json().await.unwrap())
+    }
+
+    fn user(text: &str) -> Value {
+        json!([{ "role": "user", "content": text }])
+    }
+
+    #[tokio::test]
+    async fn scripted_reply_answers_matching_requests() {
+        let (url, _mock) = mock(rules(json!([
+            { "model": "claude-haiku-*", "contains": "weather", "reply": "Sunny all week" },
+        ])))
+        .await;
+
+        let (status, body) = chat(&url, json!({ "model": "claude-haiku-4.5", "messages": user("What's the weather?") })).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(body["choices"][0]["message"]["content"], "Sunny all week");
+        assert_eq!(body["model"], "claude-haiku-4.5");
+        assert!(body["usage"]["completion_tokens"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_are_echoed() {
+        let (url, _mock) = mock(rules(json!([
+            { "model": "claude-haiku-*", "contains": "weather", "reply": "Sunny all week" },
+        ])))
+        .await;
+
+        // Right words, wrong model
+        let (_, body) = chat(&url, json!({ "model": "claude-sonnet-4", "messages": user("What's the weather?") })).await;
+        assert_eq!(body["choices"][0]["message"]["content"], "Echo: What's the weather?");
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/v1/messages", url))
+            .header("x-api-key", KEY)
+            .json(&json!({ "model": "claude-sonnet-4", "max_tokens": 100, "messages": user("hello there") }))
+            .send()
+            .await
+            .unwrap();
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["content"][0]["text"], "Echo: hello there");
+    }
+
+    #[tokio::test]
+    async fn streamed_echo_arrives_word_by_word() {
+        let (url, _mock) = mock(Vec::new()).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", url))
+            .bearer_auth(KEY)
+            .json(&json!({ "model": "auto", "stream": true, "messages": user("one two three") }))
+            .send()
+            .await
+            .unwrap();
+        let text = response.text().await.unwrap();
+        let deltas: String = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str::<Value>(data).ok())
+            .filter_map(|event| event["choices"][0]["delta"]["content"].as_str().map(str::to_string))
+            .collect();
+
+        assert_eq!(deltas, "Echo: one two three");
+        assert!(text.trim_end().ends_with("data: [DONE]"));
+    }
+
+    #[tokio::test]
+    async fn scripted_errors_and_missing_keys_are_refused() {
+        let (url, _mock) = mock(rules(json!([
+            { "contains": "overload", "status": 529, "error": "Overloaded" },
+        ])))
+        .await;
+
+        let (status, body) = chat(&url, json!({ "model": "auto", "messages": user("overload please") })).await;
+        assert_eq!(status, 529);
+        assert_eq!(body["error"]["message"], "Overloaded");
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/v1/chat/completions", url))
+            .json(&json!({ "model": "auto", "messages": user("hi") }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    #[tokio::test]
+    async fn script_must_use_error_statuses() {
+        let path = std::env::temp_dir().join(format!("kiroaas-mock-script-{}.json", std::process::id()));
+        std::fs::write(&path, r#"[{ "reply": "fine" }, { "status": 200 }]"#).unwrap();
+
+        let result = load_script(path.to_str()).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap_err(), "Mock script rule 2: status must be an HTTP error (400-599)");
+        assert!(load_script(None).await.unwrap().is_empty());
+    }
+}
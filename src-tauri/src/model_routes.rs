@@ -0,0 +1,88 @@
+use crate::config::ModelRoute;
+use serde::Serialize;
+
+/// The rule a model name resolves to
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteMatch {
+    /// Position of the rule in the table
+    pub index: usize,
+    pub route: ModelRoute,
+}
+
+/// Whether `name` matches a pattern with `*` (any run) and `?` (any one
+/// character), ignoring case; the same rules the gateway applies
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it is matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((after, matched)) = star {
+            p = after;
+            n = matched + 1;
+            star = Some((after, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// The first rule matching `model`
+pub fn resolve(routes: &[ModelRoute], model: &str) -> Option<RouteMatch> {
+    routes
+        .iter()
+        .position(|route| glob_match(&route.pattern, model))
+        .map(|index| RouteMatch {
+            index,
+            route: routes[index].clone(),
+        })
+}
+
+/// Check a routing table before it is saved or passed to the gateway
+pub fn validate(routes: &[ModelRoute]) -> Result<(), String> {
+    for (index, route) in routes.iter().enumerate() {
+        let name = format!("Model route {} ({})", index + 1, route.pattern);
+        if route.pattern.trim().is_empty() {
+            return Err(format!("Model route {} has no pattern", index + 1));
+        }
+        if route.pattern.contains(['[', ']']) {
+            return Err(format!("{}: only * and ? are supported as wildcards", name));
+        }
+        if route.target.trim().is_empty() {
+            return Err(format!("{} has no target model", name));
+        }
+        if route.target.contains(['*', '?']) {
+            return Err(format!("{}: the target must be a model name, not a pattern", name));
+        }
+        if route.max_tokens == Some(0) {
+            return Err(format!("{}: max tokens must be greater than 0", name));
+        }
+        let earlier = &routes[..index];
+        if let Some(other) = earlier
+            .iter()
+            .position(|r| r.pattern.eq_ignore_ascii_case(&route.pattern))
+        {
+            return Err(format!("{} repeats the pattern of model route {}", name, other + 1));
+        }
+        // A rule without wildcards is dead if an earlier rule already matches its name
+        if !route.pattern.contains(['*', '?']) {
+            if let Some(shadow) = resolve(earlier, &route.pattern) {
+                return Err(format!(
+                    "{} is never used: model route {} ({}) matches first",
+                    name,
+                    shadow.index + 1,
+                    shadow.route.pattern
+                ));
+            }
+        }
+    }
+    Ok(())
+}
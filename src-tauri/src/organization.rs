@@ -0,0 +1,331 @@
+use crate::conversations::{generate_id, now_millis, Conversation, ConversationsData, Folder};
+use crate::usage::UsageTotals;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// Filter for listing and searching conversations
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct ConversationFilter {
+    /// Case-insensitive text matched against titles, tags and message text
+    pub query: Option<String>,
+    pub folder_id: Option<String>,
+    /// Also match conversations in nested folders of `folder_id`
+    pub include_subfolders: bool,
+    /// Only conversations carrying all of these tags
+    pub tags: Vec<String>,
+    pub pinned_only: bool,
+    /// Archived conversations are hidden unless this is set
+    pub include_archived: bool,
+    pub archived_only: bool,
+}
+
+/// Lightweight view of a conversation for sidebar listings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub model: Option<String>,
+    pub folder_id: Option<String>,
+    pub tags: Vec<String>,
+    pub pinned: bool,
+    pub archived: bool,
+    pub deleted_at: Option<u64>,
+    pub message_count: usize,
+    pub usage: UsageTotals,
+}
+
+impl From<&Conversation> for ConversationSummary {
+    fn from(c: &Conversation) -> Self {
+        Self {
+            id: c.id.clone(),
+            title: c.title.clone(),
+            created_at: c.created_at,
+            updated_at: c.updated_at,
+            model: c.model.clone(),
+            folder_id: c.folder_id.clone(),
+            tags: c.tags.clone(),
+            pinned: c.pinned,
+            archived: c.archived,
+            deleted_at: c.deleted_at,
+            message_count: c.messages.len(),
+            usage: c.usage_totals.clone(),
+        }
+    }
+}
+
+/// A tag and the number of conversations using it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+/// List conversations matching `filter`, pinned first, then most recently updated.
+///
+/// Trashed conversations are never listed here; see `trash::list`.
+pub fn list(data: &ConversationsData, filter: &ConversationFilter) -> Vec<ConversationSummary> {
+    let folder_ids = filter.folder_id.as_ref().map(|id| {
+        let mut ids = HashSet::new();
+        ids.insert(id.clone());
+        if filter.include_subfolders {
+            ids.extend(descendant_folders(data, id));
+        }
+        ids
+    });
+    let query = filter
+        .query
+        .as_ref()
+        .map(|q| q.trim().to_lowercase())
+        .filter(|q| !q.is_empty());
+
+    let mut results: Vec<&Conversation> = data
+        .conversations
+        .iter()
+        .filter(|c| c.deleted_at.is_none())
+        .filter(|c| {
+            if filter.archived_only {
+                c.archived
+            } else {
+                filter.include_archived || !c.archived
+            }
+        })
+        .filter(|c| !filter.pinned_only || c.pinned)
+        .filter(|c| match &folder_ids {
+            Some(ids) => c.folder_id.as_ref().is_some_and(|f| ids.contains(f)),
+            None => true,
+        })
+        .filter(|c| filter.tags.iter().all(|t| c.tags.iter().any(|ct| ct.eq_ignore_ascii_case(t))))
+        .filter(|c| match &query {
+            Some(q) => matches_query(c, q),
+            None => true,
+        })
+        .collect();
+
+    results.sort_by(|a, b| {
+        b.pinned
+            .cmp(&a.pinned)
+            .then_with(|| b.updated_at.cmp(&a.updated_at))
+    });
+    results.into_iter().map(ConversationSummary::from).collect()
+}
+
+fn matches_query(conversation: &Conversation, query: &str) -> bool {
+    if conversation.title.to_lowercase().contains(query) {
+        return true;
+    }
+    if conversation.tags.iter().any(|t| t.to_lowercase().contains(query)) {
+        return true;
+    }
+    conversation.messages.iter().any(|m| match &m.content {
+        serde_json::Value::String(s) => s.to_lowercase().contains(query),
+        serde_json::Value::Array(parts) => parts.iter().any(|p| {
+            p.get("text")
+                .and_then(|t| t.as_str())
+                .is_some_and(|t| t.to_lowercase().contains(query))
+        }),
+        _ => false,
+    })
+}
+
+/// All folders nested (at any depth) under `folder_id`
+fn descendant_folders(data: &ConversationsData, folder_id: &str) -> HashSet<String> {
+    let mut found = HashSet::new();
+    let mut stack = vec![folder_id.to_string()];
+    while let Some(current) = stack.pop() {
+        for folder in data.folders.iter().filter(|f| f.parent_id.as_deref() == Some(current.as_str())) {
+            if found.insert(folder.id.clone()) {
+                stack.push(folder.id.clone());
+            }
+        }
+    }
+    found
+}
+
+fn ensure_folder_exists(data: &ConversationsData, folder_id: &Option<String>) -> Result<(), String> {
+    match folder_id {
+        Some(id) if !data.folders.iter().any(|f| &f.id == id) => Err("Folder not found".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Create a folder, optionally nested inside `parent_id`
+pub fn create_folder(data: &mut ConversationsData, name: String, parent_id: Option<String>) -> Result<Folder, String> {
+    ensure_folder_exists(data, &parent_id)?;
+    let folder = Folder {
+        id: generate_id(),
+        name,
+        parent_id,
+        created_at: now_millis(),
+    };
+    data.folders.push(folder.clone());
+    Ok(folder)
+}
+
+/// Rename a folder
+pub fn rename_folder(data: &mut ConversationsData, id: &str, name: String) -> Result<(), String> {
+    let folder = data
+        .folders
+        .iter_mut()
+        .find(|f| f.id == id)
+        .ok_or("Folder not found")?;
+    folder.name = name;
+    Ok(())
+}
+
+/// Move a folder under a new parent (`None` for the top level)
+pub fn move_folder(data: &mut ConversationsData, id: &str, parent_id: Option<String>) -> Result<(), String> {
+    ensure_folder_exists(data, &parent_id)?;
+    if let Some(parent) = &parent_id {
+        if parent == id || descendant_folders(data, id).contains(parent) {
+            return Err("Cannot move a folder into itself".to_string());
+        }
+    }
+    let folder = data
+        .folders
+        .iter_mut()
+        .find(|f| f.id == id)
+        .ok_or("Folder not found")?;
+    folder.parent_id = parent_id;
+    Ok(())
+}
+
+/// Delete a folder; its subfolders and conversations move up to its parent
+pub fn delete_folder(data: &mut ConversationsData, id: &str) -> Result<(), String> {
+    let pos = data
+        .folders
+        .iter()
+        .position(|f| f.id == id)
+        .ok_or("Folder not found")?;
+    let removed = data.folders.remove(pos);
+
+    for folder in data.folders.iter_mut() {
+        if folder.parent_id.as_deref() == Some(id) {
+            folder.parent_id = removed.parent_id.clone();
+        }
+    }
+    for conversation in data.conversations.iter_mut() {
+        if conversation.folder_id.as_deref() == Some(id) {
+            conversation.folder_id = removed.parent_id.clone();
+        }
+    }
+    Ok(())
+}
+
+/// Move a conversation into a folder (`None` for no folder)
+pub fn set_folder(data: &mut ConversationsData, id: &str, folder_id: Option<String>) -> Result<(), String> {
+    ensure_folder_exists(data, &folder_id)?;
+    find_conversation(data, id)?.folder_id = folder_id;
+    Ok(())
+}
+
+/// Replace a conversation's tags, trimming blanks and duplicates
+pub fn set_tags(data: &mut ConversationsData, id: &str, tags: Vec<String>) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    let tags = tags
+        .into_iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty() && seen.insert(t.to_lowercase()))
+        .collect();
+    find_conversation(data, id)?.tags = tags;
+    Ok(())
+}
+
+pub fn set_pinned(data: &mut ConversationsData, id: &str, pinned: bool) -> Result<(), String> {
+    find_conversation(data, id)?.pinned = pinned;
+    Ok(())
+}
+
+pub fn set_archived(data: &mut ConversationsData, id: &str, archived: bool) -> Result<(), String> {
+    let conversation = find_conversation(data, id)?;
+    conversation.archived = archived;
+    // Archived conversations should not stay at the top of the list
+    if archived {
+        conversation.pinned = false;
+    }
+    Ok(())
+}
+
+/// Keep the folder, tags, pin, archive and trash state of the stored record when
+/// the frontend saves a copy it loaded before they were changed
+pub fn keep_organization(conversation: &mut Conversation, previous: &Conversation) {
+    conversation.folder_id = previous.folder_id.clone();
+    conversation.tags = previous.tags.clone();
+    conversation.pinned = previous.pinned;
+    conversation.archived = previous.archived;
+    conversation.deleted_at = previous.deleted_at;
+}
+
+/// All tags in use with their conversation counts, alphabetically
+pub fn list_tags(data: &ConversationsData) -> Vec<TagCount> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for conversation in data.conversations.iter().filter(|c| c.deleted_at.is_none()) {
+        for tag in &conversation.tags {
+            *counts.entry(tag.clone()).or_default() += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(tag, count)| TagCount { tag, count })
+        .collect()
+}
+
+fn find_conversation<'a>(data: &'a mut ConversationsData, id: &str) -> Result<&'a mut Conversation, String> {
+    data.conversations
+        .iter_mut()
+        .find(|c| c.id == id)
+        .ok_or_else(|| "Conversation not found".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(id: &str) -> Conversation {
+        Conversation {
+            id: id.to_string(),
+            title: "Test".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            messages: Vec::new(),
+            active_leaf_id: None,
+            model: None,
+            folder_id: None,
+            tags: Vec::new(),
+            pinned: false,
+            archived: false,
+            deleted_at: None,
+            source_id: None,
+            usage_totals: Default::default(),
+            title_source: Default::default(),
+            summary: None,
+            summarized_count: 0,
+        }
+    }
+
+    #[test]
+    fn saving_a_stale_copy_keeps_the_organization() {
+        let mut data = ConversationsData {
+            conversations: vec![conversation("c")],
+            ..Default::default()
+        };
+        // The frontend is still streaming into the copy it loaded
+        let mut stale = data.conversations[0].clone();
+        let folder = create_folder(&mut data, "Work".to_string(), None).unwrap();
+        set_folder(&mut data, "c", Some(folder.id.clone())).unwrap();
+        set_tags(&mut data, "c", vec!["rust".to_string()]).unwrap();
+        set_pinned(&mut data, "c", true).unwrap();
+        crate::trash::trash(&mut data, &["c".to_string()]);
+
+        stale.title = "Renamed".to_string();
+        keep_organization(&mut stale, &data.conversations[0]);
+
+        assert_eq!(stale.title, "Renamed");
+        assert_eq!(stale.folder_id, Some(folder.id));
+        assert_eq!(stale.tags, ["rust"]);
+        assert!(stale.pinned);
+        assert!(stale.deleted_at.is_some());
+    }
+}
@@ -0,0 +1,323 @@
+use crate::config::{AppConfig, PoolStrategy};
+use crate::conversations::now_millis;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How long an account is skipped after its gateway could not be reached
+pub const UNREACHABLE_COOLDOWN_MS: u64 = 30_000;
+/// How long an account is skipped after it was throttled (without `Retry-After`)
+pub const THROTTLED_COOLDOWN_MS: u64 = 60_000;
+/// How long an account is skipped after it was refused or ran out of credits
+pub const REFUSED_COOLDOWN_MS: u64 = 5 * 60_000;
+
+/// A gateway process serving one account
+#[derive(Debug, Clone)]
+pub struct Upstream {
+    pub account: String,
+    pub label: String,
+    /// e.g. `http://127.0.0.1:8001`
+    pub base_url: String,
+}
+
+struct UpstreamState {
+    upstream: Upstream,
+    /// Shared with the leases, so a lease taken before `set_upstreams` only
+    /// releases the counter of the gateway it was routed to
+    in_flight: Arc<AtomicU32>,
+    requests: u64,
+    failures: u64,
+    /// ms since the Unix epoch until which the account is skipped
+    down_until: u64,
+    last_error: Option<String>,
+}
+
+/// Routing state of one account in the pool
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStatus {
+    pub account: String,
+    pub label: String,
+    pub base_url: String,
+    pub healthy: bool,
+    pub in_flight: u32,
+    pub requests: u64,
+    pub failures: u64,
+    pub down_until: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    upstreams: Vec<UpstreamState>,
+    strategy: PoolStrategy,
+    sticky: bool,
+    /// Next index to try for round-robin
+    next: usize,
+    /// Account last used per client key
+    sessions: HashMap<String, String>,
+}
+
+/// Spreads requests over the gateway processes of the configured accounts,
+/// skipping accounts that recently failed
+#[derive(Default)]
+pub struct AccountPool {
+    state: Mutex<PoolState>,
+}
+
+impl AccountPool {
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply the routing settings from `config`
+    pub fn configure(&self, config: &AppConfig) {
+        let mut state = self.lock();
+        state.strategy = config.pool_strategy;
+        state.sticky = config.sticky_sessions;
+        if !state.sticky {
+            state.sessions.clear();
+        }
+    }
+
+    /// Replace the gateways requests are routed to; counters and sessions start over
+    pub fn set_upstreams(&self, upstreams: Vec<Upstream>) {
+        let mut state = self.lock();
+        state.upstreams = upstreams
+            .into_iter()
+            .map(|upstream| UpstreamState {
+                upstream,
+                in_flight: Arc::default(),
+                requests: 0,
+                failures: 0,
+                down_until: 0,
+                last_error: None,
+            })
+            .collect();
+        state.next = 0;
+        state.sessions.clear();
+    }
+
+    /// Number of accounts requests can be routed to
+    pub fn account_count(&self) -> usize {
+        self.lock().upstreams.len()
+    }
+
+    /// Choose the account for a request from `session` (a client key), leaving
+    /// out the accounts in `tried`. Healthy accounts are preferred; when all of
+    /// them are down, the one that recovers first is tried anyway.
+    pub fn pick(&self, session: Option<&str>, tried: &[String]) -> Option<Lease> {
+        let mut state = self.lock();
+        let now = now_millis();
+        let candidates: Vec<usize> = (0..state.upstreams.len())
+            .filter(|i| !tried.contains(&state.upstreams[*i].upstream.account))
+            .collect();
+        let healthy: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|i| state.upstreams[*i].down_until <= now)
+            .collect();
+
+        let sticky = session
+            .filter(|_| state.sticky)
+            .and_then(|session| state.sessions.get(session))
+            .and_then(|account| {
+                healthy
+                    .iter()
+                    .copied()
+                    .find(|i| &state.upstreams[*i].upstream.account == account)
+            });
+
+        let chosen = match sticky {
+            Some(index) => index,
+            None if healthy.is_empty() => *candidates.iter().min_by_key(|i| state.upstreams[**i].down_until)?,
+            None => match state.strategy {
+                PoolStrategy::RoundRobin => {
+                    let count = state.upstreams.len();
+                    let start = state.next;
+                    let index = (0..count)
+                        .map(|offset| (start + offset) % count)
+                        .find(|i| healthy.contains(i))?;
+                    state.next = index + 1;
+                    index
+                }
+                PoolStrategy::LeastLoaded => *healthy.iter().min_by_key(|i| {
+                    let entry = &state.upstreams[**i];
+                    (entry.in_flight.load(Ordering::Relaxed), entry.requests)
+                })?,
+            },
+        };
+
+        let (upstream, in_flight) = {
+            let entry = &mut state.upstreams[chosen];
+            entry.in_flight.fetch_add(1, Ordering::Relaxed);
+            entry.requests += 1;
+            (entry.upstream.clone(), entry.in_flight.clone())
+        };
+        if let Some(session) = session.filter(|_| state.sticky) {
+            state.sessions.insert(session.to_string(), upstream.account.clone());
+        }
+        Some(Lease { upstream, in_flight })
+    }
+
+    /// Skip `account` for `cooldown_ms` after it failed a request
+    pub fn mark_down(&self, account: &str, cooldown_ms: u64, error: String) {
+        let mut state = self.lock();
+        if let Some(entry) = state.upstreams.iter_mut().find(|u| u.upstream.account == account) {
+            entry.failures += 1;
+            entry.down_until = now_millis() + cooldown_ms;
+            entry.last_error = Some(error);
+        }
+        // Clients stuck to the account move on with their next request
+        state.sessions.retain(|_, a| a != account);
+    }
+
+    /// `account` answered a request normally
+    pub fn mark_up(&self, account: &str) {
+        let mut state = self.lock();
+        if let Some(entry) = state.upstreams.iter_mut().find(|u| u.upstream.account == account) {
+            entry.down_until = 0;
+        }
+    }
+
+    pub fn status(&self) -> Vec<UpstreamStatus> {
+        let now = now_millis();
+        self.lock()
+            .upstreams
+            .iter()
+            .map(|u| UpstreamStatus {
+                account: u.upstream.account.clone(),
+                label: u.upstream.label.clone(),
+                base_url: u.upstream.base_url.clone(),
+                healthy: u.down_until <= now,
+                in_flight: u.in_flight.load(Ordering::Relaxed),
+                requests: u.requests,
+                failures: u.failures,
+                down_until: Some(u.down_until).filter(|until| *until > now),
+                last_error: u.last_error.clone(),
+            })
+            .collect()
+    }
+}
+
+/// A request routed to an account; counts as in flight until dropped
+pub struct Lease {
+    pub upstream: Upstream,
+    in_flight: Arc<AtomicU32>,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let _ = self
+            .in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(strategy: PoolStrategy, sticky: bool) -> Arc<AccountPool> {
+        let pool = Arc::new(AccountPool::default());
+        pool.configure(&AppConfig {
+            pool_strategy: strategy,
+            sticky_sessions: sticky,
+            ..Default::default()
+        });
+        pool.set_upstreams(
+            ["a", "b", "c"]
+                .iter()
+                .map(|account| Upstream {
+                    account: account.to_string(),
+                    label: account.to_uppercase(),
+                    base_url: format!("http://{}.invalid", account),
+                })
+                .collect(),
+        );
+        pool
+    }
+
+    fn account(lease: &Lease) -> &str {
+        &lease.upstream.account
+    }
+
+    #[test]
+    fn round_robin_takes_turns() {
+        let pool = pool(PoolStrategy::RoundRobin, false);
+        let picked: Vec<String> = (0..4)
+            .map(|_| pool.pick(None, &[]).unwrap().upstream.account.clone())
+            .collect();
+
+        assert_eq!(picked, ["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn least_loaded_prefers_accounts_with_fewer_requests_in_flight() {
+        let pool = pool(PoolStrategy::LeastLoaded, false);
+        let first = pool.pick(None, &[]).unwrap();
+        let second = pool.pick(None, &[]).unwrap();
+        assert_eq!((account(&first), account(&second)), ("a", "b"));
+
+        // `a` finished its request, `b` is still busy
+        drop(first);
+        assert_eq!(account(&pool.pick(None, &[]).unwrap()), "c");
+        assert_eq!(account(&pool.pick(None, &[]).unwrap()), "a");
+        assert_eq!(pool.status()[1].in_flight, 1);
+    }
+
+    #[test]
+    fn leases_from_replaced_gateways_leave_the_new_counters_alone() {
+        let pool = pool(PoolStrategy::LeastLoaded, false);
+        let old = pool.pick(None, &[]).unwrap();
+        assert_eq!(account(&old), "a");
+
+        // The gateways were swapped and reuse the account ids
+        let upstreams = pool
+            .status()
+            .into_iter()
+            .map(|u| Upstream {
+                account: u.account,
+                label: u.label,
+                base_url: u.base_url,
+            })
+            .collect();
+        pool.set_upstreams(upstreams);
+        let new = pool.pick(None, &[]).unwrap();
+        assert_eq!(account(&new), "a");
+
+        drop(old);
+        assert_eq!(pool.status()[0].in_flight, 1);
+        drop(new);
+        assert_eq!(pool.status()[0].in_flight, 0);
+    }
+
+    #[test]
+    fn sticky_sessions_keep_a_client_on_its_account() {
+        let pool = pool(PoolStrategy::RoundRobin, true);
+        assert_eq!(account(&pool.pick(Some("client-1"), &[]).unwrap()), "a");
+        assert_eq!(account(&pool.pick(Some("client-2"), &[]).unwrap()), "b");
+        assert_eq!(account(&pool.pick(Some("client-1"), &[]).unwrap()), "a");
+
+        // A failed account releases the clients stuck to it
+        pool.mark_down("a", UNREACHABLE_COOLDOWN_MS, "unreachable".to_string());
+        assert_eq!(account(&pool.pick(Some("client-1"), &[]).unwrap()), "c");
+    }
+
+    #[test]
+    fn failed_accounts_are_skipped_until_they_recover() {
+        let pool = pool(PoolStrategy::RoundRobin, false);
+        pool.mark_down("a", UNREACHABLE_COOLDOWN_MS, "unreachable".to_string());
+        pool.mark_down("b", THROTTLED_COOLDOWN_MS, "throttled".to_string());
+
+        assert_eq!(account(&pool.pick(None, &[]).unwrap()), "c");
+        assert_eq!(account(&pool.pick(None, &[]).unwrap()), "c");
+        // With every other account tried, the one recovering first is used anyway
+        assert_eq!(account(&pool.pick(None, &["c".to_string()]).unwrap()), "a");
+
+        pool.mark_up("b");
+        let status = pool.status();
+        assert!(!status[0].healthy && status[1].healthy);
+        assert_eq!(status[0].last_error.as_deref(), Some("unreachable"));
+    }
+}
@@ -0,0 +1,165 @@
+use serde::Serialize;
+use std::process::Command;
+
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+/// A process listening on a TCP port
+#[derive(Debug, Clone, Serialize)]
+pub struct PortOccupierInfo {
+    pub pid: u32,
+    pub process_name: String,
+    pub command: String,
+}
+
+#[cfg(unix)]
+fn get_process_command(pid: u32) -> String {
+    let output = Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "command="])
+        .output();
+    if let Ok(out) = output {
+        if out.status.success() {
+            return String::from_utf8_lossy(&out.stdout).trim().to_string();
+        }
+    }
+    String::new()
+}
+
+#[cfg(windows)]
+fn get_process_command(pid: u32) -> String {
+    let output = Command::new("wmic")
+        .args([
+            "process",
+            "where",
+            &format!("ProcessId={}", pid),
+            "get",
+            "CommandLine",
+            "/value",
+        ])
+        .creation_flags(0x08000000)
+        .output();
+    if let Ok(out) = output {
+        if out.status.success() {
+            let text = String::from_utf8_lossy(&out.stdout);
+            for line in text.lines() {
+                if let Some(rest) = line.strip_prefix("CommandLine=") {
+                    return rest.trim().to_string();
+                }
+            }
+        }
+    }
+    String::new()
+}
+
+/// Detect which process is listening on a TCP port.
+pub fn port_occupier(port: u16) -> Result<Option<PortOccupierInfo>, String> {
+    #[cfg(unix)]
+    {
+        let output = Command::new("lsof")
+            .args([
+                "-nP",
+                &format!("-iTCP:{}", port),
+                "-sTCP:LISTEN",
+                "-Fpc",
+            ])
+            .output()
+            .map_err(|e| format!("Failed to execute lsof: {}", e))?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let text = String::from_utf8_lossy(&output.stdout);
+        let mut pid: Option<u32> = None;
+        let mut process_name = String::new();
+
+        for line in text.lines() {
+            if let Some(rest) = line.strip_prefix('p') {
+                if pid.is_none() {
+                    pid = rest.trim().parse::<u32>().ok();
+                }
+            } else if let Some(rest) = line.strip_prefix('c') {
+                if process_name.is_empty() {
+                    process_name = rest.trim().to_string();
+                }
+            }
+            if pid.is_some() && !process_name.is_empty() {
+                break;
+            }
+        }
+
+        if let Some(found_pid) = pid {
+            let command = get_process_command(found_pid);
+            return Ok(Some(PortOccupierInfo {
+                pid: found_pid,
+                process_name,
+                command,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    #[cfg(windows)]
+    {
+        let output = Command::new("netstat")
+            .args(["-ano", "-p", "tcp"])
+            .creation_flags(0x08000000)
+            .output()
+            .map_err(|e| format!("Failed to execute netstat: {}", e))?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let text = String::from_utf8_lossy(&output.stdout);
+        let needle = format!(":{}", port);
+        let mut found_pid: Option<u32> = None;
+
+        for line in text.lines() {
+            if !line.contains("LISTENING") || !line.contains(&needle) {
+                continue;
+            }
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if let Some(pid_col) = cols.last() {
+                found_pid = pid_col.parse::<u32>().ok();
+                if found_pid.is_some() {
+                    break;
+                }
+            }
+        }
+
+        if let Some(pid) = found_pid {
+            let process_name = {
+                let out = Command::new("tasklist")
+                    .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+                    .creation_flags(0x08000000)
+                    .output();
+                if let Ok(o) = out {
+                    if o.status.success() {
+                        let row = String::from_utf8_lossy(&o.stdout);
+                        let first = row.lines().next().unwrap_or("").trim();
+                        if let Some(stripped) = first.strip_prefix('"') {
+                            stripped.split('"').next().unwrap_or("").to_string()
+                        } else {
+                            String::new()
+                        }
+                    } else {
+                        String::new()
+                    }
+                } else {
+                    String::new()
+                }
+            };
+
+            let command = get_process_command(pid);
+            return Ok(Some(PortOccupierInfo {
+                pid,
+                process_name,
+                command,
+            }));
+        }
+
+        Ok(None)
+    }
+}
@@ -0,0 +1,1166 @@
+use crate::access::AccessList;
+use crate::api_keys::{endpoint_scope, is_public, keys_match, ApiScope, KeyIdentity, KeyRegistry};
+use crate::captures::{self, Captures, Recording, REPLAY_HEADER};
+use crate::config::AppConfig;
+use crate::conversations::now_millis;
+use crate::limits::{Admission, Limiter, RefusalKind};
+use crate::metrics::Metrics;
+use crate::pool::{AccountPool, Lease, REFUSED_COOLDOWN_MS, THROTTLED_COOLDOWN_MS, UNREACHABLE_COOLDOWN_MS};
+use crate::request_log::{mask_key, RequestLog, RequestRecord};
+use crate::response_cache::{cache_key, keeps_header, CachedResponse, ResponseCache};
+use crate::tls::{Conn, Incoming};
+use crate::usage_history::UsageHistory;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Client, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+/// Headers that apply to a single connection and must not be forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// A running HTTP listener; dropping it without `stop` leaves it running
+pub struct ListenerHandle {
+    shutdown: Option<oneshot::Sender<()>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ListenerHandle {
+    pub(crate) fn new(shutdown: oneshot::Sender<()>, task: tokio::task::JoinHandle<()>) -> Self {
+        Self {
+            shutdown: Some(shutdown),
+            task,
+        }
+    }
+
+    /// Stop accepting connections and wait for open requests to finish
+    pub async fn stop(mut self) {
+        self.signal();
+        let _ = self.task.await;
+    }
+
+    /// Like `stop`, but after `timeout` cut the connections still open
+    pub async fn stop_within(mut self, timeout: std::time::Duration) {
+        self.signal();
+        if tokio::time::timeout(timeout, &mut self.task).await.is_err() {
+            self.task.abort();
+        }
+    }
+
+    /// Ask the listener to shut down without waiting for it
+    pub fn signal(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// Services shared by the proxy and the commands that inspect it
+#[derive(Clone)]
+pub struct ProxyServices {
+    pub log: Arc<RequestLog>,
+    pub keys: Arc<KeyRegistry>,
+    pub limiter: Arc<Limiter>,
+    pub metrics: Arc<Metrics>,
+    pub history: Arc<UsageHistory>,
+    pub pool: Arc<AccountPool>,
+    pub cache: Arc<ResponseCache>,
+    pub captures: Arc<Captures>,
+    pub access: Arc<AccessList>,
+}
+
+impl ProxyServices {
+    /// Open the request log, key registry, quota counters and usage history
+    pub fn open() -> Self {
+        Self {
+            log: Arc::new(RequestLog::open()),
+            keys: Arc::new(KeyRegistry::open()),
+            limiter: Arc::new(Limiter::open()),
+            metrics: Arc::new(Metrics::default()),
+            history: Arc::new(UsageHistory::open()),
+            pool: Arc::new(AccountPool::default()),
+            cache: Arc::new(ResponseCache::default()),
+            captures: Arc::new(Captures::default()),
+            access: Arc::new(AccessList::default()),
+        }
+    }
+
+    /// Apply the settings from `config` that take effect without a restart
+    pub fn configure(&self, config: &AppConfig) {
+        self.limiter.configure(config);
+        self.history.set_retention_days(config.usage_history_days);
+        self.pool.configure(config);
+        self.cache.configure(config);
+        self.captures.configure(config);
+        self.access.configure(config);
+    }
+}
+
+/// Keys and services of the proxy; it forwards to the gateways in `services.pool`
+pub struct ProxyOptions {
+    /// The gateway's own key; accepted from clients and sent upstream for client keys
+    pub master_key: String,
+    pub services: ProxyServices,
+    /// Serve HTTPS with these settings instead of plain HTTP
+    pub tls: Option<Arc<tokio_rustls::rustls::ServerConfig>>,
+}
+
+/// Shared state of the proxy's request handlers
+struct ProxyContext {
+    master_key: String,
+    client: Client<hyper::client::HttpConnector>,
+    services: ProxyServices,
+}
+
+/// Start a reverse proxy on `listen` that checks client addresses, keys and
+/// limits, forwards every request to a gateway of the account pool and records
+/// it in the request log (and in a capture file, if debug captures select it).
+/// A request an account cannot serve (unreachable, refused, throttled or out
+/// of credits) is retried on the next account.
+///
+/// Response bodies are streamed through chunk by chunk, so server-sent events
+/// reach the client as soon as the upstream produces them.
+pub async fn start(listen: SocketAddr, options: ProxyOptions) -> Result<ListenerHandle, String> {
+    let context = Arc::new(ProxyContext {
+        master_key: options.master_key,
+        client: Client::new(),
+        services: options.services,
+    });
+
+    let https = options.tls.is_some();
+    let incoming = Incoming::bind(listen, options.tls)
+        .await
+        .map_err(|e| format!("Failed to bind proxy to {}: {}", listen, e))?;
+    let make_service = make_service_fn(move |conn: &Conn| {
+        let context = context.clone();
+        let remote = conn.remote_addr();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let context = context.clone();
+                async move { Ok::<_, Infallible>(handle(context, remote, request).await) }
+            }))
+        }
+    });
+
+    let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+    let server = Server::builder(incoming).serve(make_service).with_graceful_shutdown(async {
+        let _ = shutdown_rx.await;
+    });
+    let task = tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("[Proxy] Server error: {}", e);
+        }
+    });
+    println!("[Proxy] Listening on {}{}", listen, if https { " (HTTPS)" } else { "" });
+
+    Ok(ListenerHandle::new(shutdown, task))
+}
+
+/// API key presented by the client (`Authorization: Bearer` or `x-api-key`)
+pub fn client_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+        .map(|k| k.trim().to_string())
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    // Headers named in `Connection` are hop-by-hop as well
+    let listed: Vec<HeaderName> = headers
+        .get_all(hyper::header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
+
+/// Replace a client key with the gateway's key and tell the gateway which client it was
+fn forward_as_gateway(headers: &mut HeaderMap, master_key: &str, id: &str, label: &str) {
+    let had_x_api_key = headers.remove("x-api-key").is_some();
+    headers.remove(hyper::header::AUTHORIZATION);
+    if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", master_key)) {
+        headers.insert(hyper::header::AUTHORIZATION, value);
+    }
+    if had_x_api_key {
+        if let Ok(value) = HeaderValue::from_str(master_key) {
+            headers.insert("x-api-key", value);
+        }
+    }
+    if let Ok(value) = HeaderValue::from_str(id) {
+        headers.insert("x-client-key-id", value);
+    }
+    if let Ok(value) = HeaderValue::from_str(label) {
+        headers.insert("x-client-key-label", value);
+    }
+}
+
+/// Errors the proxy answers with itself
+#[derive(Debug, Clone, Copy)]
+enum ProxyError {
+    BadRequest,
+    BadGateway,
+    Authentication,
+    Permission,
+    RateLimited,
+    QuotaExceeded,
+}
+
+impl ProxyError {
+    fn status(self) -> StatusCode {
+        match self {
+            ProxyError::BadRequest => StatusCode::BAD_REQUEST,
+            ProxyError::BadGateway => StatusCode::BAD_GATEWAY,
+            ProxyError::Authentication => StatusCode::UNAUTHORIZED,
+            ProxyError::Permission => StatusCode::FORBIDDEN,
+            ProxyError::RateLimited | ProxyError::QuotaExceeded => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// `error.type` and `error.code` in OpenAI's error shape
+    fn openai_type(self) -> (&'static str, Option<&'static str>) {
+        match self {
+            ProxyError::BadRequest => ("invalid_request_error", None),
+            ProxyError::BadGateway => ("proxy_error", None),
+            ProxyError::Authentication => ("invalid_request_error", Some("invalid_api_key")),
+            ProxyError::Permission => ("invalid_request_error", Some("permission_denied")),
+            ProxyError::RateLimited => ("requests", Some("rate_limit_exceeded")),
+            ProxyError::QuotaExceeded => ("insufficient_quota", Some("insufficient_quota")),
+        }
+    }
+
+    /// `error.type` in Anthropic's error shape
+    fn anthropic_type(self) -> &'static str {
+        match self {
+            ProxyError::BadRequest => "invalid_request_error",
+            ProxyError::BadGateway => "api_error",
+            ProxyError::Authentication => "authentication_error",
+            ProxyError::Permission => "permission_error",
+            ProxyError::RateLimited | ProxyError::QuotaExceeded => "rate_limit_error",
+        }
+    }
+}
+
+/// Sessions of requests made with the gateway's own key
+const MASTER_SESSION: &str = "master";
+
+/// How long to skip the account that sent `response`, if the response means
+/// the account cannot serve requests right now
+fn failover_cooldown(response: &Response<Body>) -> Option<u64> {
+    match response.status().as_u16() {
+        429 => Some(
+            response
+                .headers()
+                .get(hyper::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(|secs| secs * 1000)
+                .unwrap_or(THROTTLED_COOLDOWN_MS),
+        ),
+        402 | 403 => Some(REFUSED_COOLDOWN_MS),
+        503 => Some(UNREACHABLE_COOLDOWN_MS),
+        _ => None,
+    }
+}
+
+/// An error response in the shape the client's API expects
+fn error_response(path: &str, error: ProxyError, message: &str) -> Response<Body> {
+    let body = if endpoint_scope(path) == Some(ApiScope::Anthropic) {
+        serde_json::json!({
+            "type": "error",
+            "error": { "type": error.anthropic_type(), "message": message }
+        })
+    } else {
+        let (kind, code) = error.openai_type();
+        serde_json::json!({
+            "error": { "message": message, "type": kind, "code": code }
+        })
+    };
+    Response::builder()
+        .status(error.status())
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
+
+async fn handle(context: Arc<ProxyContext>, remote: SocketAddr, request: Request<Body>) -> Response<MeteredBody> {
+    let started = Instant::now();
+    let (mut parts, body) = request.into_parts();
+    let path = parts.uri.path().to_string();
+    let mut record = RequestRecord {
+        id: context.services.log.next_id(),
+        started_at: now_millis(),
+        method: parts.method.to_string(),
+        endpoint: path.clone(),
+        client_key: client_key(&parts.headers).map(|k| mask_key(&k)),
+        client_address: Some(remote.ip().to_string()),
+        ..Default::default()
+    };
+
+    if !context.services.access.allows(remote.ip()) {
+        eprintln!("[Proxy] Rejected {} {} from {}: address not allowed", record.method, path, remote.ip());
+        record.error = Some(format!("Client address {} is not allowed", remote.ip()));
+        let response = error_response(&path, ProxyError::Permission, "Your network address is not allowed to use this gateway");
+        return meter(response, record, started, &context, None, None, None);
+    }
+
+    // Request bodies are small JSON documents; buffer them to read the model
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            record.error = Some(format!("Failed to read request body: {}", e));
+            let response = error_response(&path, ProxyError::BadRequest, "Failed to read request body");
+            return meter(response, record, started, &context, None, None, None);
+        }
+    };
+    record.request_bytes = body.len() as u64;
+    let json = serde_json::from_slice::<serde_json::Value>(&body).ok();
+    record.model = json
+        .as_ref()
+        .and_then(|v| v.get("model").and_then(|m| m.as_str()).map(str::to_string));
+    let stream = json
+        .as_ref()
+        .and_then(|v| v.get("stream").and_then(|s| s.as_bool()))
+        .unwrap_or(false);
+
+    let mut admission = None;
+    let mut session = None;
+    let mut recording = None;
+    if !is_public(&path) {
+        let presented = client_key(&parts.headers).unwrap_or_default();
+        let identity = if !context.master_key.is_empty() && keys_match(&presented, &context.master_key) {
+            KeyIdentity::Master
+        } else {
+            match context.services.keys.authorize(&presented, &path, record.model.as_deref()) {
+                Ok((identity, flush)) => {
+                    if flush {
+                        let keys = context.services.keys.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = keys.save().await {
+                                eprintln!("[Keys] {}", e);
+                            }
+                        });
+                    }
+                    identity
+                }
+                Err(denied) => {
+                    let error = match denied.status() {
+                        401 => ProxyError::Authentication,
+                        _ => ProxyError::Permission,
+                    };
+                    record.error = Some(denied.message());
+                    let response = error_response(&path, error, &denied.message());
+                    return meter(response, record, started, &context, None, None, None);
+                }
+            }
+        };
+
+        let key_id = match &identity {
+            KeyIdentity::Master => None,
+            KeyIdentity::Client { id, .. } => Some(id.as_str()),
+        };
+        session = Some(key_id.unwrap_or(MASTER_SESSION).to_string());
+        // Capture what the client sent, before its key is swapped for the gateway's
+        let replayed = parts.headers.contains_key(REPLAY_HEADER);
+        let capture_key = key_id.unwrap_or(MASTER_SESSION);
+        if !replayed && context.services.captures.wants(capture_key, record.model.as_deref()) {
+            let secrets = vec![context.master_key.clone(), presented.clone()];
+            recording = Some(Recording::new(&record, capture_key, &parts, &body, secrets, started));
+        }
+        match context.services.limiter.admit(key_id, stream) {
+            Ok(admitted) => admission = Some(admitted),
+            Err(refusal) => {
+                let error = match refusal.kind {
+                    RefusalKind::QuotaExceeded => ProxyError::QuotaExceeded,
+                    RefusalKind::RateLimited | RefusalKind::TooManyStreams => ProxyError::RateLimited,
+                };
+                record.error = Some(refusal.message.clone());
+                let mut response = error_response(&path, error, &refusal.message);
+                if let Ok(value) = HeaderValue::from_str(&refusal.retry_after_secs.to_string()) {
+                    response.headers_mut().insert(hyper::header::RETRY_AFTER, value);
+                }
+                return meter(response, record, started, &context, None, None, recording);
+            }
+        }
+
+        if let KeyIdentity::Client { id, label } = identity {
+            forward_as_gateway(&mut parts.headers, &context.master_key, &id, &label);
+        }
+    }
+
+    // Deterministic completions may be answered from the response cache;
+    // `Cache-Control: no-cache` skips the lookup, `no-store` the cache altogether
+    let cache = &context.services.cache;
+    let cache_control = parts
+        .headers
+        .get(hyper::header::CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let mut cache_key = match json.as_ref() {
+        Some(json) if parts.method == Method::POST && !cache_control.contains("no-store") && cache.enabled() => {
+            cache_key(&path, json)
+        }
+        _ => None,
+    };
+    if let Some(key) = cache_key.as_deref().filter(|_| !cache_control.contains("no-cache")) {
+        if let Some((cached, age_secs)) = cache.get(key) {
+            record.cached = true;
+            record.streamed = cached.streamed;
+            return meter(replay(&cached, age_secs), record, started, &context, admission, None, recording);
+        }
+    }
+
+    strip_hop_by_hop(&mut parts.headers);
+    parts.headers.remove(hyper::header::HOST);
+    if let Ok(value) = HeaderValue::from_str(&remote.ip().to_string()) {
+        parts.headers.append("x-forwarded-for", value);
+    }
+    let path_and_query = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "/".to_string());
+
+    // Try the accounts in turn until one can serve the request
+    let pool = &context.services.pool;
+    let mut tried: Vec<String> = Vec::new();
+    loop {
+        let lease = match pool.pick(session.as_deref(), &tried) {
+            Some(lease) => lease,
+            None => {
+                record.error = Some("No gateway account is available".to_string());
+                let response = error_response(&path, ProxyError::BadGateway, "No gateway account is available");
+                return meter(response, record, started, &context, None, None, recording);
+            }
+        };
+        let account = lease.upstream.account.clone();
+        record.account = Some(account.clone());
+        let uri = match format!("{}{}", lease.upstream.base_url, path_and_query).parse::<hyper::Uri>() {
+            Ok(uri) => uri,
+            Err(e) => {
+                record.error = Some(format!("Invalid upstream URI: {}", e));
+                let response = error_response(&path, ProxyError::BadGateway, "Invalid upstream URI");
+                return meter(response, record, started, &context, None, None, recording);
+            }
+        };
+        let mut upstream_request = Request::new(Body::from(body.clone()));
+        *upstream_request.method_mut() = parts.method.clone();
+        *upstream_request.uri_mut() = uri;
+        *upstream_request.headers_mut() = parts.headers.clone();
+        let last_account = tried.len() + 1 >= pool.account_count();
+
+        match context.client.request(upstream_request).await {
+            Ok(response) => {
+                match failover_cooldown(&response) {
+                    Some(cooldown) => {
+                        let reason = format!("Gateway returned {}", response.status());
+                        pool.mark_down(&account, cooldown, reason.clone());
+                        if !last_account {
+                            eprintln!("[Proxy] Account {} failed ({}), trying the next one", lease.upstream.label, reason);
+                            tried.push(account);
+                            continue;
+                        }
+                    }
+                    None => pool.mark_up(&account),
+                }
+                let (mut parts, body) = response.into_parts();
+                strip_hop_by_hop(&mut parts.headers);
+                record.streamed = parts
+                    .headers
+                    .get(hyper::header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|v| v.starts_with("text/event-stream"));
+                let status = parts.status;
+                let headers: Vec<(String, String)> = parts
+                    .headers
+                    .iter()
+                    .filter(|(name, _)| keeps_header(name.as_str()))
+                    .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+                    .collect();
+                let response = Response::from_parts(parts, body);
+                let mut response = meter(response, record, started, &context, admission, Some(lease), recording);
+                if let Some(key) = cache_key.take() {
+                    response.headers_mut().insert("x-cache", HeaderValue::from_static("MISS"));
+                    if status == StatusCode::OK {
+                        response.body_mut().capture(key, headers, cache.max_bytes());
+                    }
+                }
+                return response;
+            }
+            Err(e) => {
+                let reason = format!("Upstream request failed: {}", e);
+                pool.mark_down(&account, UNREACHABLE_COOLDOWN_MS, reason.clone());
+                if !last_account {
+                    eprintln!("[Proxy] Account {} failed ({}), trying the next one", lease.upstream.label, reason);
+                    tried.push(account);
+                    continue;
+                }
+                record.error = Some(reason);
+                let response = error_response(&path, ProxyError::BadGateway, "The gateway is not reachable");
+                return meter(response, record, started, &context, None, None, recording);
+            }
+        }
+    }
+}
+
+fn meter(
+    response: Response<Body>,
+    mut record: RequestRecord,
+    started: Instant,
+    context: &ProxyContext,
+    admission: Option<Admission>,
+    lease: Option<Lease>,
+    mut recording: Option<Recording>,
+) -> Response<MeteredBody> {
+    record.status = response.status().as_u16();
+    let scanner = UsageScanner::new(record.streamed);
+    let (parts, body) = response.into_parts();
+    if let Some(recording) = recording.as_mut() {
+        recording.respond(&parts.headers);
+    }
+    let metered = MeteredBody {
+        inner: body,
+        meter: Some(Meter {
+            record,
+            started,
+            services: context.services.clone(),
+            completed: false,
+            scanner,
+            admission,
+            lease,
+            capture: None,
+            recording,
+        }),
+    };
+    Response::from_parts(parts, metered)
+}
+
+/// A response from the cache; streams are sent in the chunks they were received in
+fn replay(cached: &CachedResponse, age_secs: u64) -> Response<Body> {
+    let body = if cached.streamed {
+        let (mut sender, body) = Body::channel();
+        let chunks = cached.chunks.clone();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                if sender.send_data(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+        body
+    } else {
+        Body::from(cached.chunks.concat())
+    };
+    let mut builder = Response::builder().status(cached.status);
+    for (name, value) in &cached.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    builder
+        .header("x-cache", "HIT")
+        .header(hyper::header::AGE, age_secs.to_string())
+        .body(body)
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
+
+/// Most of a non-streamed response body kept to read its usage
+const MAX_SCANNED_BYTES: usize = 1024 * 1024;
+
+/// Picks token usage out of a response body as it passes through: the
+/// `usage` object of a JSON response, or of the events of an SSE stream
+/// (OpenAI's final chunk, Anthropic's `message_start` and `message_delta`)
+struct UsageScanner {
+    streamed: bool,
+    buffer: Vec<u8>,
+    overflowed: bool,
+    prompt: Option<u64>,
+    completion: Option<u64>,
+}
+
+impl UsageScanner {
+    fn new(streamed: bool) -> Self {
+        Self {
+            streamed,
+            buffer: Vec::new(),
+            overflowed: false,
+            prompt: None,
+            completion: None,
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) {
+        if self.overflowed {
+            return;
+        }
+        self.buffer.extend_from_slice(chunk);
+        if self.streamed {
+            while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                self.scan_line(&line);
+            }
+        }
+        if self.buffer.len() > MAX_SCANNED_BYTES {
+            self.buffer = Vec::new();
+            self.overflowed = true;
+        }
+    }
+
+    fn finish(&mut self) {
+        let rest = std::mem::take(&mut self.buffer);
+        if self.overflowed {
+            return;
+        }
+        if self.streamed {
+            self.scan_line(&rest);
+        } else if let Ok(value) = serde_json::from_slice::<serde_json::Value>(&rest) {
+            self.take_usage(&value);
+        }
+    }
+
+    fn scan_line(&mut self, line: &[u8]) {
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => line.trim(),
+            Err(_) => return,
+        };
+        let data = match line.strip_prefix("data:") {
+            Some(data) if data.contains("\"usage\"") => data.trim(),
+            _ => return,
+        };
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(data) {
+            self.take_usage(&value);
+        }
+    }
+
+    fn take_usage(&mut self, value: &serde_json::Value) {
+        let usage = match value.get("usage").or_else(|| value.get("message").and_then(|m| m.get("usage"))) {
+            Some(usage) => usage,
+            None => return,
+        };
+        let field = |names: [&str; 2]| names.iter().find_map(|n| usage.get(*n).and_then(|v| v.as_u64()));
+        if let Some(prompt) = field(["prompt_tokens", "input_tokens"]) {
+            self.prompt = Some(self.prompt.unwrap_or(0).max(prompt));
+        }
+        if let Some(completion) = field(["completion_tokens", "output_tokens"]) {
+            self.completion = Some(self.completion.unwrap_or(0).max(completion));
+        }
+    }
+}
+
+/// Collects timing, size and usage of a response body; records the request
+/// and its tokens when dropped
+struct Meter {
+    record: RequestRecord,
+    started: Instant,
+    services: ProxyServices,
+    completed: bool,
+    scanner: UsageScanner,
+    admission: Option<Admission>,
+    /// Keeps the account's request in flight until the body is done
+    lease: Option<Lease>,
+    capture: Option<CacheCapture>,
+    /// Debug capture of the request and response
+    recording: Option<Recording>,
+}
+
+/// A response body being collected for the response cache
+struct CacheCapture {
+    key: String,
+    headers: Vec<(String, String)>,
+    chunks: Vec<hyper::body::Bytes>,
+    bytes: usize,
+    limit: usize,
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        let mut record = std::mem::take(&mut self.record);
+        record.latency_ms = self.started.elapsed().as_millis() as u64;
+        if !self.completed && record.error.is_none() {
+            record.error = Some("Client disconnected before the response finished".to_string());
+        }
+        // Replayed responses used no tokens upstream
+        if !record.cached {
+            self.scanner.finish();
+            record.prompt_tokens = self.scanner.prompt;
+            record.completion_tokens = self.scanner.completion;
+            if let Some(admission) = self.admission.take() {
+                admission.record_tokens(self.scanner.prompt.unwrap_or(0) + self.scanner.completion.unwrap_or(0));
+            }
+        }
+        if let Some(capture) = self.capture.take() {
+            if self.completed && record.error.is_none() && capture.bytes <= capture.limit {
+                let response = CachedResponse {
+                    status: record.status,
+                    headers: capture.headers,
+                    chunks: capture.chunks,
+                    streamed: record.streamed,
+                };
+                self.services
+                    .cache
+                    .insert(capture.key, record.endpoint.clone(), record.model.clone(), response);
+            }
+        }
+        if let Some(recording) = self.recording.take() {
+            captures::save_in_background(self.services.captures.clone(), recording.finish(&record));
+        }
+        self.services.metrics.observe_request(&record);
+        self.services.history.observe_request(&record);
+        self.services.log.record(record);
+    }
+}
+
+/// Response body passed through unchanged while being measured
+pub struct MeteredBody {
+    inner: Body,
+    meter: Option<Meter>,
+}
+
+impl MeteredBody {
+    /// Keep a copy of the body and store it in the response cache under `key`
+    /// once it is complete, unless it grows beyond `limit` bytes
+    fn capture(&mut self, key: String, headers: Vec<(String, String)>, limit: usize) {
+        if let Some(meter) = self.meter.as_mut() {
+            meter.capture = Some(CacheCapture {
+                key,
+                headers,
+                chunks: Vec::new(),
+                bytes: 0,
+                limit,
+            });
+        }
+    }
+}
+
+impl HttpBody for MeteredBody {
+    type Data = hyper::body::Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        let polled = Pin::new(&mut this.inner).poll_data(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(meter) = this.meter.as_mut() {
+                    if meter.record.ttfb_ms.is_none() {
+                        meter.record.ttfb_ms = Some(meter.started.elapsed().as_millis() as u64);
+                    }
+                    meter.record.response_bytes += chunk.len() as u64;
+                    meter.scanner.feed(chunk);
+                    if let Some(recording) = meter.recording.as_mut() {
+                        recording.push(chunk);
+                    }
+                    if let Some(capture) = meter.capture.as_mut() {
+                        capture.bytes += chunk.len();
+                        if capture.bytes <= capture.limit {
+                            capture.chunks.push(chunk.clone());
+                        } else {
+                            capture.chunks.clear();
+                        }
+                    }
+                }
+                // A body of known length may not be polled again after its last chunk
+                if this.inner.is_end_stream() {
+                    if let Some(mut meter) = this.meter.take() {
+                        meter.completed = true;
+                    }
+                }
+            }
+            Poll::Ready(Some(Err(e))) => {
+                if let Some(mut meter) = this.meter.take() {
+                    meter.record.error = Some(format!("Upstream body failed: {}", e));
+                }
+            }
+            Poll::Ready(None) => {
+                if let Some(mut meter) = this.meter.take() {
+                    meter.completed = true;
+                }
+            }
+            Poll::Pending => {}
+        }
+        polled
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pool::Upstream;
+    use crate::request_log::RequestLogFilter;
+    use serde_json::{json, Value};
+
+    const MASTER_KEY: &str = "master-key-for-tests";
+
+    /// A gateway stand-in answering every request with `status`; the body
+    /// tells which gateway served the request, at which path and with which key
+    async fn gateway(status: u16) -> (String, ListenerHandle) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let name = base_url.clone();
+        let make_service = make_service_fn(move |_: &hyper::server::conn::AddrStream| {
+            let name = name.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let body = json!({
+                        "gateway": name,
+                        "path": request.uri().path(),
+                        "authorization": request
+                            .headers()
+                            .get(hyper::header::AUTHORIZATION)
+                            .and_then(|v| v.to_str().ok()),
+                    });
+                    async move {
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .header(hyper::header::CONTENT_TYPE, "application/json")
+                                .body(Body::from(body.to_string()))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                let _ = shutdown_rx.await;
+            });
+        let task = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        (base_url, ListenerHandle::new(shutdown, task))
+    }
+
+    const SSE_CHUNKS: [&str; 3] = [
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}],\"usage\":{\"prompt_tokens\":7,\"completion_tokens\":2}}\n\n",
+        "data: [DONE]\n\n",
+    ];
+
+    /// A gateway stand-in streaming `SSE_CHUNKS`; everything after the first
+    /// chunk waits until `release` is notified
+    async fn sse_gateway(release: Arc<tokio::sync::Notify>) -> (String, ListenerHandle) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let make_service = make_service_fn(move |_: &hyper::server::conn::AddrStream| {
+            let release = release.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_: Request<Body>| {
+                    let release = release.clone();
+                    async move {
+                        let (mut sender, body) = Body::channel();
+                        tokio::spawn(async move {
+                            let _ = sender.send_data(SSE_CHUNKS[0].into()).await;
+                            release.notified().await;
+                            for chunk in &SSE_CHUNKS[1..] {
+                                let _ = sender.send_data((*chunk).into()).await;
+                            }
+                        });
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+                                .body(body)
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                let _ = shutdown_rx.await;
+            });
+        let task = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        (base_url, ListenerHandle::new(shutdown, task))
+    }
+
+    /// The request log entry written for requests made with `key`
+    fn logged(context: &ProxyContext, key: &str) -> RequestRecord {
+        let masked = mask_key(key);
+        context
+            .services
+            .log
+            .query(&RequestLogFilter::default())
+            .into_iter()
+            .find(|r| r.client_key.as_deref() == Some(masked.as_str()))
+            .unwrap()
+    }
+
+    /// Proxy state routing to `gateways`, one account each
+    fn proxy(gateways: &[&str], config: &AppConfig) -> Arc<ProxyContext> {
+        let services = ProxyServices::open();
+        services.pool.set_upstreams(
+            gateways
+                .iter()
+                .enumerate()
+                .map(|(i, base_url)| Upstream {
+                    account: format!("account-{}", i),
+                    label: format!("Account {}", i),
+                    base_url: base_url.to_string(),
+                })
+                .collect(),
+        );
+        services.configure(config);
+        Arc::new(ProxyContext {
+            master_key: MASTER_KEY.to_string(),
+            client: Client::new(),
+            services,
+        })
+    }
+
+    async fn send(context: &Arc<ProxyContext>, method: Method, path: &str, key: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(hyper::header::AUTHORIZATION, format!("Bearer {}", key))
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = handle(context.clone(), "127.0.0.1:50000".parse().unwrap(), request).await;
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    async fn sse_chunks_pass_through_as_they_arrive() {
+        let release = Arc::new(tokio::sync::Notify::new());
+        let (url, _gateway) = sse_gateway(release.clone()).await;
+        let context = proxy(&[&url], &AppConfig::default());
+        let created = context
+            .services
+            .keys
+            .create("sse test".to_string(), vec![ApiScope::Openai], Vec::new(), None)
+            .await
+            .unwrap();
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/v1/chat/completions")
+            .header(hyper::header::AUTHORIZATION, format!("Bearer {}", created.secret))
+            .body(Body::from(json!({ "model": "m", "stream": true }).to_string()))
+            .unwrap();
+        let response = handle(context.clone(), "127.0.0.1:50000".parse().unwrap(), request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], "text/event-stream");
+
+        // The first event reaches the client while the gateway still holds back the rest
+        let mut body = response.into_body();
+        let first = body.data().await.unwrap().unwrap();
+        assert_eq!(first, SSE_CHUNKS[0]);
+        release.notify_one();
+        let mut rest = Vec::new();
+        while let Some(chunk) = body.data().await {
+            rest.extend_from_slice(&chunk.unwrap());
+        }
+        drop(body);
+        assert_eq!(rest, SSE_CHUNKS[1..].concat().as_bytes());
+
+        let record = logged(&context, &created.secret);
+        assert!(record.streamed);
+        assert_eq!(record.status, 200);
+        assert_eq!(record.response_bytes, SSE_CHUNKS.concat().len() as u64);
+        assert!(record.ttfb_ms.is_some_and(|ttfb| ttfb <= record.latency_ms));
+        assert_eq!((record.prompt_tokens, record.completion_tokens), (Some(7), Some(2)));
+        assert_eq!(record.error, None);
+    }
+
+    #[tokio::test]
+    async fn request_log_records_the_request_metadata() {
+        let (url, _gateway) = gateway(200).await;
+        let context = proxy(&[&url], &AppConfig::default());
+        let created = context
+            .services
+            .keys
+            .create("log test".to_string(), vec![ApiScope::Openai], Vec::new(), None)
+            .await
+            .unwrap();
+        let request = json!({ "model": "logged-model", "messages": [] });
+
+        let (status, body) = send(&context, Method::POST, "/v1/chat/completions", &created.secret, request.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let record = logged(&context, &created.secret);
+        assert_eq!(record.method, "POST");
+        assert_eq!(record.endpoint, "/v1/chat/completions");
+        assert_eq!(record.model.as_deref(), Some("logged-model"));
+        assert_eq!(record.account.as_deref(), Some("account-0"));
+        assert_eq!(record.client_address.as_deref(), Some("127.0.0.1"));
+        assert!(!record.streamed);
+        assert_eq!(record.request_bytes, request.to_string().len() as u64);
+        assert_eq!(record.response_bytes, body.to_string().len() as u64);
+        assert!(record.ttfb_ms.is_some());
+        // The secret itself is never logged
+        assert!(!record.client_key.unwrap().contains(&created.secret));
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_accept_only_the_master_key() {
+        let (url, _gateway) = gateway(200).await;
+        let context = proxy(&[&url], &AppConfig::default());
+        let created = context
+            .services
+            .keys
+            .create("admin test".to_string(), vec![ApiScope::Openai, ApiScope::Anthropic], Vec::new(), None)
+            .await
+            .unwrap();
+
+        let (status, body) = send(&context, Method::POST, "/admin/drain", &created.secret, json!({})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["code"], "permission_denied");
+
+        let (status, body) = send(&context, Method::POST, "/admin/drain", MASTER_KEY, json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["path"], "/admin/drain");
+        assert_eq!(body["authorization"], format!("Bearer {}", MASTER_KEY));
+    }
+
+    #[tokio::test]
+    async fn model_restricted_keys_must_name_an_allowed_model() {
+        let (url, _gateway) = gateway(200).await;
+        let context = proxy(&[&url], &AppConfig::default());
+        let created = context
+            .services
+            .keys
+            .create("model test".to_string(), vec![ApiScope::Openai], vec!["allowed".to_string()], None)
+            .await
+            .unwrap();
+        let key = created.secret.as_str();
+
+        let (status, _) = send(&context, Method::POST, "/v1/chat/completions", key, json!({ "model": "allowed" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&context, Method::POST, "/v1/chat/completions", key, json!({ "model": "other" })).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&context, Method::POST, "/v1/chat/completions", key, json!({ "messages": [] })).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&context, Method::POST, "/v1/chat/completions", key, json!("not an object")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // Endpoints that take no model stay usable
+        let (status, _) = send(&context, Method::GET, "/v1/models", key, json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&context, Method::GET, "/usage", key, json!({})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["path"], "/usage");
+    }
+
+    #[tokio::test]
+    async fn round_robin_spreads_requests_over_the_gateways() {
+        let (first, _first) = gateway(200).await;
+        let (second, _second) = gateway(200).await;
+        let config = AppConfig {
+            sticky_sessions: false,
+            ..Default::default()
+        };
+        let context = proxy(&[&first, &second], &config);
+
+        let mut served = Vec::new();
+        for _ in 0..4 {
+            let (status, body) = send(&context, Method::GET, "/v1/models", MASTER_KEY, json!({})).await;
+            assert_eq!(status, StatusCode::OK);
+            served.push(body["gateway"].as_str().unwrap().to_string());
+        }
+
+        assert_eq!(served, [first.clone(), second.clone(), first, second]);
+    }
+
+    #[tokio::test]
+    async fn sticky_sessions_keep_a_client_on_one_gateway() {
+        let (first, _first) = gateway(200).await;
+        let (second, _second) = gateway(200).await;
+        let context = proxy(&[&first, &second], &AppConfig::default());
+        let created = context
+            .services
+            .keys
+            .create("sticky test".to_string(), vec![ApiScope::Openai], Vec::new(), None)
+            .await
+            .unwrap();
+
+        let (_, master) = send(&context, Method::GET, "/v1/models", MASTER_KEY, json!({})).await;
+        let (_, client) = send(&context, Method::GET, "/v1/models", &created.secret, json!({})).await;
+        for _ in 0..3 {
+            let (_, body) = send(&context, Method::GET, "/v1/models", &created.secret, json!({})).await;
+            assert_eq!(body["gateway"], client["gateway"]);
+        }
+
+        assert_ne!(master["gateway"], client["gateway"]);
+        // The gateway sees its own key, not the client's
+        assert_eq!(client["authorization"], format!("Bearer {}", MASTER_KEY));
+    }
+
+    #[tokio::test]
+    async fn unavailable_gateway_fails_over_to_the_next() {
+        let (failing, _failing) = gateway(503).await;
+        let (healthy, _healthy) = gateway(200).await;
+        let context = proxy(&[&failing, &healthy], &AppConfig::default());
+
+        let (status, body) = send(&context, Method::POST, "/v1/chat/completions", MASTER_KEY, json!({ "model": "m" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["gateway"], healthy.as_str());
+
+        let accounts = context.services.pool.status();
+        assert!(!accounts[0].healthy);
+        assert_eq!(accounts[0].failures, 1);
+        assert!(accounts[1].healthy);
+
+        // The failed gateway is skipped while it cools down
+        let (_, body) = send(&context, Method::POST, "/v1/chat/completions", MASTER_KEY, json!({ "model": "m" })).await;
+        assert_eq!(body["gateway"], healthy.as_str());
+    }
+
+    #[tokio::test]
+    async fn last_gateway_error_reaches_the_client() {
+        let (failing, _failing) = gateway(503).await;
+        let context = proxy(&[&failing], &AppConfig::default());
+
+        let (status, body) = send(&context, Method::POST, "/v1/chat/completions", MASTER_KEY, json!({ "model": "m" })).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["gateway"], failing.as_str());
+    }
+}
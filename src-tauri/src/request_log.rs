@@ -0,0 +1,186 @@
+use crate::conversations::get_conversations_path;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Requests kept in memory for queries
+const CAPACITY: usize = 10_000;
+/// Size at which the log file is rotated
+const MAX_FILE_BYTES: u64 = 20 * 1024 * 1024;
+
+/// Metadata of one request that went through the proxy
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestRecord {
+    pub id: u64,
+    /// When the request arrived (ms since the Unix epoch)
+    pub started_at: u64,
+    pub method: String,
+    /// Request path without the query string
+    pub endpoint: String,
+    /// `model` from the request body, if any
+    pub model: Option<String>,
+    /// Masked API key the client authenticated with
+    pub client_key: Option<String>,
+    /// IP address the request came from
+    #[serde(default)]
+    pub client_address: Option<String>,
+    /// Id of the pooled account that served the request
+    pub account: Option<String>,
+    /// HTTP status returned to the client (0 if none was sent)
+    pub status: u16,
+    /// Until the response body was fully sent
+    pub latency_ms: u64,
+    /// Until the first byte of the response body
+    pub ttfb_ms: Option<u64>,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    /// Whether the response was a server-sent event stream
+    pub streamed: bool,
+    /// Whether the response was replayed from the response cache
+    #[serde(default)]
+    pub cached: bool,
+    /// Token usage reported in the response body
+    pub prompt_tokens: Option<u64>,
+    pub completion_tokens: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Filter for querying the request log; all fields are optional
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestLogFilter {
+    /// Substring of the endpoint path
+    pub endpoint: Option<String>,
+    pub model: Option<String>,
+    pub client_key: Option<String>,
+    pub client_address: Option<String>,
+    pub account: Option<String>,
+    pub status_min: Option<u16>,
+    pub status_max: Option<u16>,
+    pub errors_only: bool,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl RequestLogFilter {
+    fn matches(&self, record: &RequestRecord) -> bool {
+        self.endpoint.as_ref().is_none_or(|e| record.endpoint.contains(e.as_str()))
+            && self.model.as_ref().is_none_or(|m| record.model.as_ref() == Some(m))
+            && self.client_key.as_ref().is_none_or(|k| record.client_key.as_ref() == Some(k))
+            && self.client_address.as_ref().is_none_or(|a| record.client_address.as_ref() == Some(a))
+            && self.account.as_ref().is_none_or(|a| record.account.as_ref() == Some(a))
+            && self.status_min.is_none_or(|s| record.status >= s)
+            && self.status_max.is_none_or(|s| record.status <= s)
+            && (!self.errors_only || record.error.is_some() || record.status >= 400)
+            && self.since.is_none_or(|t| record.started_at >= t)
+            && self.until.is_none_or(|t| record.started_at <= t)
+    }
+}
+
+/// Recent proxied requests in memory, appended to a JSON Lines file
+pub struct RequestLog {
+    entries: Mutex<VecDeque<RequestRecord>>,
+    next_id: Mutex<u64>,
+    path: Option<PathBuf>,
+}
+
+fn get_log_path() -> Option<PathBuf> {
+    Some(get_conversations_path().ok()?.with_file_name("request-log.jsonl"))
+}
+
+/// Shorten an API key to something recognizable that cannot be used
+pub fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let head: String = chars[..4].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
+impl RequestLog {
+    /// Open the log, loading the most recent entries from disk
+    pub fn open() -> Self {
+        let path = get_log_path();
+        let mut entries = VecDeque::new();
+        if let Some(file) = path.as_ref().and_then(|p| std::fs::File::open(p).ok()) {
+            for line in BufReader::new(file).lines().map_while(Result::ok) {
+                if let Ok(record) = serde_json::from_str::<RequestRecord>(&line) {
+                    entries.push_back(record);
+                    if entries.len() > CAPACITY {
+                        entries.pop_front();
+                    }
+                }
+            }
+        }
+        let next_id = entries.back().map(|r: &RequestRecord| r.id + 1).unwrap_or(1);
+        Self {
+            entries: Mutex::new(entries),
+            next_id: Mutex::new(next_id),
+            path,
+        }
+    }
+
+    /// Allocate an id for a new request
+    pub fn next_id(&self) -> u64 {
+        let mut next = self.next_id.lock().unwrap_or_else(|e| e.into_inner());
+        let id = *next;
+        *next += 1;
+        id
+    }
+
+    /// Add a finished request
+    pub fn record(&self, record: RequestRecord) {
+        if let Some(path) = &self.path {
+            if let Err(e) = append_line(path, &record) {
+                eprintln!("[Proxy] Failed to write request log: {}", e);
+            }
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.push_back(record);
+        if entries.len() > CAPACITY {
+            entries.pop_front();
+        }
+    }
+
+    /// Matching requests, newest first
+    pub fn query(&self, filter: &RequestLogFilter) -> Vec<RequestRecord> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .iter()
+            .rev()
+            .filter(|r| filter.matches(r))
+            .skip(filter.offset)
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    /// Remove all entries from memory and disk
+    pub fn clear(&self) -> Result<(), String> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        if let Some(path) = &self.path {
+            if path.exists() {
+                std::fs::remove_file(path).map_err(|e| format!("Failed to delete request log: {}", e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn append_line(path: &PathBuf, record: &RequestRecord) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::metadata(path).map(|m| m.len() > MAX_FILE_BYTES).unwrap_or(false) {
+        std::fs::rename(path, path.with_extension("1.jsonl"))?;
+    }
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+    let line = serde_json::to_string(record).map_err(std::io::Error::other)?;
+    writeln!(file, "{}", line)
+}
@@ -0,0 +1,305 @@
+use crate::config::AppConfig;
+use crate::conversations::now_millis;
+use hyper::body::Bytes;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Endpoints whose responses may be cached
+const CACHED_ENDPOINTS: &[&str] = &["/v1/chat/completions", "/v1/messages"];
+/// Request fields that do not affect the response
+const IGNORED_FIELDS: &[&str] = &["user", "metadata"];
+/// Response headers replayed from a cached response
+const KEPT_HEADERS: &[&str] = &["content-type", "anthropic-version", "openai-version"];
+
+/// A complete upstream response, body kept in the chunks it arrived in
+#[derive(Debug)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub chunks: Vec<Bytes>,
+    pub streamed: bool,
+}
+
+struct Entry {
+    response: Arc<CachedResponse>,
+    endpoint: String,
+    model: Option<String>,
+    bytes: usize,
+    created_at: u64,
+    expires_at: u64,
+    hits: u64,
+    /// Position in use order, for evicting the least recently used entry
+    used: u64,
+}
+
+/// One cached response, without its body
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheEntryInfo {
+    pub key: String,
+    pub endpoint: String,
+    pub model: Option<String>,
+    pub streamed: bool,
+    pub bytes: usize,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub hits: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    pub ttl_secs: u64,
+    /// Since the app started
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    enabled: bool,
+    ttl_secs: u64,
+    max_bytes: usize,
+    entries: HashMap<String, Entry>,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+    clock: u64,
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &str) -> bool {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.bytes -= entry.bytes;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop expired entries, then the least recently used until the cache fits
+    fn evict(&mut self) {
+        let now = now_millis();
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.expires_at <= now)
+            .map(|(k, _)| k.clone())
+            .collect();
+        for key in expired {
+            self.remove(&key);
+        }
+        while self.bytes > self.max_bytes {
+            let oldest = match self.entries.iter().min_by_key(|(_, e)| e.used) {
+                Some((key, _)) => key.clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+        }
+    }
+}
+
+/// Responses to deterministic requests (temperature 0), replayed instead of
+/// asking the gateway again
+#[derive(Default)]
+pub struct ResponseCache {
+    state: Mutex<CacheState>,
+}
+
+impl ResponseCache {
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply the cache settings from `config`; disabling it empties the cache
+    pub fn configure(&self, config: &AppConfig) {
+        let mut state = self.lock();
+        state.enabled = config.response_cache_enabled;
+        state.ttl_secs = config.response_cache_ttl_secs;
+        state.max_bytes = config.response_cache_max_mb as usize * 1024 * 1024;
+        if !state.enabled {
+            state.entries.clear();
+            state.bytes = 0;
+        }
+        state.evict();
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.lock().enabled
+    }
+
+    /// Largest response worth capturing for the cache
+    pub fn max_bytes(&self) -> usize {
+        self.lock().max_bytes
+    }
+
+    /// The cached response for `key` and its age in seconds
+    pub fn get(&self, key: &str) -> Option<(Arc<CachedResponse>, u64)> {
+        let mut state = self.lock();
+        let now = now_millis();
+        state.clock += 1;
+        let clock = state.clock;
+        let found = match state.entries.get_mut(key) {
+            Some(entry) if entry.expires_at > now => {
+                entry.hits += 1;
+                entry.used = clock;
+                Some((entry.response.clone(), now.saturating_sub(entry.created_at) / 1000))
+            }
+            Some(_) => {
+                state.remove(key);
+                None
+            }
+            None => None,
+        };
+        if found.is_some() {
+            state.hits += 1;
+        } else {
+            state.misses += 1;
+        }
+        found
+    }
+
+    /// Store a complete response for `key`
+    pub fn insert(&self, key: String, endpoint: String, model: Option<String>, response: CachedResponse) {
+        let mut state = self.lock();
+        let bytes: usize = response.chunks.iter().map(|c| c.len()).sum();
+        if !state.enabled || bytes > state.max_bytes {
+            return;
+        }
+        state.remove(&key);
+        let now = now_millis();
+        state.clock += 1;
+        let entry = Entry {
+            response: Arc::new(response),
+            endpoint,
+            model,
+            bytes,
+            created_at: now,
+            expires_at: now + state.ttl_secs * 1000,
+            hits: 0,
+            used: state.clock,
+        };
+        state.bytes += bytes;
+        state.entries.insert(key, entry);
+        state.evict();
+    }
+
+    /// Cached responses, newest first
+    pub fn entries(&self) -> Vec<CacheEntryInfo> {
+        let mut state = self.lock();
+        state.evict();
+        let mut entries: Vec<CacheEntryInfo> = state
+            .entries
+            .iter()
+            .map(|(key, e)| CacheEntryInfo {
+                key: key.clone(),
+                endpoint: e.endpoint.clone(),
+                model: e.model.clone(),
+                streamed: e.response.streamed,
+                bytes: e.bytes,
+                created_at: e.created_at,
+                expires_at: e.expires_at,
+                hits: e.hits,
+            })
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+        entries
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut state = self.lock();
+        state.evict();
+        CacheStats {
+            enabled: state.enabled,
+            entries: state.entries.len(),
+            bytes: state.bytes,
+            max_bytes: state.max_bytes,
+            ttl_secs: state.ttl_secs,
+            hits: state.hits,
+            misses: state.misses,
+        }
+    }
+
+    /// Remove one entry, or all of them; returns how many were removed
+    pub fn purge(&self, key: Option<&str>) -> usize {
+        let mut state = self.lock();
+        match key {
+            Some(key) => state.remove(key) as usize,
+            None => {
+                let count = state.entries.len();
+                state.entries.clear();
+                state.bytes = 0;
+                count
+            }
+        }
+    }
+}
+
+/// Whether a response header is replayed from the cache
+pub fn keeps_header(name: &str) -> bool {
+    KEPT_HEADERS.iter().any(|h| name.eq_ignore_ascii_case(h))
+}
+
+/// Cache key of a request to `path`, if its response can be cached: a
+/// completion with temperature 0, keyed on the body with sorted keys
+pub fn cache_key(path: &str, body: &Value) -> Option<String> {
+    if !CACHED_ENDPOINTS.contains(&path) {
+        return None;
+    }
+    let object = body.as_object()?;
+    if object.get("temperature").and_then(|t| t.as_f64()) != Some(0.0) {
+        return None;
+    }
+    let mut canonical = String::new();
+    write_canonical(body, true, &mut canonical);
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(canonical.as_bytes());
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+/// JSON with object keys sorted, so equal requests give equal text
+fn write_canonical(value: &Value, top_level: bool, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map
+                .keys()
+                .filter(|k| !top_level || !IGNORED_FIELDS.contains(&k.as_str()))
+                .collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(&map[key], false, out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, false, out);
+            }
+            out.push(']');
+        }
+        // `0` and `0.0` mean the same to the model
+        Value::Number(number) => match number.as_f64() {
+            Some(number) => out.push_str(&number.to_string()),
+            None => out.push_str(&number.to_string()),
+        },
+        other => out.push_str(&other.to_string()),
+    }
+}
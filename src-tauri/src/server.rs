@@ -1,7 +1,11 @@
-use crate::config::AppConfig;
+use crate::config::{Account, AppConfig, AuthMethod, BackendMode, ReadinessMode};
+use crate::pool::Upstream;
+use crate::ports::{port_occupier, PortOccupierInfo};
+use crate::proxy::{ListenerHandle, ProxyOptions, ProxyServices};
 use serde::{Deserialize, Serialize};
 use std::process::{Child, Command, Stdio};
 use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
@@ -22,24 +26,138 @@ pub struct ServerStatus {
     pub error: Option<String>,
 }
 
+/// Why the server did not start
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StartError {
+    /// Another process already listens on `port`
+    PortInUse {
+        port: u16,
+        occupier: Option<PortOccupierInfo>,
+        message: String,
+    },
+    Failed { message: String },
+}
+
+impl StartError {
+    fn port_in_use(port: u16) -> Self {
+        let occupier = port_occupier(port).ok().flatten();
+        let message = match &occupier {
+            Some(occupier) => format!(
+                "Port {} is already in use by {} (PID {})",
+                port, occupier.process_name, occupier.pid
+            ),
+            None => format!("Port {} is already in use by another process", port),
+        };
+        StartError::PortInUse { port, occupier, message }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            StartError::PortInUse { message, .. } | StartError::Failed { message } => message,
+        }
+    }
+}
+
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl From<String> for StartError {
+    fn from(message: String) -> Self {
+        StartError::Failed { message }
+    }
+}
+
+impl From<&str> for StartError {
+    fn from(message: &str) -> Self {
+        StartError::Failed {
+            message: message.to_string(),
+        }
+    }
+}
+
+/// A step of starting the server, reported while `start` runs
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum StartProgress {
+    /// Unpacking the bundled gateway, on the first start after an install or update
+    Extracting,
+    Spawning { gateways: usize },
+    /// Readiness check number `attempt`; `pending` are the checks not passed yet
+    WaitingForReady {
+        mode: ReadinessMode,
+        attempt: u32,
+        elapsed_ms: u64,
+        timeout_ms: u64,
+        pending: Vec<String>,
+    },
+    Ready { port: u16 },
+}
+
+/// Receives the steps of starting the server
+pub type ProgressSink = Arc<dyn Fn(&StartProgress) + Send + Sync>;
+
+/// A gateway process to start: one per pooled account, or a single one for
+/// the credential in the config
+struct GatewaySpec {
+    account: Option<Account>,
+    host: String,
+    port: u16,
+}
+
+/// What a running server was started with, for draining and restarting it
+struct RunningServer {
+    /// With the port actually served on
+    config: AppConfig,
+    /// `server_port` as configured, before a fallback port was chosen
+    configured_port: u16,
+    /// Base URLs of the gateway processes, which can be drained
+    drain_urls: Vec<String>,
+}
+
 /// Server manager for controlling the Python backend
 pub struct ServerManager {
-    process: Option<Child>,
+    processes: Vec<Child>,
+    /// Mock backends serving in place of the processes
+    mocks: Vec<ListenerHandle>,
     status: ServerStatus,
     logs: Arc<Mutex<Vec<String>>>,
+    proxy: Option<ListenerHandle>,
+    proxy_services: ProxyServices,
+    progress: Option<ProgressSink>,
+    running: Option<RunningServer>,
 }
 
 impl ServerManager {
     /// Create a new server manager
-    pub fn new() -> Self {
+    pub fn new(proxy_services: ProxyServices) -> Self {
         Self {
-            process: None,
+            processes: Vec::new(),
+            mocks: Vec::new(),
             status: ServerStatus {
                 status: "stopped".to_string(),
                 port: None,
                 error: None,
             },
             logs: Arc::new(Mutex::new(Vec::new())),
+            proxy: None,
+            proxy_services,
+            progress: None,
+            running: None,
+        }
+    }
+
+    /// Report the steps of each start to `sink`
+    pub fn on_progress(&mut self, sink: ProgressSink) {
+        self.progress = Some(sink);
+    }
+
+    fn report(&self, progress: StartProgress) {
+        if let Some(sink) = &self.progress {
+            sink(&progress);
         }
     }
 
@@ -53,13 +171,67 @@ impl ServerManager {
         self.logs.lock().unwrap().clear();
     }
 
-    /// Start the Python server with the given configuration
-    pub async fn start(&mut self, config: AppConfig) -> Result<ServerStatus, String> {
+    /// Start the Python server with the given configuration. `server_port` is
+    /// checked first, so a port held by another process fails right away (or
+    /// moves to a fallback port, reported in the returned status)
+    pub async fn start(&mut self, config: AppConfig) -> Result<ServerStatus, StartError> {
         // Stop existing server if running
-        if self.process.is_some() {
+        if !self.processes.is_empty() || !self.mocks.is_empty() || self.proxy.is_some() {
             self.stop().await?;
         }
+        self.launch(config, None).await
+    }
+
+    /// Restart with `config` without dropping connections. Behind the proxy,
+    /// new gateways are started and take over new requests before the old
+    /// ones are drained and stopped; otherwise (or when the proxy itself has
+    /// to change) this is a draining stop followed by a start
+    pub async fn restart(&mut self, config: AppConfig) -> Result<ServerStatus, StartError> {
+        let serving_port = match (&self.running, &self.proxy) {
+            (Some(running), Some(_)) if self.is_proxied(&config) && can_swap_gateways(running, &config) => {
+                running.config.server_port
+            }
+            _ => return self.start(config).await,
+        };
+        let previous = self.running.take();
+        let previous_status = self.status.clone();
+        let old_mocks = std::mem::take(&mut self.mocks);
+        let old_processes = std::mem::take(&mut self.processes);
+        match self.launch(config, Some(serving_port)).await {
+            Ok(status) => {
+                if let Some(previous) = previous {
+                    let deadline =
+                        std::time::Instant::now() + std::time::Duration::from_secs(previous.config.drain_timeout_secs);
+                    self.drain_gateways(&previous.drain_urls, &previous.config.proxy_api_key, deadline)
+                        .await;
+                    self.retire(old_mocks, old_processes, deadline).await?;
+                }
+                Ok(status)
+            }
+            Err(e) => {
+                // New gateways that did not become ready may still be running
+                self.kill_gateways();
+                // The old gateways never stopped serving
+                self.mocks = old_mocks;
+                self.processes = old_processes;
+                self.running = previous;
+                self.status = previous_status;
+                Err(e)
+            }
+        }
+    }
 
+    /// Whether `config` is served through the proxy. Client API keys are
+    /// checked by the proxy, so with any active key it runs as well; the
+    /// gateways accept only `proxy_api_key`
+    fn is_proxied(&self, config: &AppConfig) -> bool {
+        needs_proxy(config) || self.proxy_services.keys.has_active()
+    }
+
+    /// Start gateways for `config` and wait until they are ready. With
+    /// `serving_port`, the running proxy on that port is pointed at them
+    /// instead of starting a new one
+    async fn launch(&mut self, mut config: AppConfig, serving_port: Option<u16>) -> Result<ServerStatus, StartError> {
         // Update status to starting
         self.status = ServerStatus {
             status: "starting".to_string(),
@@ -67,11 +239,407 @@ impl ServerManager {
             error: None,
         };
 
-        // Get the Python executable path
-        let python_exe = self.get_python_executable_path()?;
+        if let Err(e) = crate::model_routes::validate(&config.model_routes) {
+            return Err(self.fail_start(e.into()));
+        }
+        if let Err(e) = crate::access::validate(&config).and_then(|_| crate::access::check_exposure(&config)) {
+            return Err(self.fail_start(e.into()));
+        }
+        let ready_line = match ready_line(&config) {
+            Ok(line) => line,
+            Err(e) => return Err(self.fail_start(e.into())),
+        };
+
+        let configured_port = config.server_port;
+        config.server_port = match serving_port {
+            Some(port) => port,
+            None => match choose_port(&config) {
+                Ok(port) => port,
+                Err(e) => return Err(self.fail_start(e)),
+            },
+        };
+
+        // Enabled accounts each get their own gateway, always behind the proxy
+        let accounts: Vec<Account> = config.accounts.iter().filter(|a| a.enabled).cloned().collect();
+        for (i, account) in accounts.iter().enumerate() {
+            if accounts[..i].iter().any(|other| other.id == account.id) {
+                let error = format!("Account id '{}' is used more than once", account.id);
+                return Err(self.fail_start(error.into()));
+            }
+        }
+        let pooled = !accounts.is_empty();
+        let proxied = self.is_proxied(&config);
+        let tls = if config.tls_enabled && serving_port.is_none() {
+            match crate::tls::server_config(&config).await {
+                Ok(tls) => Some(tls),
+                Err(e) => return Err(self.fail_start(e.into())),
+            }
+        } else {
+            None
+        };
+
+        // Behind the proxy the gateways only listen on loopback, on internal ports
+        let mut gateways: Vec<GatewaySpec> = Vec::new();
+        if proxied {
+            let slots: Vec<Option<Account>> = if pooled {
+                accounts.into_iter().map(Some).collect()
+            } else {
+                vec![None]
+            };
+            let mut used = vec![config.server_port];
+            for (i, account) in slots.into_iter().enumerate() {
+                let port = match config.proxy_upstream_port {
+                    Some(port) => port
+                        .checked_add(i as u16)
+                        .ok_or_else(|| "The proxy upstream port range runs past 65535".to_string()),
+                    None => loop {
+                        match free_local_port() {
+                            Ok(port) if used.contains(&port) => continue,
+                            found => break found,
+                        }
+                    },
+                };
+                let port = match port {
+                    Ok(port) => port,
+                    Err(e) => return Err(self.fail_start(e.into())),
+                };
+                if port == config.server_port {
+                    let error = "The proxy upstream port must differ from the server port";
+                    return Err(self.fail_start(error.into()));
+                }
+                if config.proxy_upstream_port.is_some() && port_in_use("127.0.0.1", port) {
+                    return Err(self.fail_start(StartError::port_in_use(port)));
+                }
+                used.push(port);
+                gateways.push(GatewaySpec {
+                    account,
+                    host: "127.0.0.1".to_string(),
+                    port,
+                });
+            }
+        } else {
+            gateways.push(GatewaySpec {
+                account: None,
+                host: config.server_host.clone(),
+                port: config.server_port,
+            });
+        }
+
+        // Set by a gateway's output reader once it prints `ready_line`
+        let ready_lines: Vec<Arc<AtomicBool>> = gateways.iter().map(|_| Arc::new(AtomicBool::new(false))).collect();
+        if config.backend_mode == BackendMode::Mock {
+            self.report(StartProgress::Spawning {
+                gateways: gateways.len(),
+            });
+            if let Err(e) = self.start_mocks(&config, &gateways).await {
+                self.kill_gateways();
+                return Err(self.fail_start(e.into()));
+            }
+        } else {
+            // Get the Python executable path
+            let python_exe = self.get_python_executable_path()?;
+
+            // Clear old logs
+            self.logs.lock().unwrap().clear();
+
+            // Log the executable path for debugging
+            if let Ok(mut logs) = self.logs.lock() {
+                logs.push(format!("Starting server with executable: {}", python_exe));
+            }
+
+            self.report(StartProgress::Spawning {
+                gateways: gateways.len(),
+            });
+            for (gateway, ready) in gateways.iter().zip(&ready_lines) {
+                let watch = ready_line.clone().map(|line| (line, ready.clone()));
+                match self.spawn_gateway(&python_exe, &config, gateway, watch) {
+                    Ok(child) => self.processes.push(child),
+                    Err(e) => {
+                        self.kill_gateways();
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+
+        // Poll until every gateway is truly ready; the mock backend prints no
+        // output, so it is checked on its health endpoint instead of a log line
+        let mode = match config.backend_mode {
+            BackendMode::Mock if config.readiness_mode == ReadinessMode::LogLine => ReadinessMode::Health,
+            _ => config.readiness_mode,
+        };
+        let health_path = match config.health_path.trim() {
+            path if path.starts_with('/') => path.to_string(),
+            path => format!("/{}", path),
+        };
+        let base_urls: Vec<String> = gateways
+            .iter()
+            .map(|gateway| {
+                let host = if gateway.host == "0.0.0.0" {
+                    "127.0.0.1"
+                } else {
+                    &gateway.host
+                };
+                format!("http://{}:{}", host, gateway.port)
+            })
+            .collect();
+        let health_urls: Vec<String> = base_urls.iter().map(|url| format!("{}{}", url, health_path)).collect();
+        // What each gateway is waited on, for the logs and progress reports
+        let checks: Vec<String> = match mode {
+            ReadinessMode::Health => health_urls.clone(),
+            ReadinessMode::Models => base_urls.iter().map(|url| format!("{}/v1/models", url)).collect(),
+            ReadinessMode::LogLine => gateways
+                .iter()
+                .map(|gateway| {
+                    format!(
+                        "'{}' from the gateway on port {}",
+                        ready_line.as_deref().unwrap_or_default(),
+                        gateway.port
+                    )
+                })
+                .collect(),
+        };
+        let mut ready = vec![false; gateways.len()];
+
+        let timeout = std::time::Duration::from_secs(config.startup_timeout_secs);
+        let poll_interval = std::time::Duration::from_millis(config.startup_poll_interval_ms.max(50));
+        let start_time = std::time::Instant::now();
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(2))
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        let mut attempt = 0;
+
+        loop {
+            // Check if a process has died during startup
+            let mut failure = None;
+            for (process, gateway) in self.processes.iter_mut().zip(&gateways) {
+                match process.try_wait() {
+                    Ok(Some(exit_status)) => {
+                        failure = Some(match &gateway.account {
+                            Some(account) => format!(
+                                "Gateway for account '{}' exited during startup: {}",
+                                account.label, exit_status
+                            ),
+                            None => format!("Server process exited during startup: {}", exit_status),
+                        });
+                        break;
+                    }
+                    Ok(None) => {} // Still running, good
+                    Err(e) => {
+                        failure = Some(format!("Failed to check process status: {}", e));
+                        break;
+                    }
+                }
+            }
+            if let Some(err_msg) = failure {
+                // Without every gateway the pool is incomplete; stop the others too
+                self.kill_gateways();
+                if let Ok(mut logs) = self.logs.lock() {
+                    logs.push(format!("[Error] {}", err_msg));
+                }
+                self.status = ServerStatus {
+                    status: "error".to_string(),
+                    port: None,
+                    error: Some(err_msg.clone()),
+                };
+                return Err(err_msg.into());
+            }
+
+            attempt += 1;
+            self.report(StartProgress::WaitingForReady {
+                mode,
+                attempt,
+                elapsed_ms: start_time.elapsed().as_millis() as u64,
+                timeout_ms: timeout.as_millis() as u64,
+                pending: pending_checks(&checks, &ready),
+            });
+            for (i, check) in checks.iter().enumerate() {
+                if ready[i] {
+                    continue;
+                }
+                ready[i] = match mode {
+                    ReadinessMode::Health => probe(&client, check, None).await,
+                    ReadinessMode::Models => probe(&client, check, Some(&config.proxy_api_key)).await,
+                    ReadinessMode::LogLine => ready_lines[i].load(Ordering::Relaxed),
+                };
+                if ready[i] {
+                    if let Ok(mut logs) = self.logs.lock() {
+                        logs.push(format!("Readiness check passed: {}", check));
+                    }
+                }
+            }
+            if ready.iter().all(|r| *r) {
+                break;
+            }
+
+            // Check timeout
+            if start_time.elapsed() > timeout {
+                let err_msg = format!(
+                    "Server failed to become ready within {}s (waiting for {})",
+                    timeout.as_secs(),
+                    pending_checks(&checks, &ready).join(", ")
+                );
+                if let Ok(mut logs) = self.logs.lock() {
+                    logs.push(format!("[Error] {}", err_msg));
+                }
+                self.status = ServerStatus {
+                    status: "error".to_string(),
+                    port: Some(config.server_port),
+                    error: Some(err_msg.clone()),
+                };
+                return Err(err_msg.into());
+            }
+
+            tokio::time::sleep(poll_interval).await;
+        }
+
+        if proxied {
+            let upstreams = gateways
+                .iter()
+                .map(|gateway| {
+                    let (account, label) = match &gateway.account {
+                        Some(account) => (account.id.clone(), account.label.clone()),
+                        None => (DEFAULT_ACCOUNT.to_string(), DEFAULT_ACCOUNT.to_string()),
+                    };
+                    Upstream {
+                        account,
+                        label,
+                        base_url: format!("http://127.0.0.1:{}", gateway.port),
+                    }
+                })
+                .collect();
+            self.proxy_services.pool.set_upstreams(upstreams);
+            self.proxy_services.configure(&config);
+            let ports: Vec<String> = gateways.iter().map(|g| g.port.to_string()).collect();
+            let listen = format!("{}:{}", config.server_host, config.server_port)
+                .parse::<std::net::SocketAddr>()
+                .map_err(|e| format!("Invalid server address: {}", e))?;
+            let options = ProxyOptions {
+                master_key: config.proxy_api_key.clone(),
+                services: self.proxy_services.clone(),
+                tls,
+            };
+            let started = match serving_port {
+                Some(_) => {
+                    if let Ok(mut logs) = self.logs.lock() {
+                        logs.push(format!("Request proxy switched to gateway ports: {}", ports.join(", ")));
+                    }
+                    None
+                }
+                None => Some(crate::proxy::start(listen, options).await),
+            };
+            match started {
+                None => {}
+                Some(Ok(proxy)) => {
+                    if let Ok(mut logs) = self.logs.lock() {
+                        logs.push(format!(
+                            "Request proxy listening on {}://{} (gateway ports: {})",
+                            if config.tls_enabled { "https" } else { "http" },
+                            listen,
+                            ports.join(", ")
+                        ));
+                    }
+                    self.proxy = Some(proxy);
+                }
+                Some(Err(e)) => {
+                    self.kill_gateways();
+                    if let Ok(mut logs) = self.logs.lock() {
+                        logs.push(format!("[Error] {}", e));
+                    }
+                    self.status = ServerStatus {
+                        status: "error".to_string(),
+                        port: None,
+                        error: Some(e.clone()),
+                    };
+                    return Err(e.into());
+                }
+            }
+        }
+
+        // Metrics follow the first gateway
+        if let Some(pid) = self.processes.first().map(|p| p.id()) {
+            self.proxy_services.metrics.gateway_started(pid, health_urls[0].clone());
+        }
+
+        if config.server_port != configured_port {
+            if let Ok(mut logs) = self.logs.lock() {
+                logs.push(format!(
+                    "Port {} is in use; serving on port {} instead",
+                    configured_port, config.server_port
+                ));
+            }
+        }
+
+        // Update status to running
+        self.status = ServerStatus {
+            status: "running".to_string(),
+            port: Some(config.server_port),
+            error: None,
+        };
+        self.report(StartProgress::Ready {
+            port: config.server_port,
+        });
+        let drain_urls = match config.backend_mode {
+            BackendMode::Kiro => base_urls,
+            BackendMode::Mock => Vec::new(),
+        };
+        self.running = Some(RunningServer {
+            config,
+            configured_port,
+            drain_urls,
+        });
+
+        Ok(self.status.clone())
+    }
+
+    /// Record a start that failed before anything was spawned
+    fn fail_start(&mut self, error: StartError) -> StartError {
+        if let Ok(mut logs) = self.logs.lock() {
+            logs.push(format!("[Error] {}", error));
+        }
+        self.status = ServerStatus {
+            status: "error".to_string(),
+            port: None,
+            error: Some(error.to_string()),
+        };
+        error
+    }
+
+    /// Serve every gateway with the built-in mock backend instead of a process
+    async fn start_mocks(&mut self, config: &AppConfig, gateways: &[GatewaySpec]) -> Result<(), String> {
+        let rules = crate::mock::load_script(config.mock_script.as_deref()).await?;
+        self.logs.lock().unwrap().clear();
+        if let Ok(mut logs) = self.logs.lock() {
+            logs.push(format!(
+                "Starting mock backend ({} scripted replies, echo otherwise); Kiro is not contacted",
+                rules.len()
+            ));
+        }
+        for gateway in gateways {
+            let listen = format!("{}:{}", gateway.host, gateway.port)
+                .parse::<std::net::SocketAddr>()
+                .map_err(|e| format!("Invalid server address: {}", e))?;
+            let options = crate::mock::MockOptions {
+                api_key: config.proxy_api_key.clone(),
+                rules: rules.clone(),
+            };
+            self.mocks.push(crate::mock::start(listen, options).await?);
+        }
+        Ok(())
+    }
 
+    /// Spawn one gateway process and forward its output to the logs; with
+    /// `ready_line`, the flag is set once the process prints that text
+    fn spawn_gateway(
+        &self,
+        python_exe: &str,
+        config: &AppConfig,
+        gateway: &GatewaySpec,
+        ready_line: Option<(String, Arc<AtomicBool>)>,
+    ) -> Result<Child, String> {
         // Build environment variables from config
-        let mut cmd = Command::new(&python_exe);
+        let mut cmd = Command::new(python_exe);
 
         // In development mode, run main.py directly
         #[cfg(debug_assertions)]
@@ -79,11 +647,16 @@ impl ServerManager {
             cmd.arg("../python-backend/main.py");
         }
 
+        let region = gateway
+            .account
+            .as_ref()
+            .and_then(|a| a.region.as_deref())
+            .unwrap_or(&config.kiro_region);
         cmd.env("TAURI_MANAGED", "true")
             .env("PROXY_API_KEY", &config.proxy_api_key)
-            .env("SERVER_HOST", &config.server_host)
-            .env("SERVER_PORT", config.server_port.to_string())
-            .env("KIRO_REGION", &config.kiro_region)
+            .env("SERVER_HOST", &gateway.host)
+            .env("SERVER_PORT", gateway.port.to_string())
+            .env("KIRO_REGION", region)
             .env("FIRST_TOKEN_TIMEOUT", config.first_token_timeout.to_string())
             .env("STREAMING_READ_TIMEOUT", config.streaming_read_timeout.to_string())
             .env("FAKE_REASONING", config.fake_reasoning.to_string())
@@ -92,23 +665,29 @@ impl ServerManager {
             .env("LOG_LEVEL", &config.log_level)
             .env("DEBUG_MODE", &config.debug_mode);
 
+        // Model routing table, applied by the gateway
+        if !config.model_routes.is_empty() {
+            let routes = serde_json::to_string(&config.model_routes)
+                .map_err(|e| format!("Failed to serialize model routes: {}", e))?;
+            cmd.env("MODEL_ROUTES", routes);
+        }
+
         // Set authentication method
-        match config.auth_method {
-            crate::config::AuthMethod::RefreshToken => {
-                if let Some(token) = &config.refresh_token {
-                    cmd.env("REFRESH_TOKEN", token);
-                }
-            }
-            crate::config::AuthMethod::CredsFile => {
-                if let Some(file) = &config.kiro_creds_file {
-                    cmd.env("KIRO_CREDS_FILE", file);
-                }
-            }
-            crate::config::AuthMethod::CliDb => {
-                if let Some(db) = &config.kiro_cli_db_file {
-                    cmd.env("KIRO_CLI_DB_FILE", db);
-                }
-            }
+        match &gateway.account {
+            Some(account) => set_auth_env(
+                &mut cmd,
+                &account.auth_method,
+                &account.refresh_token,
+                &account.kiro_creds_file,
+                &account.kiro_cli_db_file,
+            ),
+            None => set_auth_env(
+                &mut cmd,
+                &config.auth_method,
+                &config.refresh_token,
+                &config.kiro_creds_file,
+                &config.kiro_cli_db_file,
+            ),
         }
 
         // Set VPN proxy if configured
@@ -137,14 +716,6 @@ impl ServerManager {
             cmd.creation_flags(CREATE_NO_WINDOW);
         }
 
-        // Clear old logs
-        self.logs.lock().unwrap().clear();
-
-        // Log the executable path for debugging
-        if let Ok(mut logs) = self.logs.lock() {
-            logs.push(format!("Starting server with executable: {}", python_exe));
-        }
-
         // Spawn the process
         let mut child = cmd.spawn().map_err(|e| {
             if let Ok(mut logs) = self.logs.lock() {
@@ -153,183 +724,184 @@ impl ServerManager {
             format!("Failed to start server: {}", e)
         })?;
 
+        // Pooled gateways tag their lines with the account
+        let prefix = gateway
+            .account
+            .as_ref()
+            .map(|a| format!("[{}] ", a.label))
+            .unwrap_or_default();
+
         // Capture stdout for logs and ready signal
         if let Some(stdout) = child.stdout.take() {
             let logs = self.logs.clone();
+            let prefix = prefix.clone();
+            let ready_line = ready_line.clone();
             let reader = BufReader::new(stdout);
             thread::spawn(move || {
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        println!("[Server] {}", line);
-                        if let Ok(mut logs) = logs.lock() {
-                            logs.push(line.clone());
-                            // Keep only last 1000 lines
-                            if logs.len() > 1000 {
-                                logs.remove(0);
-                            }
+                for line in reader.lines().map_while(Result::ok) {
+                    watch_ready_line(&ready_line, &line);
+                    let line = format!("{}{}", prefix, line);
+                    println!("[Server] {}", line);
+                    if let Ok(mut logs) = logs.lock() {
+                        logs.push(line.clone());
+                        // Keep only last 1000 lines
+                        if logs.len() > 1000 {
+                            logs.remove(0);
                         }
                     }
                 }
             });
         }
 
-        // Capture stderr for logs
+        // Capture stderr for logs (and ready signal; uvicorn logs there)
         if let Some(stderr) = child.stderr.take() {
             let logs = self.logs.clone();
             let reader = BufReader::new(stderr);
             thread::spawn(move || {
-                for line in reader.lines() {
-                    if let Ok(line) = line {
-                        eprintln!("[Server Error] {}", line);
-                        if let Ok(mut logs) = logs.lock() {
-                            logs.push(line.clone());
-                            // Keep only last 1000 lines
-                            if logs.len() > 1000 {
-                                logs.remove(0);
-                            }
+                for line in reader.lines().map_while(Result::ok) {
+                    watch_ready_line(&ready_line, &line);
+                    let line = format!("{}{}", prefix, line);
+                    eprintln!("[Server Error] {}", line);
+                    if let Ok(mut logs) = logs.lock() {
+                        logs.push(line.clone());
+                        // Keep only last 1000 lines
+                        if logs.len() > 1000 {
+                            logs.remove(0);
                         }
                     }
                 }
             });
         }
 
-        // Store the process
-        self.process = Some(child);
+        Ok(child)
+    }
 
-        // Poll /health endpoint until the server is truly ready
-        let health_url = {
-            let host = if config.server_host == "0.0.0.0" {
-                "127.0.0.1"
-            } else {
-                &config.server_host
-            };
-            format!("http://{}:{}/health", host, config.server_port)
+    /// Stop the running server: refuse new connections, let requests in
+    /// flight finish (for up to `drain_timeout_secs`), then signal the gateways
+    pub async fn stop(&mut self) -> Result<(), String> {
+        let running = self.running.take();
+        let drain_timeout = running
+            .as_ref()
+            .map(|running| std::time::Duration::from_secs(running.config.drain_timeout_secs))
+            .unwrap_or_default();
+        let deadline = std::time::Instant::now() + drain_timeout;
+
+        self.proxy_services.metrics.gateway_stopped();
+        if let Some(proxy) = self.proxy.as_mut() {
+            proxy.signal();
+        }
+        if let Some(running) = &running {
+            self.drain_gateways(&running.drain_urls, &running.config.proxy_api_key, deadline)
+                .await;
+        }
+        if let Some(proxy) = self.proxy.take() {
+            proxy
+                .stop_within(deadline.saturating_duration_since(std::time::Instant::now()))
+                .await;
+        }
+        let mocks = std::mem::take(&mut self.mocks);
+        let processes = std::mem::take(&mut self.processes);
+        self.retire(mocks, processes, deadline).await?;
+
+        // Update status
+        self.status = ServerStatus {
+            status: "stopped".to_string(),
+            port: None,
+            error: None,
         };
 
-        let timeout = std::time::Duration::from_secs(30);
-        let poll_interval = std::time::Duration::from_millis(500);
-        let start_time = std::time::Instant::now();
-        let client = reqwest::Client::builder()
+        Ok(())
+    }
+
+    /// Ask the gateways at `urls` to refuse new requests, then wait until
+    /// the requests they have in flight finish or `deadline` passes
+    async fn drain_gateways(&self, urls: &[String], api_key: &str, deadline: std::time::Instant) {
+        if urls.is_empty() || std::time::Instant::now() >= deadline {
+            return;
+        }
+        let client = match reqwest::Client::builder()
             .timeout(std::time::Duration::from_secs(2))
             .build()
-            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
-
-        loop {
-            // Check if the process has died during startup
-            if let Some(ref mut proc) = self.process {
-                match proc.try_wait() {
-                    Ok(Some(exit_status)) => {
-                        self.process = None;
-                        let err_msg = format!("Server process exited during startup: {}", exit_status);
-                        if let Ok(mut logs) = self.logs.lock() {
-                            logs.push(format!("[Error] {}", err_msg));
-                        }
-                        self.status = ServerStatus {
-                            status: "error".to_string(),
-                            port: None,
-                            error: Some(err_msg.clone()),
-                        };
-                        return Err(err_msg);
-                    }
-                    Ok(None) => {} // Still running, good
-                    Err(e) => {
-                        let err_msg = format!("Failed to check process status: {}", e);
-                        self.status = ServerStatus {
-                            status: "error".to_string(),
-                            port: None,
-                            error: Some(err_msg.clone()),
-                        };
-                        return Err(err_msg);
-                    }
-                }
-            }
+        {
+            Ok(client) => client,
+            Err(_) => return,
+        };
 
-            // Try health check
-            match client.get(&health_url).send().await {
-                Ok(resp) if resp.status().is_success() => {
-                    if let Ok(mut logs) = self.logs.lock() {
-                        logs.push(format!("Health check passed: {}", health_url));
-                    }
-                    break;
+        let started = std::time::Instant::now();
+        let mut pending = Vec::new();
+        let mut in_flight = 0;
+        for url in urls {
+            match drain_request(client.post(format!("{}/admin/drain", url)), api_key).await {
+                Some(0) => {}
+                Some(count) => {
+                    in_flight += count;
+                    pending.push(url);
                 }
-                _ => {}
+                None => eprintln!("[Server] Could not drain {}; stopping it without waiting", url),
             }
+        }
+        if pending.is_empty() {
+            return;
+        }
+        if let Ok(mut logs) = self.logs.lock() {
+            logs.push(format!("Draining gateways: waiting for {} requests in flight", in_flight));
+        }
 
-            // Check timeout
-            if start_time.elapsed() > timeout {
-                let err_msg = format!(
-                    "Server failed to become healthy within {}s (health URL: {})",
-                    timeout.as_secs(),
-                    health_url
-                );
-                if let Ok(mut logs) = self.logs.lock() {
-                    logs.push(format!("[Error] {}", err_msg));
+        while !pending.is_empty() && std::time::Instant::now() < deadline {
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+            let mut still_busy = Vec::new();
+            for url in pending {
+                if let Some(1..) = drain_request(client.get(format!("{}/admin/drain", url)), api_key).await {
+                    still_busy.push(url);
                 }
-                self.status = ServerStatus {
-                    status: "error".to_string(),
-                    port: Some(config.server_port),
-                    error: Some(err_msg.clone()),
-                };
-                return Err(err_msg);
             }
-
-            tokio::time::sleep(poll_interval).await;
+            pending = still_busy;
         }
 
-        // Update status to running
-        self.status = ServerStatus {
-            status: "running".to_string(),
-            port: Some(config.server_port),
-            error: None,
+        let line = if pending.is_empty() {
+            format!("Gateways drained in {}ms", started.elapsed().as_millis())
+        } else {
+            format!(
+                "Drain timed out; {} gateways still had requests in flight",
+                pending.len()
+            )
         };
-
-        Ok(self.status.clone())
+        println!("[Server] {}", line);
+        if let Ok(mut logs) = self.logs.lock() {
+            logs.push(line);
+        }
     }
 
-    /// Stop the running server
-    pub async fn stop(&mut self) -> Result<(), String> {
-        if let Some(mut child) = self.process.take() {
-            #[cfg(unix)]
-            let pid = child.id() as i32;
-
-            // Try graceful shutdown first - kill the entire process group
-            #[cfg(unix)]
-            unsafe {
-                // Send SIGTERM to the entire process group (negative PID)
-                libc::kill(-pid, libc::SIGTERM);
-            }
+    /// Stop mock backends and gateway processes that no longer serve: wait
+    /// for their open requests until `deadline`, then signal the processes
+    async fn retire(
+        &self,
+        mocks: Vec<ListenerHandle>,
+        mut children: Vec<Child>,
+        deadline: std::time::Instant,
+    ) -> Result<(), String> {
+        for mock in mocks {
+            mock.stop_within(deadline.saturating_duration_since(std::time::Instant::now()))
+                .await;
+        }
 
-            #[cfg(windows)]
-            {
-                // Kill the entire process tree using taskkill
-                let pid = child.id();
-                let _ = Command::new("taskkill")
-                    .args(["/F", "/T", "/PID", &pid.to_string()])
-                    .creation_flags(CREATE_NO_WINDOW)
-                    .output();
-            }
+        // Try graceful shutdown first - signal every process group
+        for child in &children {
+            signal_group(child, false);
+        }
 
-            // Wait for process to exit (with timeout)
-            let timeout = std::time::Duration::from_secs(10);
-            let start = std::time::Instant::now();
+        // Wait for the processes to exit (with timeout)
+        let timeout = std::time::Duration::from_secs(10);
+        let start = std::time::Instant::now();
 
+        for child in &mut children {
             loop {
                 match child.try_wait() {
                     Ok(Some(_)) => break,
                     Ok(None) => {
                         if start.elapsed() > timeout {
                             // Force kill the entire process group if timeout
-                            #[cfg(unix)]
-                            unsafe {
-                                libc::kill(-pid, libc::SIGKILL);
-                            }
-                            #[cfg(windows)]
-                            {
-                                let _ = Command::new("taskkill")
-                                    .args(["/F", "/T", "/PID", &child.id().to_string()])
-                                    .creation_flags(CREATE_NO_WINDOW)
-                                    .output();
-                            }
+                            signal_group(child, true);
                             break;
                         }
                         std::thread::sleep(std::time::Duration::from_millis(100));
@@ -341,13 +913,6 @@ impl ServerManager {
             }
         }
 
-        // Update status
-        self.status = ServerStatus {
-            status: "stopped".to_string(),
-            port: None,
-            error: None,
-        };
-
         Ok(())
     }
 
@@ -356,24 +921,19 @@ impl ServerManager {
         self.status.clone()
     }
 
-    /// Kill the server process synchronously (for use in window close handler)
+    /// Port the server listens on, while it is running
+    pub fn running_port(&self) -> Option<u16> {
+        self.status.port.filter(|_| self.status.status == "running")
+    }
+
+    /// Kill the server processes synchronously (for use in window close handler)
     pub fn kill_process(&mut self) {
-        if let Some(child) = self.process.take() {
-            #[cfg(unix)]
-            unsafe {
-                let pid = child.id() as i32;
-                // Kill the entire process group
-                libc::kill(-pid, libc::SIGKILL);
-            }
-            #[cfg(windows)]
-            {
-                let pid = child.id();
-                let _ = Command::new("taskkill")
-                    .args(["/F", "/T", "/PID", &pid.to_string()])
-                    .creation_flags(CREATE_NO_WINDOW)
-                    .output();
-            }
+        self.proxy_services.metrics.gateway_stopped();
+        if let Some(mut proxy) = self.proxy.take() {
+            proxy.signal();
         }
+        self.kill_gateways();
+        self.running = None;
         self.status = ServerStatus {
             status: "stopped".to_string(),
             port: None,
@@ -381,6 +941,16 @@ impl ServerManager {
         };
     }
 
+    /// Kill the gateway processes and mock backends, leaving the proxy running
+    fn kill_gateways(&mut self) {
+        for mut mock in self.mocks.drain(..) {
+            mock.signal();
+        }
+        for child in self.processes.drain(..) {
+            signal_group(&child, true);
+        }
+    }
+
     /// Get the path to the Python executable
     fn get_python_executable_path(&self) -> Result<String, String> {
         // In production, the Python executable is bundled with the app
@@ -446,6 +1016,7 @@ impl ServerManager {
                 if !tar_gz_path.exists() {
                     return Err(format!("Bundled archive not found at: {:?}", tar_gz_path));
                 }
+                self.report(StartProgress::Extracting);
 
                 // Clean and recreate extract directory
                 if extract_dir.exists() {
@@ -490,21 +1061,171 @@ impl ServerManager {
 impl Drop for ServerManager {
     fn drop(&mut self) {
         // Ensure server is stopped when manager is dropped
-        if let Some(child) = self.process.take() {
-            // Kill the entire process group
-            #[cfg(unix)]
-            unsafe {
-                let pid = child.id() as i32;
-                libc::kill(-pid, libc::SIGKILL);
+        for child in self.processes.drain(..) {
+            signal_group(&child, true);
+        }
+    }
+}
+
+/// Id and label of the gateway for the credential in the config when no accounts are pooled
+const DEFAULT_ACCOUNT: &str = "default";
+
+/// Pass a credential to the gateway
+fn set_auth_env(
+    cmd: &mut Command,
+    method: &AuthMethod,
+    refresh_token: &Option<String>,
+    kiro_creds_file: &Option<String>,
+    kiro_cli_db_file: &Option<String>,
+) {
+    match method {
+        AuthMethod::RefreshToken => {
+            if let Some(token) = refresh_token {
+                cmd.env("REFRESH_TOKEN", token);
+            }
+        }
+        AuthMethod::CredsFile => {
+            if let Some(file) = kiro_creds_file {
+                cmd.env("KIRO_CREDS_FILE", file);
             }
-            #[cfg(windows)]
-            {
-                let pid = child.id();
-                let _ = Command::new("taskkill")
-                    .args(["/F", "/T", "/PID", &pid.to_string()])
-                    .creation_flags(CREATE_NO_WINDOW)
-                    .output();
+        }
+        AuthMethod::CliDb => {
+            if let Some(db) = kiro_cli_db_file {
+                cmd.env("KIRO_CLI_DB_FILE", db);
             }
         }
     }
 }
+
+/// Terminate the process group of `child` (SIGTERM), or kill it when `force`
+fn signal_group(child: &Child, force: bool) {
+    #[cfg(unix)]
+    unsafe {
+        // Negative PID addresses the entire process group
+        let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+        libc::kill(-(child.id() as i32), signal);
+    }
+
+    #[cfg(windows)]
+    {
+        // Kill the entire process tree using taskkill
+        let _ = force;
+        let _ = Command::new("taskkill")
+            .args(["/F", "/T", "/PID", &child.id().to_string()])
+            .creation_flags(CREATE_NO_WINDOW)
+            .output();
+    }
+}
+
+/// Whether `url` answers with a success status
+async fn probe(client: &reqwest::Client, url: &str, api_key: Option<&str>) -> bool {
+    let mut request = client.get(url);
+    if let Some(key) = api_key {
+        request = request.bearer_auth(key);
+    }
+    matches!(request.send().await, Ok(response) if response.status().is_success())
+}
+
+/// The readiness checks not passed yet
+fn pending_checks(checks: &[String], ready: &[bool]) -> Vec<String> {
+    checks
+        .iter()
+        .zip(ready)
+        .filter(|(_, ready)| !**ready)
+        .map(|(check, _)| check.clone())
+        .collect()
+}
+
+/// Set the ready flag if a line of gateway output contains the ready text
+fn watch_ready_line(ready_line: &Option<(String, Arc<AtomicBool>)>, line: &str) {
+    if let Some((text, ready)) = ready_line {
+        if line.contains(text.as_str()) {
+            ready.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// How often draining gateways are asked whether requests are still in flight
+const DRAIN_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Send a drain request and return the gateway's count of requests in flight
+async fn drain_request(request: reqwest::RequestBuilder, api_key: &str) -> Option<u64> {
+    let response = request.bearer_auth(api_key).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let body: serde_json::Value = response.json().await.ok()?;
+    body.get("in_flight").and_then(|count| count.as_u64())
+}
+
+/// Whether `config` needs the proxy: pooled accounts, HTTPS, address
+/// restrictions, request limits, the response cache and debug captures are
+/// all served by it
+fn needs_proxy(config: &AppConfig) -> bool {
+    config.proxy_enabled
+        || config.accounts.iter().any(|account| account.enabled)
+        || config.tls_enabled
+        || crate::access::restricts(config)
+        || crate::limits::any_configured(config)
+        || config.response_cache_enabled
+        || config.capture_enabled
+}
+
+/// Whether the running proxy can serve `config` as it is, so a restart only
+/// needs new gateways behind it
+fn can_swap_gateways(running: &RunningServer, config: &AppConfig) -> bool {
+    let current = &running.config;
+    // The old gateways still hold a fixed upstream port until drained
+    config.proxy_upstream_port.is_none()
+        && config.server_host == current.server_host
+        && config.server_port == running.configured_port
+        && config.proxy_api_key == current.proxy_api_key
+        && config.tls_enabled == current.tls_enabled
+        && config.tls_cert_file == current.tls_cert_file
+        && config.tls_key_file == current.tls_key_file
+        && config.tls_extra_names == current.tls_extra_names
+}
+
+/// The text gateways print once they are ready, in the log line readiness mode
+pub fn ready_line(config: &AppConfig) -> Result<Option<String>, String> {
+    match config.readiness_mode {
+        ReadinessMode::LogLine => match config.readiness_log_line.as_deref().map(str::trim) {
+            Some(line) if !line.is_empty() => Ok(Some(line.to_string())),
+            _ => Err("The log line readiness mode needs a log line to wait for".to_string()),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Whether another process already listens on `host:port`
+fn port_in_use(host: &str, port: u16) -> bool {
+    matches!(
+        std::net::TcpListener::bind((host, port)),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse
+    )
+}
+
+/// `server_port`, or if another process holds it the first free port after
+/// it up to `server_port_fallback_end`
+fn choose_port(config: &AppConfig) -> Result<u16, StartError> {
+    let host = config.server_host.as_str();
+    let port = config.server_port;
+    if !port_in_use(host, port) {
+        return Ok(port);
+    }
+    let end = config.server_port_fallback_end.unwrap_or(port);
+    (port..=end)
+        .skip(1)
+        .find(|p| !port_in_use(host, *p))
+        .ok_or_else(|| StartError::port_in_use(port))
+}
+
+/// Ask the OS for a free port on the loopback interface
+fn free_local_port() -> Result<u16, String> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")
+        .map_err(|e| format!("Failed to find a free port: {}", e))?;
+    listener
+        .local_addr()
+        .map(|addr| addr.port())
+        .map_err(|e| format!("Failed to find a free port: {}", e))
+}
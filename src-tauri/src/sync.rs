@@ -0,0 +1,447 @@
+use crate::conversations::{generate_id, get_conversations_path, now_millis, write_synced, Conversation, ConversationsData};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Conflicts kept for the conflict report
+const MAX_CONFLICTS: usize = 200;
+
+/// Version vector: number of changes each device has made to a conversation
+pub type VectorClock = BTreeMap<String, u64>;
+
+/// A conversation as written to the shared folder (`conversations/<id>.json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SyncRecord {
+    id: String,
+    clock: VectorClock,
+    /// Device that wrote this version
+    device: String,
+    updated_at: u64,
+    /// Tombstone left when the conversation was permanently deleted
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    conversation: Option<Conversation>,
+}
+
+/// What this device knew about a conversation after the last sync
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SyncEntry {
+    clock: VectorClock,
+    /// Hash of the conversation as last synced; a different hash means a local change
+    hash: String,
+    #[serde(default)]
+    deleted: bool,
+}
+
+/// Local sync bookkeeping, stored next to the conversations file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SyncState {
+    device_id: String,
+    folder: Option<String>,
+    entries: HashMap<String, SyncEntry>,
+    conflicts: Vec<SyncConflict>,
+}
+
+/// How two versions of a conversation conflicted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// Both devices edited the conversation; messages were merged and the most
+    /// recently updated version's title and settings were kept
+    ConcurrentEdit,
+    /// One device deleted the conversation while another edited it; the edit was kept
+    EditedAfterDelete,
+}
+
+/// A conflict resolved during sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncConflict {
+    pub conversation_id: String,
+    pub title: String,
+    pub kind: ConflictKind,
+    /// Device whose title and settings were kept
+    pub winner_device: String,
+    pub resolved_at: u64,
+}
+
+/// Result of one sync run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncReport {
+    /// Conversations written to the shared folder
+    pub pushed: usize,
+    /// Conversations updated or added locally from the shared folder
+    pub pulled: usize,
+    /// Conversations removed locally because another device deleted them
+    pub deleted: usize,
+    pub conflicts: Vec<SyncConflict>,
+}
+
+impl SyncReport {
+    /// Whether local conversations were changed and need saving
+    pub fn changed_local(&self) -> bool {
+        self.pulled > 0 || self.deleted > 0 || !self.conflicts.is_empty()
+    }
+}
+
+/// A finished sync whose bookkeeping still has to be stored
+pub struct SyncOutcome {
+    pub report: SyncReport,
+    state: SyncState,
+}
+
+impl SyncOutcome {
+    /// Store the sync bookkeeping. Call this only after the synced
+    /// conversations have been saved, otherwise conversations missing from the
+    /// local file would look deleted to the next sync.
+    pub async fn commit(self) -> Result<SyncReport, String> {
+        save_state(&self.state).await?;
+        Ok(self.report)
+    }
+}
+
+/// Ordering of two vector clocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Causality {
+    Equal,
+    Before,
+    After,
+    Concurrent,
+}
+
+fn compare(a: &VectorClock, b: &VectorClock) -> Causality {
+    let mut less = false;
+    let mut greater = false;
+    for device in a.keys().chain(b.keys()) {
+        let x = a.get(device).copied().unwrap_or(0);
+        let y = b.get(device).copied().unwrap_or(0);
+        match x.cmp(&y) {
+            Ordering::Less => less = true,
+            Ordering::Greater => greater = true,
+            Ordering::Equal => {}
+        }
+    }
+    match (less, greater) {
+        (false, false) => Causality::Equal,
+        (true, false) => Causality::Before,
+        (false, true) => Causality::After,
+        (true, true) => Causality::Concurrent,
+    }
+}
+
+fn merge_clocks(a: &VectorClock, b: &VectorClock) -> VectorClock {
+    let mut merged = a.clone();
+    for (device, &count) in b {
+        let entry = merged.entry(device.clone()).or_insert(0);
+        *entry = (*entry).max(count);
+    }
+    merged
+}
+
+fn tick(clock: &mut VectorClock, device: &str) {
+    *clock.entry(device.to_string()).or_insert(0) += 1;
+}
+
+fn conversation_hash(conversation: &Conversation) -> String {
+    let bytes = serde_json::to_vec(conversation).unwrap_or_default();
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Merge two concurrently edited versions.
+///
+/// Messages from both sides are kept (the message tree turns diverging edits
+/// into branches); title, folder, tags and other settings come from the more
+/// recently updated version.
+fn merge_conversations(local: &Conversation, local_device: &str, remote: &Conversation, remote_device: &str) -> (Conversation, String) {
+    let remote_wins = (remote.updated_at, remote_device) > (local.updated_at, local_device);
+    let (winner, loser, device) = if remote_wins {
+        (remote, local, remote_device)
+    } else {
+        (local, remote, local_device)
+    };
+
+    let mut merged = winner.clone();
+    let known: HashSet<String> = merged.messages.iter().map(|m| m.id.clone()).collect();
+    merged
+        .messages
+        .extend(loser.messages.iter().filter(|m| !known.contains(&m.id)).cloned());
+    merged.updated_at = merged.updated_at.max(loser.updated_at);
+    (merged, device.to_string())
+}
+
+fn get_state_path() -> Result<PathBuf, String> {
+    let path = get_conversations_path()?;
+    let parent = path.parent().ok_or("Failed to get conversations directory")?;
+    Ok(parent.join("sync-state.json"))
+}
+
+async fn load_state() -> Result<SyncState, String> {
+    let path = get_state_path()?;
+    if !path.exists() {
+        return Ok(SyncState::default());
+    }
+    let content = fs::read(&path)
+        .await
+        .map_err(|e| format!("Failed to read sync state: {}", e))?;
+    serde_json::from_slice(&content).map_err(|e| format!("Failed to parse sync state: {}", e))
+}
+
+async fn save_state(state: &SyncState) -> Result<(), String> {
+    let path = get_state_path()?;
+    let content = serde_json::to_vec_pretty(state).map_err(|e| format!("Failed to serialize sync state: {}", e))?;
+    let tmp_path = path.with_extension("json.tmp");
+    write_synced(&tmp_path, &content)
+        .await
+        .map_err(|e| format!("Failed to write sync state: {}", e))?;
+    fs::rename(&tmp_path, &path)
+        .await
+        .map_err(|e| format!("Failed to rename sync state temp file: {}", e))
+}
+
+/// Read every record in the shared folder, ignoring foreign files such as
+/// "conflicted copy" duplicates created by the sync tool
+async fn read_remote(dir: &Path) -> Result<HashMap<String, SyncRecord>, String> {
+    let mut records = HashMap::new();
+    if !dir.exists() {
+        return Ok(records);
+    }
+    let mut entries = fs::read_dir(dir)
+        .await
+        .map_err(|e| format!("Failed to read sync folder: {}", e))?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let stem = match name.strip_suffix(".json") {
+            Some(stem) => stem.to_string(),
+            None => continue,
+        };
+        let record = fs::read(entry.path())
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<SyncRecord>(&bytes).ok());
+        match record {
+            Some(record) if record.id == stem => {
+                records.insert(stem, record);
+            }
+            Some(_) => {}
+            None => eprintln!("[Sync] Skipping unreadable record {}", name),
+        }
+    }
+    Ok(records)
+}
+
+async fn write_remote(dir: &Path, record: &SyncRecord) -> Result<(), String> {
+    let content = serde_json::to_vec_pretty(record).map_err(|e| format!("Failed to serialize sync record: {}", e))?;
+    let path = dir.join(format!("{}.json", record.id));
+    let tmp_path = dir.join(format!(".{}.json.tmp", record.id));
+    write_synced(&tmp_path, &content)
+        .await
+        .map_err(|e| format!("Failed to write sync record: {}", e))?;
+    fs::rename(&tmp_path, &path)
+        .await
+        .map_err(|e| format!("Failed to rename sync record: {}", e))
+}
+
+/// Synchronize `data` with the shared folder at `folder`.
+///
+/// Each conversation is stored as its own file with a vector clock. Versions
+/// that causally follow each other replace one another; concurrent versions
+/// are merged and reported as conflicts. Permanent deletions are propagated
+/// through tombstones. The caller saves `data` when `SyncReport::changed_local`
+/// is true and then commits the outcome.
+pub async fn sync(folder: &Path, data: &mut ConversationsData) -> Result<SyncOutcome, String> {
+    let remote_dir = folder.join("conversations");
+    fs::create_dir_all(&remote_dir)
+        .await
+        .map_err(|e| format!("Failed to create sync folder: {}", e))?;
+
+    let mut state = load_state().await?;
+    if state.device_id.is_empty() {
+        state.device_id = generate_id();
+    }
+    let folder_name = folder.to_string_lossy().to_string();
+    if state.folder.as_deref() != Some(folder_name.as_str()) {
+        // A different folder has its own history; start over and merge everything
+        state.entries.clear();
+        state.folder = Some(folder_name);
+    }
+    let device = state.device_id.clone();
+
+    // Local versions, with clocks advanced for everything changed since the last sync
+    let mut local: HashMap<String, SyncRecord> = HashMap::new();
+    for conversation in &data.conversations {
+        let hash = conversation_hash(conversation);
+        let entry = state.entries.get(&conversation.id).cloned().unwrap_or_default();
+        let mut clock = entry.clock;
+        if entry.hash != hash || entry.deleted {
+            tick(&mut clock, &device);
+        }
+        local.insert(
+            conversation.id.clone(),
+            SyncRecord {
+                id: conversation.id.clone(),
+                clock,
+                device: device.clone(),
+                updated_at: conversation.updated_at,
+                deleted: false,
+                conversation: Some(conversation.clone()),
+            },
+        );
+    }
+    for (id, entry) in &state.entries {
+        if local.contains_key(id) {
+            continue;
+        }
+        let mut clock = entry.clock.clone();
+        if !entry.deleted {
+            // Permanently deleted here since the last sync
+            tick(&mut clock, &device);
+        }
+        local.insert(
+            id.clone(),
+            SyncRecord {
+                id: id.clone(),
+                clock,
+                device: device.clone(),
+                updated_at: now_millis(),
+                deleted: true,
+                conversation: None,
+            },
+        );
+    }
+
+    let remote = read_remote(&remote_dir).await?;
+    let ids: BTreeSet<String> = local.keys().chain(remote.keys()).cloned().collect();
+
+    let mut report = SyncReport::default();
+    let mut results: HashMap<String, SyncRecord> = HashMap::new();
+
+    for id in ids {
+        let result = match (local.remove(&id), remote.get(&id)) {
+            (Some(l), None) => {
+                write_remote(&remote_dir, &l).await?;
+                report.pushed += 1;
+                l
+            }
+            (None, Some(r)) => {
+                if !r.deleted {
+                    report.pulled += 1;
+                }
+                r.clone()
+            }
+            (Some(l), Some(r)) => match compare(&l.clock, &r.clock) {
+                Causality::Equal => l,
+                Causality::After => {
+                    write_remote(&remote_dir, &l).await?;
+                    report.pushed += 1;
+                    l
+                }
+                Causality::Before => {
+                    match (l.deleted, r.deleted) {
+                        (false, true) => report.deleted += 1,
+                        (_, false) => report.pulled += 1,
+                        (true, true) => {}
+                    }
+                    r.clone()
+                }
+                Causality::Concurrent => {
+                    let mut clock = merge_clocks(&l.clock, &r.clock);
+                    tick(&mut clock, &device);
+                    let resolved = match (&l.conversation, &r.conversation) {
+                        (Some(lc), Some(rc)) => {
+                            let (conversation, winner) = merge_conversations(lc, &l.device, rc, &r.device);
+                            Some((conversation, winner, ConflictKind::ConcurrentEdit))
+                        }
+                        (Some(lc), None) => Some((lc.clone(), l.device.clone(), ConflictKind::EditedAfterDelete)),
+                        (None, Some(rc)) => Some((rc.clone(), r.device.clone(), ConflictKind::EditedAfterDelete)),
+                        (None, None) => None,
+                    };
+                    let merged = match resolved {
+                        // Deleted on both devices
+                        None => SyncRecord { clock, ..l },
+                        Some((conversation, winner_device, kind)) => {
+                            report.conflicts.push(SyncConflict {
+                                conversation_id: id.clone(),
+                                title: conversation.title.clone(),
+                                kind,
+                                winner_device,
+                                resolved_at: now_millis(),
+                            });
+                            report.pulled += 1;
+                            SyncRecord {
+                                id: id.clone(),
+                                clock,
+                                device: device.clone(),
+                                updated_at: conversation.updated_at,
+                                deleted: false,
+                                conversation: Some(conversation),
+                            }
+                        }
+                    };
+                    write_remote(&remote_dir, &merged).await?;
+                    report.pushed += 1;
+                    merged
+                }
+            },
+            (None, None) => continue,
+        };
+        results.insert(id, result);
+    }
+
+    // Apply the results locally
+    let mut by_id: HashMap<String, Conversation> = data
+        .conversations
+        .drain(..)
+        .map(|c| (c.id.clone(), c))
+        .collect();
+    for (id, record) in &results {
+        match &record.conversation {
+            Some(conversation) if !record.deleted => {
+                by_id.insert(id.clone(), conversation.clone());
+            }
+            _ => {
+                by_id.remove(id);
+            }
+        }
+    }
+    let mut conversations: Vec<Conversation> = by_id.into_values().collect();
+    conversations.sort_by_key(|c| std::cmp::Reverse(c.updated_at));
+    data.conversations = conversations;
+
+    state.entries = results
+        .into_iter()
+        .map(|(id, record)| {
+            let hash = record.conversation.as_ref().map(conversation_hash).unwrap_or_default();
+            (
+                id,
+                SyncEntry {
+                    clock: record.clock,
+                    hash,
+                    deleted: record.deleted,
+                },
+            )
+        })
+        .collect();
+
+    crate::attachments::sync_blobs(data, &folder.join("attachments")).await?;
+
+    state.conflicts.extend(report.conflicts.iter().cloned());
+    let excess = state.conflicts.len().saturating_sub(MAX_CONFLICTS);
+    state.conflicts.drain(..excess);
+
+    Ok(SyncOutcome { report, state })
+}
+
+/// Conflicts resolved by past syncs, most recent last
+pub async fn conflicts() -> Result<Vec<SyncConflict>, String> {
+    Ok(load_state().await?.conflicts)
+}
+
+/// Forget the conflict report
+pub async fn clear_conflicts() -> Result<(), String> {
+    let mut state = load_state().await?;
+    state.conflicts.clear();
+    save_state(&state).await
+}
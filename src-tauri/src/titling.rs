@@ -0,0 +1,493 @@
+use crate::branches::active_path;
+use crate::config::AppConfig;
+use crate::conversations::{Conversation, ConversationMessage};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Messages needed before a conversation is worth titling (one exchange)
+const TITLE_MIN_MESSAGES: usize = 2;
+/// Messages added since the last summary before it is refreshed
+const SUMMARY_EVERY_MESSAGES: usize = 10;
+/// Characters of each message sent to the model
+const MAX_MESSAGE_CHARS: usize = 2000;
+const MAX_TITLE_CHARS: usize = 80;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where a conversation's title came from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleSource {
+    /// Set by the frontend (first words of the first message)
+    #[default]
+    Client,
+    /// Generated through the gateway
+    Generated,
+    /// Set by the user; never replaced automatically
+    Manual,
+}
+
+/// What a conversation needs from the titling task
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Needs {
+    pub title: bool,
+    pub summary: bool,
+}
+
+impl Needs {
+    pub fn any(&self) -> bool {
+        self.title || self.summary
+    }
+}
+
+/// Decide whether `conversation` should get a generated title or a refreshed summary
+pub fn needs(conversation: &Conversation, config: &AppConfig) -> Needs {
+    if conversation.deleted_at.is_some() {
+        return Needs::default();
+    }
+    let count = active_path(conversation).len();
+    Needs {
+        title: config.auto_title
+            && conversation.title_source == TitleSource::Client
+            && count >= TITLE_MIN_MESSAGES,
+        summary: config.auto_summary && count >= conversation.summarized_count + SUMMARY_EVERY_MESSAGES,
+    }
+}
+
+/// Keep the title and summary produced here when the frontend saves a
+/// conversation it loaded before they were generated
+pub fn keep_generated(conversation: &mut Conversation, previous: &Conversation) {
+    conversation.summary = previous.summary.clone();
+    conversation.summarized_count = previous.summarized_count;
+    if previous.title_source != TitleSource::Client {
+        conversation.title = previous.title.clone();
+        conversation.title_source = previous.title_source;
+    }
+}
+
+/// Conversations waiting for a title or summary, without duplicates
+#[derive(Clone)]
+pub struct TitlingQueue {
+    sender: mpsc::UnboundedSender<String>,
+    pending: Arc<Mutex<HashSet<String>>>,
+}
+
+impl TitlingQueue {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<String>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (
+            Self {
+                sender,
+                pending: Arc::new(Mutex::new(HashSet::new())),
+            },
+            receiver,
+        )
+    }
+
+    /// Queue a conversation unless it is already waiting
+    pub fn enqueue(&self, id: &str) {
+        let mut pending = match self.pending.lock() {
+            Ok(pending) => pending,
+            Err(_) => return,
+        };
+        if pending.insert(id.to_string()) && self.sender.send(id.to_string()).is_err() {
+            pending.remove(id);
+        }
+    }
+
+    /// Mark a conversation as taken off the queue so it can be queued again
+    pub fn finish(&self, id: &str) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(id);
+        }
+    }
+}
+
+/// Spaces out requests to the gateway so titling never competes with chats
+pub struct RateLimiter {
+    min_interval: Duration,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Duration) -> Self {
+        Self { min_interval, last: None }
+    }
+
+    pub fn set_interval(&mut self, min_interval: Duration) {
+        self.min_interval = min_interval;
+    }
+
+    /// Wait until the next request is allowed
+    pub async fn wait(&mut self) {
+        if let Some(last) = self.last {
+            let elapsed = last.elapsed();
+            if elapsed < self.min_interval {
+                tokio::time::sleep(self.min_interval - elapsed).await;
+            }
+        }
+        self.last = Some(Instant::now());
+    }
+}
+
+/// An OpenAI-compatible chat completions endpoint
+#[derive(Debug, Clone)]
+pub struct Gateway {
+    pub base_url: String,
+    pub api_key: String,
+}
+
+impl Gateway {
+    pub fn new(base_url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: api_key.into(),
+        }
+    }
+
+    /// The local gateway described by `config`
+    pub fn from_config(config: &AppConfig) -> Self {
+        let host = if config.server_host == "0.0.0.0" {
+            "127.0.0.1"
+        } else {
+            config.server_host.as_str()
+        };
+        let scheme = if config.tls_enabled { "https" } else { "http" };
+        Self::new(format!("{}://{}:{}", scheme, host, config.server_port), config.proxy_api_key.clone())
+    }
+
+    /// Send a non-streaming chat completion and return the reply text
+    pub async fn complete(&self, client: &reqwest::Client, model: &str, messages: Vec<Value>, max_tokens: u32) -> Result<String, String> {
+        let response = client
+            .post(format!("{}/v1/chat/completions", self.base_url))
+            .bearer_auth(&self.api_key)
+            .timeout(REQUEST_TIMEOUT)
+            .json(&json!({
+                "model": model,
+                "messages": messages,
+                "max_tokens": max_tokens,
+                "stream": false,
+            }))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach gateway: {}", e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Gateway returned {}: {}", status, body));
+        }
+
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse gateway response: {}", e))?;
+        let content = &body["choices"][0]["message"]["content"];
+        let text = content_text(content);
+        if text.trim().is_empty() {
+            return Err("Gateway returned an empty reply".to_string());
+        }
+        Ok(text)
+    }
+}
+
+/// Title and summary generated for a conversation; `None` where nothing usable came back
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Generated {
+    pub title: Option<String>,
+    pub summary: Option<String>,
+}
+
+/// Ask the gateway for what `conversation` needs. A failed title request is an
+/// error (the conversation keeps the title it has); a failed summary is only logged
+pub async fn generate(
+    gateway: &Gateway,
+    client: &reqwest::Client,
+    limiter: &mut RateLimiter,
+    model: &str,
+    conversation: &Conversation,
+    needs: Needs,
+) -> Result<Generated, String> {
+    let mut generated = Generated::default();
+    if needs.title {
+        limiter.wait().await;
+        let reply = gateway.complete(client, model, title_prompt(conversation), 32).await?;
+        generated.title = clean_title(&reply);
+    }
+    if needs.summary {
+        limiter.wait().await;
+        match gateway.complete(client, model, summary_prompt(conversation), 400).await {
+            Ok(reply) => generated.summary = clean_summary(&reply),
+            Err(e) => eprintln!("[Titling] Failed to summarize {}: {}", conversation.id, e),
+        }
+    }
+    Ok(generated)
+}
+
+/// Store `generated` in `conversation`, whose active branch had
+/// `summarized_count` messages when it was sent. A title the user set
+/// while the request was running is kept.
+pub fn apply(conversation: &mut Conversation, generated: Generated, summarized_count: usize) {
+    if let Some(title) = generated.title {
+        if conversation.title_source == TitleSource::Client {
+            conversation.title = title;
+            conversation.title_source = TitleSource::Generated;
+        }
+    }
+    if generated.summary.is_some() {
+        conversation.summary = generated.summary;
+        conversation.summarized_count = summarized_count;
+    }
+}
+
+/// Plain text of message content, ignoring images and files
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn transcript(messages: &[ConversationMessage]) -> String {
+    messages
+        .iter()
+        .map(|m| {
+            let text: String = content_text(&m.content).chars().take(MAX_MESSAGE_CHARS).collect();
+            format!("{}: {}", m.role, text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Prompt asking for a short title for the start of the conversation
+pub fn title_prompt(conversation: &Conversation) -> Vec<Value> {
+    let path = active_path(conversation);
+    let opening = &path[..path.len().min(4)];
+    vec![
+        json!({
+            "role": "system",
+            "content": "You name chat conversations. Reply with a concise title of at most six words \
+                        in the language of the conversation. No quotes, no punctuation at the end, nothing else.",
+        }),
+        json!({ "role": "user", "content": transcript(opening) }),
+    ]
+}
+
+/// Prompt asking to extend the running summary with the messages added since it was written
+pub fn summary_prompt(conversation: &Conversation) -> Vec<Value> {
+    let path = active_path(conversation);
+    let new_messages = &path[conversation.summarized_count.min(path.len())..];
+    let previous = conversation.summary.as_deref().unwrap_or("(none)");
+    vec![
+        json!({
+            "role": "system",
+            "content": "You maintain a running summary of a chat conversation. Update the previous summary \
+                        with the new messages. Reply with the updated summary only, at most five sentences, \
+                        in the language of the conversation.",
+        }),
+        json!({
+            "role": "user",
+            "content": format!("Previous summary:\n{}\n\nNew messages:\n{}", previous, transcript(new_messages)),
+        }),
+    ]
+}
+
+/// Strip reasoning blocks, labels and quotes from a generated title
+pub fn clean_title(raw: &str) -> Option<String> {
+    let mut text = raw.to_string();
+    while let (Some(start), Some(end)) = (text.find("<thinking>"), text.find("</thinking>")) {
+        if end < start {
+            break;
+        }
+        text.replace_range(start..end + "</thinking>".len(), "");
+    }
+
+    let line = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line
+        .strip_prefix("Title:")
+        .or_else(|| line.strip_prefix("title:"))
+        .unwrap_or(line)
+        .trim()
+        .trim_matches(|c| matches!(c, '"' | '\'' | '“' | '”' | '*' | '#'))
+        .trim_end_matches(['.', '。'])
+        .trim();
+    if line.is_empty() {
+        return None;
+    }
+    Some(line.chars().take(MAX_TITLE_CHARS).collect())
+}
+
+/// Strip reasoning blocks from a generated summary
+pub fn clean_summary(raw: &str) -> Option<String> {
+    let text = match (raw.find("<thinking>"), raw.find("</thinking>")) {
+        (Some(start), Some(end)) if start < end => format!("{}{}", &raw[..start], &raw[end + "</thinking>".len()..]),
+        _ => raw.to_string(),
+    };
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    /// A gateway stand-in: title requests get `title` (status and reply),
+    /// summary requests `summary`. Received requests are kept in the returned list.
+    async fn stub_gateway(title: (u16, &'static str), summary: (u16, &'static str)) -> (Gateway, Arc<Mutex<Vec<Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let log = received.clone();
+        let make_service = make_service_fn(move |_: &hyper::server::conn::AddrStream| {
+            let log = log.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let log = log.clone();
+                    async move {
+                        let authorization = request
+                            .headers()
+                            .get(hyper::header::AUTHORIZATION)
+                            .and_then(|v| v.to_str().ok())
+                            .map(str::to_string);
+                        let bytes = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let mut body: Value = serde_json::from_slice(&bytes).unwrap();
+                        let is_title = body["messages"][0]["content"]
+                            .as_str()
+                            .is_some_and(|prompt| prompt.starts_with("You name chat conversations"));
+                        body["authorization"] = json!(authorization);
+                        log.lock().unwrap().push(body);
+                        let (status, reply) = if is_title { title } else { summary };
+                        let completion = json!({ "choices": [{ "message": { "role": "assistant", "content": reply } }] });
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .header(hyper::header::CONTENT_TYPE, "application/json")
+                                .body(Body::from(completion.to_string()))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::from_tcp(listener).unwrap().serve(make_service);
+        tokio::spawn(server);
+        (Gateway::new(base_url, "gateway-key"), received)
+    }
+
+    fn conversation() -> Conversation {
+        serde_json::from_value(json!({
+            "id": "c1",
+            "title": "How do I prune",
+            "createdAt": 1,
+            "updatedAt": 1,
+            "model": "claude-sonnet-4",
+            "activeLeafId": "m2",
+            "messages": [
+                { "id": "m1", "role": "user", "content": "How do I prune a young apple tree?", "timestamp": 1 },
+                { "id": "m2", "parentId": "m1", "role": "assistant", "content": "Cut in late winter.", "timestamp": 2 },
+            ],
+        }))
+        .unwrap()
+    }
+
+    /// Ask `gateway` for a title and a summary of `conversation`
+    async fn generate_both(gateway: &Gateway, conversation: &Conversation) -> Result<Generated, String> {
+        let needs = Needs { title: true, summary: true };
+        let mut limiter = RateLimiter::new(Duration::ZERO);
+        generate(gateway, &reqwest::Client::new(), &mut limiter, "titler", conversation, needs).await
+    }
+
+    #[tokio::test]
+    async fn generates_a_title_and_summary_through_the_gateway() {
+        let (gateway, received) = stub_gateway(
+            (200, "<thinking>short</thinking>Title: \"Pruning young apple trees.\""),
+            (200, "The user asked how to prune an apple tree."),
+        )
+        .await;
+        let mut conversation = conversation();
+
+        let generated = generate_both(&gateway, &conversation).await.unwrap();
+        apply(&mut conversation, generated, 2);
+
+        assert_eq!(conversation.title, "Pruning young apple trees");
+        assert_eq!(conversation.title_source, TitleSource::Generated);
+        assert_eq!(conversation.summary.as_deref(), Some("The user asked how to prune an apple tree."));
+        assert_eq!(conversation.summarized_count, 2);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0]["model"], "titler");
+        assert_eq!(received[0]["max_tokens"], 32);
+        assert_eq!(received[0]["stream"], false);
+        assert_eq!(received[0]["authorization"], "Bearer gateway-key");
+        assert!(received[0]["messages"][1]["content"]
+            .as_str()
+            .unwrap()
+            .contains("user: How do I prune a young apple tree?"));
+    }
+
+    #[tokio::test]
+    async fn gateway_error_keeps_the_current_title() {
+        let (gateway, _) = stub_gateway((500, "upstream exploded"), (200, "unused")).await;
+        let conversation = conversation();
+
+        let result = generate_both(&gateway, &conversation).await;
+
+        let error = result.unwrap_err();
+        assert!(error.starts_with("Gateway returned 500"), "{}", error);
+        assert_eq!(conversation.title, "How do I prune");
+        assert_eq!(conversation.title_source, TitleSource::Client);
+    }
+
+    #[tokio::test]
+    async fn failed_summary_still_keeps_the_title() {
+        let (gateway, _) = stub_gateway((200, "Pruning apple trees"), (503, "busy")).await;
+        let mut conversation = conversation();
+
+        let generated = generate_both(&gateway, &conversation).await.unwrap();
+        apply(&mut conversation, generated, 2);
+
+        assert_eq!(conversation.title, "Pruning apple trees");
+        assert_eq!(conversation.summary, None);
+        assert_eq!(conversation.summarized_count, 0);
+    }
+
+    #[tokio::test]
+    async fn unreachable_gateway_is_an_error() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let gateway = Gateway::new(format!("http://{}", listener.local_addr().unwrap()), "gateway-key");
+        drop(listener);
+
+        let result = generate_both(&gateway, &conversation()).await;
+
+        assert!(result.unwrap_err().starts_with("Failed to reach gateway"));
+    }
+
+    #[test]
+    fn title_set_by_the_user_meanwhile_is_kept() {
+        let mut conversation = conversation();
+        conversation.title = "Orchard notes".to_string();
+        conversation.title_source = TitleSource::Manual;
+
+        let generated = Generated {
+            title: Some("Pruning apple trees".to_string()),
+            summary: None,
+        };
+        apply(&mut conversation, generated, 2);
+
+        assert_eq!(conversation.title, "Orchard notes");
+        assert_eq!(conversation.title_source, TitleSource::Manual);
+    }
+}
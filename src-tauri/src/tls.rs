@@ -0,0 +1,525 @@
+use crate::config::AppConfig;
+use crate::conversations::get_conversations_path;
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use time::OffsetDateTime;
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Common name of the generated certificate authority
+const CA_NAME: &str = "KiroaaS Local CA";
+const CA_VALID_DAYS: i64 = 10 * 365;
+/// Browsers refuse server certificates valid for longer than 398 days
+const SERVER_VALID_DAYS: i64 = 397;
+/// Clients that do not finish the handshake in time are dropped
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Where the certificate served over HTTPS comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateSource {
+    /// `tls_cert_file` and `tls_key_file`
+    CertificateFile,
+    /// Issued at each start by the app's own certificate authority
+    LocalCa,
+}
+
+/// HTTPS settings and the certificate clients need to trust
+#[derive(Debug, Clone, Serialize)]
+pub struct TlsStatus {
+    pub enabled: bool,
+    pub source: CertificateSource,
+    /// Host names and IP addresses the generated certificate is valid for
+    pub names: Vec<String>,
+    /// PEM of the local certificate authority, once it exists
+    pub ca_certificate: Option<String>,
+    /// SHA-256 of the authority's certificate, as `AB:CD:...`
+    pub ca_fingerprint: Option<String>,
+    pub ca_path: Option<String>,
+}
+
+/// The app's certificate authority, kept in the data directory
+struct LocalCa {
+    cert_pem: String,
+    key_pem: String,
+}
+
+fn get_tls_dir() -> Result<PathBuf, String> {
+    let path = get_conversations_path()?;
+    let parent = path.parent().ok_or("Failed to get data directory")?;
+    Ok(parent.join("tls"))
+}
+
+fn uses_certificate_file(config: &AppConfig) -> Result<bool, String> {
+    let set = |path: &Option<String>| path.as_deref().is_some_and(|p| !p.trim().is_empty());
+    match (set(&config.tls_cert_file), set(&config.tls_key_file)) {
+        (true, true) => Ok(true),
+        (false, false) => Ok(false),
+        _ => Err("Set both the TLS certificate and key file, or neither to use the local CA".to_string()),
+    }
+}
+
+/// Guards creating the local CA, so concurrent callers do not each make one
+static CA_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Now moved by `days`, for certificate validity
+fn days_from_now(days: i64) -> OffsetDateTime {
+    OffsetDateTime::now_utc() + time::Duration::days(days)
+}
+
+async fn read_local_ca() -> Result<Option<LocalCa>, String> {
+    let dir = get_tls_dir()?;
+    let cert = fs::read_to_string(dir.join("ca.pem")).await;
+    let key = fs::read_to_string(dir.join("ca-key.pem")).await;
+    match (cert, key) {
+        (Ok(cert_pem), Ok(key_pem)) => Ok(Some(LocalCa { cert_pem, key_pem })),
+        (cert, key) => match [cert.err(), key.err()]
+            .into_iter()
+            .flatten()
+            .find(|e| e.kind() != io::ErrorKind::NotFound)
+        {
+            Some(e) => Err(format!("Failed to read local CA: {}", e)),
+            // Half a CA is no CA; it is created again
+            None => Ok(None),
+        },
+    }
+}
+
+/// Create a new certificate authority, replacing any existing one
+async fn create_local_ca() -> Result<LocalCa, String> {
+    let mut params = CertificateParams::default();
+    params.distinguished_name.push(DnType::CommonName, CA_NAME);
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.not_before = days_from_now(-1);
+    params.not_after = days_from_now(CA_VALID_DAYS);
+    let key = KeyPair::generate().map_err(|e| format!("Failed to generate CA key: {}", e))?;
+    let cert = params
+        .self_signed(&key)
+        .map_err(|e| format!("Failed to create CA certificate: {}", e))?;
+    let ca = LocalCa {
+        cert_pem: cert.pem(),
+        key_pem: key.serialize_pem(),
+    };
+
+    let dir = get_tls_dir()?;
+    fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create TLS directory: {}", e))?;
+    let key_path = dir.join("ca-key.pem");
+    fs::write(&key_path, &ca.key_pem)
+        .await
+        .map_err(|e| format!("Failed to write CA key: {}", e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .map_err(|e| format!("Failed to restrict CA key permissions: {}", e))?;
+    }
+    fs::write(dir.join("ca.pem"), &ca.cert_pem)
+        .await
+        .map_err(|e| format!("Failed to write CA certificate: {}", e))?;
+    println!("[TLS] Created local certificate authority in {}", dir.display());
+    Ok(ca)
+}
+
+async fn ensure_local_ca() -> Result<LocalCa, String> {
+    let _guard = CA_LOCK.lock().await;
+    match read_local_ca().await? {
+        Some(ca) => Ok(ca),
+        None => create_local_ca().await,
+    }
+}
+
+/// Replace the local certificate authority; clients must trust the new one
+pub async fn regenerate_local_ca() -> Result<(), String> {
+    let _guard = CA_LOCK.lock().await;
+    create_local_ca().await.map(|_| ())
+}
+
+fn machine_hostname() -> Option<String> {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        let result = unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+        if result != 0 {
+            return None;
+        }
+        let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+        String::from_utf8(buffer[..end].to_vec()).ok().filter(|h| !h.is_empty())
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").ok().filter(|h| !h.is_empty())
+    }
+}
+
+/// Address of the interface used to reach other hosts; connecting a UDP
+/// socket sends nothing
+pub fn lan_address() -> Option<std::net::IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_unspecified() && !ip.is_loopback())
+}
+
+/// Host names and addresses the generated server certificate covers:
+/// loopback, the machine's name and LAN address, `server_host` and `tls_extra_names`
+pub fn server_names(config: &AppConfig) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    if let Some(hostname) = machine_hostname() {
+        let short = hostname.split('.').next().unwrap_or(&hostname).to_string();
+        names.push(hostname);
+        names.push(format!("{}.local", short));
+        names.push(short);
+    }
+    if let Some(ip) = lan_address() {
+        names.push(ip.to_string());
+    }
+    let host = config.server_host.trim();
+    if !host.is_empty() && host != "0.0.0.0" && host != "::" {
+        names.push(host.to_string());
+    }
+    names.extend(config.tls_extra_names.iter().map(|n| n.trim().to_string()));
+    let mut unique: Vec<String> = Vec::new();
+    for name in names {
+        if !name.is_empty() && !unique.iter().any(|n| n.eq_ignore_ascii_case(&name)) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+/// A server certificate for `names`, signed by the local CA
+fn issue_server_certificate(
+    ca: &LocalCa,
+    names: Vec<String>,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), String> {
+    let ca_key = KeyPair::from_pem(&ca.key_pem).map_err(|e| format!("Failed to read CA key: {}", e))?;
+    let ca_cert = CertificateParams::from_ca_cert_pem(&ca.cert_pem)
+        .and_then(|params| params.self_signed(&ca_key))
+        .map_err(|e| format!("Failed to read CA certificate: {}", e))?;
+
+    let mut params =
+        CertificateParams::new(names).map_err(|e| format!("Invalid host name for the certificate: {}", e))?;
+    params.distinguished_name.push(DnType::CommonName, "KiroaaS");
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature, KeyUsagePurpose::KeyEncipherment];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.not_before = days_from_now(-1);
+    params.not_after = days_from_now(SERVER_VALID_DAYS);
+    let key = KeyPair::generate().map_err(|e| format!("Failed to generate server key: {}", e))?;
+    let cert = params
+        .signed_by(&key, &ca_cert, &ca_key)
+        .map_err(|e| format!("Failed to create server certificate: {}", e))?;
+
+    // Send the CA along so clients can build the chain
+    let chain = vec![cert.der().clone(), ca_cert.der().clone()];
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+    Ok((chain, key))
+}
+
+fn parse_certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = rustls_pemfile::certs(&mut &pem[..])
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to parse TLS certificate: {}", e))?;
+    if certs.is_empty() {
+        return Err("The TLS certificate file holds no certificate".to_string());
+    }
+    Ok(certs)
+}
+
+async fn read_certificate_file(config: &AppConfig) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), String> {
+    let cert_path = config.tls_cert_file.as_deref().unwrap_or_default().trim();
+    let key_path = config.tls_key_file.as_deref().unwrap_or_default().trim();
+    let cert_pem = fs::read(cert_path)
+        .await
+        .map_err(|e| format!("Failed to read TLS certificate {}: {}", cert_path, e))?;
+    let key_pem = fs::read(key_path)
+        .await
+        .map_err(|e| format!("Failed to read TLS key {}: {}", key_path, e))?;
+    let certs = parse_certificates(&cert_pem)?;
+    let key = rustls_pemfile::private_key(&mut &key_pem[..])
+        .map_err(|e| format!("Failed to parse TLS key: {}", e))?
+        .ok_or("The TLS key file holds no private key")?;
+    Ok((certs, key))
+}
+
+/// The TLS settings the API is served with: the configured certificate, or
+/// one issued by the local CA (created on first use)
+pub async fn server_config(config: &AppConfig) -> Result<Arc<ServerConfig>, String> {
+    let (certs, key) = if uses_certificate_file(config)? {
+        read_certificate_file(config).await?
+    } else {
+        let ca = ensure_local_ca().await?;
+        issue_server_certificate(&ca, server_names(config))?
+    };
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+    // The proxy speaks HTTP/1.1 only
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(server_config))
+}
+
+fn fingerprint(cert_pem: &str) -> Option<String> {
+    let der = parse_certificates(cert_pem.as_bytes()).ok()?.into_iter().next()?;
+    let digest = Sha256::digest(der.as_ref());
+    Some(digest.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":"))
+}
+
+pub async fn status(config: &AppConfig) -> Result<TlsStatus, String> {
+    let source = if uses_certificate_file(config)? {
+        CertificateSource::CertificateFile
+    } else {
+        CertificateSource::LocalCa
+    };
+    let ca = read_local_ca().await?;
+    let ca_path = get_tls_dir()?.join("ca.pem");
+    Ok(TlsStatus {
+        enabled: config.tls_enabled,
+        source,
+        names: server_names(config),
+        ca_fingerprint: ca.as_ref().and_then(|ca| fingerprint(&ca.cert_pem)),
+        ca_path: ca.as_ref().map(|_| ca_path.to_string_lossy().to_string()),
+        ca_certificate: ca.map(|ca| ca.cert_pem),
+    })
+}
+
+/// PEM of the local CA for clients to trust, creating the CA if needed
+pub async fn ca_certificate() -> Result<String, String> {
+    Ok(ensure_local_ca().await?.cert_pem)
+}
+
+/// An HTTP client for the app's own API, trusting the certificate it is served with.
+/// Requests go to loopback, so with a configured certificate its host names are not checked.
+pub async fn local_client(config: &AppConfig) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder();
+    if config.tls_enabled {
+        let roots = if uses_certificate_file(config)? {
+            builder = builder.danger_accept_invalid_hostnames(true);
+            let path = config.tls_cert_file.as_deref().unwrap_or_default().trim();
+            fs::read(path)
+                .await
+                .map_err(|e| format!("Failed to read TLS certificate {}: {}", path, e))?
+        } else {
+            ensure_local_ca().await?.cert_pem.into_bytes()
+        };
+        for cert in parse_certificates(&roots)? {
+            let cert = reqwest::Certificate::from_der(cert.as_ref())
+                .map_err(|e| format!("Failed to load TLS certificate: {}", e))?;
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    builder.build().map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
+
+/// A connection to the API, over plain TCP or TLS
+pub enum Conn {
+    Plain(AddrStream),
+    Tls(Box<TlsStream<TcpStream>>, SocketAddr),
+}
+
+impl Conn {
+    pub fn remote_addr(&self) -> SocketAddr {
+        match self {
+            Conn::Plain(stream) => stream.remote_addr(),
+            Conn::Tls(_, remote) => *remote,
+        }
+    }
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Conn::Tls(stream, _) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Conn::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Conn::Tls(stream, _) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Conn::Tls(stream, _) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Conn::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Conn::Tls(stream, _) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Accepts API connections; TLS handshakes run in their own tasks so a slow
+/// client does not hold up the others
+pub enum Incoming {
+    Plain(AddrIncoming),
+    Tls {
+        connections: mpsc::Receiver<Conn>,
+        acceptor: tokio::task::JoinHandle<()>,
+    },
+}
+
+impl Incoming {
+    /// Listen on `listen`, with TLS if `tls` is given
+    pub async fn bind(listen: SocketAddr, tls: Option<Arc<ServerConfig>>) -> io::Result<Self> {
+        let tls = match tls {
+            Some(tls) => tls,
+            None => return AddrIncoming::bind(&listen).map(Incoming::Plain).map_err(io::Error::other),
+        };
+        let listener = TcpListener::bind(listen).await?;
+        let acceptor = TlsAcceptor::from(tls);
+        let (sender, connections) = mpsc::channel(64);
+        let acceptor = tokio::spawn(async move {
+            loop {
+                let (stream, remote) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        eprintln!("[TLS] Failed to accept connection: {}", e);
+                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+                let _ = stream.set_nodelay(true);
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send(Conn::Tls(Box::new(stream), remote)).await;
+                        }
+                        Ok(Err(e)) => eprintln!("[TLS] Handshake with {} failed: {}", remote, e),
+                        Err(_) => eprintln!("[TLS] Handshake with {} timed out", remote),
+                    }
+                });
+            }
+        });
+        Ok(Incoming::Tls { connections, acceptor })
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Conn;
+    type Error = io::Error;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        match self.get_mut() {
+            Incoming::Plain(incoming) => Pin::new(incoming)
+                .poll_accept(cx)
+                .map(|conn| conn.map(|conn| conn.map(Conn::Plain))),
+            Incoming::Tls { connections, .. } => connections.poll_recv(cx).map(|conn| conn.map(Ok)),
+        }
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        // Free the port once the server is done with it
+        if let Incoming::Tls { acceptor, .. } = self {
+            acceptor.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    /// Answer one HTTPS request on a loopback port with `server_config`
+    async fn serve_once(server_config: Arc<ServerConfig>) -> (u16, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let acceptor = TlsAcceptor::from(server_config);
+        let task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = tokio::io::AsyncReadExt::read(&mut stream, &mut request).await;
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok")
+                .await;
+            let _ = stream.shutdown().await;
+        });
+        (port, task)
+    }
+
+    #[tokio::test]
+    async fn local_ca_is_created_once_and_trusted_by_the_local_client() {
+        let config = AppConfig {
+            tls_enabled: true,
+            ..Default::default()
+        };
+
+        let created = ca_certificate().await.unwrap();
+        assert_eq!(ca_certificate().await.unwrap(), created);
+        let status = status(&config).await.unwrap();
+        assert_eq!(status.source, CertificateSource::LocalCa);
+        assert_eq!(status.ca_certificate.as_deref(), Some(created.as_str()));
+        assert!(status.ca_fingerprint.is_some());
+        // Kept with the other stores, which tests redirect to a temp directory
+        assert!(PathBuf::from(status.ca_path.unwrap()).starts_with(get_tls_dir().unwrap()));
+        assert!(get_tls_dir().unwrap().starts_with(std::env::temp_dir()));
+
+        let (port, server) = serve_once(server_config(&config).await.unwrap()).await;
+        let response = local_client(&config)
+            .await
+            .unwrap()
+            .get(format!("https://127.0.0.1:{}/", port))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        server.await.unwrap();
+
+        regenerate_local_ca().await.unwrap();
+        assert_ne!(ca_certificate().await.unwrap(), created);
+    }
+
+    #[tokio::test]
+    async fn certificate_and_key_files_go_together() {
+        let config = AppConfig {
+            tls_enabled: true,
+            tls_cert_file: Some("cert.pem".to_string()),
+            ..Default::default()
+        };
+
+        let error = server_config(&config).await.err().unwrap();
+        assert!(error.contains("both the TLS certificate and key file"));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::conversations::{now_millis, ConversationsData};
+use crate::organization::ConversationSummary;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Move conversations to the trash, returning how many were trashed
+pub fn trash(data: &mut ConversationsData, ids: &[String]) -> usize {
+    let now = now_millis();
+    let mut count = 0;
+    for conversation in data.conversations.iter_mut() {
+        if conversation.deleted_at.is_none() && ids.contains(&conversation.id) {
+            conversation.deleted_at = Some(now);
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Restore conversations from the trash, returning how many were restored
+pub fn restore(data: &mut ConversationsData, ids: &[String]) -> usize {
+    let mut count = 0;
+    for conversation in data.conversations.iter_mut() {
+        if conversation.deleted_at.is_some() && ids.contains(&conversation.id) {
+            conversation.deleted_at = None;
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Permanently remove trashed conversations, returning how many were removed
+pub fn purge(data: &mut ConversationsData, ids: &[String]) -> usize {
+    let before = data.conversations.len();
+    data.conversations
+        .retain(|c| c.deleted_at.is_none() || !ids.contains(&c.id));
+    before - data.conversations.len()
+}
+
+/// Permanently remove everything in the trash
+pub fn empty(data: &mut ConversationsData) -> usize {
+    let before = data.conversations.len();
+    data.conversations.retain(|c| c.deleted_at.is_none());
+    before - data.conversations.len()
+}
+
+/// Permanently remove conversations trashed more than `retention_days` ago.
+///
+/// A retention of 0 keeps trashed conversations until they are purged manually.
+pub fn purge_expired(data: &mut ConversationsData, retention_days: u32) -> usize {
+    if retention_days == 0 {
+        return 0;
+    }
+    let cutoff = now_millis().saturating_sub(retention_days as u64 * DAY_MS);
+    let before = data.conversations.len();
+    data.conversations.retain(|c| match c.deleted_at {
+        Some(deleted_at) => deleted_at > cutoff,
+        None => true,
+    });
+    before - data.conversations.len()
+}
+
+/// Trashed conversations, most recently deleted first
+pub fn list(data: &ConversationsData) -> Vec<ConversationSummary> {
+    let mut trashed: Vec<_> = data
+        .conversations
+        .iter()
+        .filter(|c| c.deleted_at.is_some())
+        .collect();
+    trashed.sort_by_key(|c| std::cmp::Reverse(c.deleted_at));
+    trashed.into_iter().map(ConversationSummary::from).collect()
+}
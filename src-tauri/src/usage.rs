@@ -0,0 +1,293 @@
+use crate::config::ModelPrice;
+use crate::conversations::{Conversation, ConversationMessage, ConversationsData};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Model name used when neither the response nor the conversation names one
+const UNKNOWN_MODEL: &str = "unknown";
+
+/// Token usage reported for a single response
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MessageUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Part of `completion_tokens` spent on reasoning
+    pub reasoning_tokens: u64,
+    /// Time from sending the request to the end of the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// Model that actually produced the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Cost at the prices configured when the response was recorded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+/// Summed usage over a set of responses
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UsageTotals {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub reasoning_tokens: u64,
+    /// Number of responses with usage data
+    pub responses: u64,
+    pub total_latency_ms: u64,
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, usage: &MessageUsage) {
+        self.prompt_tokens += usage.prompt_tokens;
+        self.completion_tokens += usage.completion_tokens;
+        self.reasoning_tokens += usage.reasoning_tokens;
+        self.responses += 1;
+        self.total_latency_ms += usage.latency_ms.unwrap_or(0);
+        self.cost += usage.cost.unwrap_or(0.0);
+    }
+
+    fn merge(&mut self, other: &UsageTotals) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.reasoning_tokens += other.reasoning_tokens;
+        self.responses += other.responses;
+        self.total_latency_ms += other.total_latency_ms;
+        self.cost += other.cost;
+    }
+}
+
+/// Running usage totals by day (`YYYY-MM-DD`, local time) and model.
+///
+/// Entries are added as responses are recorded and are not reduced when
+/// conversations are deleted, since the tokens were consumed either way.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageLedger {
+    pub daily: BTreeMap<String, BTreeMap<String, UsageTotals>>,
+}
+
+/// Usage of one model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelUsage {
+    pub model: String,
+    pub usage: UsageTotals,
+}
+
+/// Usage on one day, overall and per model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyUsage {
+    pub date: String,
+    pub usage: UsageTotals,
+    pub models: Vec<ModelUsage>,
+}
+
+fn day_of(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp_millis(timestamp as i64)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+fn price_of(usage: &MessageUsage, prices: &HashMap<String, ModelPrice>, model: &str) -> Option<f64> {
+    let price = prices.get(model)?;
+    Some(
+        usage.prompt_tokens as f64 * price.input_per_million / 1_000_000.0
+            + usage.completion_tokens as f64 * price.output_per_million / 1_000_000.0,
+    )
+}
+
+/// Account for usage that arrived since `previous` was stored.
+///
+/// A response is counted once, the first time it is saved with usage data; its
+/// cost is fixed at that point. Usage recorded earlier is carried over while the
+/// message content is unchanged; a message whose content changed under the same
+/// id (e.g. a regenerated answer) is a new response. The conversation's totals
+/// are then refreshed from its messages.
+pub fn record(
+    ledger: &mut UsageLedger,
+    prices: &HashMap<String, ModelPrice>,
+    conversation: &mut Conversation,
+    previous: Option<&Conversation>,
+) {
+    let recorded: HashMap<&str, &ConversationMessage> = previous
+        .map(|p| {
+            p.messages
+                .iter()
+                .filter(|m| m.usage.is_some())
+                .map(|m| (m.id.as_str(), m))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    for message in conversation.messages.iter_mut() {
+        if let Some(stored) = recorded.get(message.id.as_str()) {
+            if stored.content == message.content {
+                message.usage = stored.usage.clone();
+                continue;
+            }
+        }
+        let usage = match message.usage.as_mut() {
+            Some(usage) => usage,
+            None => continue,
+        };
+
+        let model = usage
+            .model
+            .clone()
+            .or_else(|| conversation.model.clone())
+            .unwrap_or_else(|| UNKNOWN_MODEL.to_string());
+        usage.cost = price_of(usage, prices, &model);
+        ledger
+            .daily
+            .entry(day_of(message.timestamp))
+            .or_default()
+            .entry(model)
+            .or_default()
+            .add(usage);
+    }
+
+    let mut totals = UsageTotals::default();
+    for usage in conversation.messages.iter().filter_map(|m| m.usage.as_ref()) {
+        totals.add(usage);
+    }
+    conversation.usage_totals = totals;
+}
+
+/// Record usage for every conversation in `data` against its stored version in `previous`
+pub fn record_all(data: &mut ConversationsData, previous: &ConversationsData, prices: &HashMap<String, ModelPrice>) {
+    let stored: HashMap<&str, &Conversation> = previous.conversations.iter().map(|c| (c.id.as_str(), c)).collect();
+    for conversation in data.conversations.iter_mut() {
+        let before = stored.get(conversation.id.as_str()).copied();
+        record(&mut data.usage, prices, conversation, before);
+    }
+}
+
+/// Totals per model over all days, highest token count first
+pub fn by_model(ledger: &UsageLedger) -> Vec<ModelUsage> {
+    let mut models: BTreeMap<&str, UsageTotals> = BTreeMap::new();
+    for day in ledger.daily.values() {
+        for (model, totals) in day {
+            models.entry(model.as_str()).or_default().merge(totals);
+        }
+    }
+    let mut result: Vec<ModelUsage> = models
+        .into_iter()
+        .map(|(model, usage)| ModelUsage {
+            model: model.to_string(),
+            usage,
+        })
+        .collect();
+    result.sort_by_key(|m| std::cmp::Reverse(m.usage.prompt_tokens + m.usage.completion_tokens));
+    result
+}
+
+fn in_range(date: &str, from: Option<&str>, to: Option<&str>) -> bool {
+    match (from, to) {
+        (Some(from), _) if date < from => false,
+        (_, Some(to)) if date > to => false,
+        _ => true,
+    }
+}
+
+/// Totals per day between `from` and `to` (inclusive, `YYYY-MM-DD`), oldest first
+pub fn by_day(ledger: &UsageLedger, from: Option<&str>, to: Option<&str>) -> Vec<DailyUsage> {
+    ledger
+        .daily
+        .iter()
+        .filter(|(date, _)| in_range(date, from, to))
+        .map(|(date, models)| {
+            let mut usage = UsageTotals::default();
+            for totals in models.values() {
+                usage.merge(totals);
+            }
+            DailyUsage {
+                date: date.clone(),
+                usage,
+                models: models
+                    .iter()
+                    .map(|(model, usage)| ModelUsage {
+                        model: model.clone(),
+                        usage: usage.clone(),
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(content: &str, completion_tokens: u64) -> ConversationMessage {
+        ConversationMessage {
+            id: "answer".to_string(),
+            role: "assistant".to_string(),
+            content: serde_json::Value::String(content.to_string()),
+            timestamp: 0,
+            parent_id: None,
+            usage: Some(MessageUsage {
+                prompt_tokens: 10,
+                completion_tokens,
+                model: Some("model".to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn conversation(messages: Vec<ConversationMessage>) -> Conversation {
+        Conversation {
+            id: "c".to_string(),
+            title: "Test".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            messages,
+            active_leaf_id: None,
+            model: None,
+            folder_id: None,
+            tags: Vec::new(),
+            pinned: false,
+            archived: false,
+            deleted_at: None,
+            source_id: None,
+            usage_totals: Default::default(),
+            title_source: Default::default(),
+            summary: None,
+            summarized_count: 0,
+        }
+    }
+
+    fn completion_tokens(ledger: &UsageLedger) -> u64 {
+        by_model(ledger).iter().map(|m| m.usage.completion_tokens).sum()
+    }
+
+    #[test]
+    fn saving_an_answer_again_records_it_once() {
+        let mut ledger = UsageLedger::default();
+        let mut first = conversation(vec![answer("Hello", 5)]);
+        record(&mut ledger, &HashMap::new(), &mut first, None);
+
+        // The frontend may send the same answer again, with or without its usage
+        let mut again = conversation(vec![answer("Hello", 5)]);
+        again.messages[0].usage = None;
+        record(&mut ledger, &HashMap::new(), &mut again, Some(&first));
+
+        assert_eq!(completion_tokens(&ledger), 5);
+        assert_eq!(again.messages[0].usage.as_ref().map(|u| u.completion_tokens), Some(5));
+        assert_eq!(again.usage_totals.responses, 1);
+    }
+
+    #[test]
+    fn a_regenerated_answer_records_its_new_tokens() {
+        let mut ledger = UsageLedger::default();
+        let mut first = conversation(vec![answer("Hello", 5)]);
+        record(&mut ledger, &HashMap::new(), &mut first, None);
+
+        let mut regenerated = conversation(vec![answer("Hello there, how can I help?", 8)]);
+        record(&mut ledger, &HashMap::new(), &mut regenerated, Some(&first));
+
+        assert_eq!(completion_tokens(&ledger), 13);
+        assert_eq!(regenerated.usage_totals.completion_tokens, 8);
+    }
+}
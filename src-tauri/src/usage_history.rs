@@ -0,0 +1,293 @@
+use crate::conversations::{get_conversations_path, now_millis, write_synced};
+use crate::request_log::RequestRecord;
+use crate::titling::Gateway;
+use chrono::{Datelike, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::fs;
+
+const HOUR_MS: u64 = 60 * 60 * 1000;
+const DAY_MS: u64 = 24 * HOUR_MS;
+
+/// Credits reported by the gateway's `/usage` endpoint at one point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreditSample {
+    /// ms since the Unix epoch
+    pub at: u64,
+    pub used: f64,
+    pub limit: f64,
+}
+
+/// Requests and tokens through the proxy during one hour
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HourSample {
+    pub requests: u64,
+    pub errors: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl HourSample {
+    fn add(&mut self, other: &HourSample) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct HistoryData {
+    credits: Vec<CreditSample>,
+    /// Keyed by the start of the hour (ms since the Unix epoch)
+    hours: BTreeMap<u64, HourSample>,
+}
+
+/// Width of the buckets of a usage series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeriesBucket {
+    Hour,
+    /// Local calendar day
+    Day,
+    /// Local calendar week, starting on Monday
+    Week,
+}
+
+/// Usage during one bucket of a series
+#[derive(Debug, Clone, Serialize)]
+pub struct UsagePoint {
+    /// Start of the bucket (ms since the Unix epoch)
+    pub start: u64,
+    pub requests: u64,
+    pub errors: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Last credit reading in the bucket
+    pub credits_used: Option<f64>,
+    pub credit_limit: Option<f64>,
+    /// Credits consumed since the previous reading before the bucket
+    pub credits_spent: Option<f64>,
+}
+
+fn get_history_path() -> Result<PathBuf, String> {
+    let path = get_conversations_path()?;
+    let parent = path.parent().ok_or("Failed to get data directory")?;
+    Ok(parent.join("usage-history.json"))
+}
+
+/// Start of the bucket containing `at`
+fn bucket_start(bucket: SeriesBucket, at: u64) -> u64 {
+    if bucket == SeriesBucket::Hour {
+        return at - at % HOUR_MS;
+    }
+    let local = match Local.timestamp_millis_opt(at as i64).single() {
+        Some(local) => local,
+        None => return at - at % DAY_MS,
+    };
+    let mut date = local.date_naive();
+    if bucket == SeriesBucket::Week {
+        date -= chrono::Duration::days(date.weekday().num_days_from_monday() as i64);
+    }
+    date.and_hms_opt(0, 0, 0)
+        .and_then(|t| Local.from_local_datetime(&t).earliest())
+        .map(|t| t.timestamp_millis() as u64)
+        .unwrap_or(at - at % DAY_MS)
+}
+
+/// Credits used and limit from a `/usage` response (first usage breakdown)
+fn parse_credits(body: &Value) -> Option<(f64, f64)> {
+    let breakdown = body.get("usageBreakdownList")?.get(0)?;
+    let number = |names: [&str; 2]| names.iter().find_map(|n| breakdown.get(*n).and_then(|v| v.as_f64()));
+    let used = number(["currentUsageWithPrecision", "currentUsage"])?;
+    let limit = number(["usageLimitWithPrecision", "usageLimit"]).unwrap_or(0.0);
+    Some((used, limit))
+}
+
+/// Ask the gateway for the current credit usage
+pub async fn fetch_credits(client: &reqwest::Client, gateway: &Gateway) -> Result<CreditSample, String> {
+    let response = client
+        .get(format!("{}/usage", gateway.base_url))
+        .bearer_auth(&gateway.api_key)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach gateway: {}", e))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("Gateway returned {} for /usage", status));
+    }
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse usage response: {}", e))?;
+    let (used, limit) = parse_credits(&body).ok_or("Usage response has no credit breakdown")?;
+    Ok(CreditSample {
+        at: now_millis(),
+        used,
+        limit,
+    })
+}
+
+/// Credit readings and hourly request/token counts, kept for `retention_days`
+pub struct UsageHistory {
+    data: Mutex<HistoryData>,
+    retention_days: Mutex<u32>,
+    save_lock: tokio::sync::Mutex<()>,
+}
+
+impl UsageHistory {
+    /// Load the history, starting empty if it does not exist or cannot be read
+    pub fn open() -> Self {
+        let data = get_history_path()
+            .ok()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|content| serde_json::from_slice::<HistoryData>(&content).ok())
+            .unwrap_or_default();
+        Self {
+            data: Mutex::new(data),
+            retention_days: Mutex::new(crate::config::AppConfig::default().usage_history_days),
+            save_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HistoryData> {
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_retention_days(&self, days: u32) {
+        *self.retention_days.lock().unwrap_or_else(|e| e.into_inner()) = days;
+    }
+
+    /// Count a request that went through the proxy in the hour it started
+    pub fn observe_request(&self, record: &RequestRecord) {
+        let hour = record.started_at - record.started_at % HOUR_MS;
+        let mut data = self.lock();
+        let sample = data.hours.entry(hour).or_default();
+        sample.requests += 1;
+        if record.error.is_some() || record.status >= 400 {
+            sample.errors += 1;
+        }
+        sample.prompt_tokens += record.prompt_tokens.unwrap_or(0);
+        sample.completion_tokens += record.completion_tokens.unwrap_or(0);
+    }
+
+    pub fn add_credits(&self, sample: CreditSample) {
+        self.lock().credits.push(sample);
+    }
+
+    /// Drop samples older than the retention period and write the history to disk
+    pub async fn save(&self) -> Result<(), String> {
+        let _guard = self.save_lock.lock().await;
+        let days = *self.retention_days.lock().unwrap_or_else(|e| e.into_inner());
+        let content = {
+            let mut data = self.lock();
+            if days > 0 {
+                let cutoff = now_millis().saturating_sub(days as u64 * DAY_MS);
+                data.credits.retain(|c| c.at >= cutoff);
+                data.hours = data.hours.split_off(&(cutoff - cutoff % HOUR_MS));
+            }
+            serde_json::to_vec(&*data).map_err(|e| format!("Failed to serialize usage history: {}", e))?
+        };
+        let path = get_history_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create data directory: {}", e))?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        write_synced(&tmp_path, &content)
+            .await
+            .map_err(|e| format!("Failed to write usage history: {}", e))?;
+        fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|e| format!("Failed to rename usage history temp file: {}", e))
+    }
+
+    /// Usage between `from` and `to` (ms since the Unix epoch, inclusive) in buckets
+    /// of the given width, oldest first. Buckets without any data are left out.
+    pub fn series(&self, bucket: SeriesBucket, from: Option<u64>, to: Option<u64>) -> Vec<UsagePoint> {
+        let in_range = |at: u64| from.is_none_or(|f| at >= f) && to.is_none_or(|t| at <= t);
+        let data = self.lock();
+
+        let mut traffic: BTreeMap<u64, HourSample> = BTreeMap::new();
+        // An hour counts if any part of it is in the range
+        let hour_in_range = |hour: u64| from.is_none_or(|f| hour + HOUR_MS > f) && to.is_none_or(|t| hour <= t);
+        for (hour, sample) in data.hours.iter().filter(|(hour, _)| hour_in_range(**hour)) {
+            traffic.entry(bucket_start(bucket, *hour)).or_default().add(sample);
+        }
+
+        // Last reading per bucket, and the reading before it to measure spending
+        let mut credits: BTreeMap<u64, (&CreditSample, Option<&CreditSample>)> = BTreeMap::new();
+        let mut previous: Option<&CreditSample> = None;
+        for sample in &data.credits {
+            if in_range(sample.at) {
+                let start = bucket_start(bucket, sample.at);
+                let before = credits.get(&start).map(|(_, before)| *before).unwrap_or(previous);
+                credits.insert(start, (sample, before));
+            }
+            previous = Some(sample);
+        }
+
+        let mut starts: Vec<u64> = traffic.keys().chain(credits.keys()).copied().collect();
+        starts.sort_unstable();
+        starts.dedup();
+        starts
+            .into_iter()
+            .map(|start| {
+                let sample = traffic.get(&start).cloned().unwrap_or_default();
+                let reading = credits.get(&start);
+                UsagePoint {
+                    start,
+                    requests: sample.requests,
+                    errors: sample.errors,
+                    prompt_tokens: sample.prompt_tokens,
+                    completion_tokens: sample.completion_tokens,
+                    credits_used: reading.map(|(last, _)| last.used),
+                    credit_limit: reading.map(|(last, _)| last.limit),
+                    // A drop means the credits were reset; count from zero then
+                    credits_spent: reading.map(|(last, before)| match before {
+                        Some(before) if last.used >= before.used => last.used - before.used,
+                        _ => last.used,
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+fn csv_number(value: Option<f64>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Render a usage series as CSV with local ISO 8601 bucket starts
+pub fn to_csv(points: &[UsagePoint]) -> String {
+    let mut csv = String::from(
+        "bucket_start,requests,errors,prompt_tokens,completion_tokens,credits_used,credit_limit,credits_spent\n",
+    );
+    for point in points {
+        let start = Local
+            .timestamp_millis_opt(point.start as i64)
+            .single()
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{}\n",
+            start,
+            point.requests,
+            point.errors,
+            point.prompt_tokens,
+            point.completion_tokens,
+            csv_number(point.credits_used),
+            csv_number(point.credit_limit),
+            csv_number(point.credits_spent),
+        ));
+    }
+    csv
+}
@@ -11,7 +11,7 @@ import { useConfig } from './hooks/useConfig';
 import { useI18n } from './hooks/useI18n';
 import { useServerStatus } from './hooks/useServerStatus';
 import { useConversations } from './hooks/useConversations';
-import { startServer, stopServer, getServerLogs, getAppVersion, getDeviceModel, updateTrayServerState } from './lib/tauri';
+import { startServer, stopServer, restartServer, getServerLogs, getAppVersion, getDeviceModel, updateTrayServerState } from './lib/tauri';
 import { checkVersionUpdate } from './lib/versionCheck';
 import { platform, arch, version } from '@tauri-apps/api/os';
 
@@ -226,13 +226,10 @@ export default function App() {
 
   const handleRestartServer = async () => {
     setIsRestarting(true);
-    setPendingAction('stop');
+    setPendingAction('start');
     try {
-      await stopServer();
-      // Wait a bit for the server to fully stop
-      await new Promise(resolve => setTimeout(resolve, 500));
-      setPendingAction('start');
-      await startServer(config);
+      // Requests in flight finish on the old gateways while new ones start
+      await restartServer(config);
     } catch (err) {
       console.error(err);
       setPendingAction(null);
@@ -14,7 +14,7 @@ import {
 } from '@/components/ui/select';
 import { useI18n } from '@/hooks/useI18n';
 import { ConversationSidebar } from './ConversationSidebar';
-import type { Conversation } from '@/lib/conversations';
+import type { Conversation, MessageUsage } from '@/lib/conversations';
 
 function CopyButton({ text }: { text: string }) {
   const [copied, setCopied] = useState(false);
@@ -44,6 +44,7 @@ export interface MessageContent {
 export interface Message {
   role: 'user' | 'assistant';
   content: string | MessageContent[];
+  usage?: MessageUsage;
 }
 
 interface ChatViewProps {
@@ -162,6 +163,7 @@ export function ChatView({
     setImages([]);
     setIsLoading(true);
 
+    const startedAt = Date.now();
     try {
       const response = await fetch(`http://${fetchHost}:${port}/v1/chat/completions`, {
         method: 'POST',
@@ -176,6 +178,7 @@ export function ChatView({
             content: m.content,
           })),
           stream: true,
+          stream_options: { include_usage: true },
         }),
       });
 
@@ -190,6 +193,7 @@ export function ChatView({
       let assistantContent = '';
       let messageAdded = false;
       let currentMessages = newMessages;
+      let usage: MessageUsage | undefined;
 
       while (true) {
         const { done, value } = await reader.read();
@@ -205,6 +209,14 @@ export function ChatView({
 
             try {
               const parsed = JSON.parse(data);
+              if (parsed.usage) {
+                usage = {
+                  promptTokens: parsed.usage.prompt_tokens ?? 0,
+                  completionTokens: parsed.usage.completion_tokens ?? 0,
+                  reasoningTokens: parsed.usage.completion_tokens_details?.reasoning_tokens ?? 0,
+                  model: parsed.model,
+                };
+              }
               const content = parsed.choices?.[0]?.delta?.content || '';
               if (content) {
                 assistantContent += content;
@@ -226,6 +238,13 @@ export function ChatView({
           }
         }
       }
+
+      if (usage && messageAdded) {
+        onMessagesChange([
+          ...currentMessages.slice(0, -1),
+          { role: 'assistant', content: assistantContent, usage: { ...usage, latencyMs: Date.now() - startedAt } },
+        ]);
+      }
     } catch (error) {
       console.error('Chat error:', error);
       onMessagesChange([
@@ -1,4 +1,5 @@
 import { useState, useEffect, useCallback, useRef } from 'react';
+import { listen } from '@tauri-apps/api/event';
 import {
   loadConversations,
   createConversation as createConversationApi,
@@ -7,7 +8,7 @@ import {
   renameConversation as renameConversationApi,
 } from '@/lib/tauri';
 import type { Conversation, ConversationMessage } from '@/lib/conversations';
-import { generateId, generateTitle } from '@/lib/conversations';
+import { generateId, generateTitle, getActiveBranch, isSameContent } from '@/lib/conversations';
 import type { Message } from '@/components/ChatView';
 
 /**
@@ -36,10 +37,12 @@ export function useConversations() {
     const load = async () => {
       try {
         const data = await loadConversations();
-        setConversations(data.conversations);
+        // Trashed conversations are only shown in the trash view
+        const visible = data.conversations.filter(c => !c.deletedAt);
+        setConversations(visible);
         // Select the most recent conversation if available
-        if (data.conversations.length > 0) {
-          setCurrentConversationId(data.conversations[0].id);
+        if (visible.length > 0) {
+          setCurrentConversationId(visible[0].id);
         }
         setIsLoading(false);
       } catch (err) {
@@ -52,14 +55,48 @@ export function useConversations() {
     load();
   }, []);
 
+  // Apply titles and summaries generated in the background
+  useEffect(() => {
+    const unlisten = listen<{ id: string; title: string; summary: string | null }>('conversation-titled', event => {
+      const { id, title, summary } = event.payload;
+      setConversations(prev => prev.map(c =>
+        c.id === id ? { ...c, title, summary: summary ?? undefined } : c
+      ));
+    });
+    return () => {
+      unlisten.then(fn => fn());
+    };
+  }, []);
+
+  // Reload after another device's changes were synced in
+  useEffect(() => {
+    const unlisten = listen('conversations-synced', async () => {
+      try {
+        const data = await loadConversations();
+        const visible = data.conversations.filter(c => !c.deletedAt);
+        setConversations(visible);
+        const activeId = currentConversationIdRef.current;
+        if (activeId && !visible.some(c => c.id === activeId)) {
+          setCurrentConversationId(visible.length > 0 ? visible[0].id : null);
+        }
+      } catch (err) {
+        console.error('Failed to reload synced conversations:', err);
+      }
+    });
+    return () => {
+      unlisten.then(fn => fn());
+    };
+  }, []);
+
   // Get current conversation
   const currentConversation = conversations.find(c => c.id === currentConversationId) || null;
 
   // Get messages for current conversation
-  const messages: Message[] = currentConversation?.messages.map(m => ({
+  const messages: Message[] = (currentConversation ? getActiveBranch(currentConversation) : []).map(m => ({
     role: m.role,
     content: m.content,
-  })) || [];
+    usage: m.usage,
+  }));
 
   // Create a new conversation
   const createConversation = useCallback(async (model?: string) => {
@@ -108,12 +145,17 @@ export function useConversations() {
       if (newMessages.length === 0) return;
 
       const now = Date.now();
-      const conversationMessages: ConversationMessage[] = newMessages.map(m => ({
-        id: generateId(),
-        role: m.role,
-        content: m.content,
-        timestamp: now,
-      }));
+      const conversationMessages: ConversationMessage[] = [];
+      for (const m of newMessages) {
+        conversationMessages.push({
+          id: generateId(),
+          role: m.role,
+          content: m.content,
+          timestamp: now,
+          parentId: conversationMessages[conversationMessages.length - 1]?.id,
+          usage: m.usage,
+        });
+      }
 
       // Generate title from first user message
       const firstUserMessage = newMessages.find(m => m.role === 'user');
@@ -124,10 +166,12 @@ export function useConversations() {
         title,
         updatedAt: now,
         messages: conversationMessages,
+        activeLeafId: conversationMessages[conversationMessages.length - 1].id,
         model,
       };
 
       // Optimistically update UI state first, then persist in background
+      conversationsRef.current = [updatedConversation, ...conversationsRef.current.filter(c => c.id !== conv.id)];
       setConversations(prev => prev.map(c =>
         c.id === conv.id ? updatedConversation : c
       ));
@@ -144,16 +188,40 @@ export function useConversations() {
     if (!conversation) return;
 
     const now = Date.now();
+    const activeBranch = getActiveBranch(conversation);
+    // Messages keep their IDs only while they match the stored branch. From the
+    // first edited or regenerated message on, new IDs start a sibling branch and
+    // the previous version stays in the tree.
+    let diverged = false;
+    let parentId: string | undefined;
     const conversationMessages: ConversationMessage[] = newMessages.map((m, index) => {
-      // Reuse existing message IDs where possible
-      const existingMessage = conversation.messages[index];
-      return {
-        id: existingMessage?.id || generateId(),
+      const existingMessage = diverged ? undefined : activeBranch[index];
+      // An answer still streaming into the active leaf grows without forking
+      const streamed = existingMessage !== undefined
+        && index === newMessages.length - 1
+        && index === activeBranch.length - 1
+        && m.role === 'assistant'
+        && typeof m.content === 'string'
+        && typeof existingMessage.content === 'string'
+        && m.content.startsWith(existingMessage.content);
+      const reused = existingMessage !== undefined
+        && existingMessage.role === m.role
+        && (streamed || isSameContent(existingMessage.content, m.content));
+      if (!reused) {
+        diverged = true;
+      }
+      const message: ConversationMessage = {
+        id: reused ? existingMessage.id : generateId(),
         role: m.role,
         content: m.content,
-        timestamp: existingMessage?.timestamp || now,
+        timestamp: reused ? existingMessage.timestamp : now,
+        parentId,
+        usage: m.usage,
       };
+      parentId = message.id;
+      return message;
     });
+    const pathIds = new Set(conversationMessages.map(m => m.id));
 
     // Generate title from first user message if title is still default
     let title = conversation.title;
@@ -168,11 +236,17 @@ export function useConversations() {
       ...conversation,
       title,
       updatedAt: now,
-      messages: conversationMessages,
+      // Other branches stay as they are, as in the backend's apply_active_path
+      messages: [...conversation.messages.filter(m => !pathIds.has(m.id)), ...conversationMessages],
+      activeLeafId: parentId,
       model: model || conversation.model,
     };
 
-    // Optimistically update UI state first, then persist in background
+    // Optimistically update UI state first, then persist in background. The ref
+    // is updated right away so the next streamed chunk sees this branch.
+    conversationsRef.current = conversationsRef.current.map(c =>
+      c.id === activeId ? updatedConversation : c
+    );
     setConversations(prev => prev.map(c =>
       c.id === activeId ? updatedConversation : c
     ));
@@ -4,14 +4,64 @@
 
 export type AuthMethod = 'refresh_token' | 'creds_file' | 'cli_db';
 
+/** 'mock' serves canned replies without credentials or network, for development and tests */
+export type BackendMode = 'kiro' | 'mock';
+export type ReadinessMode = 'health' | 'models' | 'log_line';
+
 export type ServerStatus = 'stopped' | 'starting' | 'running' | 'error';
 
+export interface ModelPrice {
+  input_per_million: number;
+  output_per_million: number;
+}
+
+/** Sends requests for models matching `pattern` (`*` and `?`, any case) to `target` */
+export interface ModelRoute {
+  pattern: string;
+  target: string;
+  /** Default when the request sets no maximum */
+  max_tokens?: number;
+  /** Overrides fake_reasoning for matching requests */
+  fake_reasoning?: boolean;
+}
+
+/** Limits for one client key, or for all proxied traffic combined; unset = unlimited */
+export interface ClientLimits {
+  requests_per_minute?: number;
+  max_concurrent_streams?: number;
+  daily_tokens?: number;
+  monthly_tokens?: number;
+}
+
+export type PoolStrategy = 'round_robin' | 'least_loaded';
+
+/** A Kiro account served by its own gateway in the account pool */
+export interface Account {
+  id: string;
+  label: string;
+  auth_method: AuthMethod;
+  refresh_token?: string;
+  kiro_creds_file?: string;
+  kiro_cli_db_file?: string;
+  /** Unset uses kiro_region */
+  region?: string;
+  enabled: boolean;
+}
+
 export interface AppConfig {
   // Authentication (one required)
   auth_method: AuthMethod;
   refresh_token?: string;
   kiro_creds_file?: string;
   kiro_cli_db_file?: string;
+  /** When any account is enabled, the pool replaces the credential above */
+  accounts: Account[];
+  pool_strategy: PoolStrategy;
+  /** Keep a client key on the same account while it is healthy */
+  sticky_sessions: boolean;
+  backend_mode: BackendMode;
+  /** JSON file of scripted mock replies; unset echoes every request */
+  mock_script?: string;
 
   // Required
   proxy_api_key: string;
@@ -19,7 +69,59 @@ export interface AppConfig {
   // Server
   server_host: string;
   server_port: number;
+  /** When server_port is taken, use the first free port after it up to this one */
+  server_port_fallback_end?: number;
+  /** Seconds to wait for the gateways to become ready */
+  startup_timeout_secs: number;
+  /** Milliseconds between readiness checks */
+  startup_poll_interval_ms: number;
+  readiness_mode: ReadinessMode;
+  health_path: string;
+  /** Gateway output that means it is ready, for the log_line mode */
+  readiness_log_line?: string;
+  /** Seconds a stop or restart waits for requests in flight before signalling the gateways */
+  drain_timeout_secs: number;
   kiro_region: string;
+  /** Serve server_port through the built-in request-logging proxy */
+  proxy_enabled: boolean;
+  /** Internal gateway port behind the proxy; unset picks a free port. Pooled accounts count up from it */
+  proxy_upstream_port?: number;
+  global_limits: ClientLimits;
+  /** Keyed by client API key id */
+  key_limits: Record<string, ClientLimits>;
+  /** Serve the API over HTTPS (through the proxy) */
+  tls_enabled: boolean;
+  /** PEM certificate chain; unset uses a certificate from the app's local CA */
+  tls_cert_file?: string;
+  tls_key_file?: string;
+  /** Extra host names and IPs for the local CA's certificate */
+  tls_extra_names: string[];
+  /** Client networks in CIDR notation, e.g. 192.168.1.0/24; empty allows any (loopback always is) */
+  allowed_networks: string[];
+  /** Client networks refused even when allowed */
+  denied_networks: string[];
+  /** Non-loopback server_host the user confirmed exposing the gateway on */
+  lan_exposure_host?: string;
+  /** Address to serve Prometheus metrics on, e.g. 127.0.0.1:9464; unset disables it */
+  metrics_listen?: string;
+  /** Days of credit and traffic history to keep; 0 keeps everything */
+  usage_history_days: number;
+  /** Replay responses to repeated temperature-0 requests */
+  response_cache_enabled: boolean;
+  response_cache_ttl_secs: number;
+  response_cache_max_mb: number;
+  /** First matching rule wins */
+  model_routes: ModelRoute[];
+  /** Record full request/response pairs through the proxy for debugging */
+  capture_enabled: boolean;
+  /** Client key ids to capture ('master' for the gateway's own key); none and no models = everything */
+  capture_key_ids: string[];
+  /** Model name patterns (`*` and `?`) to capture */
+  capture_models: string[];
+  /** Directory for capture files; unset uses the data directory */
+  capture_dir?: string;
+  /** Captures to keep, oldest deleted first (0 = keep all) */
+  capture_max_count: number;
 
   // Advanced
   vpn_proxy_url?: string;
@@ -35,16 +137,54 @@ export interface AppConfig {
   auto_launch: boolean;
   auto_start_server: boolean;
 
+  // Conversations
+  trash_retention_days: number;
+  conversation_backup_count: number;
+  /** USD per million tokens, keyed by model name */
+  model_prices: Record<string, ModelPrice>;
+  auto_title: boolean;
+  auto_summary: boolean;
+  titling_model?: string;
+  titling_interval_secs: number;
+  /** Shared folder to sync conversations through; unset disables sync */
+  sync_folder?: string;
+  sync_interval_secs: number;
+
   // Client identity
   client_id?: string;
 }
 
 export const DEFAULT_CONFIG: AppConfig = {
   auth_method: 'refresh_token',
+  accounts: [],
+  pool_strategy: 'round_robin',
+  sticky_sessions: true,
+  backend_mode: 'kiro',
   proxy_api_key: '',
   server_host: '127.0.0.1',
   server_port: 8000,
+  startup_timeout_secs: 30,
+  startup_poll_interval_ms: 500,
+  readiness_mode: 'health',
+  health_path: '/health',
+  drain_timeout_secs: 60,
   kiro_region: 'us-east-1',
+  proxy_enabled: false,
+  global_limits: {},
+  key_limits: {},
+  tls_enabled: false,
+  tls_extra_names: [],
+  allowed_networks: [],
+  denied_networks: [],
+  usage_history_days: 90,
+  response_cache_enabled: false,
+  response_cache_ttl_secs: 3600,
+  response_cache_max_mb: 64,
+  model_routes: [],
+  capture_enabled: false,
+  capture_key_ids: [],
+  capture_models: [],
+  capture_max_count: 500,
   first_token_timeout: 15,
   streaming_read_timeout: 300,
   fake_reasoning: true,
@@ -54,6 +194,13 @@ export const DEFAULT_CONFIG: AppConfig = {
   debug_mode: 'off',
   auto_launch: false,
   auto_start_server: false,
+  trash_retention_days: 30,
+  conversation_backup_count: 7,
+  model_prices: {},
+  auto_title: true,
+  auto_summary: false,
+  titling_interval_secs: 10,
+  sync_interval_secs: 300,
 };
 
 export interface ServerStatusInfo {
@@ -4,11 +4,33 @@
 
 import type { MessageContent } from '@/components/ChatView';
 
+export interface MessageUsage {
+  promptTokens: number;
+  completionTokens: number;
+  reasoningTokens: number;
+  latencyMs?: number;
+  /** Model that actually produced the response */
+  model?: string;
+  /** Set by the backend from the configured model prices */
+  cost?: number;
+}
+
+export interface UsageTotals {
+  promptTokens: number;
+  completionTokens: number;
+  reasoningTokens: number;
+  responses: number;
+  totalLatencyMs: number;
+  cost: number;
+}
+
 export interface ConversationMessage {
   id: string;
   role: 'user' | 'assistant';
   content: string | MessageContent[];
   timestamp: number;
+  parentId?: string;
+  usage?: MessageUsage;
 }
 
 export interface Conversation {
@@ -17,11 +39,60 @@ export interface Conversation {
   createdAt: number;
   updatedAt: number;
   messages: ConversationMessage[];
+  activeLeafId?: string;
   model?: string;
+  folderId?: string;
+  tags?: string[];
+  pinned?: boolean;
+  archived?: boolean;
+  deletedAt?: number;
+  sourceId?: string;
+  usageTotals?: UsageTotals;
+  /** Where the title came from; generated and manual titles are kept by the backend */
+  titleSource?: 'client' | 'generated' | 'manual';
+  summary?: string;
+  summarizedCount?: number;
+}
+
+export interface Folder {
+  id: string;
+  name: string;
+  parentId?: string;
+  createdAt: number;
 }
 
 export interface ConversationsData {
+  version?: number;
   conversations: Conversation[];
+  folders?: Folder[];
+}
+
+/**
+ * Get the messages on the active branch of a conversation, root first
+ */
+export function getActiveBranch(conversation: Conversation): ConversationMessage[] {
+  // Flat lists (no parent links yet) are already a single branch
+  if (!conversation.messages.some(m => m.parentId)) {
+    return conversation.messages;
+  }
+
+  const byId = new Map(conversation.messages.map(m => [m.id, m]));
+  const path: ConversationMessage[] = [];
+  const visited = new Set<string>();
+  let current = conversation.activeLeafId ? byId.get(conversation.activeLeafId) : undefined;
+  while (current && !visited.has(current.id)) {
+    visited.add(current.id);
+    path.push(current);
+    current = current.parentId ? byId.get(current.parentId) : undefined;
+  }
+  return path.reverse();
+}
+
+/**
+ * Whether two message contents are identical
+ */
+export function isSameContent(a: string | MessageContent[], b: string | MessageContent[]): boolean {
+  return typeof a === 'string' && typeof b === 'string' ? a === b : JSON.stringify(a) === JSON.stringify(b);
 }
 
 /**
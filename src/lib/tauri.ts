@@ -5,8 +5,29 @@
  */
 
 import { invoke } from '@tauri-apps/api/tauri';
-import type { AppConfig, ServerStatusInfo } from './config';
+import type { AppConfig, ClientLimits, ModelRoute, ReadinessMode, ServerStatusInfo } from './config';
 
+/** What startServer rejects with */
+export type StartServerError =
+  | { kind: 'port_in_use'; port: number; occupier?: PortOccupierInfo; message: string }
+  | { kind: 'failed'; message: string };
+
+/** Payload of the `server-start-progress` event, sent while startServer runs */
+export type StartProgress =
+  | { stage: 'extracting' }
+  | { stage: 'spawning'; gateways: number }
+  | {
+      stage: 'waiting_for_ready';
+      mode: ReadinessMode;
+      attempt: number;
+      elapsed_ms: number;
+      timeout_ms: number;
+      /** Readiness checks not passed yet */
+      pending: string[];
+    }
+  | { stage: 'ready'; port: number };
+
+/** Starts the server; the resolved status has the port actually used, which may be a fallback port */
 export async function startServer(config: AppConfig): Promise<ServerStatusInfo> {
   return await invoke('start_server', { config });
 }
@@ -15,6 +36,11 @@ export async function stopServer(): Promise<void> {
   return await invoke('stop_server');
 }
 
+/** Restart without dropping requests in flight; rejects like startServer */
+export async function restartServer(config: AppConfig): Promise<ServerStatusInfo> {
+  return await invoke('restart_server', { config });
+}
+
 export async function getServerStatus(): Promise<ServerStatusInfo> {
   return await invoke('get_server_status');
 }
@@ -85,10 +111,18 @@ export async function getDeviceModel(): Promise<string> {
 }
 
 // Conversation management
-import type { Conversation, ConversationsData } from './conversations';
+import type { Conversation, ConversationsData, Folder, UsageTotals } from './conversations';
+
+/**
+ * Load all conversations. Attachments are inlined as data URLs unless
+ * `inlineAttachments` is false; references can then be resolved with `getAttachment`.
+ */
+export async function loadConversations(inlineAttachments = true): Promise<ConversationsData> {
+  return await invoke('load_conversations_cmd', { inlineAttachments });
+}
 
-export async function loadConversations(): Promise<ConversationsData> {
-  return await invoke('load_conversations_cmd');
+export async function getAttachment(reference: string): Promise<string> {
+  return await invoke('get_attachment', { reference });
 }
 
 export async function saveConversations(data: ConversationsData): Promise<void> {
@@ -107,10 +141,595 @@ export async function deleteConversation(id: string): Promise<void> {
   return await invoke('delete_conversation', { id });
 }
 
+export async function restoreConversation(id: string): Promise<void> {
+  return await invoke('restore_conversation', { id });
+}
+
+export async function deleteConversations(ids: string[]): Promise<number> {
+  return await invoke('delete_conversations', { ids });
+}
+
+export async function restoreConversations(ids: string[]): Promise<number> {
+  return await invoke('restore_conversations', { ids });
+}
+
+export async function purgeConversations(ids: string[]): Promise<number> {
+  return await invoke('purge_conversations', { ids });
+}
+
+export async function emptyTrash(): Promise<number> {
+  return await invoke('empty_trash');
+}
+
 export async function renameConversation(id: string, title: string): Promise<void> {
   return await invoke('rename_conversation', { id, title });
 }
 
+/** Queue a conversation for a title generated through the gateway */
+export async function generateConversationTitle(id: string): Promise<void> {
+  return await invoke('generate_conversation_title', { id });
+}
+
+export interface ConversationFilter {
+  query?: string;
+  folder_id?: string;
+  include_subfolders?: boolean;
+  tags?: string[];
+  pinned_only?: boolean;
+  include_archived?: boolean;
+  archived_only?: boolean;
+}
+
+export interface ConversationSummary {
+  id: string;
+  title: string;
+  created_at: number;
+  updated_at: number;
+  model: string | null;
+  folder_id: string | null;
+  tags: string[];
+  pinned: boolean;
+  archived: boolean;
+  deleted_at: number | null;
+  message_count: number;
+  usage: UsageTotals;
+}
+
+export interface TagCount {
+  tag: string;
+  count: number;
+}
+
+export async function listConversations(filter?: ConversationFilter): Promise<ConversationSummary[]> {
+  return await invoke('list_conversations', { filter });
+}
+
+export async function listTrash(): Promise<ConversationSummary[]> {
+  return await invoke('list_trash');
+}
+
+export async function createFolder(name: string, parentId?: string): Promise<Folder> {
+  return await invoke('create_folder', { name, parentId });
+}
+
+export async function renameFolder(id: string, name: string): Promise<void> {
+  return await invoke('rename_folder', { id, name });
+}
+
+export async function moveFolder(id: string, parentId?: string): Promise<void> {
+  return await invoke('move_folder', { id, parentId });
+}
+
+export async function deleteFolder(id: string): Promise<void> {
+  return await invoke('delete_folder', { id });
+}
+
+export async function setConversationFolder(id: string, folderId?: string): Promise<void> {
+  return await invoke('set_conversation_folder', { id, folderId });
+}
+
+export async function setConversationTags(id: string, tags: string[]): Promise<void> {
+  return await invoke('set_conversation_tags', { id, tags });
+}
+
+export async function setConversationPinned(id: string, pinned: boolean): Promise<void> {
+  return await invoke('set_conversation_pinned', { id, pinned });
+}
+
+export async function setConversationArchived(id: string, archived: boolean): Promise<void> {
+  return await invoke('set_conversation_archived', { id, archived });
+}
+
+export async function listConversationTags(): Promise<TagCount[]> {
+  return await invoke('list_conversation_tags');
+}
+
+export interface BranchInfo {
+  leaf_id: string;
+  fork_message_id: string | null;
+  message_count: number;
+  updated_at: number;
+  preview: string;
+  active: boolean;
+}
+
+export async function forkConversation(id: string, messageId: string): Promise<Conversation> {
+  return await invoke('fork_conversation', { id, messageId });
+}
+
+export async function switchConversationBranch(id: string, messageId: string): Promise<Conversation> {
+  return await invoke('switch_conversation_branch', { id, messageId });
+}
+
+export async function listConversationBranches(id: string): Promise<BranchInfo[]> {
+  return await invoke('list_conversation_branches', { id });
+}
+
+export async function getMessageSiblings(id: string, messageId: string): Promise<string[]> {
+  return await invoke('get_message_siblings', { id, messageId });
+}
+
+export interface SkippedImportItem {
+  source_id: string | null;
+  title: string | null;
+  reason: string;
+}
+
+export interface FileImportReport {
+  path: string;
+  format: 'chatgpt' | 'claude' | 'unknown';
+  imported: number;
+  duplicates: number;
+  skipped: SkippedImportItem[];
+  error: string | null;
+}
+
+export interface ImportReport {
+  files: FileImportReport[];
+  total_imported: number;
+}
+
+export async function importConversations(paths: string[]): Promise<ImportReport> {
+  return await invoke('import_conversations', { paths });
+}
+
+export interface BackupInfo {
+  name: string;
+  created_at: number;
+  size: number;
+}
+
+export interface RecoveryReport {
+  recovered_at: number;
+  error: string;
+  quarantined_path: string | null;
+  salvaged_conversations: number;
+  unreadable_conversations: number;
+  restored_backup: string | null;
+}
+
+export async function listConversationBackups(): Promise<BackupInfo[]> {
+  return await invoke('list_conversation_backups');
+}
+
+export async function restoreConversationBackup(name: string): Promise<ConversationsData> {
+  return await invoke('restore_conversation_backup', { name });
+}
+
+export async function getConversationRecoveryReport(): Promise<RecoveryReport | null> {
+  return await invoke('get_conversation_recovery_report');
+}
+
+export type KeySource = 'keyring' | 'passphrase';
+
+export interface EncryptionStatus {
+  enabled: boolean;
+  key_source: KeySource | null;
+  locked: boolean;
+  /** Backups and attachments the last key change could not convert */
+  failed_files?: string[];
+}
+
+export interface ModelUsage {
+  model: string;
+  usage: UsageTotals;
+}
+
+export interface DailyUsage {
+  date: string;
+  usage: UsageTotals;
+  models: ModelUsage[];
+}
+
+export async function getConversationUsage(id: string): Promise<UsageTotals> {
+  return await invoke('get_conversation_usage', { id });
+}
+
+export async function getUsageByModel(): Promise<ModelUsage[]> {
+  return await invoke('get_usage_by_model');
+}
+
+/** Daily usage between `from` and `to` (inclusive, YYYY-MM-DD) */
+export async function getUsageByDay(from?: string, to?: string): Promise<DailyUsage[]> {
+  return await invoke('get_usage_by_day', { from: from ?? null, to: to ?? null });
+}
+
+export interface SyncConflict {
+  conversation_id: string;
+  title: string;
+  kind: 'concurrent_edit' | 'edited_after_delete';
+  winner_device: string;
+  resolved_at: number;
+}
+
+export interface SyncReport {
+  pushed: number;
+  pulled: number;
+  deleted: number;
+  conflicts: SyncConflict[];
+}
+
+export async function syncConversationsNow(): Promise<SyncReport> {
+  return await invoke('sync_conversations_now');
+}
+
+export async function getSyncConflicts(): Promise<SyncConflict[]> {
+  return await invoke('get_sync_conflicts');
+}
+
+export async function clearSyncConflicts(): Promise<void> {
+  return await invoke('clear_sync_conflicts');
+}
+
+export interface RequestRecord {
+  id: number;
+  started_at: number;
+  method: string;
+  endpoint: string;
+  model?: string;
+  client_key?: string;
+  /** IP address the request came from */
+  client_address?: string;
+  /** Pooled account that served the request */
+  account?: string;
+  status: number;
+  latency_ms: number;
+  ttfb_ms?: number;
+  request_bytes: number;
+  response_bytes: number;
+  streamed: boolean;
+  /** Replayed from the response cache */
+  cached: boolean;
+  prompt_tokens?: number;
+  completion_tokens?: number;
+  error?: string;
+}
+
+export interface RequestLogFilter {
+  endpoint?: string;
+  model?: string;
+  client_key?: string;
+  client_address?: string;
+  account?: string;
+  status_min?: number;
+  status_max?: number;
+  errors_only?: boolean;
+  since?: number;
+  until?: number;
+  limit?: number;
+  offset?: number;
+}
+
+/** Requests recorded by the proxy, newest first */
+export async function queryRequestLog(filter?: RequestLogFilter): Promise<RequestRecord[]> {
+  return await invoke('query_request_log', { filter: filter ?? null });
+}
+
+export async function clearRequestLog(): Promise<void> {
+  return await invoke('clear_request_log');
+}
+
+export type ApiScope = 'openai' | 'anthropic';
+
+export interface ApiKey {
+  id: string;
+  label: string;
+  /** Masked secret, e.g. "sk-k…9f2a" */
+  hint: string;
+  scopes: ApiScope[];
+  /** Empty = any model */
+  allowed_models: string[];
+  created_at: number;
+  expires_at?: number;
+  revoked_at?: number;
+  last_used_at?: number;
+}
+
+export interface CreatedApiKey {
+  key: ApiKey;
+  /** Shown once; only a hash is stored */
+  secret: string;
+}
+
+export interface LimitStatus {
+  /** "global" or a client key id */
+  scope: string;
+  limits: ClientLimits;
+  daily_tokens: number;
+  monthly_tokens: number;
+  active_streams: number;
+  available_requests?: number;
+}
+
+export type SeriesBucket = 'hour' | 'day' | 'week';
+
+export interface UsagePoint {
+  /** Start of the bucket, ms since the Unix epoch */
+  start: number;
+  requests: number;
+  errors: number;
+  prompt_tokens: number;
+  completion_tokens: number;
+  credits_used?: number;
+  credit_limit?: number;
+  credits_spent?: number;
+}
+
+/** Credits, requests and tokens over time; `from`/`to` in ms since the Unix epoch */
+export async function getUsageSeries(bucket: SeriesBucket, from?: number, to?: number): Promise<UsagePoint[]> {
+  return await invoke('get_usage_series', { bucket, from: from ?? null, to: to ?? null });
+}
+
+/** Write a usage series to a CSV file; returns the number of rows */
+export async function exportUsageCsv(
+  path: string,
+  bucket: SeriesBucket,
+  from?: number,
+  to?: number
+): Promise<number> {
+  return await invoke('export_usage_csv', { path, bucket, from: from ?? null, to: to ?? null });
+}
+
+/** Rate limits, quotas and their consumption: global first, then per client key */
+export async function getQuotaStatus(): Promise<LimitStatus[]> {
+  return await invoke('get_quota_status');
+}
+
+export interface UpstreamStatus {
+  /** Account id ("default" without pooled accounts) */
+  account: string;
+  label: string;
+  base_url: string;
+  healthy: boolean;
+  in_flight: number;
+  requests: number;
+  failures: number;
+  /** Skipped until this time after a failure (ms since epoch) */
+  down_until?: number;
+  last_error?: string;
+}
+
+export interface RouteMatch {
+  /** Position of the rule in model_routes */
+  index: number;
+  route: ModelRoute;
+}
+
+/** Which routing rule a model name resolves to; pass `routes` to preview unsaved edits */
+export async function resolveModelRoute(model: string, routes?: ModelRoute[]): Promise<RouteMatch | null> {
+  return await invoke('resolve_model_route', { model, routes: routes ?? null });
+}
+
+/** Routing state of every account behind the proxy */
+export async function getAccountPoolStatus(): Promise<UpstreamStatus[]> {
+  return await invoke('get_account_pool_status');
+}
+
+export interface CacheStats {
+  enabled: boolean;
+  entries: number;
+  bytes: number;
+  max_bytes: number;
+  ttl_secs: number;
+  /** Since the app started */
+  hits: number;
+  misses: number;
+}
+
+export interface CacheEntryInfo {
+  key: string;
+  endpoint: string;
+  model?: string;
+  streamed: boolean;
+  bytes: number;
+  created_at: number;
+  expires_at: number;
+  hits: number;
+}
+
+export async function getResponseCacheStats(): Promise<CacheStats> {
+  return await invoke('get_response_cache_stats');
+}
+
+/** Cached responses, newest first */
+export async function listResponseCache(): Promise<CacheEntryInfo[]> {
+  return await invoke('list_response_cache');
+}
+
+/** Remove one cached response, or all of them without a key; returns how many were removed */
+export async function purgeResponseCache(key?: string): Promise<number> {
+  return await invoke('purge_response_cache', { key: key ?? null });
+}
+
+export interface CaptureChunk {
+  /** ms since the request arrived */
+  at_ms: number;
+  data: string;
+}
+
+export interface CaptureSummary {
+  id: string;
+  started_at: number;
+  method: string;
+  path: string;
+  model?: string;
+  /** Client key id, or 'master' for the gateway's own key */
+  client_key_id: string;
+  account?: string;
+  status: number;
+  streamed: boolean;
+  cached: boolean;
+  response_bytes: number;
+  duration_ms: number;
+  error?: string;
+}
+
+/** A captured request and response; secrets are replaced with [REDACTED] */
+export interface Capture extends CaptureSummary {
+  request_id: number;
+  request_headers: [string, string][];
+  request_body: string;
+  response_headers: [string, string][];
+  chunks: CaptureChunk[];
+  /** The body was larger than a capture keeps */
+  truncated: boolean;
+  ttfb_ms?: number;
+}
+
+export interface ReplayResult {
+  capture_id: string;
+  status: number;
+  response_headers: [string, string][];
+  streamed: boolean;
+  chunks: CaptureChunk[];
+  ttfb_ms?: number;
+  duration_ms: number;
+  /** The body is the same as the captured one */
+  identical: boolean;
+}
+
+/** Debug captures of proxied requests, newest first */
+export async function listCaptures(): Promise<CaptureSummary[]> {
+  return await invoke('list_captures');
+}
+
+export async function getCapture(id: string): Promise<Capture> {
+  return await invoke('get_capture', { id });
+}
+
+/** Delete one capture, or all of them without an id; returns how many were deleted */
+export async function deleteCaptures(id?: string): Promise<number> {
+  return await invoke('delete_captures', { id: id ?? null });
+}
+
+/** Send a captured request to the running gateway again */
+export async function replayCapture(id: string): Promise<ReplayResult> {
+  return await invoke('replay_capture', { id });
+}
+
+export interface TlsStatus {
+  enabled: boolean;
+  source: 'certificate_file' | 'local_ca';
+  /** Host names and IPs the local CA's certificate covers */
+  names: string[];
+  /** PEM of the local CA, once created */
+  ca_certificate?: string;
+  /** SHA-256, as AB:CD:... */
+  ca_fingerprint?: string;
+  ca_path?: string;
+}
+
+/** HTTPS settings and the local CA certificate clients need to trust */
+export async function getTlsStatus(): Promise<TlsStatus> {
+  return await invoke('get_tls_status');
+}
+
+/** Save the local CA certificate (PEM) for installing on client devices */
+export async function exportTlsCaCertificate(path: string): Promise<void> {
+  return await invoke('export_tls_ca_certificate', { path });
+}
+
+/** Replace the local CA; restart the server and re-trust it on clients afterwards */
+export async function regenerateTlsCa(): Promise<TlsStatus> {
+  return await invoke('regenerate_tls_ca');
+}
+
+export interface LanExposure {
+  host: string;
+  /** Reachable from other machines */
+  exposed: boolean;
+  /** lan_exposure_host matches host */
+  confirmed: boolean;
+  /** Why proxy_api_key is too weak to start on this host, if it is */
+  key_weakness?: string;
+  /** Allowed or denied networks are set */
+  restricted: boolean;
+  /** Addresses other machines would use */
+  urls: string[];
+}
+
+/** Whether a configuration (the saved one by default) exposes the gateway to the network */
+export async function getLanExposure(config?: AppConfig): Promise<LanExposure> {
+  return await invoke('get_lan_exposure', { config: config ?? null });
+}
+
+/** Client API keys accepted by the request proxy */
+export async function listApiKeys(): Promise<ApiKey[]> {
+  return await invoke('list_api_keys');
+}
+
+export async function createApiKey(
+  label: string,
+  scopes: ApiScope[],
+  allowedModels?: string[],
+  expiresAt?: number
+): Promise<CreatedApiKey> {
+  return await invoke('create_api_key', {
+    label,
+    scopes,
+    allowedModels: allowedModels ?? null,
+    expiresAt: expiresAt ?? null,
+  });
+}
+
+export async function revokeApiKey(id: string): Promise<void> {
+  return await invoke('revoke_api_key', { id });
+}
+
+export async function deleteApiKey(id: string): Promise<void> {
+  return await invoke('delete_api_key', { id });
+}
+
+export async function getEncryptionStatus(): Promise<EncryptionStatus> {
+  return await invoke('get_encryption_status');
+}
+
+export async function enableConversationEncryption(
+  keySource: KeySource,
+  passphrase?: string
+): Promise<EncryptionStatus> {
+  return await invoke('enable_conversation_encryption', { keySource, passphrase: passphrase ?? null });
+}
+
+export async function disableConversationEncryption(): Promise<EncryptionStatus> {
+  return await invoke('disable_conversation_encryption');
+}
+
+export async function rotateConversationKey(
+  keySource: KeySource,
+  passphrase?: string
+): Promise<EncryptionStatus> {
+  return await invoke('rotate_conversation_key', { keySource, passphrase: passphrase ?? null });
+}
+
+export async function unlockConversations(passphrase?: string): Promise<EncryptionStatus> {
+  return await invoke('unlock_conversations', { passphrase: passphrase ?? null });
+}
+
+export async function lockConversations(): Promise<EncryptionStatus> {
+  return await invoke('lock_conversations');
+}
+
 export async function updateTrayLanguage(labels: {
   startServerLabel: string;
   stopServerLabel: string;